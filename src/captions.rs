@@ -0,0 +1,87 @@
+use crate::chat::ChatClient;
+use crate::loaders::{Document, DocumentImage, ImageFormat};
+use crate::retry::RetryPolicy;
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rig::OneOrMany;
+use rig::client::CompletionClient;
+use rig::completion::Prompt;
+use rig::completion::message::{ImageDetail, ImageMediaType, Message, UserContent};
+use tracing::{debug, info, warn};
+
+const CAPTION_PROMPT: &str = "Describe this figure from a document so it can be found by a text search. \
+Say what kind of figure it is, transcribe any title, labels or legend, and summarise what it shows. \
+Answer in a few sentences of plain text.";
+
+/// About what an image costs a vision model, counted against `--tpm`.
+const IMAGE_TOKENS: usize = 1000;
+
+/// Sends every image extracted from the documents to a vision model and adds
+/// its caption as a document of its own, tagged with the source and page of
+/// the figure. The images are dropped from their documents afterwards.
+pub async fn caption_images(
+    client: &ChatClient,
+    model: &str,
+    retry: &RetryPolicy,
+    documents: &mut Vec<Document>,
+) -> Result<()> {
+    let total: usize = documents.iter().map(|d| d.images.len()).sum();
+    if total == 0 {
+        return Ok(());
+    }
+    info!("Captioning {} images with {}", total, model);
+    let agent = client.agent(model).build();
+
+    let mut captions = Vec::new();
+    for document in documents.iter_mut() {
+        for image in std::mem::take(&mut document.images) {
+            let message = match image_message(&image) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Skipping image on page {}: {}", image.page, e);
+                    continue;
+                }
+            };
+            let caption = retry
+                .run("Captioning", IMAGE_TOKENS, || {
+                    agent.prompt(message.clone()).into_future()
+                })
+                .await;
+            match caption {
+                Ok(caption) => {
+                    debug!("Caption for page {}: {}", image.page, caption);
+                    let mut captioned =
+                        Document::new(format!("Figure on page {}: {}", image.page, caption.trim()));
+                    captioned.metadata = document.metadata.clone();
+                    captions.push(
+                        captioned
+                            .with_metadata("page", image.page.to_string())
+                            .with_metadata("content", "figure caption"),
+                    );
+                }
+                Err(e) => warn!("Failed to caption image on page {}: {}", image.page, e),
+            }
+        }
+    }
+
+    info!("Captioned {} of {} images", captions.len(), total);
+    documents.extend(captions);
+    Ok(())
+}
+
+fn image_message(image: &DocumentImage) -> Result<Message> {
+    let media_type = match image.format {
+        ImageFormat::Jpeg => ImageMediaType::JPEG,
+        ImageFormat::Png => ImageMediaType::PNG,
+    };
+    let content = OneOrMany::many([
+        UserContent::image_base64(
+            STANDARD.encode(&image.data),
+            Some(media_type),
+            Some(ImageDetail::Auto),
+        ),
+        UserContent::text(CAPTION_PROMPT),
+    ])?;
+    Ok(Message::User { content })
+}
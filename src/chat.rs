@@ -0,0 +1,212 @@
+//! The chat model answers, captions, propositions and summaries come from:
+//! OpenAI's, or the same models deployed on Azure OpenAI, as
+//! `--chat-provider` says.
+
+use anyhow::{Context, bail};
+use futures::{Stream, StreamExt};
+use rig::client::{CompletionClient, ProviderClient};
+use rig::completion::{
+    CompletionError, CompletionModel, CompletionRequest, CompletionResponse, GetTokenUsage, Usage,
+};
+use rig::providers::{azure, openai};
+use rig::streaming::{
+    RawStreamingChoice, RawStreamingToolCall, StreamedAssistantContent, StreamingCompletionResponse,
+};
+use serde::{Deserialize, Serialize};
+
+/// The Azure OpenAI API version used unless `--azure-api-version` or
+/// `AZURE_OPENAI_API_VERSION` says otherwise.
+pub const AZURE_API_VERSION: &str = "2024-10-21";
+
+/// Where chat models are called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChatProvider {
+    /// The OpenAI API
+    #[default]
+    Openai,
+    /// An Azure OpenAI resource, with models named by their deployments
+    Azure,
+}
+
+/// Where an Azure OpenAI resource is and how to reach it: `--azure-endpoint`
+/// and `--azure-api-version`.
+#[derive(Debug, Clone, Default)]
+pub struct AzureSettings {
+    pub endpoint: Option<String>,
+    pub api_version: Option<String>,
+}
+
+impl AzureSettings {
+    /// A client for the resource, signed in with the key in
+    /// `AZURE_OPENAI_API_KEY` or else the Microsoft Entra ID (AAD) token in
+    /// `AZURE_OPENAI_AD_TOKEN`. The endpoint and API version fall back to
+    /// `AZURE_OPENAI_ENDPOINT` and `AZURE_OPENAI_API_VERSION`.
+    pub fn client(&self) -> anyhow::Result<azure::Client> {
+        let endpoint = self
+            .endpoint
+            .clone()
+            .or_else(|| std::env::var("AZURE_OPENAI_ENDPOINT").ok())
+            .context("Azure OpenAI needs --azure-endpoint or AZURE_OPENAI_ENDPOINT to be set")?;
+        let api_version = self
+            .api_version
+            .clone()
+            .or_else(|| std::env::var("AZURE_OPENAI_API_VERSION").ok())
+            .unwrap_or_else(|| AZURE_API_VERSION.to_string());
+        let auth = match (
+            std::env::var("AZURE_OPENAI_API_KEY"),
+            std::env::var("AZURE_OPENAI_AD_TOKEN"),
+        ) {
+            (Ok(key), _) => azure::AzureOpenAIAuth::ApiKey(key),
+            (_, Ok(token)) => azure::AzureOpenAIAuth::Token(token),
+            _ => {
+                bail!("Azure OpenAI needs AZURE_OPENAI_API_KEY or AZURE_OPENAI_AD_TOKEN to be set")
+            }
+        };
+        // rig puts the base URL in front of the endpoint it is given, so the
+        // endpoint goes in as the base URL and the endpoint is left empty.
+        Ok(azure::Client::builder()
+            .api_key(auth)
+            .base_url(endpoint.trim_end_matches('/'))
+            .azure_endpoint(String::new())
+            .api_version(&api_version)
+            .build()?)
+    }
+}
+
+/// A client for chat models from either provider.
+#[derive(Clone)]
+pub enum ChatClient {
+    OpenAi(openai::Client),
+    Azure(azure::Client),
+}
+
+impl ChatClient {
+    /// The OpenAI client, with the key in `OPENAI_API_KEY`, or the Azure
+    /// OpenAI resource `azure` describes.
+    pub fn new(provider: ChatProvider, azure: &AzureSettings) -> anyhow::Result<Self> {
+        Ok(match provider {
+            ChatProvider::Openai => ChatClient::OpenAi(openai::Client::from_env()),
+            ChatProvider::Azure => ChatClient::Azure(azure.client()?),
+        })
+    }
+}
+
+impl CompletionClient for ChatClient {
+    type CompletionModel = ChatModel;
+}
+
+/// A chat model from either provider. Their raw responses differ, so
+/// they are passed on as JSON, and streamed responses pass on only their
+/// token usage.
+#[derive(Clone)]
+pub enum ChatModel {
+    OpenAi(<openai::Client as CompletionClient>::CompletionModel),
+    Azure(azure::CompletionModel),
+}
+
+/// What is left of a provider's final streamed response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamedUsage {
+    usage: Option<Usage>,
+}
+
+impl GetTokenUsage for StreamedUsage {
+    fn token_usage(&self) -> Option<Usage> {
+        self.usage
+    }
+}
+
+impl CompletionModel for ChatModel {
+    type Response = serde_json::Value;
+    type StreamingResponse = StreamedUsage;
+
+    type Client = ChatClient;
+
+    fn make(client: &Self::Client, model: impl Into<String>) -> Self {
+        match client {
+            ChatClient::OpenAi(client) => ChatModel::OpenAi(client.completion_model(model)),
+            ChatClient::Azure(client) => ChatModel::Azure(client.completion_model(model)),
+        }
+    }
+
+    async fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<CompletionResponse<Self::Response>, CompletionError> {
+        match self {
+            ChatModel::OpenAi(model) => model.completion(request).await.map(as_json),
+            ChatModel::Azure(model) => model.completion(request).await.map(as_json),
+        }
+    }
+
+    async fn stream(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<StreamingCompletionResponse<Self::StreamingResponse>, CompletionError> {
+        match self {
+            ChatModel::OpenAi(model) => model.stream(request).await.map(usage),
+            ChatModel::Azure(model) => model.stream(request).await.map(usage),
+        }
+    }
+}
+
+/// Streams what the provider streams, keeping only the token usage of its
+/// final response.
+fn usage<R>(response: StreamingCompletionResponse<R>) -> StreamingCompletionResponse<StreamedUsage>
+where
+    R: Clone + Unpin + GetTokenUsage + Send + 'static,
+{
+    relay(response, |response| StreamedUsage {
+        usage: response.token_usage(),
+    })
+}
+
+fn as_json<R: Serialize>(response: CompletionResponse<R>) -> CompletionResponse<serde_json::Value> {
+    CompletionResponse {
+        choice: response.choice,
+        usage: response.usage,
+        raw_response: serde_json::to_value(response.raw_response).unwrap_or_default(),
+    }
+}
+
+/// Streams what `stream` streams, back in the raw form it was read from,
+/// with its final response passed through `finish`.
+pub fn relay<R, S>(
+    stream: impl Stream<Item = Result<StreamedAssistantContent<R>, CompletionError>> + Send + 'static,
+    finish: fn(R) -> S,
+) -> StreamingCompletionResponse<S>
+where
+    R: Clone + Unpin + GetTokenUsage + Send + 'static,
+    S: Clone + Unpin + GetTokenUsage + Send + 'static,
+{
+    let stream = stream.map(move |content| {
+        Ok(match content? {
+            StreamedAssistantContent::Text(text) => RawStreamingChoice::Message(text.text),
+            StreamedAssistantContent::ToolCall(call) => {
+                RawStreamingChoice::ToolCall(RawStreamingToolCall {
+                    id: call.id,
+                    call_id: call.call_id,
+                    name: call.function.name,
+                    arguments: call.function.arguments,
+                    signature: call.signature,
+                    additional_params: call.additional_params,
+                })
+            }
+            StreamedAssistantContent::ToolCallDelta { id, content } => {
+                RawStreamingChoice::ToolCallDelta { id, content }
+            }
+            StreamedAssistantContent::Reasoning(reasoning) => RawStreamingChoice::Reasoning {
+                id: reasoning.id,
+                reasoning: reasoning.reasoning.concat(),
+                signature: reasoning.signature,
+            },
+            StreamedAssistantContent::ReasoningDelta { id, reasoning } => {
+                RawStreamingChoice::ReasoningDelta { id, reasoning }
+            }
+            StreamedAssistantContent::Final(response) => {
+                RawStreamingChoice::FinalResponse(finish(response))
+            }
+        })
+    });
+    StreamingCompletionResponse::stream(Box::pin(stream))
+}
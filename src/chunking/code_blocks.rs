@@ -0,0 +1,151 @@
+//! Code examples in technical documents, as fenced (```` ``` ```` or `~~~`)
+//! or indented blocks, and how they are chunked: together with the sentence
+//! that introduces them and never mid-line, so an answer doesn't quote half
+//! a snippet.
+
+use super::ChunkUnit;
+use super::sentences::sentences;
+use super::tables::Segment;
+
+/// Longest sentence kept with the code block after it, in words; a longer
+/// one is more likely a paragraph the sentence splitter couldn't break up.
+const MAX_INTRO_WORDS: usize = 60;
+
+/// Splits prose into code blocks and the text around them. Each block comes
+/// with the sentence before it, which usually says what the code is for.
+/// An indented block needs a blank line before it, as in Markdown.
+pub fn segments(text: &str) -> Vec<Segment<'_>> {
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    // The marker and start of the fenced block being read, and the start of
+    // the indented one.
+    let mut fence: Option<(&str, usize)> = None;
+    let mut indented: Option<usize> = None;
+    let (mut offset, mut last_code_end, mut after_blank) = (0, 0, true);
+    for line in text.split_inclusive('\n') {
+        let end = offset + line.len();
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~"]
+            .into_iter()
+            .find(|marker| trimmed.starts_with(marker));
+        match (fence, marker) {
+            (Some((open, start)), Some(marker)) if marker == open => {
+                blocks.push((start, end));
+                fence = None;
+            }
+            (Some(_), _) => {}
+            (None, Some(marker)) => {
+                if let Some(start) = indented.take() {
+                    blocks.push((start, last_code_end));
+                }
+                fence = Some((marker, offset));
+            }
+            (None, None) => {
+                let is_code = line.starts_with("    ") || line.starts_with('\t');
+                if line.trim().is_empty() {
+                    // Blank lines only belong to an indented block when more
+                    // code follows them.
+                } else if is_code && (indented.is_some() || after_blank) {
+                    indented.get_or_insert(offset);
+                    last_code_end = end;
+                } else if let Some(start) = indented.take() {
+                    blocks.push((start, last_code_end));
+                }
+            }
+        }
+        after_blank = line.trim().is_empty();
+        offset = end;
+    }
+    if let Some((_, start)) = fence {
+        blocks.push((start, text.len()));
+    }
+    if let Some(start) = indented {
+        blocks.push((start, last_code_end));
+    }
+
+    let mut segments = Vec::new();
+    let mut prose_start = 0;
+    for (start, end) in blocks {
+        let prose = &text[prose_start..start];
+        let intro = sentences(prose)
+            .last()
+            .filter(|sentence| sentence.split_whitespace().count() <= MAX_INTRO_WORDS)
+            .and_then(|sentence| prose.rfind(sentence))
+            .map_or(start, |intro| prose_start + intro);
+        if !text[prose_start..intro].trim().is_empty() {
+            segments.push(Segment::Prose(&text[prose_start..intro]));
+        }
+        segments.push(Segment::Code(text[intro..end].trim_end()));
+        prose_start = end;
+    }
+    if !text[prose_start..].trim().is_empty() {
+        segments.push(Segment::Prose(&text[prose_start..]));
+    }
+    segments
+}
+
+/// A code block that fits in a chunk of `max_size` `unit`s is kept whole,
+/// with its introduction. A longer one is cut between lines, the
+/// introduction going with the first part, and a fenced block's fences are
+/// repeated around every part so each is still a complete snippet.
+pub fn chunk_code(block: &str, max_size: usize, unit: ChunkUnit) -> Vec<String> {
+    if unit.count(block) <= max_size {
+        return vec![block.trim().to_string()];
+    }
+    let lines: Vec<&str> = block.lines().collect();
+    let is_fence = |line: &&str| {
+        let line = line.trim_start();
+        line.starts_with("```") || line.starts_with("~~~")
+    };
+    // The introduction, plus the opening fence; the body; the closing fence.
+    let (intro, body, closing): (Vec<&str>, &[&str], Option<&str>) =
+        match lines.iter().position(is_fence) {
+            Some(open) => {
+                let close = lines.len() - 1;
+                let closed = close > open && is_fence(&lines[close]);
+                let body_end = if closed { close } else { lines.len() };
+                (
+                    lines[..=open].to_vec(),
+                    &lines[open + 1..body_end],
+                    closed.then_some(lines[close]),
+                )
+            }
+            None => {
+                let first = lines
+                    .iter()
+                    .position(|line| line.starts_with("    ") || line.starts_with('\t'))
+                    .unwrap_or(0);
+                (lines[..first].to_vec(), &lines[first..], None)
+            }
+        };
+    let opening = intro.last().copied().filter(is_fence);
+    let wrap = |first: bool, part: &[&str]| {
+        let mut chunk: Vec<&str> = if first {
+            intro.clone()
+        } else {
+            opening.into_iter().collect()
+        };
+        chunk.extend(part);
+        chunk.extend(closing);
+        chunk.join("\n")
+    };
+
+    let mut chunks = Vec::new();
+    let mut part: Vec<&str> = Vec::new();
+    let overhead = |first: bool| unit.count(&wrap(first, &[]));
+    let mut total = 0;
+    for line in body {
+        let size = unit.count(line);
+        let first = chunks.is_empty();
+        if !part.is_empty() && overhead(first) + total + size > max_size {
+            chunks.push(wrap(first, &part));
+            part.clear();
+            total = 0;
+        }
+        part.push(line);
+        total += size;
+    }
+    if !part.is_empty() || chunks.is_empty() {
+        chunks.push(wrap(chunks.is_empty(), &part));
+    }
+    chunks
+}
@@ -0,0 +1,45 @@
+use super::{ChunkUnit, Chunker};
+use regex::Regex;
+
+/// Cuts text before every match of `pattern`, such as `^Article \d+`, so
+/// each piece starts with its marker. A piece that fits in `size` units is
+/// one chunk, however short; a longer one is chunked further by `inner`.
+pub struct DelimiterChunker {
+    pub pattern: Regex,
+    pub unit: ChunkUnit,
+    pub size: usize,
+    pub inner: Box<dyn Chunker>,
+}
+
+impl Chunker for DelimiterChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let mut starts: Vec<usize> = self
+            .pattern
+            .find_iter(text)
+            .map(|found| found.start())
+            .filter(|&start| start > 0)
+            .collect();
+        starts.dedup();
+        starts.push(text.len());
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for end in starts {
+            let piece = text[start..end].trim();
+            start = end;
+            if piece.is_empty() {
+                continue;
+            }
+            if self.unit.count(piece) <= self.size {
+                chunks.push(piece.to_string());
+            } else {
+                chunks.extend(self.inner.chunk(piece));
+            }
+        }
+        chunks
+    }
+
+    fn keeps_tables(&self) -> bool {
+        self.inner.keeps_tables()
+    }
+}
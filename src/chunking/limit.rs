@@ -0,0 +1,254 @@
+//! Keeping chunks within the embedding model's input limit. A chunk over it
+//! (a table kept whole, a long code block, text with no spaces to cut at)
+//! would fail its whole batch with an error that doesn't say which chunk,
+//! or be cut short by a model that quietly truncates, so it is split into
+//! pieces that fit before anything is sent.
+
+use super::Chunk;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::warn;
+
+/// The longest text an embedding model takes, in the tokens it counts.
+#[derive(Clone)]
+pub struct TokenLimit {
+    pub max_tokens: usize,
+    /// A text's length in the model's tokens.
+    pub count: Arc<dyn Fn(&str) -> usize + Send + Sync>,
+}
+
+impl TokenLimit {
+    /// A limit counted in OpenAI's cl100k tokens, which is near enough for
+    /// models whose own tokenizer isn't at hand.
+    pub fn cl100k(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            count: Arc::new(|text| tiktoken_rs::cl100k_base_singleton().count_ordinary(text)),
+        }
+    }
+}
+
+/// Splits every chunk longer than one of `limits` allows into consecutive
+/// pieces that fit them all, cut at whitespace where possible, and numbers
+/// each document's chunks again. Pieces keep the metadata, position,
+/// parent and summary of the chunk they were cut from, and start with its
+/// header.
+pub fn split_over_limit(chunks: Vec<Chunk>, limits: &[TokenLimit]) -> Vec<Chunk> {
+    let fits = |text: &str| {
+        limits
+            .iter()
+            .all(|limit| (limit.count)(text) <= limit.max_tokens)
+    };
+    if chunks.iter().all(|chunk| fits(&chunk.text)) {
+        return chunks;
+    }
+    let mut split = Vec::with_capacity(chunks.len());
+    // Chunks are numbered among their document's chunks in turn.
+    let mut next_index: HashMap<(Option<String>, usize), usize> = HashMap::new();
+    for chunk in chunks {
+        let document = (chunk.source().map(str::to_string), chunk.document);
+        let index = next_index.entry(document).or_default();
+        if fits(&chunk.text) {
+            split.push(Chunk {
+                index: *index,
+                ..chunk
+            });
+            *index += 1;
+            continue;
+        }
+        let (header, body) = match &chunk.header {
+            Some(header) => (
+                format!("{}\n\n", header),
+                chunk
+                    .text
+                    .strip_prefix(header.as_str())
+                    .map_or(chunk.text.as_str(), str::trim_start),
+            ),
+            None => (String::new(), chunk.text.as_str()),
+        };
+        let pieces = pieces(&header, body, limits);
+        warn!(
+            "Chunk {} of {} is {} tokens, over the embedding model's limit of {}; split it into {}",
+            chunk.index,
+            chunk.source().unwrap_or("the document"),
+            limits
+                .iter()
+                .map(|limit| (limit.count)(&chunk.text))
+                .max()
+                .unwrap_or_default(),
+            limits
+                .iter()
+                .map(|limit| limit.max_tokens)
+                .min()
+                .unwrap_or_default(),
+            pieces.len()
+        );
+        for text in pieces {
+            split.push(Chunk {
+                text: format!("{}{}", header, text),
+                index: *index,
+                ..chunk.clone()
+            });
+            *index += 1;
+        }
+    }
+    split
+}
+
+/// `text` packed into pieces that, after `header`, are within every one
+/// of `limits`, cut after whitespace, with any run of text too long on its
+/// own cut in halves until it fits.
+fn pieces(header: &str, text: &str, limits: &[TokenLimit]) -> Vec<String> {
+    let header_tokens: Vec<usize> = limits.iter().map(|limit| (limit.count)(header)).collect();
+    // Room for the text after the header, though never none at all.
+    let room: Vec<usize> = limits
+        .iter()
+        .zip(&header_tokens)
+        .map(|(limit, header)| limit.max_tokens.saturating_sub(*header).max(1))
+        .collect();
+    let over = |tokens: &[usize]| tokens.iter().zip(&room).any(|(tokens, room)| tokens > room);
+    let count =
+        |text: &str| -> Vec<usize> { limits.iter().map(|limit| (limit.count)(text)).collect() };
+
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut piece_tokens = vec![0; limits.len()];
+    for word in text.split_inclusive(char::is_whitespace) {
+        for part in fitting(word, &|text| !over(&count(text))) {
+            let tokens = count(part);
+            let added: Vec<usize> = piece_tokens
+                .iter()
+                .zip(&tokens)
+                .map(|(a, b)| a + b)
+                .collect();
+            // Counts of parts add up to about the count of their whole, so
+            // a piece is counted again before it is cut short of the limit.
+            let combined = if over(&added) {
+                count(&format!("{}{}", piece, part))
+            } else {
+                added
+            };
+            if over(&combined) {
+                pieces.push(std::mem::take(&mut piece));
+                piece_tokens = tokens;
+            } else {
+                piece_tokens = combined;
+            }
+            piece.push_str(part);
+        }
+    }
+    pieces.push(piece);
+    pieces.retain(|piece| !piece.trim().is_empty());
+    pieces
+}
+
+/// `text`, or its halves, and theirs, until each `fits`.
+fn fitting<'a>(text: &'a str, fits: &dyn Fn(&str) -> bool) -> Vec<&'a str> {
+    if fits(text) {
+        return vec![text];
+    }
+    let half = text.chars().count() / 2;
+    let Some((middle, _)) = text.char_indices().nth(half).filter(|&(i, _)| i > 0) else {
+        return vec![text];
+    };
+    let (first, second) = text.split_at(middle);
+    let mut halves = fitting(first, fits);
+    halves.extend(fitting(second, fits));
+    halves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A limit counted in words, which are easy to reckon with.
+    fn words(max_tokens: usize) -> TokenLimit {
+        TokenLimit {
+            max_tokens,
+            count: Arc::new(|text| text.split_whitespace().count()),
+        }
+    }
+
+    fn chunk(source: &str, document: usize, index: usize, text: &str) -> Chunk {
+        let mut chunk = Chunk {
+            text: text.to_string(),
+            metadata: Default::default(),
+            index,
+            char_range: 0..0,
+            parent: None,
+            summary: None,
+            document,
+            header: None,
+        };
+        chunk
+            .metadata
+            .insert("source".to_string(), source.to_string());
+        chunk
+    }
+
+    fn texts(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|chunk| chunk.text.as_str()).collect()
+    }
+
+    #[test]
+    fn leaves_chunks_that_fit_alone() {
+        let chunks = vec![chunk("a", 0, 0, "one two"), chunk("a", 0, 5, "three")];
+        assert_eq!(split_over_limit(chunks.clone(), &[words(2)]), chunks);
+    }
+
+    #[test]
+    fn splits_at_whitespace_and_numbers_again() {
+        let chunks = vec![
+            chunk("a", 0, 0, "one two three four five"),
+            chunk("a", 0, 1, "six"),
+        ];
+        let split = split_over_limit(chunks, &[words(2)]);
+        assert_eq!(texts(&split), ["one two ", "three four ", "five", "six"]);
+        let indexes: Vec<usize> = split.iter().map(|chunk| chunk.index).collect();
+        assert_eq!(indexes, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn numbers_each_document_on_its_own() {
+        // The second document's first chunk is gone, say as a duplicate, so
+        // its chunks don't start at 0; they start again all the same.
+        let chunks = vec![
+            chunk("a", 0, 0, "one two three"),
+            chunk("a", 1, 1, "four"),
+            chunk("b", 0, 3, "five"),
+        ];
+        let split = split_over_limit(chunks, &[words(2)]);
+        let numbered: Vec<(usize, usize)> = split
+            .iter()
+            .map(|chunk| (chunk.document, chunk.index))
+            .collect();
+        assert_eq!(numbered, [(0, 0), (0, 1), (1, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn keeps_the_header_on_every_piece() {
+        let mut long = chunk("a", 0, 0, "Document: a.pdf\n\none two three four");
+        long.header = Some("Document: a.pdf".to_string());
+        let split = split_over_limit(vec![long], &[words(4)]);
+        assert_eq!(
+            texts(&split),
+            [
+                "Document: a.pdf\n\none two ",
+                "Document: a.pdf\n\nthree four"
+            ]
+        );
+    }
+
+    #[test]
+    fn fits_every_limit() {
+        let chars = TokenLimit {
+            max_tokens: 8,
+            count: Arc::new(|text| text.trim().chars().count()),
+        };
+        let split = split_over_limit(
+            vec![chunk("a", 0, 0, "aaaa bb cccccccccccc")],
+            &[words(3), chars],
+        );
+        assert_eq!(texts(&split), ["aaaa bb ", "cccccc", "cccccc"]);
+    }
+}
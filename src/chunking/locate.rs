@@ -0,0 +1,192 @@
+use crate::loaders::Document;
+use std::ops::Range;
+
+/// Number of a chunk's opening words matched against the document.
+const MATCH_WORDS: usize = 8;
+
+/// Number of a chunk's opening characters matched against the document,
+/// for chunks that don't start at a word.
+const MATCH_CHARS: usize = 32;
+
+/// Most opening lines skipped to find a chunk that starts with lines
+/// repeated from elsewhere, like a table's header or a code block's fence.
+const MAX_REPEATED_LINES: usize = 2;
+
+/// Where a chunk was cut from in its document.
+pub struct Location {
+    /// Character offsets of the chunk's first and last words.
+    pub char_range: Range<usize>,
+    /// The pages the chunk spans, like `14` or `14-15`, for documents loaded
+    /// page by page.
+    pub pages: Option<String>,
+}
+
+/// Finds where a document's chunks came from, in order. Chunking rearranges
+/// whitespace, page breaks included, so chunks are traced back by their
+/// words: each chunk's opening words are looked for in the document, from
+/// where the chunk before it started. A chunk cut by characters, inside a
+/// word (as in Chinese or Japanese, with no spaces to cut at), is traced
+/// back by its opening characters instead.
+pub struct Locator<'a> {
+    words: Vec<Word<'a>>,
+    /// Every character of the words, gathered the first time a chunk isn't
+    /// found by its words.
+    chars: Option<Vec<Char>>,
+    paged: bool,
+    /// Index of the word the last chunk started in.
+    from: usize,
+}
+
+struct Word<'a> {
+    text: &'a str,
+    chars: Range<usize>,
+    page: Option<u32>,
+}
+
+struct Char {
+    c: char,
+    /// Character offset in the document.
+    offset: usize,
+    /// Index of the word it is in.
+    word: usize,
+}
+
+impl<'a> Locator<'a> {
+    pub fn new(document: &'a Document) -> Self {
+        let text = &document.text;
+        let mut words = Vec::new();
+        let mut start: Option<(usize, usize)> = None;
+        let mut page = 0;
+        let ends = text.char_indices().chain([(text.len(), ' ')]);
+        for (chars, (byte, c)) in ends.enumerate() {
+            if c.is_whitespace() {
+                if let Some((start_byte, start_char)) = start.take() {
+                    words.push(Word {
+                        text: &text[start_byte..byte],
+                        chars: start_char..chars,
+                        page: document.pages.get(page).copied(),
+                    });
+                }
+                page += usize::from(c == '\u{c}');
+            } else if start.is_none() {
+                start = Some((byte, chars));
+            }
+        }
+        Self {
+            words,
+            chars: None,
+            paged: !document.pages.is_empty(),
+            from: 0,
+        }
+    }
+
+    pub fn locate(&mut self, text: &str) -> Option<Location> {
+        let mut rest = text;
+        for _ in 0..=MAX_REPEATED_LINES {
+            if let Some(location) = self.locate_words(rest) {
+                return Some(location);
+            }
+            rest = rest.split_once('\n')?.1;
+        }
+        None
+    }
+
+    fn locate_words(&mut self, text: &str) -> Option<Location> {
+        let chunk: Vec<&str> = text.split_whitespace().collect();
+        if chunk.is_empty() {
+            return None;
+        }
+        let opening = &chunk[..chunk.len().min(MATCH_WORDS)];
+        let matches = |start: &usize| {
+            self.words[*start..]
+                .iter()
+                .map(|word| word.text)
+                .take(opening.len())
+                .eq(opening.iter().copied())
+        };
+        let Some(start) = (self.from..self.words.len())
+            .find(matches)
+            .or_else(|| (0..self.from).find(matches))
+        else {
+            return self.locate_chars(text);
+        };
+        self.from = start;
+
+        let words = &self.words[start..(start + chunk.len()).min(self.words.len())];
+        let char_range = words[0].chars.start..words[words.len() - 1].chars.end;
+        Some(Location {
+            char_range,
+            pages: self.pages(words),
+        })
+    }
+
+    fn locate_chars(&mut self, text: &str) -> Option<Location> {
+        let words = &self.words;
+        let chars = self.chars.get_or_insert_with(|| {
+            let mut chars = Vec::new();
+            for (index, word) in words.iter().enumerate() {
+                chars.extend(word.text.chars().enumerate().map(|(i, c)| Char {
+                    c,
+                    offset: word.chars.start + i,
+                    word: index,
+                }));
+            }
+            chars
+        });
+        let chunk: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+        let opening = &chunk[..chunk.len().min(MATCH_CHARS)];
+        let matches = |start: &usize| {
+            chars[*start..]
+                .iter()
+                .map(|c| c.c)
+                .take(opening.len())
+                .eq(opening.iter().copied())
+        };
+        let from = chars.partition_point(|c| c.word < self.from);
+        let start = (from..chars.len())
+            .find(matches)
+            .or_else(|| (0..from).find(matches))?;
+
+        let (first, last) = (
+            &chars[start],
+            &chars[(start + chunk.len()).min(chars.len()) - 1],
+        );
+        let char_range = first.offset..last.offset + 1;
+        let (first, last) = (first.word, last.word);
+        self.from = first;
+        Some(Location {
+            char_range,
+            pages: self.pages(&self.words[first..=last]),
+        })
+    }
+
+    /// The pages `words` are on, for documents loaded page by page.
+    fn pages(&self, words: &[Word]) -> Option<String> {
+        self.paged.then(|| {
+            let mut pages: Vec<u32> = words.iter().filter_map(|word| word.page).collect();
+            pages.dedup();
+            page_label(&pages)
+        })
+    }
+}
+
+/// Writes page numbers as ranges: `3`, `3-4`, `9, 12`.
+fn page_label(pages: &[u32]) -> String {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for &page in pages {
+        match runs.last_mut() {
+            Some((_, last)) if page == *last + 1 => *last = page,
+            _ => runs.push((page, page)),
+        }
+    }
+    runs.iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
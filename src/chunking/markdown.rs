@@ -0,0 +1,177 @@
+use super::recursive::{RecursiveChunker, pack};
+use super::{ChunkUnit, Chunker, heading, split_before};
+
+/// Splits Markdown into chunks of up to `size` units at its top-level
+/// headings, then at each deeper level only where a section is still too
+/// long, and finally between blocks. Fenced code blocks and tables are never
+/// cut, even when longer than a chunk; a long paragraph is split as
+/// [`RecursiveChunker`] would.
+pub struct MarkdownChunker {
+    pub unit: ChunkUnit,
+    pub size: usize,
+    pub overlap: usize,
+}
+
+impl Chunker for MarkdownChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let mut chunks = Vec::new();
+        self.split(text, 1, &mut chunks);
+        chunks
+    }
+
+    fn keeps_tables(&self) -> bool {
+        true
+    }
+}
+
+impl MarkdownChunker {
+    fn split(&self, text: &str, level: usize, chunks: &mut Vec<String>) {
+        let text = text.trim();
+        if self.unit.count(text) <= self.size {
+            if !text.is_empty() {
+                chunks.push(text.to_string());
+            }
+            return;
+        }
+        if level > 6 {
+            pack(
+                blocks(text),
+                "\n\n",
+                self.unit,
+                self.size,
+                chunks,
+                |block, chunks| {
+                    if block
+                        .lines()
+                        .any(|line| line.starts_with("```") || line.starts_with('|'))
+                    {
+                        chunks.push(block.to_string());
+                    } else {
+                        let recursive = RecursiveChunker {
+                            unit: self.unit,
+                            size: self.size,
+                            overlap: self.overlap,
+                        };
+                        chunks.extend(recursive.chunk(block));
+                    }
+                },
+            );
+            return;
+        }
+        let sections = split_before(text, |line| {
+            heading(line).is_some_and(|(heading_level, _)| heading_level == level)
+        });
+        pack(
+            sections,
+            "\n\n",
+            self.unit,
+            self.size,
+            chunks,
+            |section, chunks| self.split(section, level + 1, chunks),
+        );
+    }
+}
+
+/// Splits Markdown into blocks: paragraphs, fenced code blocks (blank
+/// lines and all) and tables (runs of `|` rows). A heading stays with the
+/// block after it.
+fn blocks(text: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let (mut start, mut offset) = (0, 0);
+    let mut in_fence = false;
+    let mut in_table = false;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let fence = trimmed.starts_with("```");
+        let row = !in_fence && trimmed.starts_with('|');
+        if in_fence {
+            offset += line.len();
+            if fence {
+                blocks.push(&text[start..offset]);
+                start = offset;
+                in_fence = false;
+            }
+            continue;
+        }
+        // A block ends at a blank line, and at the edges of tables and code.
+        let block = text[start..offset].trim();
+        let lone_heading = !block.contains('\n') && heading(block).is_some();
+        if (trimmed.is_empty() || fence || row != in_table) && !lone_heading {
+            blocks.push(&text[start..offset]);
+            start = offset;
+        }
+        in_fence = fence;
+        in_table = row;
+        offset += line.len();
+    }
+    blocks.push(&text[start..]);
+    blocks
+        .into_iter()
+        .map(str::trim)
+        .filter(|block| !block.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(size: usize, text: &str) -> Vec<String> {
+        MarkdownChunker {
+            unit: ChunkUnit::Words,
+            size,
+            overlap: 0,
+        }
+        .chunk(text)
+    }
+
+    #[test]
+    fn splits_at_the_top_level_first() {
+        let text = "# A\n\nOne two.\n\n## A.1\n\nThree.\n\n# B\n\nFour five six.";
+        assert_eq!(
+            chunk(7, text),
+            [
+                "# A\n\nOne two.\n\n## A.1\n\nThree.",
+                "# B\n\nFour five six."
+            ]
+        );
+        assert_eq!(
+            chunk(5, text),
+            [
+                "# A\n\nOne two.",
+                "## A.1\n\nThree.",
+                "# B\n\nFour five six."
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_code_blocks_and_tables_whole() {
+        let code = "```\nfn main() {\n\n    println!(\"a b c d e f\");\n}\n```";
+        let table = "| a | b |\n| - | - |\n| 1 | 2 |\n| 3 | 4 |";
+        let text = format!("Intro words.\n\n{}\n\n{}", code, table);
+        assert_eq!(chunk(3, &text), ["Intro words.", code, table]);
+    }
+
+    #[test]
+    fn headings_in_code_blocks_are_not_split_at() {
+        let text = "# Real\n\n```\n# not a heading\nmore code\n```";
+        // The heading stays with the block after it, and the block whole.
+        assert_eq!(chunk(3, text), [text]);
+    }
+
+    #[test]
+    fn long_paragraphs_are_split_like_recursive_chunks() {
+        let text = "# Über\n\nÇa va. Très bien merci beaucoup.";
+        assert_eq!(
+            chunk(4, text),
+            ["# Über", "Ça va.", "Très bien merci beaucoup."]
+        );
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        assert!(chunk(5, "").is_empty());
+        assert!(chunk(5, "\n\n").is_empty());
+    }
+}
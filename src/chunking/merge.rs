@@ -0,0 +1,83 @@
+use super::ChunkUnit;
+
+/// Shortest run of characters [`join_chars`] takes for an overlap.
+const MIN_SHARED_CHARS: usize = 8;
+
+/// Folds chunks smaller than `min_size` into the chunk before them (or the
+/// one after, for a document's first chunk), so a document no longer ends
+/// in a stub of a few words. Words a small chunk shares with its neighbour
+/// through the overlap aren't repeated, which drops a small chunk outright
+/// when the overlap already covers it. A document too short for even one
+/// chunk of `min_size` is kept as a single chunk.
+pub fn merge_small(chunks: Vec<String>, min_size: usize, unit: ChunkUnit) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    // Small opening chunks, waiting for one to follow them.
+    let mut pending: Option<String> = None;
+    for chunk in chunks {
+        let chunk = match pending.take() {
+            Some(before) => join(&before, &chunk, unit),
+            None => chunk,
+        };
+        if unit.count(&chunk) >= min_size {
+            merged.push(chunk);
+            continue;
+        }
+        match merged.last_mut() {
+            Some(last) => *last = join(last, &chunk, unit),
+            None => pending = Some(chunk),
+        }
+    }
+    merged.extend(pending);
+    merged
+}
+
+/// Appends `second` to `first`, leaving out the words (or, counting in
+/// characters, the text) at its start that `first` already ends with.
+fn join(first: &str, second: &str, unit: ChunkUnit) -> String {
+    if unit == ChunkUnit::Chars {
+        return join_chars(first, second);
+    }
+    let ends: Vec<&str> = first.split_whitespace().collect();
+    let starts: Vec<&str> = second.split_whitespace().collect();
+    let shared = (1..=ends.len().min(starts.len()))
+        .rev()
+        .find(|&n| ends[ends.len() - n..] == starts[..n])
+        .unwrap_or(0);
+
+    let mut rest = second.trim_start();
+    for _ in 0..shared {
+        rest = rest
+            .trim_start_matches(|c: char| !c.is_whitespace())
+            .trim_start();
+    }
+    if rest.is_empty() {
+        return first.to_string();
+    }
+    // A chunk that picks up mid-text from an overlap carries on the same
+    // line (or the next, counting in lines); one cut at a boundary starts a
+    // new paragraph.
+    let separator = match (shared > 0, unit) {
+        (true, ChunkUnit::Lines) => "\n",
+        (true, _) => " ",
+        (false, _) => "\n\n",
+    };
+    format!("{}{}{}", first.trim_end(), separator, rest)
+}
+
+/// Like [`join`], for chunks cut between characters rather than words. Only
+/// an overlap of at least [`MIN_SHARED_CHARS`] counts, since a character
+/// or two can easily match by chance.
+fn join_chars(first: &str, second: &str) -> String {
+    let (first, second) = (first.trim_end(), second.trim_start());
+    let shared = second
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([second.len()])
+        .rev()
+        .find(|&i| first.ends_with(&second[..i]))
+        .filter(|&i| second[..i].chars().count() >= MIN_SHARED_CHARS);
+    match shared {
+        Some(shared) => format!("{}{}", first, &second[shared..]),
+        None => format!("{}\n\n{}", first, second),
+    }
+}
@@ -0,0 +1,895 @@
+mod code_blocks;
+mod delimiter;
+mod limit;
+mod locate;
+mod markdown;
+mod merge;
+mod paragraph;
+mod parents;
+mod profiles;
+mod recursive;
+mod semantic;
+mod sentences;
+mod tables;
+mod words;
+
+use crate::loaders::Document;
+use crate::progress;
+use anyhow::{Result, bail};
+use delimiter::DelimiterChunker;
+pub use limit::{TokenLimit, split_over_limit};
+use locate::Locator;
+use markdown::MarkdownChunker;
+use paragraph::ParagraphChunker;
+pub use profiles::{ChunkProfile, load_profiles};
+use recursive::RecursiveChunker;
+use regex::Regex;
+use rig::Embed;
+use rig::embeddings::{EmbedError, EmbeddingModel, TextEmbedder};
+use semantic::SemanticChunker;
+use sentences::SentenceChunker;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::{Range, RangeInclusive};
+use tables::Segment;
+use tracing::debug;
+use unicode_segmentation::UnicodeSegmentation;
+use words::WordChunker;
+
+/// A piece of a document that gets embedded and retrieved, along with the
+/// metadata inherited from its document (source, chapter, ...) and the
+/// page it is on.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Chunk {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// Position among the chunks of its document, from 0.
+    #[serde(default)]
+    pub index: usize,
+    /// Character offsets in its document's text of the first and last words
+    /// the chunk was cut from.
+    #[serde(default)]
+    pub char_range: Range<usize>,
+    /// With [`ChunkOptions::parent_size`], the larger chunk around this
+    /// one, which is what the model reads when this chunk is retrieved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Box<Chunk>>,
+    /// With `--summaries`, a sentence saying what the chunk is about, which
+    /// is embedded along with its text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Position of its document among the documents chunked together, so
+    /// the documents of a file with several are told apart.
+    #[serde(skip)]
+    pub document: usize,
+    /// The header or section path the text starts with, if any.
+    #[serde(skip)]
+    pub header: Option<String>,
+}
+
+impl Chunk {
+    /// The file or URL the chunk's document was loaded from, which is what
+    /// identifies a document across runs.
+    pub fn source(&self) -> Option<&str> {
+        self.metadata.get("source").map(String::as_str)
+    }
+
+    /// The page or pages the chunk spans, like `14` or `14-15`.
+    pub fn page(&self) -> Option<&str> {
+        self.metadata.get("page").map(String::as_str)
+    }
+
+    /// The path of the section the chunk falls under, like
+    /// `Chapter 3 > Installation`.
+    pub fn section(&self) -> Option<&str> {
+        self.metadata.get("section").map(String::as_str)
+    }
+}
+
+impl Embed for Chunk {
+    fn embed(&self, embedder: &mut TextEmbedder) -> Result<(), EmbedError> {
+        embedder.embed(self.text.clone());
+        if let Some(summary) = &self.summary {
+            embedder.embed(summary.clone());
+        }
+        Ok(())
+    }
+}
+
+/// How documents are cut into chunks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Chunking {
+    /// Windows of `--chunk-size` words (or `--chunk-unit`s)
+    #[default]
+    Words,
+    /// Windows of `--chunk-size` tokens of OpenAI's cl100k tokenizer; the
+    /// same as words with `--chunk-unit tokens`
+    Tokens,
+    /// Whole sentences packed up to `--chunk-size`, overlapping by
+    /// `--chunk-overlap` sentences
+    Sentences,
+    /// Whole paragraphs packed up to `--chunk-size`; only paragraphs longer
+    /// than a chunk are split into windows
+    Paragraph,
+    /// Split by sections, then paragraphs, then sentences, then windows,
+    /// going only as fine as needed to fit `--chunk-size`
+    Recursive,
+    /// For Markdown: split at headings, level by level, then between blocks,
+    /// keeping fenced code blocks and tables whole even when longer than
+    /// `--chunk-size`
+    Markdown,
+    /// Sentences grouped by topic: a new chunk starts where the embeddings
+    /// of neighbouring sentences stop being similar. Embeds every sentence
+    /// once more, so it costs extra embedding calls
+    Semantic,
+}
+
+/// What `--chunk-size` and `--chunk-overlap` count.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChunkUnit {
+    /// Words, as separated by whitespace
+    #[default]
+    Words,
+    /// Characters, for languages like Chinese and Japanese that aren't
+    /// written with spaces between words
+    Chars,
+    /// Tokens of OpenAI's cl100k tokenizer, whatever the embedding model:
+    /// OpenAI's models count in them, and other models' tokenizers come to
+    /// roughly as many, so a chunk size maps roughly onto their input limit
+    Tokens,
+    /// Lines, for source code and other line-oriented text
+    Lines,
+}
+
+impl ChunkUnit {
+    /// A text's length in this unit.
+    pub fn count(self, text: &str) -> usize {
+        match self {
+            ChunkUnit::Words => text.split_whitespace().count(),
+            ChunkUnit::Chars => text.trim().chars().count(),
+            ChunkUnit::Tokens => tiktoken_rs::cl100k_base_singleton().count_ordinary(text),
+            ChunkUnit::Lines => text.trim_end().lines().count(),
+        }
+    }
+
+    /// The pieces chunks are cut between, each with its length in this
+    /// unit: words, or for characters, the words and spaces Unicode word
+    /// boundaries separate, which in Chinese and Japanese are mostly single
+    /// characters, or lines. Words are joined back with single spaces,
+    /// while characters and lines keep the spacing they had.
+    pub fn pieces(self, text: &str) -> Vec<(&str, usize)> {
+        match self {
+            ChunkUnit::Words => text.split_whitespace().map(|word| (word, 1)).collect(),
+            ChunkUnit::Chars => text
+                .trim()
+                .split_word_bounds()
+                .map(|piece| (piece, piece.chars().count()))
+                .collect(),
+            ChunkUnit::Tokens => {
+                let bpe = tiktoken_rs::cl100k_base_singleton();
+                // Words are encoded with the space before them, as they are
+                // in running text, so their counts add up to about the count
+                // of the whole chunk.
+                text.split_whitespace()
+                    .map(|word| (word, bpe.count_ordinary(&format!(" {}", word))))
+                    .collect()
+            }
+            ChunkUnit::Lines => text.trim_end().lines().map(|line| (line, 1)).collect(),
+        }
+    }
+
+    /// The unit's name, in the plural.
+    pub fn name(self) -> &'static str {
+        match self {
+            ChunkUnit::Words => "words",
+            ChunkUnit::Chars => "characters",
+            ChunkUnit::Tokens => "tokens",
+            ChunkUnit::Lines => "lines",
+        }
+    }
+
+    /// What [`pieces`](Self::pieces) are joined back together with.
+    pub fn separator(self) -> &'static str {
+        match self {
+            ChunkUnit::Words | ChunkUnit::Tokens => " ",
+            ChunkUnit::Chars => "",
+            ChunkUnit::Lines => "\n",
+        }
+    }
+}
+
+/// Settings for [`chunk_documents`].
+#[derive(Debug, Clone)]
+pub struct ChunkOptions {
+    pub chunking: Chunking,
+    /// What `size`, `overlap` and `min_size` count.
+    pub unit: ChunkUnit,
+    /// Largest chunk, in `unit`s.
+    pub size: usize,
+    /// Overlap between chunks, in `unit`s, or in sentences with
+    /// [`Chunking::Sentences`].
+    pub overlap: usize,
+    /// Smallest chunk, in the same unit as `size`; smaller ones are merged
+    /// into a neighbour.
+    pub min_size: usize,
+    /// Similarity between neighbouring sentences below which
+    /// [`Chunking::Semantic`] starts a new chunk; when unset, breaks go at
+    /// the least similar tenth of each document's sentence pairs.
+    pub semantic_threshold: Option<f64>,
+    /// Chunk each section separately and start every chunk with its
+    /// section path.
+    pub sections: bool,
+    /// Start every chunk with a line naming its document, section and page.
+    pub headers: bool,
+    /// Also cut each document into chunks of this size, without overlap,
+    /// and give every chunk the one it falls in as its parent.
+    pub parent_size: Option<usize>,
+    /// Cut documents before every match, like `^Article \d+`, and chunk
+    /// with the strategy only what is still longer than `size`.
+    pub split: Option<Regex>,
+    /// Settings for the documents whose source matches a profile's glob,
+    /// used by the first that matches in place of these.
+    pub profiles: Vec<ChunkProfile>,
+}
+
+impl ChunkOptions {
+    /// Checks that chunks of these sizes can be cut at all, explaining how
+    /// to fix settings that can't.
+    pub fn validate(&self) -> Result<()> {
+        if self.size == 0 {
+            bail!("--chunk-size must be at least 1");
+        }
+        // Sentence overlaps are counted in sentences, which always move on.
+        if self.chunking != Chunking::Sentences && self.overlap >= self.size {
+            bail!(
+                "--chunk-overlap {} is not smaller than --chunk-size {}, so chunks would never \
+                 move on; try a smaller overlap, like --chunk-overlap 10%",
+                self.overlap,
+                self.size
+            );
+        }
+        if let Some(parent_size) = self.parent_size.filter(|&parent| parent <= self.size) {
+            bail!(
+                "--parent-chunk-size {} is not larger than --chunk-size {}, so parents would be \
+                 no bigger than their chunks; try --parent-chunk-size {}",
+                parent_size,
+                self.size,
+                self.size * 4
+            );
+        }
+        if self.min_size > self.size {
+            bail!(
+                "--min-chunk-size {} is larger than --chunk-size {}, so every chunk would be \
+                 merged into the next; try a smaller minimum, like --min-chunk-size {}",
+                self.min_size,
+                self.size,
+                self.size / 4
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `--chunk-overlap`: a count in the chunking strategy's unit, or a share
+/// of `--chunk-size` like `10%`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overlap {
+    Count(usize),
+    Percent(f64),
+}
+
+impl std::str::FromStr for Overlap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let Some(percent) = s.strip_suffix('%') else {
+            return match s.parse() {
+                Ok(count) => Ok(Overlap::Count(count)),
+                Err(_) => bail!(
+                    "invalid overlap {:?}, expected a count like 50 or a share like 10%",
+                    s
+                ),
+            };
+        };
+        match percent.trim().parse::<f64>() {
+            Ok(percent) if (0.0..100.0).contains(&percent) => Ok(Overlap::Percent(percent)),
+            Ok(_) => bail!("an overlap percentage must be under 100%"),
+            Err(_) => bail!("invalid overlap percentage {:?}", s),
+        }
+    }
+}
+
+impl Chunking {
+    /// The overlap used when `--chunk-overlap` isn't given, in this
+    /// strategy's unit.
+    pub fn default_overlap(self) -> usize {
+        match self {
+            Chunking::Words
+            | Chunking::Tokens
+            | Chunking::Paragraph
+            | Chunking::Recursive
+            | Chunking::Markdown
+            | Chunking::Semantic => 50,
+            Chunking::Sentences => 1,
+        }
+    }
+
+    /// The overlap, in this strategy's unit, for chunks of `size`. A
+    /// percentage is rounded down, and the default shrinks to a tenth of
+    /// chunks too small for it.
+    pub fn overlap(self, size: usize, overlap: Option<Overlap>) -> Result<usize> {
+        match overlap {
+            Some(Overlap::Count(count)) => Ok(count),
+            Some(Overlap::Percent(_)) if self == Chunking::Sentences => bail!(
+                "--chunking sentences counts --chunk-overlap in sentences, so it can't be a \
+                 percentage of --chunk-size; try --chunk-overlap 1"
+            ),
+            Some(Overlap::Percent(percent)) => Ok((size as f64 * percent / 100.0) as usize),
+            None if self != Chunking::Sentences && self.default_overlap() >= size => Ok(size / 10),
+            None => Ok(self.default_overlap()),
+        }
+    }
+
+    /// The unit chunks are sized in, which is always tokens for
+    /// [`Chunking::Tokens`].
+    pub fn unit(self, unit: Option<ChunkUnit>) -> Result<ChunkUnit> {
+        match (self, unit) {
+            (Chunking::Tokens, None | Some(ChunkUnit::Tokens)) => Ok(ChunkUnit::Tokens),
+            (Chunking::Tokens, Some(_)) => bail!(
+                "--chunking tokens always counts in tokens, so it can't be combined with another \
+                 --chunk-unit; try --chunking words instead"
+            ),
+            (_, unit) => Ok(unit.unwrap_or_default()),
+        }
+    }
+}
+
+/// Cuts text into chunks; each `--chunking` strategy is one implementation.
+pub trait Chunker {
+    /// Cuts one text into chunks.
+    fn chunk(&self, text: &str) -> Vec<String>;
+
+    /// Whether tables are left in the text for the chunker to keep whole,
+    /// rather than taken out and chunked on their own.
+    fn keeps_tables(&self) -> bool {
+        false
+    }
+}
+
+/// The chunker for the chosen strategy. It is handed every text it will be
+/// asked to chunk up front, for strategies that look at them all first.
+async fn chunker<M: EmbeddingModel>(
+    options: &ChunkOptions,
+    texts: &[&str],
+    model: &M,
+) -> Result<Box<dyn Chunker>> {
+    let (unit, size, overlap) = (options.unit, options.size, options.overlap);
+    let chunker: Box<dyn Chunker> = match options.chunking {
+        Chunking::Words | Chunking::Tokens => Box::new(WordChunker {
+            unit,
+            size,
+            overlap,
+        }),
+        Chunking::Sentences => Box::new(SentenceChunker {
+            unit,
+            size,
+            overlap,
+        }),
+        Chunking::Paragraph => Box::new(ParagraphChunker {
+            unit,
+            size,
+            overlap,
+        }),
+        Chunking::Recursive => Box::new(RecursiveChunker {
+            unit,
+            size,
+            overlap,
+        }),
+        Chunking::Markdown => Box::new(MarkdownChunker {
+            unit,
+            size,
+            overlap,
+        }),
+        Chunking::Semantic => Box::new(SemanticChunker::new(texts, options, model).await?),
+    };
+    Ok(match &options.split {
+        Some(pattern) => Box::new(DelimiterChunker {
+            pattern: pattern.clone(),
+            unit,
+            size,
+            inner: chunker,
+        }),
+        None => chunker,
+    })
+}
+
+/// Cuts documents into chunks with the chosen strategy, each document on its
+/// own so no chunk straddles two. Only [`Chunking::Semantic`] uses the
+/// embedding model. With [`ChunkOptions::sections`], documents are first
+/// split at their headings, and each chunk starts with the path of the
+/// section it came from, like `Chapter 3 > Installation`, so it still makes
+/// sense retrieved on its own. With [`ChunkOptions::headers`], each chunk
+/// starts with its [`context_header`] instead. With
+/// [`ChunkOptions::parent_size`], every chunk also carries the larger chunk
+/// it falls in. Documents matching one of [`ChunkOptions::profiles`] are
+/// chunked with its settings instead.
+pub async fn chunk_documents<M: EmbeddingModel>(
+    documents: &[Document],
+    options: &ChunkOptions,
+    model: &M,
+) -> Result<Vec<Chunk>> {
+    if options.profiles.is_empty() {
+        let chunks = chunk_group(documents, options, model).await?;
+        return Ok(numbered(chunks));
+    }
+    // Each document goes to the first profile it matches, or to none;
+    // groups are chunked in turn, and their chunks put back in order.
+    let profile = |document: &Document| {
+        let source = document.metadata.get("source").map_or("", String::as_str);
+        options
+            .profiles
+            .iter()
+            .position(|profile| profile.pattern.matches(source))
+    };
+    let groups: Vec<Option<usize>> = documents.iter().map(profile).collect();
+    let mut chunks: Vec<Vec<Chunk>> = vec![Vec::new(); documents.len()];
+    for group in [None]
+        .into_iter()
+        .chain((0..options.profiles.len()).map(Some))
+    {
+        let (indexes, members): (Vec<usize>, Vec<Document>) = documents
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| groups[*i] == group)
+            .map(|(i, document)| (i, document.clone()))
+            .unzip();
+        if members.is_empty() {
+            continue;
+        }
+        let group_options = match group {
+            Some(profile) => {
+                let profile = &options.profiles[profile];
+                debug!(
+                    "Chunking {} documents matching {} by {:?}",
+                    members.len(),
+                    profile.pattern,
+                    profile.options.chunking
+                );
+                &profile.options
+            }
+            None => options,
+        };
+        let group_chunks = chunk_group(&members, group_options, model).await?;
+        for (i, document_chunks) in indexes.into_iter().zip(group_chunks) {
+            chunks[i] = document_chunks;
+        }
+    }
+    Ok(numbered(chunks))
+}
+
+/// Every document's chunks in turn, each marked with its document's
+/// position.
+fn numbered(chunks: Vec<Vec<Chunk>>) -> Vec<Chunk> {
+    chunks
+        .into_iter()
+        .enumerate()
+        .flat_map(|(document, chunks)| {
+            chunks
+                .into_iter()
+                .map(move |chunk| Chunk { document, ..chunk })
+        })
+        .collect()
+}
+
+/// Chunks documents with one set of settings, parents and all, returning
+/// every document's chunks in turn.
+async fn chunk_group<M: EmbeddingModel>(
+    documents: &[Document],
+    options: &ChunkOptions,
+    model: &M,
+) -> Result<Vec<Vec<Chunk>>> {
+    let mut chunks = chunk_each(documents, options, model).await?;
+    if let Some(parent_size) = options.parent_size {
+        let parent_options = ChunkOptions {
+            size: parent_size,
+            overlap: 0,
+            min_size: 0,
+            parent_size: None,
+            ..options.clone()
+        };
+        let parents = chunk_each(documents, &parent_options, model).await?;
+        for (children, parents) in chunks.iter_mut().zip(&parents) {
+            parents::attach_parents(children, parents);
+        }
+    }
+    Ok(chunks)
+}
+
+/// Chunks each document, by section or whole, and starts the chunks with
+/// their header or section path, returning every document's chunks in turn.
+async fn chunk_each<M: EmbeddingModel>(
+    documents: &[Document],
+    options: &ChunkOptions,
+    model: &M,
+) -> Result<Vec<Vec<Chunk>>> {
+    let mut chunks = if options.sections {
+        chunk_sections(documents, options, model).await?
+    } else {
+        chunk_texts(documents, options, model).await?
+    };
+    for chunk in chunks.iter_mut().flatten() {
+        let header = if options.headers {
+            context_header(chunk)
+        } else if options.sections {
+            chunk.section().map(str::to_string)
+        } else {
+            None
+        };
+        if let Some(header) = header {
+            chunk.text = format!("{}\n\n{}", header, chunk.text);
+            chunk.header = Some(header);
+        }
+    }
+    Ok(chunks)
+}
+
+/// Chunks each document's sections separately, numbering and placing the
+/// chunks within their whole document.
+async fn chunk_sections<M: EmbeddingModel>(
+    documents: &[Document],
+    options: &ChunkOptions,
+    model: &M,
+) -> Result<Vec<Vec<Chunk>>> {
+    let sections: Vec<Vec<(Document, usize)>> = documents.iter().map(split_sections).collect();
+    let counts: Vec<usize> = sections.iter().map(Vec::len).collect();
+    let (sections, offsets): (Vec<Document>, Vec<usize>) = sections.into_iter().flatten().unzip();
+    let mut chunked = chunk_texts(&sections, options, model)
+        .await?
+        .into_iter()
+        .zip(offsets);
+
+    let mut chunks = Vec::new();
+    for count in counts {
+        let document_chunks = chunked
+            .by_ref()
+            .take(count)
+            .flat_map(|(section_chunks, offset)| {
+                section_chunks.into_iter().map(move |mut chunk| {
+                    chunk.char_range =
+                        chunk.char_range.start + offset..chunk.char_range.end + offset;
+                    chunk
+                })
+            });
+        let document_chunks = document_chunks
+            .enumerate()
+            .map(|(index, chunk)| Chunk { index, ..chunk })
+            .collect();
+        chunks.push(document_chunks);
+    }
+    Ok(chunks)
+}
+
+/// A line placing a chunk in its document, like `Document: Annual Report |
+/// Section: Results > Revenue | Page: 14`, so a chunk that is ambiguous on
+/// its own (a table of figures, "as shown above") is embedded and retrieved
+/// along with what it is about. The document is named by its title, or
+/// else its file name.
+fn context_header(chunk: &Chunk) -> Option<String> {
+    let document = chunk.metadata.get("title").map(String::as_str).or_else(|| {
+        let source = chunk.source()?;
+        Some(source.rsplit(['/', '\\']).next().unwrap_or(source))
+    });
+    // With --sections, the section path already starts with the chapter.
+    let chapter = chunk.metadata.get("chapter").map(String::as_str);
+    let section = match (chapter, chunk.section()) {
+        (Some(chapter), Some(section)) if !section.starts_with(chapter) => {
+            Some(format!("{} > {}", chapter, section))
+        }
+        (_, Some(section)) => Some(section.to_string()),
+        (chapter, None) => chapter.map(str::to_string),
+    };
+
+    let fields: Vec<String> = [
+        ("Document", document.map(str::to_string)),
+        ("Section", section),
+        ("Page", chunk.page().map(str::to_string)),
+    ]
+    .into_iter()
+    .filter_map(|(label, value)| Some(format!("{}: {}", label, value?)))
+    .collect();
+    (!fields.is_empty()).then(|| fields.join(" | "))
+}
+
+/// Chunks each document, returning every document's chunks in turn.
+async fn chunk_texts<M: EmbeddingModel>(
+    documents: &[Document],
+    options: &ChunkOptions,
+    model: &M,
+) -> Result<Vec<Vec<Chunk>>> {
+    let texts: Vec<&str> = documents.iter().map(|d| d.text.as_str()).collect();
+    let chunker = chunker(options, &texts, model).await?;
+    let progress = progress::start("Chunking", "texts", documents.len());
+    let texts = documents
+        .iter()
+        .map(|document| {
+            // Tables and code blocks are taken out and chunked on their own,
+            // so no strategy cuts one mid-row or mid-snippet, unless the
+            // chunker keeps them whole itself. Source code has no tables,
+            // just lines that start with `|`.
+            let chunks = if chunker.keeps_tables() || document.metadata.contains_key("language") {
+                chunker.chunk(&document.text)
+            } else {
+                tables::segments(&document.text)
+                    .into_iter()
+                    .flat_map(|segment| match segment {
+                        Segment::Prose(text) => code_blocks::segments(text),
+                        segment => vec![segment],
+                    })
+                    .flat_map(|segment| match segment {
+                        Segment::Prose(text) => chunker.chunk(text),
+                        Segment::Table(table) => {
+                            tables::chunk_table(table, options.size, options.unit)
+                        }
+                        Segment::Code(code) => {
+                            code_blocks::chunk_code(code, options.size, options.unit)
+                        }
+                    })
+                    .collect()
+            };
+            let count = chunks.len();
+            let chunks = merge::merge_small(chunks, options.min_size, options.unit);
+            if chunks.len() < count {
+                debug!(
+                    "Merged {} chunks smaller than {} into their neighbours",
+                    count - chunks.len(),
+                    options.min_size
+                );
+            }
+            progress.advance(1);
+            chunks
+        })
+        .collect();
+    Ok(with_metadata(documents, texts))
+}
+
+/// Splits a document at its Markdown-style heading lines (`#` to `######`,
+/// outside code fences) into one document per section. Each section's
+/// `section` metadata is its path of headings, under the chapter or section
+/// the whole document already belongs to. Source code is left whole.
+/// Sections come with the character offset their text starts at.
+fn split_sections(document: &Document) -> Vec<(Document, usize)> {
+    if document.metadata.contains_key("language") {
+        return vec![(document.clone(), 0)];
+    }
+    let parents: Vec<String> = ["chapter", "section"]
+        .iter()
+        .filter_map(|key| document.metadata.get(*key).cloned())
+        .collect();
+
+    let mut sections = Vec::new();
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut body = String::new();
+    let mut in_fence = false;
+    // Indexes into `document.pages` of the page being read and the page the
+    // current section started on.
+    let (mut page, mut first) = (0, 0);
+    // Character offsets of the line being read and of the section's body.
+    let (mut offset, mut body_start) = (0, 0);
+    let mut flush =
+        |headings: &[(usize, String)], body: &mut String, pages: RangeInclusive<usize>, start| {
+            if body.trim().is_empty() {
+                body.clear();
+                return;
+            }
+            let is_trimmed = |c: char| c.is_whitespace() && c != '\u{c}';
+            let start = start + body.chars().take_while(|&c| is_trimmed(c)).count();
+            let mut path = parents.clone();
+            path.extend(headings.iter().map(|(_, title)| title.clone()));
+            // Form feeds stay, so the section's text still lines up with its pages.
+            let text = std::mem::take(body);
+            let mut section = Document {
+                text: text.trim_matches(is_trimmed).to_string(),
+                pages: document
+                    .pages
+                    .get(pages)
+                    .map(<[u32]>::to_vec)
+                    .unwrap_or_default(),
+                ..document.clone()
+            };
+            if !path.is_empty() {
+                section
+                    .metadata
+                    .insert("section".to_string(), path.join(" > "));
+            }
+            sections.push((section, start));
+        };
+
+    for line in document.text.split_inclusive(['\n', '\u{c}']) {
+        let ends_page = line.ends_with('\u{c}');
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        match heading(line.trim_end()).filter(|_| !in_fence) {
+            Some((level, title)) => {
+                flush(&headings, &mut body, first..=page, body_start);
+                headings.retain(|&(parent, _)| parent < level);
+                headings.push((level, title.to_string()));
+                page += usize::from(ends_page);
+                first = page;
+                body_start = offset + line.chars().count();
+            }
+            None => {
+                body.push_str(line);
+                page += usize::from(ends_page);
+            }
+        }
+        offset += line.chars().count();
+    }
+    flush(&headings, &mut body, first..=page, body_start);
+    sections
+}
+
+/// The level and title of a heading line like `## Installation`.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let title = line.trim_start_matches('#');
+    let level = line.len() - title.len();
+    let title = title.strip_prefix(' ')?.trim();
+    ((1..=6).contains(&level) && !title.is_empty()).then_some((level, title))
+}
+
+/// Turns each document's chunk texts into chunks carrying its metadata,
+/// numbered and placed in the document, with the pages they span for
+/// documents loaded page by page.
+fn with_metadata(documents: &[Document], texts: Vec<Vec<String>>) -> Vec<Vec<Chunk>> {
+    documents
+        .iter()
+        .zip(texts)
+        .map(|(document, texts)| {
+            let mut locator = Locator::new(document);
+            texts
+                .into_iter()
+                .enumerate()
+                .map(|(index, text)| {
+                    let mut metadata = document.metadata.clone();
+                    let location = locator.locate(&text);
+                    let char_range = match location {
+                        Some(location) => {
+                            if let Some(pages) = location.pages {
+                                metadata.insert("page".to_string(), pages);
+                            }
+                            location.char_range
+                        }
+                        None => 0..0,
+                    };
+                    Chunk {
+                        text: text.replace('\u{c}', "\n"),
+                        metadata,
+                        index,
+                        char_range,
+                        parent: None,
+                        summary: None,
+                        document: 0,
+                        header: None,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Splits text before each line matching `is_break`, leaving fenced code
+/// blocks alone.
+fn split_before(text: &str, is_break: impl Fn(&str) -> bool) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let (mut start, mut offset) = (0, 0);
+    let mut in_fence = false;
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if !in_fence && is_break(line.trim_end()) && offset > start {
+            pieces.push(text[start..offset].trim());
+            start = offset;
+        }
+        offset += line.len();
+    }
+    pieces.push(text[start..].trim());
+    pieces.retain(|piece| !piece.is_empty());
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(chunking: Chunking, size: usize, overlap: usize) -> ChunkOptions {
+        ChunkOptions {
+            chunking,
+            unit: ChunkUnit::Words,
+            size,
+            overlap,
+            min_size: 0,
+            semantic_threshold: None,
+            sections: false,
+            headers: false,
+            parent_size: None,
+            split: None,
+            profiles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn overlaps_parse_as_counts_or_shares() {
+        assert_eq!("50".parse::<Overlap>().unwrap(), Overlap::Count(50));
+        assert_eq!(" 0 ".parse::<Overlap>().unwrap(), Overlap::Count(0));
+        assert_eq!("10%".parse::<Overlap>().unwrap(), Overlap::Percent(10.0));
+        assert_eq!("12.5 %".parse::<Overlap>().unwrap(), Overlap::Percent(12.5));
+        for invalid in ["", "-1", "ten", "100%", "150%", "-5%", "x%"] {
+            assert!(invalid.parse::<Overlap>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn overlaps_are_counted_in_the_strategy_unit() {
+        let words = Chunking::Words;
+        assert_eq!(
+            words.overlap(300, Some(Overlap::Percent(10.0))).unwrap(),
+            30
+        );
+        // Shares are rounded down.
+        assert_eq!(words.overlap(15, Some(Overlap::Percent(10.0))).unwrap(), 1);
+        assert_eq!(words.overlap(300, None).unwrap(), 50);
+        // The default shrinks to fit chunks smaller than it.
+        assert_eq!(words.overlap(40, None).unwrap(), 4);
+        assert_eq!(Chunking::Sentences.overlap(40, None).unwrap(), 1);
+        assert!(
+            Chunking::Sentences
+                .overlap(40, Some(Overlap::Percent(10.0)))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn overlaps_must_be_smaller_than_chunks() {
+        assert!(options(Chunking::Words, 100, 99).validate().is_ok());
+        assert!(options(Chunking::Words, 100, 100).validate().is_err());
+        assert!(options(Chunking::Recursive, 10, 50).validate().is_err());
+        // Sentence overlaps are counted in sentences.
+        assert!(options(Chunking::Sentences, 10, 50).validate().is_ok());
+    }
+
+    #[test]
+    fn sizes_that_cannot_be_chunked_are_rejected() {
+        assert!(options(Chunking::Words, 0, 0).validate().is_err());
+        let parent = |parent_size| ChunkOptions {
+            parent_size: Some(parent_size),
+            ..options(Chunking::Words, 100, 10)
+        };
+        assert!(parent(100).validate().is_err());
+        assert!(parent(101).validate().is_ok());
+        let min = |min_size| ChunkOptions {
+            min_size,
+            ..options(Chunking::Words, 100, 10)
+        };
+        assert!(min(100).validate().is_ok());
+        assert!(min(101).validate().is_err());
+    }
+
+    #[test]
+    fn token_chunking_counts_only_tokens() {
+        assert_eq!(Chunking::Tokens.unit(None).unwrap(), ChunkUnit::Tokens);
+        assert!(Chunking::Tokens.unit(Some(ChunkUnit::Chars)).is_err());
+        assert_eq!(Chunking::Words.unit(None).unwrap(), ChunkUnit::Words);
+    }
+
+    #[test]
+    fn units_count_multibyte_text() {
+        assert_eq!(ChunkUnit::Chars.count(" 日本語 "), 3);
+        assert_eq!(ChunkUnit::Words.count("naïve café"), 2);
+        let pieces = ChunkUnit::Chars.pieces("日本語です");
+        assert_eq!(pieces.iter().map(|(_, count)| count).sum::<usize>(), 5);
+        assert_eq!(ChunkUnit::Lines.count("one\ntwo\n\n"), 2);
+    }
+}
@@ -0,0 +1,113 @@
+use super::words::WordChunker;
+use super::{ChunkUnit, Chunker};
+
+/// Packs whole paragraphs (separated by blank lines or form feeds) into
+/// chunks of up to `size` units, keeping the blank lines between them. A
+/// paragraph longer than a whole chunk is split into windows, with
+/// `overlap` units of overlap; otherwise chunks don't overlap, since each
+/// starts at a paragraph break.
+pub struct ParagraphChunker {
+    pub unit: ChunkUnit,
+    pub size: usize,
+    pub overlap: usize,
+}
+
+impl Chunker for ParagraphChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let mut chunks = Vec::new();
+        let mut chunk: Vec<&str> = Vec::new();
+        let mut total = 0;
+        for paragraph in paragraphs(text) {
+            let count = self.unit.count(paragraph);
+            if !chunk.is_empty() && total + count > self.size {
+                chunks.push(chunk.join("\n\n"));
+                chunk.clear();
+                total = 0;
+            }
+            if count > self.size {
+                chunks.extend(
+                    WordChunker {
+                        unit: self.unit,
+                        size: self.size,
+                        overlap: self.overlap,
+                    }
+                    .chunk(paragraph),
+                );
+                continue;
+            }
+            chunk.push(paragraph);
+            total += count;
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk.join("\n\n"));
+        }
+        chunks
+    }
+}
+
+pub fn paragraphs(text: &str) -> Vec<&str> {
+    let mut paragraphs = Vec::new();
+    for page in text.split('\u{c}') {
+        let mut start = 0;
+        let mut offset = 0;
+        for line in page.split_inclusive('\n') {
+            if line.trim().is_empty() {
+                paragraphs.push(page[start..offset].trim());
+                start = offset + line.len();
+            }
+            offset += line.len();
+        }
+        paragraphs.push(page[start..].trim());
+    }
+    paragraphs.retain(|paragraph| !paragraph.is_empty());
+    paragraphs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(size: usize, overlap: usize, text: &str) -> Vec<String> {
+        ParagraphChunker {
+            unit: ChunkUnit::Words,
+            size,
+            overlap,
+        }
+        .chunk(text)
+    }
+
+    #[test]
+    fn paragraphs_break_at_blank_lines_and_pages() {
+        assert_eq!(
+            paragraphs("First line\nsame paragraph.\n\n  \nSecond.\u{c}Third, next page."),
+            [
+                "First line\nsame paragraph.",
+                "Second.",
+                "Third, next page."
+            ]
+        );
+    }
+
+    #[test]
+    fn paragraphs_are_packed_while_they_fit() {
+        let text = "One two.\n\nThree four.\n\nFive six seven.";
+        assert_eq!(
+            chunk(4, 0, text),
+            ["One two.\n\nThree four.", "Five six seven."]
+        );
+    }
+
+    #[test]
+    fn a_paragraph_longer_than_a_chunk_is_split_into_windows() {
+        assert_eq!(
+            chunk(3, 1, "Short.\n\nA paragraph of six words here."),
+            ["Short.", "A paragraph of", "of six words", "words here."]
+        );
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        assert!(chunk(10, 0, "").is_empty());
+        assert!(chunk(10, 0, "\n\n\u{c}\n").is_empty());
+    }
+}
@@ -0,0 +1,25 @@
+use super::Chunk;
+
+/// Gives each of a document's chunks the parent chunk it overlaps most, found by where both were cut from in the document.
+/// Chunks that couldn't be placed, or that are as long as their parent,
+/// are left without one.
+pub fn attach_parents(children: &mut [Chunk], parents: &[Chunk]) {
+    for child in children {
+        let range = &child.char_range;
+        let parent = parents
+            .iter()
+            .map(|parent| {
+                let shared = parent.char_range.end.min(range.end);
+                (
+                    shared.saturating_sub(parent.char_range.start.max(range.start)),
+                    parent,
+                )
+            })
+            .filter(|&(shared, _)| shared > 0)
+            .max_by_key(|&(shared, _)| shared)
+            .map(|(_, parent)| parent);
+        child.parent = parent
+            .filter(|parent| parent.text != child.text)
+            .map(|parent| Box::new(parent.clone()));
+    }
+}
@@ -0,0 +1,92 @@
+use super::{ChunkOptions, ChunkUnit, Chunking, Overlap};
+use anyhow::{Context, Result, anyhow, bail};
+use clap::ValueEnum;
+use glob::Pattern;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// `--chunk-profiles`: chunk settings for the documents whose source
+/// matches `pattern`, in place of those given on the command line.
+#[derive(Debug, Clone)]
+pub struct ChunkProfile {
+    pub pattern: Pattern,
+    pub options: ChunkOptions,
+}
+
+/// One profile as written in the file, with the names of the command-line
+/// flags it overrides.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileEntry {
+    #[serde(rename = "match")]
+    pattern: String,
+    chunking: Option<String>,
+    chunk_unit: Option<String>,
+    chunk_size: Option<usize>,
+    chunk_overlap: Option<Value>,
+    min_chunk_size: Option<usize>,
+}
+
+/// Reads the profiles in `path`: a JSON array of objects, each with a
+/// `match` glob and any of `chunking`, `chunk_unit`, `chunk_size`,
+/// `chunk_overlap` and `min_chunk_size`, taking the rest from `defaults`
+/// and from the `--chunk-unit` and `--chunk-overlap` flags, if given.
+/// An overlap or unit left out is worked out for the profile's own
+/// strategy and size, as it would be on the command line.
+pub fn load_profiles(
+    path: &Path,
+    defaults: &ChunkOptions,
+    unit: Option<ChunkUnit>,
+    overlap: Option<Overlap>,
+) -> Result<Vec<ChunkProfile>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read chunk profiles: {:?}", path))?;
+    let entries: Vec<ProfileEntry> = serde_json::from_str(&text)
+        .with_context(|| format!("Failed to parse chunk profiles: {:?}", path))?;
+    entries
+        .into_iter()
+        .map(|entry| {
+            let name = entry.pattern.clone();
+            profile(entry, defaults, unit, overlap)
+                .with_context(|| format!("Invalid chunk profile for {:?} in {:?}", name, path))
+        })
+        .collect()
+}
+
+fn profile(
+    entry: ProfileEntry,
+    defaults: &ChunkOptions,
+    unit: Option<ChunkUnit>,
+    overlap: Option<Overlap>,
+) -> Result<ChunkProfile> {
+    let pattern = Pattern::new(&entry.pattern)
+        .with_context(|| format!("Invalid glob pattern: {}", entry.pattern))?;
+    let chunking = match &entry.chunking {
+        Some(chunking) => Chunking::from_str(chunking, true).map_err(|e| anyhow!(e))?,
+        None => defaults.chunking,
+    };
+    let unit = match &entry.chunk_unit {
+        Some(unit) => Some(ChunkUnit::from_str(unit, true).map_err(|e| anyhow!(e))?),
+        None => unit,
+    };
+    let overlap = match &entry.chunk_overlap {
+        Some(Value::Number(count)) => Some(count.to_string().parse()?),
+        Some(Value::String(overlap)) => Some(overlap.parse()?),
+        Some(_) => bail!("chunk_overlap must be a count like 50 or a share like \"10%\""),
+        None => overlap,
+    };
+    let size = entry.chunk_size.unwrap_or(defaults.size);
+    let options = ChunkOptions {
+        chunking,
+        unit: chunking.unit(unit)?,
+        size,
+        overlap: chunking.overlap(size, overlap)?,
+        min_size: entry.min_chunk_size.unwrap_or(defaults.min_size),
+        profiles: Vec::new(),
+        ..defaults.clone()
+    };
+    options.validate()?;
+    Ok(ChunkProfile { pattern, options })
+}
@@ -0,0 +1,152 @@
+use super::paragraph::paragraphs;
+use super::sentences::sentences;
+use super::words::WordChunker;
+use super::{ChunkUnit, Chunker, heading, split_before};
+
+/// Cuts text into pieces along one kind of boundary.
+type Splitter = fn(&str) -> Vec<&str>;
+
+/// The boundaries [`RecursiveChunker`] tries in turn, with the separator
+/// pieces are packed back together with.
+const SPLITTERS: &[(Splitter, &str)] =
+    &[(sections, "\n\n"), (paragraphs, "\n\n"), (sentences, " ")];
+
+/// Splits text into chunks of up to `size` units along the coarsest
+/// boundaries that will do: sections (at Markdown-style `#` headings), then
+/// paragraphs, then sentences, and finally windows, with `overlap` units of
+/// overlap. Neighbouring pieces are packed together while they fit.
+pub struct RecursiveChunker {
+    pub unit: ChunkUnit,
+    pub size: usize,
+    pub overlap: usize,
+}
+
+impl Chunker for RecursiveChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let mut chunks = Vec::new();
+        self.split(text, 0, &mut chunks);
+        chunks
+    }
+}
+
+impl RecursiveChunker {
+    fn split(&self, text: &str, level: usize, chunks: &mut Vec<String>) {
+        let text = text.trim();
+        if self.unit.count(text) <= self.size {
+            if !text.is_empty() {
+                chunks.push(text.to_string());
+            }
+            return;
+        }
+        let Some((split, separator)) = SPLITTERS.get(level) else {
+            let words = WordChunker {
+                unit: self.unit,
+                size: self.size,
+                overlap: self.overlap,
+            };
+            chunks.extend(words.chunk(text));
+            return;
+        };
+        pack(
+            split(text),
+            separator,
+            self.unit,
+            self.size,
+            chunks,
+            |piece, chunks| self.split(piece, level + 1, chunks),
+        );
+    }
+}
+
+/// Packs neighbouring pieces into chunks of up to `size` units, handing
+/// pieces too long for a chunk of their own to `oversized`.
+pub fn pack<'a>(
+    pieces: Vec<&'a str>,
+    separator: &str,
+    unit: ChunkUnit,
+    size: usize,
+    chunks: &mut Vec<String>,
+    mut oversized: impl FnMut(&'a str, &mut Vec<String>),
+) {
+    let mut packed: Vec<&str> = Vec::new();
+    let mut total = 0;
+    for piece in pieces {
+        let count = unit.count(piece);
+        if !packed.is_empty() && total + count > size {
+            chunks.push(packed.join(separator));
+            packed.clear();
+            total = 0;
+        }
+        if count > size {
+            oversized(piece, chunks);
+            continue;
+        }
+        packed.push(piece);
+        total += count;
+    }
+    if !packed.is_empty() {
+        chunks.push(packed.join(separator));
+    }
+}
+
+/// Splits text before each Markdown-style heading line.
+fn sections(text: &str) -> Vec<&str> {
+    split_before(text, |line| heading(line).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(size: usize, overlap: usize, text: &str) -> Vec<String> {
+        RecursiveChunker {
+            unit: ChunkUnit::Words,
+            size,
+            overlap,
+        }
+        .chunk(text)
+    }
+
+    #[test]
+    fn text_that_fits_is_one_chunk() {
+        assert_eq!(
+            chunk(10, 0, "  A few words.\n\nAnd more. "),
+            ["A few words.\n\nAnd more."]
+        );
+    }
+
+    #[test]
+    fn goes_only_as_fine_as_needed() {
+        let text = "# One\n\nAlpha beta.\n\n# Two\n\nGamma delta. Epsilon zeta eta theta.";
+        assert_eq!(
+            chunk(4, 0, text),
+            [
+                "# One\n\nAlpha beta.",
+                "# Two",
+                "Gamma delta.",
+                "Epsilon zeta eta theta."
+            ]
+        );
+    }
+
+    #[test]
+    fn long_sentences_fall_back_to_windows() {
+        let chunks = chunk(3, 1, "one two three four five six");
+        assert_eq!(chunks, ["one two three", "three four five", "five six"]);
+    }
+
+    #[test]
+    fn every_chunk_fits() {
+        let text = "# Intro\n\nÜber alles. Ça va très bien, merci beaucoup.\n\n\
+                    Second paragraph with rather more words than fit in one.";
+        for chunk in chunk(5, 2, text) {
+            assert!(ChunkUnit::Words.count(&chunk) <= 5, "{:?}", chunk);
+        }
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        assert!(chunk(10, 0, "").is_empty());
+        assert!(chunk(10, 0, "\n \n").is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+use super::sentences::sentences;
+use super::tables::{self, Segment};
+use super::words::WordChunker;
+use super::{ChunkOptions, ChunkUnit, Chunker};
+use anyhow::Result;
+use rig::embeddings::EmbeddingModel;
+use std::collections::HashMap;
+use tracing::info;
+
+/// Share of a text's neighbouring sentence pairs that [`SemanticChunker`]
+/// breaks between when no threshold is given.
+const BREAK_PERCENTILE: f64 = 0.1;
+
+/// Groups sentences into chunks by topic, cutting where neighbouring
+/// sentences are least alike.
+pub struct SemanticChunker {
+    unit: ChunkUnit,
+    size: usize,
+    overlap: usize,
+    threshold: Option<f64>,
+    /// Embeddings of every sentence in the texts to be chunked.
+    vectors: HashMap<String, Vec<f64>>,
+}
+
+impl SemanticChunker {
+    /// Embeds the sentences of all the texts that will be chunked together,
+    /// in as few requests as the model allows. Tables are left out, since
+    /// they are chunked on their own.
+    pub async fn new<M: EmbeddingModel>(
+        texts: &[&str],
+        options: &ChunkOptions,
+        model: &M,
+    ) -> Result<Self> {
+        let mut unique: Vec<String> = texts
+            .iter()
+            .flat_map(|text| tables::segments(text))
+            .filter_map(|segment| match segment {
+                Segment::Prose(text) => Some(text),
+                Segment::Table(_) | Segment::Code(_) => None,
+            })
+            .flat_map(sentences)
+            .map(str::to_string)
+            .collect();
+        unique.sort();
+        unique.dedup();
+        if !unique.is_empty() {
+            info!("Embedding {} sentences to find topic breaks", unique.len());
+        }
+        let mut vectors = HashMap::with_capacity(unique.len());
+        for batch in unique.chunks(M::MAX_DOCUMENTS) {
+            let embeddings = model.embed_texts(batch.to_vec()).await?;
+            vectors.extend(
+                batch
+                    .iter()
+                    .cloned()
+                    .zip(embeddings.into_iter().map(|embedding| embedding.vec)),
+            );
+        }
+        Ok(Self {
+            unit: options.unit,
+            size: options.size,
+            overlap: options.overlap,
+            threshold: options.semantic_threshold,
+            vectors,
+        })
+    }
+}
+
+impl Chunker for SemanticChunker {
+    /// Packs sentences into chunks, starting a new one wherever a sentence is
+    /// less similar to the one before than the threshold, or the chunk is
+    /// full. A sentence that wasn't embedded up front counts as unlike its
+    /// neighbours.
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let sentences = sentences(text);
+        let similarities: Vec<f64> = sentences
+            .windows(2)
+            .map(
+                |pair| match (self.vectors.get(pair[0]), self.vectors.get(pair[1])) {
+                    (Some(a), Some(b)) => cosine_similarity(a, b),
+                    _ => f64::NEG_INFINITY,
+                },
+            )
+            .collect();
+        // `breaks[i]` is whether a chunk may end after sentence `i`.
+        let breaks: Vec<bool> = match self.threshold {
+            Some(threshold) => similarities.iter().map(|&s| s < threshold).collect(),
+            None => {
+                let mut order: Vec<usize> = (0..similarities.len()).collect();
+                order.sort_by(|&a, &b| similarities[a].total_cmp(&similarities[b]));
+                let count = (similarities.len() as f64 * BREAK_PERCENTILE).ceil() as usize;
+                let mut breaks = vec![false; similarities.len()];
+                for &i in &order[..count] {
+                    breaks[i] = true;
+                }
+                breaks
+            }
+        };
+
+        let mut chunks = Vec::new();
+        let mut chunk: Vec<&str> = Vec::new();
+        let mut total = 0;
+        for (i, sentence) in sentences.iter().enumerate() {
+            let count = self.unit.count(sentence);
+            let topic_break = i > 0 && breaks[i - 1];
+            if !chunk.is_empty() && (topic_break || total + count > self.size) {
+                chunks.push(chunk.join(" "));
+                chunk.clear();
+                total = 0;
+            }
+            if count > self.size {
+                chunks.extend(
+                    WordChunker {
+                        unit: self.unit,
+                        size: self.size,
+                        overlap: self.overlap,
+                    }
+                    .chunk(sentence),
+                );
+                continue;
+            }
+            chunk.push(sentence);
+            total += count;
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk.join(" "));
+        }
+        chunks
+    }
+}
+
+fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    dot / (norm(a) * norm(b)).max(f64::EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chunker whose sentences each point along the axis given, so
+    /// sentences on the same axis are alike and others aren't.
+    fn chunker(
+        size: usize,
+        threshold: Option<f64>,
+        sentences: &[(&str, usize)],
+    ) -> SemanticChunker {
+        let vectors = sentences
+            .iter()
+            .map(|&(sentence, axis)| {
+                let mut vec = vec![0.1; 4];
+                vec[axis] = 1.0;
+                (sentence.to_string(), vec)
+            })
+            .collect();
+        SemanticChunker {
+            unit: ChunkUnit::Words,
+            size,
+            overlap: 0,
+            threshold,
+            vectors,
+        }
+    }
+
+    const TOPICS: &[(&str, usize)] = &[
+        ("Cats purr.", 0),
+        ("Cats nap.", 0),
+        ("Rust compiles.", 1),
+        ("Rust borrows.", 1),
+    ];
+
+    #[test]
+    fn breaks_where_neighbours_are_unlike() {
+        let text = "Cats purr. Cats nap. Rust compiles. Rust borrows.";
+        assert_eq!(
+            chunker(100, Some(0.5), TOPICS).chunk(text),
+            ["Cats purr. Cats nap.", "Rust compiles. Rust borrows."]
+        );
+        // Without a threshold, the least similar tenth of the pairs, here
+        // the one pair between topics, are broken at.
+        assert_eq!(
+            chunker(100, None, TOPICS).chunk(text),
+            ["Cats purr. Cats nap.", "Rust compiles. Rust borrows."]
+        );
+    }
+
+    #[test]
+    fn full_chunks_break_within_a_topic() {
+        let text = "Cats purr. Cats nap. Rust compiles. Rust borrows.";
+        assert_eq!(
+            chunker(2, Some(0.5), TOPICS).chunk(text),
+            ["Cats purr.", "Cats nap.", "Rust compiles.", "Rust borrows."]
+        );
+    }
+
+    #[test]
+    fn sentences_not_embedded_are_unlike_their_neighbours() {
+        let text = "Cats purr. Dogs bark. Cats nap.";
+        assert_eq!(
+            chunker(100, Some(-1.0), TOPICS).chunk(text),
+            ["Cats purr.", "Dogs bark.", "Cats nap."]
+        );
+    }
+
+    #[test]
+    fn a_sentence_longer_than_a_chunk_is_split_into_windows() {
+        let long = "This one sentence runs on.";
+        assert_eq!(
+            chunker(2, Some(0.5), &[(long, 0)]).chunk(long),
+            ["This one", "sentence runs", "on."]
+        );
+    }
+
+    #[test]
+    fn empty_and_single_sentence_texts() {
+        assert!(chunker(10, None, TOPICS).chunk("").is_empty());
+        assert_eq!(chunker(10, None, TOPICS).chunk("Cats nap."), ["Cats nap."]);
+        let japanese = [("猫が鳴く。", 0), ("猫が寝る。", 0), ("錆が出る。", 2)];
+        let chunks = chunker(10, Some(0.5), &japanese).chunk("猫が鳴く。猫が寝る。錆が出る。");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1], "錆が出る。");
+    }
+}
@@ -0,0 +1,161 @@
+use super::words::WordChunker;
+use super::{ChunkUnit, Chunker};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Words that end in a full stop without ending the sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "mr.", "mrs.", "ms.", "dr.", "prof.", "st.", "jr.", "sr.", "vs.", "e.g.", "i.e.", "cf.", "al.",
+    "fig.", "vol.", "inc.", "ltd.", "approx.",
+];
+
+/// Packs whole sentences into chunks of up to `size` units, repeating the
+/// last `overlap` sentences of each chunk at the start of the next. A
+/// sentence longer than a whole chunk is split into windows on its own.
+pub struct SentenceChunker {
+    pub unit: ChunkUnit,
+    pub size: usize,
+    pub overlap: usize,
+}
+
+impl Chunker for SentenceChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let sentences: Vec<(&str, usize)> = sentences(text)
+            .into_iter()
+            .map(|sentence| (sentence, self.unit.count(sentence)))
+            .collect();
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < sentences.len() {
+            let (sentence, count) = sentences[start];
+            if count > self.size {
+                chunks.extend(
+                    WordChunker {
+                        unit: self.unit,
+                        size: self.size,
+                        overlap: 0,
+                    }
+                    .chunk(sentence),
+                );
+                start += 1;
+                continue;
+            }
+
+            let (mut end, mut total) = (start, 0);
+            while end < sentences.len() && total + sentences[end].1 <= self.size {
+                total += sentences[end].1;
+                end += 1;
+            }
+            let chunk: Vec<&str> = sentences[start..end].iter().map(|(s, _)| *s).collect();
+            chunks.push(chunk.join(" "));
+
+            if end >= sentences.len() {
+                break;
+            }
+            start = end.saturating_sub(self.overlap).max(start + 1);
+        }
+
+        chunks
+    }
+}
+
+/// Splits text into sentences by the Unicode sentence boundary rules,
+/// rejoining the breaks those rules make after common abbreviations and
+/// initials (`Dr.`, `e.g.`, `J. Smith`).
+pub fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences: Vec<&str> = Vec::new();
+    let mut start: Option<usize> = None;
+    for (offset, piece) in text.split_sentence_bound_indices() {
+        let begin = *start.get_or_insert(offset);
+        let end = offset + piece.len();
+        let last_word = piece.split_whitespace().next_back().unwrap_or_default();
+        let abbreviated = ABBREVIATIONS.contains(&last_word.to_lowercase().as_str())
+            || (last_word.len() == 2
+                && last_word.ends_with('.')
+                && last_word.starts_with(|c: char| c.is_uppercase()));
+        if abbreviated && end < text.len() {
+            continue;
+        }
+        let sentence = text[begin..end].trim();
+        if !sentence.is_empty() {
+            sentences.push(sentence);
+        }
+        start = None;
+    }
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(size: usize, overlap: usize, text: &str) -> Vec<String> {
+        SentenceChunker {
+            unit: ChunkUnit::Words,
+            size,
+            overlap,
+        }
+        .chunk(text)
+    }
+
+    #[test]
+    fn abbreviations_and_initials_do_not_end_sentences() {
+        assert_eq!(
+            sentences("Dr. Smith met J. Doe, e.g. at noon. They talked. Then left!"),
+            [
+                "Dr. Smith met J. Doe, e.g. at noon.",
+                "They talked.",
+                "Then left!"
+            ]
+        );
+    }
+
+    #[test]
+    fn sentences_are_packed_whole_and_overlap() {
+        let text = "One two three. Four five. Six seven eight. Nine.";
+        assert_eq!(
+            chunk(5, 0, text),
+            ["One two three. Four five.", "Six seven eight. Nine."]
+        );
+        assert_eq!(
+            chunk(5, 1, text),
+            [
+                "One two three. Four five.",
+                "Four five. Six seven eight.",
+                "Six seven eight. Nine."
+            ]
+        );
+    }
+
+    #[test]
+    fn an_overlap_of_every_sentence_still_moves_on() {
+        let text = "One two. Three four. Five six.";
+        assert_eq!(
+            chunk(4, 10, text),
+            ["One two. Three four.", "Three four. Five six."]
+        );
+    }
+
+    #[test]
+    fn a_sentence_longer_than_a_chunk_is_split_into_windows() {
+        assert_eq!(
+            chunk(3, 1, "Short one. This sentence has far too many words."),
+            ["Short one.", "This sentence has", "far too many", "words."]
+        );
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        assert!(sentences("").is_empty());
+        assert!(chunk(10, 1, "").is_empty());
+        assert!(chunk(10, 1, "  \n ").is_empty());
+    }
+
+    #[test]
+    fn multibyte_sentences_split_at_their_stops() {
+        assert_eq!(
+            sentences("東京は首都です。大阪は大きい。Ça va? Très bien."),
+            ["東京は首都です。", "大阪は大きい。", "Ça va?", "Très bien."]
+        );
+    }
+}
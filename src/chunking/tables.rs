@@ -0,0 +1,97 @@
+//! Tables in extracted text, as Markdown-style `|` rows, and how they are
+//! chunked: on their own and never mid-row, so an answer about a table isn't
+//! built from half of it.
+
+use super::ChunkUnit;
+
+/// A stretch of a document's text: running text, a Markdown-style table,
+/// or a code block with the sentence introducing it.
+pub enum Segment<'a> {
+    Prose(&'a str),
+    Table(&'a str),
+    Code(&'a str),
+}
+
+/// Splits text into tables (runs of two or more `|` rows outside code
+/// fences) and the prose around them.
+pub fn segments(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut prose_start = 0;
+    let mut table: Option<(usize, usize)> = None;
+    let mut in_fence = false;
+    let mut offset = 0;
+    let mut end_table = |table: Option<(usize, usize)>, prose_start: &mut usize, end: usize| {
+        let Some((start, rows)) = table else {
+            return;
+        };
+        if rows < 2 {
+            return;
+        }
+        if !text[*prose_start..start].trim().is_empty() {
+            segments.push(Segment::Prose(&text[*prose_start..start]));
+        }
+        segments.push(Segment::Table(text[start..end].trim_end()));
+        *prose_start = end;
+    };
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if !in_fence && trimmed.starts_with('|') {
+            let (start, rows) = table.unwrap_or((offset, 0));
+            table = Some((start, rows + 1));
+        } else {
+            end_table(table.take(), &mut prose_start, offset);
+        }
+        offset += line.len();
+    }
+    end_table(table, &mut prose_start, offset);
+    if !text[prose_start..].trim().is_empty() {
+        segments.push(Segment::Prose(&text[prose_start..]));
+    }
+    segments
+}
+
+/// A table that fits in a chunk of `max_size` `unit`s is kept whole. A longer one is cut between rows, with its header (and the
+/// `---` row under it) repeated at the top of every chunk so each still says
+/// what its columns are.
+pub fn chunk_table(table: &str, max_size: usize, unit: ChunkUnit) -> Vec<String> {
+    let size = |text: &str| unit.count(text);
+    if size(table) <= max_size {
+        return vec![table.to_string()];
+    }
+
+    let lines: Vec<&str> = table.lines().collect();
+    let header_rows = if lines.get(1).is_some_and(|line| is_rule_row(line)) {
+        2
+    } else {
+        1
+    };
+    let (header, rows) = lines.split_at(header_rows.min(lines.len()));
+    let header_size: usize = header.iter().map(|row| size(row)).sum();
+
+    let mut chunks = Vec::new();
+    let mut chunk: Vec<&str> = header.to_vec();
+    let mut total = header_size;
+    for row in rows {
+        let row_size = size(row);
+        if chunk.len() > header.len() && total + row_size > max_size {
+            chunks.push(chunk.join("\n"));
+            chunk.truncate(header.len());
+            total = header_size;
+        }
+        chunk.push(row);
+        total += row_size;
+    }
+    if chunk.len() > header.len() {
+        chunks.push(chunk.join("\n"));
+    }
+    chunks
+}
+
+/// The `| --- | :---: |` row separating a table's header from its body.
+fn is_rule_row(line: &str) -> bool {
+    line.contains('-') && line.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+}
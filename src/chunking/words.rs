@@ -0,0 +1,130 @@
+use super::{ChunkUnit, Chunker};
+
+/// Windows of up to `size` units, each repeating about the last `overlap`
+/// units of the one before. Chunks start and end between the unit's
+/// [`pieces`](ChunkUnit::pieces), so never inside a word; a single piece
+/// longer than `size` is a chunk of its own.
+pub struct WordChunker {
+    pub unit: ChunkUnit,
+    pub size: usize,
+    pub overlap: usize,
+}
+
+impl Chunker for WordChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let pieces = self.unit.pieces(text);
+        let separator = self.unit.separator();
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < pieces.len() {
+            let (mut end, mut total) = (start, 0);
+            while end < pieces.len() && (end == start || total + pieces[end].1 <= self.size) {
+                total += pieces[end].1;
+                end += 1;
+            }
+            let chunk: Vec<&str> = pieces[start..end].iter().map(|(piece, _)| *piece).collect();
+            let chunk = chunk.join(separator);
+            // Lines keep the indentation they start with.
+            let chunk = match self.unit {
+                ChunkUnit::Lines => chunk.trim_end().trim_start_matches(['\n', '\r']),
+                _ => chunk.trim(),
+            };
+            if !chunk.is_empty() {
+                chunks.push(chunk.to_string());
+            }
+
+            if end >= pieces.len() {
+                break;
+            }
+
+            // Step back over whole pieces until about `overlap` units repeat,
+            // always moving on by at least one piece.
+            let (mut next, mut repeated) = (end, 0);
+            while next > start + 1 && repeated + pieces[next - 1].1 <= self.overlap {
+                next -= 1;
+                repeated += pieces[next].1;
+            }
+            start = next;
+        }
+
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(unit: ChunkUnit, size: usize, overlap: usize, text: &str) -> Vec<String> {
+        WordChunker {
+            unit,
+            size,
+            overlap,
+        }
+        .chunk(text)
+    }
+
+    #[test]
+    fn windows_overlap_by_whole_words() {
+        assert_eq!(
+            chunk(ChunkUnit::Words, 4, 1, "one two three four five six seven"),
+            ["one two three four", "four five six seven"]
+        );
+        assert_eq!(
+            chunk(ChunkUnit::Words, 3, 0, "one two three four five six seven"),
+            ["one two three", "four five six", "seven"]
+        );
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        for unit in [ChunkUnit::Words, ChunkUnit::Chars, ChunkUnit::Tokens] {
+            assert!(chunk(unit, 10, 2, "").is_empty());
+            assert!(chunk(unit, 10, 2, " \n\t ").is_empty());
+        }
+    }
+
+    #[test]
+    fn token_windows_fit_the_size() {
+        let text = "Tokenizers split uncommon words like antidisestablishmentarianism \
+                    into several tokens, while common ones like the and of take one each.";
+        let chunks = chunk(ChunkUnit::Tokens, 8, 2, text);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            // A chunk's count can differ from its words' by the space the
+            // first word was counted with.
+            assert!(ChunkUnit::Tokens.count(chunk) <= 8 + 1, "{:?}", chunk);
+        }
+        assert!(chunks[0].starts_with("Tokenizers"));
+        assert!(chunks.last().unwrap().ends_with("each."));
+    }
+
+    #[test]
+    fn a_word_longer_than_a_chunk_is_a_chunk_of_its_own() {
+        let long = "antidisestablishmentarianism";
+        assert!(ChunkUnit::Tokens.count(long) > 2);
+        assert_eq!(chunk(ChunkUnit::Tokens, 2, 0, long), [long]);
+    }
+
+    #[test]
+    fn an_overlap_as_large_as_the_size_still_moves_on() {
+        let chunks = chunk(ChunkUnit::Words, 3, 5, "a b c d e");
+        assert_eq!(chunks, ["a b c", "b c d", "c d e"]);
+    }
+
+    #[test]
+    fn multibyte_text_is_cut_between_characters() {
+        let text = "日本語のテキストを分割します。東京は首都です。";
+        let chunks = chunk(ChunkUnit::Chars, 5, 0, text);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= 5, "{:?}", chunk);
+        }
+        assert_eq!(chunks.concat(), text);
+
+        let words = chunk(ChunkUnit::Tokens, 3, 1, "naïve café über straße déjà vu");
+        assert_eq!(words.first().unwrap().split(' ').next(), Some("naïve"));
+        assert!(words.last().unwrap().ends_with("vu"));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::loaders::Document;
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use tracing::info;
+use unicode_normalization::UnicodeNormalization;
+
+/// Lines that say nothing about a document's subject: copyright notices,
+/// blank-page notes, page counters and download stamps.
+const BOILERPLATE: &[&str] = &[
+    r"^\W*(this|the) page (is )?(intentionally|deliberately) (been )?(left )?blank\W*$",
+    r"^(copyright\s*)?(©|\(c\)|copyright)\s*\d{4}\b",
+    r"\ball rights reserved\W*$",
+    r"^page\s+\d+(\s*(of|/)\s*\d+)?$",
+    r"^downloaded from\s+\S+",
+];
+
+/// Built-in rules only remove lines up to this many words, as notices are
+/// short: a sentence that merely starts with `Copyright 2021` is kept.
+const MAX_BOILERPLATE_WORDS: usize = 12;
+
+/// Characters that render as nothing but split words for search: zero-width
+/// spaces and joiners, byte order marks and soft hyphens.
+static INVISIBLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("[\u{200b}\u{200c}\u{200d}\u{2060}\u{feff}\u{ad}]").unwrap());
+
+static SPACES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[ \t]{2,}").unwrap());
+
+static BLANK_LINES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\n{3,}").unwrap());
+
+/// The lines `--clean` removes: the built-in boilerplate, plus any patterns
+/// from `--clean-rules`.
+#[derive(Debug, Clone)]
+pub struct CleanRules {
+    builtin: Vec<Regex>,
+    custom: Vec<Regex>,
+}
+
+impl CleanRules {
+    /// The built-in rules, and those in `path`: one regular expression per
+    /// line, matched case-insensitively anywhere in a line of text. Blank
+    /// lines and lines starting with `#` are skipped.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let builtin = BOILERPLATE
+            .iter()
+            .map(|pattern| rule(pattern))
+            .collect::<Result<_>>()?;
+        let mut custom = Vec::new();
+        if let Some(path) = path {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read clean rules: {:?}", path))?;
+            for line in text.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                custom.push(rule(line).with_context(|| format!("Invalid rule in {:?}", path))?);
+            }
+        }
+        Ok(Self { builtin, custom })
+    }
+
+    fn is_boilerplate(&self, line: &str) -> bool {
+        let line = line.trim();
+        if line.is_empty() {
+            return false;
+        }
+        let short = line.split_whitespace().count() <= MAX_BOILERPLATE_WORDS;
+        (short && self.builtin.iter().any(|rule| rule.is_match(line)))
+            || self.custom.iter().any(|rule| rule.is_match(line))
+    }
+}
+
+fn rule(pattern: &str) -> Result<Regex> {
+    Ok(RegexBuilder::new(pattern).case_insensitive(true).build()?)
+}
+
+/// Tidies the documents' text before it is chunked: drops the lines `rules`
+/// match, folds Unicode compatibility characters (ligatures, full-width
+/// letters, non-breaking spaces) into their plain forms, straightens curly
+/// quotes, removes invisible characters and collapses runs of spaces and
+/// blank lines. Source code keeps its spacing.
+pub fn clean_documents(documents: &mut [Document], rules: &CleanRules) {
+    let mut removed = 0;
+    for document in documents.iter_mut() {
+        let is_code = document.metadata.contains_key("language");
+        // Page by page, so the form feeds between pages are kept.
+        let pages: Vec<String> = document
+            .text
+            .split('\u{c}')
+            .map(|page| {
+                let page = normalize(page);
+                let mut lines = Vec::new();
+                for line in page.lines() {
+                    if rules.is_boilerplate(line) {
+                        removed += 1;
+                    } else if is_code {
+                        lines.push(line.trim_end().to_string());
+                    } else {
+                        lines.push(SPACES.replace_all(line.trim_end(), " ").into_owned());
+                    }
+                }
+                BLANK_LINES
+                    .replace_all(&lines.join("\n"), "\n\n")
+                    .into_owned()
+            })
+            .collect();
+        document.text = pages.join("\u{c}");
+    }
+    info!(
+        "Cleaned {} documents, removing {} boilerplate lines",
+        documents.len(),
+        removed
+    );
+}
+
+fn normalize(text: &str) -> String {
+    let text: String = text
+        .nfkc()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{2032}' => '\'',
+            '\u{201c}' | '\u{201d}' | '\u{201e}' | '\u{2033}' => '"',
+            c => c,
+        })
+        .collect();
+    INVISIBLE.replace_all(&text, "").into_owned()
+}
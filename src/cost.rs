@@ -0,0 +1,119 @@
+//! What a run is about to spend: the embedding of the chunks not embedded
+//! before, and each question asked of the chat model, from list prices.
+
+use anyhow::{Context, Result, bail};
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Dollars per million tokens embedded, by model.
+const EMBEDDING_PRICES: &[(&str, f64)] = &[
+    ("text-embedding-3-small", 0.02),
+    ("text-embedding-3-large", 0.13),
+    ("text-embedding-ada-002", 0.10),
+    ("embed-english-v3.0", 0.10),
+    ("embed-multilingual-v3.0", 0.10),
+    ("embed-english-light-v3.0", 0.10),
+    ("embed-multilingual-light-v3.0", 0.10),
+    ("voyage-3-large", 0.18),
+    ("voyage-3.5-lite", 0.02),
+    ("voyage-3.5", 0.06),
+    ("voyage-3-lite", 0.02),
+    ("voyage-3", 0.06),
+    ("voyage-code-3", 0.18),
+    ("gemini-embedding-001", 0.15),
+];
+
+/// Dollars per million input and output tokens, by chat model. Dated
+/// versions, like `gpt-4o-2024-08-06`, go by the longest name they start
+/// with.
+const CHAT_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("o3-mini", 1.10, 4.40),
+    ("o4-mini", 1.10, 4.40),
+];
+
+/// What a question and its answer add to the retrieved chunks, in tokens.
+const QUESTION_TOKENS: usize = 50;
+const ANSWER_TOKENS: usize = 400;
+
+/// Estimates under this many dollars go ahead without asking.
+const CONFIRM_FROM: f64 = 0.01;
+
+/// The price of embedding with `model`; nothing for `self_hosted` models,
+/// like those Ollama runs, and `None` for models whose price isn't known.
+fn embedding_price(self_hosted: bool, model: &str) -> Option<f64> {
+    if self_hosted {
+        return Some(0.0);
+    }
+    EMBEDDING_PRICES
+        .iter()
+        .find(|(name, _)| *name == model)
+        .map(|&(_, price)| price)
+}
+
+fn chat_prices(model: &str) -> Option<(f64, f64)> {
+    CHAT_PRICES
+        .iter()
+        .filter(|(name, _, _)| model.starts_with(name))
+        .max_by_key(|(name, _, _)| name.len())
+        .map(|&(_, input, output)| (input, output))
+}
+
+/// Prints what embedding `tokens` tokens with `embedding_model` and asking
+/// `chat_model` a question with `context_tokens` of retrieved chunks will
+/// cost. When the embedding costs a cent or more, asks whether to go on,
+/// unless `yes` is set or nobody is at the terminal to answer.
+pub fn confirm(
+    self_hosted: bool,
+    embedding_model: &str,
+    tokens: usize,
+    chat_model: &str,
+    context_tokens: usize,
+    yes: bool,
+) -> Result<()> {
+    let embedding =
+        embedding_price(self_hosted, embedding_model).map(|price| dollars(tokens, price));
+    match embedding {
+        Some(cost) => println!(
+            "Embedding {} tokens with {}: about ${:.4}",
+            tokens, embedding_model, cost
+        ),
+        None => println!(
+            "Embedding {} tokens with {} (price unknown)",
+            tokens, embedding_model
+        ),
+    }
+    match chat_prices(chat_model) {
+        Some((input, output)) => println!(
+            "Each question to {}: about ${:.4}",
+            chat_model,
+            dollars(context_tokens + QUESTION_TOKENS, input) + dollars(ANSWER_TOKENS, output)
+        ),
+        None => println!("Each question to {} (price unknown)", chat_model),
+    }
+
+    if yes || embedding.is_some_and(|cost| cost < CONFIRM_FROM) || !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    print!("Continue? [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .context("Failed to read the answer")?;
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        bail!("Stopped before embedding; pass --yes to go ahead without asking");
+    }
+    Ok(())
+}
+
+fn dollars(tokens: usize, per_million: f64) -> f64 {
+    tokens as f64 * per_million / 1_000_000.0
+}
@@ -0,0 +1,322 @@
+use crate::chunking::Chunk;
+use crate::watch::EmbeddedChunk;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use tracing::{debug, info};
+
+/// Chunks are compared as sets of overlapping runs of this many words.
+const SHINGLE_WORDS: usize = 5;
+
+/// MinHash signature length, split into bands for locality-sensitive
+/// hashing: chunks sharing any band are compared in full.
+const SIGNATURE_LEN: usize = 64;
+const BAND_ROWS: usize = 4;
+
+/// Estimated share of shingles two chunks must have in common to count as
+/// copies of each other.
+const MIN_SIMILARITY: f64 = 0.85;
+
+/// Finds the chunks whose text is a copy of an earlier one's: exactly the
+/// same words (ignoring case, punctuation and spacing), or with `near`,
+/// mostly the same shingles.
+#[derive(Default)]
+struct Matcher {
+    near: bool,
+    exact: HashMap<u64, usize>,
+    signatures: Vec<Vec<u64>>,
+    bands: HashMap<(usize, u64), Vec<usize>>,
+}
+
+impl Matcher {
+    fn new(near: bool) -> Self {
+        Self {
+            near,
+            ..Self::default()
+        }
+    }
+
+    /// The earlier text this one copies, and whether exactly, or else
+    /// remembers it as the next original.
+    fn find_or_add(&mut self, text: &str) -> Option<(usize, bool)> {
+        let words = normalized_words(text);
+        let exact = hash(&words);
+        if let Some(&index) = self.exact.get(&exact) {
+            return Some((index, true));
+        }
+        let index = self.exact.len();
+        if self.near {
+            let signature = signature(&words);
+            let keys: Vec<(usize, u64)> = signature
+                .chunks(BAND_ROWS)
+                .enumerate()
+                .map(|(band, rows)| (band, hash(rows)))
+                .collect();
+            let original = keys
+                .iter()
+                .filter_map(|key| self.bands.get(key))
+                .flatten()
+                .copied()
+                .find(|&i| similarity(&self.signatures[i], &signature) >= MIN_SIMILARITY);
+            if let Some(original) = original {
+                return Some((original, false));
+            }
+            for key in keys {
+                self.bands.entry(key).or_default().push(index);
+            }
+            self.signatures.push(signature);
+        }
+        self.exact.insert(exact, index);
+        None
+    }
+}
+
+/// For each chunk, the earlier chunk it copies, if any: exactly, or with
+/// `near` also nearly. Copies needn't be embedded, since they can share
+/// their original's embedding.
+pub fn find_copies(chunks: &[Chunk], near: bool) -> Vec<Option<usize>> {
+    let mut matcher = Matcher::new(near);
+    let mut originals: Vec<usize> = Vec::new();
+    let (mut exact, mut nearly) = (0, 0);
+    let copies: Vec<Option<usize>> = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| match matcher.find_or_add(&chunk.text) {
+            Some((original, is_exact)) => {
+                if is_exact {
+                    exact += 1;
+                } else {
+                    nearly += 1;
+                }
+                Some(originals[original])
+            }
+            None => {
+                originals.push(i);
+                None
+            }
+        })
+        .collect();
+    if exact + nearly > 0 {
+        info!(
+            "Skipping embedding {} duplicate chunks: {} exact copies and {} near-copies",
+            exact + nearly,
+            exact,
+            nearly
+        );
+    }
+    copies
+}
+
+/// Drops chunks that are near-copies of an earlier chunk, as happens when
+/// several revisions of a report are loaded together. The copy that is
+/// kept lists where the others came from under `aliases`, so answers can
+/// still mention every document that says the same thing.
+pub fn remove_duplicates(chunks: Vec<EmbeddedChunk>) -> Vec<EmbeddedChunk> {
+    let mut kept: Vec<(EmbeddedChunk, BTreeSet<String>)> = Vec::new();
+    let mut matcher = Matcher::new(true);
+    let mut dropped = 0;
+
+    for (mut chunk, embeddings) in chunks {
+        // Aliases are worked out afresh, whatever an earlier run recorded.
+        chunk.metadata.remove("aliases");
+        if let Some((index, _)) = matcher.find_or_add(&chunk.text) {
+            let copy = location(&chunk);
+            debug!(
+                "Dropping chunk {} of {}, a copy of chunk {} of {}",
+                chunk.index,
+                copy,
+                kept[index].0.0.index,
+                location(&kept[index].0.0)
+            );
+            if copy != location(&kept[index].0.0) {
+                kept[index].1.insert(copy);
+            }
+            dropped += 1;
+            continue;
+        }
+        kept.push(((chunk, embeddings), BTreeSet::new()));
+    }
+
+    if dropped > 0 {
+        info!("Dropped {} near-duplicate chunks", dropped);
+    }
+    kept.into_iter()
+        .map(|((mut chunk, embeddings), aliases)| {
+            if !aliases.is_empty() {
+                let aliases: Vec<String> = aliases.into_iter().collect();
+                chunk
+                    .metadata
+                    .insert("aliases".to_string(), aliases.join("; "));
+            }
+            (chunk, embeddings)
+        })
+        .collect()
+}
+
+/// Where a chunk came from: its source, and page when it has one.
+fn location(chunk: &Chunk) -> String {
+    let source = chunk.source().unwrap_or("document");
+    match chunk.page() {
+        Some(page) => format!("{} (page {})", source, page),
+        None => source.to_string(),
+    }
+}
+
+/// A text's words, lowercased and without punctuation, so copies that
+/// differ only in formatting compare equal.
+fn normalized_words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect()
+        })
+        .filter(|word: &String| !word.is_empty())
+        .collect()
+}
+
+/// The MinHash signature of a text's word shingles: for each of a family
+/// of hash functions, the smallest hash of any shingle. Two signatures
+/// agree in about as many places as the texts share shingles.
+fn signature(words: &[String]) -> Vec<u64> {
+    let shingles: Vec<u64> = if words.len() <= SHINGLE_WORDS {
+        vec![hash(words)]
+    } else {
+        words.windows(SHINGLE_WORDS).map(hash).collect()
+    };
+
+    (0..SIGNATURE_LEN as u64)
+        .map(|seed| {
+            let seed = mix(seed);
+            shingles
+                .iter()
+                .map(|&shingle| mix(shingle ^ seed))
+                .min()
+                .unwrap_or(u64::MAX)
+        })
+        .collect()
+}
+
+fn similarity(a: &[u64], b: &[u64]) -> f64 {
+    let same = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    same as f64 / a.len() as f64
+}
+
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The splitmix64 finaliser, which turns one hash into a family of
+/// independent-looking ones when fed different seeds.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rig::OneOrMany;
+    use rig::embeddings::Embedding;
+
+    fn chunk(source: &str, page: Option<&str>, text: &str) -> Chunk {
+        let mut chunk = Chunk {
+            text: text.to_string(),
+            metadata: Default::default(),
+            index: 0,
+            char_range: 0..0,
+            parent: None,
+            summary: None,
+            document: 0,
+            header: None,
+        };
+        chunk
+            .metadata
+            .insert("source".to_string(), source.to_string());
+        if let Some(page) = page {
+            chunk.metadata.insert("page".to_string(), page.to_string());
+        }
+        chunk
+    }
+
+    fn embedded(chunk: Chunk) -> EmbeddedChunk {
+        let embedding = Embedding {
+            document: chunk.text.clone(),
+            vec: vec![0.0],
+        };
+        (chunk, OneOrMany::one(embedding))
+    }
+
+    /// A long passage, with its last word replaced by `ending`.
+    fn passage(ending: &str) -> String {
+        let words: Vec<String> = (0..200).map(|i| format!("word{}", i)).collect();
+        format!("{} {}", words.join(" "), ending)
+    }
+
+    #[test]
+    fn points_copies_at_the_chunk_they_copy() {
+        let chunks = [
+            chunk("a", None, "The first chunk."),
+            chunk("a", None, &passage("end")),
+            chunk("b", None, "the FIRST   chunk"),
+            chunk("b", None, "Something else."),
+            chunk("b", None, &passage("finish")),
+        ];
+        assert_eq!(
+            find_copies(&chunks, true),
+            [None, None, Some(0), None, Some(1)]
+        );
+        // Without `near`, only the exact copy is found.
+        assert_eq!(
+            find_copies(&chunks, false),
+            [None, None, Some(0), None, None]
+        );
+    }
+
+    #[test]
+    fn keeps_unrelated_chunks() {
+        let chunks = [
+            chunk("a", None, "Apples are red."),
+            chunk("a", None, "Bananas are yellow."),
+        ];
+        assert_eq!(find_copies(&chunks, true), [None, None]);
+    }
+
+    #[test]
+    fn lists_where_dropped_copies_came_from() {
+        let mut stale = chunk("a", Some("1"), "Revenue grew by ten percent.");
+        stale
+            .metadata
+            .insert("aliases".to_string(), "old".to_string());
+        let chunks = vec![
+            embedded(stale),
+            embedded(chunk("a", Some("4"), "Costs fell.")),
+            embedded(chunk("b", Some("2"), "Revenue grew by ten percent!")),
+            // A copy in the same place isn't another place to cite.
+            embedded(chunk("a", Some("1"), "revenue grew by ten percent")),
+            embedded(chunk("c", None, "Costs fell.")),
+        ];
+        let kept = remove_duplicates(chunks);
+        let kept: Vec<(&str, Option<&str>)> = kept
+            .iter()
+            .map(|(chunk, _)| {
+                (
+                    chunk.text.as_str(),
+                    chunk.metadata.get("aliases").map(String::as_str),
+                )
+            })
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                ("Revenue grew by ten percent.", Some("b (page 2)")),
+                ("Costs fell.", Some("c")),
+            ]
+        );
+    }
+}
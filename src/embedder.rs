@@ -0,0 +1,552 @@
+//! The embedding model chunks and questions are embedded with: OpenAI's
+//! (from OpenAI or deployed on Azure OpenAI), Cohere's, Voyage's, Google's
+//! Gemini, a sentence-transformers model hosted by Hugging Face, one served by
+//! Ollama, one run in-process on this machine, as `--embedding-provider`
+//! says, or one served by a server speaking OpenAI's embeddings API, at
+//! `--embedding-base-url`.
+
+use crate::chunking::TokenLimit;
+use crate::gemini::{GEMINI_EMBEDDING_MODEL, GeminiModel};
+use crate::huggingface::{HUGGINGFACE_EMBEDDING_MODEL, HuggingFaceModel};
+use crate::local::{LOCAL_EMBEDDING_MODEL, LocalModel};
+use crate::voyage::{VOYAGE_EMBEDDING_MODEL, VoyageModel};
+use anyhow::{Context, bail};
+use rig::client::{EmbeddingsClient, Nothing};
+use rig::embeddings::{Embedding, EmbeddingError, EmbeddingModel};
+use rig::providers::{azure, cohere, ollama, openai};
+use rig::wasm_compat::WasmCompatSend;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// OpenAI model used when `--embedding-model` isn't given.
+pub const OPENAI_EMBEDDING_MODEL: &str = openai::TEXT_EMBEDDING_3_SMALL;
+
+/// The OpenAI models that can return shorter vectors, and the size of
+/// their full ones.
+const OPENAI_DIMENSIONS: &[(&str, usize)] = &[
+    (openai::TEXT_EMBEDDING_3_SMALL, 1536),
+    (openai::TEXT_EMBEDDING_3_LARGE, 3072),
+];
+
+/// Tokens one text may hold for OpenAI's embedding models.
+const OPENAI_MAX_TOKENS: usize = 8191;
+
+/// Cohere model used when `--embedding-model` isn't given.
+pub const COHERE_EMBEDDING_MODEL: &str = cohere::EMBED_ENGLISH_V3;
+
+/// Cohere's `input_type`s for text that is searched and text searched
+/// with.
+const COHERE_DOCUMENT: &str = "search_document";
+const COHERE_QUERY: &str = "search_query";
+
+/// Tokens one text may hold for Cohere's embedding models, which cut off
+/// the rest unseen.
+const COHERE_MAX_TOKENS: usize = 512;
+
+/// Ollama model used when `--embedding-model` isn't given.
+pub const OLLAMA_EMBEDDING_MODEL: &str = ollama::ALL_MINILM;
+
+/// Where Ollama serves its models, unless `--ollama-url` or
+/// `OLLAMA_API_BASE_URL` says otherwise.
+pub const OLLAMA_URL: &str = "http://localhost:11434";
+
+/// The prefix a model is trained to see before questions and before the
+/// passages they are matched against.
+const SEARCH_PROMPT: &str = "Represent this sentence for searching relevant passages: ";
+
+/// Prefixes of open models that embed questions and passages apart, by a
+/// part of their name: the query prefix, then the passage prefix.
+const PREFIXES: &[(&str, &str, &str)] = &[
+    ("multilingual-e5", "query: ", "passage: "),
+    ("e5-small", "query: ", "passage: "),
+    ("e5-base", "query: ", "passage: "),
+    ("e5-large", "query: ", "passage: "),
+    ("bge-small-en", SEARCH_PROMPT, ""),
+    ("bge-base-en", SEARCH_PROMPT, ""),
+    ("bge-large-en", SEARCH_PROMPT, ""),
+    ("mxbai-embed-large", SEARCH_PROMPT, ""),
+    ("snowflake-arctic-embed", SEARCH_PROMPT, ""),
+    ("nomic-embed-text", "search_query: ", "search_document: "),
+];
+
+/// The models an Ollama server has pulled, from `/api/tags`.
+#[derive(Deserialize)]
+struct OllamaTags {
+    models: Vec<OllamaTag>,
+}
+
+#[derive(Deserialize)]
+struct OllamaTag {
+    name: String,
+}
+
+/// What is put before each text a model served by Hugging Face, Ollama or
+/// `--embedding-base-url`, or run locally, embeds: for questions, `query`, and for passages,
+/// `passage`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Prefixes {
+    pub query: String,
+    pub passage: String,
+    /// Whether questions are embedded, rather than passages.
+    for_queries: bool,
+}
+
+impl Prefixes {
+    /// The prefixes `model` is known to want, if any, unless `query` or
+    /// `passage` say otherwise.
+    pub fn new(model: &str, query: Option<&str>, passage: Option<&str>) -> Self {
+        let name = model.to_lowercase();
+        let known = PREFIXES.iter().find(|(part, _, _)| name.contains(part));
+        let (known_query, known_passage) =
+            known.map_or(("", ""), |&(_, query, passage)| (query, passage));
+        Self {
+            query: query.unwrap_or(known_query).to_string(),
+            passage: passage.unwrap_or(known_passage).to_string(),
+            for_queries: false,
+        }
+    }
+
+    fn prefix(&self) -> &str {
+        if self.for_queries {
+            &self.query
+        } else {
+            &self.passage
+        }
+    }
+
+    fn apply(&self, texts: impl IntoIterator<Item = String>) -> Vec<String> {
+        texts
+            .into_iter()
+            .map(|text| format!("{}{}", self.prefix(), text))
+            .collect()
+    }
+}
+
+/// Where text is embedded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum EmbeddingProvider {
+    /// The OpenAI embeddings API
+    #[default]
+    Openai,
+    /// The Cohere embed API (embed-english-v3.0 and the like), with the
+    /// key in COHERE_API_KEY
+    Cohere,
+    /// The Voyage AI embed API (voyage-3 and the like), with the key in
+    /// VOYAGE_API_KEY
+    Voyage,
+    /// Google's Gemini API (text-embedding-004 and the like), with the key
+    /// in GEMINI_API_KEY
+    Gemini,
+    /// A sentence-transformers model on the Hugging Face Inference API,
+    /// with the token in HF_TOKEN, or on a Text Embeddings Inference server
+    /// at --huggingface-url
+    #[value(alias = "hf")]
+    Huggingface,
+    /// An OpenAI model deployed on an Azure OpenAI resource, named by its
+    /// deployment
+    Azure,
+    /// A model served by Ollama (https://ollama.com), like all-minilm or
+    /// nomic-embed-text: nothing is sent to OpenAI and nothing is billed
+    Ollama,
+    /// A BERT sentence-transformers model, like all-MiniLM-L6-v2 or
+    /// bge-small-en-v1.5, run in this process: nothing is sent anywhere,
+    /// and no server has to be running
+    Local,
+}
+
+impl EmbeddingProvider {
+    /// The model used when none is named.
+    pub fn default_model(self) -> &'static str {
+        match self {
+            EmbeddingProvider::Openai | EmbeddingProvider::Azure => OPENAI_EMBEDDING_MODEL,
+            EmbeddingProvider::Cohere => COHERE_EMBEDDING_MODEL,
+            EmbeddingProvider::Voyage => VOYAGE_EMBEDDING_MODEL,
+            EmbeddingProvider::Gemini => GEMINI_EMBEDDING_MODEL,
+            EmbeddingProvider::Huggingface => HUGGINGFACE_EMBEDDING_MODEL,
+            EmbeddingProvider::Ollama => OLLAMA_EMBEDDING_MODEL,
+            EmbeddingProvider::Local => LOCAL_EMBEDDING_MODEL,
+        }
+    }
+}
+
+/// An embedding model from any of the providers. Documents and questions
+/// are embedded with the same model, but some embed them differently, so the copy that embeds questions comes from
+/// [`Embedder::for_queries`].
+#[derive(Clone)]
+pub enum Embedder {
+    OpenAi(openai::EmbeddingModel),
+    /// A model on a server other than OpenAI's, at `url`, whose vectors are
+    /// `ndims` long.
+    OpenAiCompatible {
+        model: openai::EmbeddingModel,
+        url: String,
+        ndims: usize,
+        prefixes: Prefixes,
+    },
+    Cohere(cohere::EmbeddingModel),
+    Voyage(VoyageModel),
+    Gemini(GeminiModel),
+    HuggingFace {
+        model: HuggingFaceModel,
+        prefixes: Prefixes,
+    },
+    Azure(azure::EmbeddingModel),
+    Ollama {
+        model: ollama::EmbeddingModel<reqwest::Client>,
+        prefixes: Prefixes,
+    },
+    Local {
+        model: LocalModel,
+        prefixes: Prefixes,
+    },
+}
+
+impl Embedder {
+    /// `model` as served by Ollama at `url`. The server is asked up front
+    /// whether it has the model, so a missing server or model fails with a
+    /// hint rather than on the first batch of chunks, and one text is
+    /// embedded to learn the model's vector size. Texts are embedded with
+    /// `prefixes` before them.
+    pub async fn ollama(url: &str, model: &str, prefixes: Prefixes) -> anyhow::Result<Self> {
+        let tags_url = format!("{}/api/tags", url.trim_end_matches('/'));
+        let tags: OllamaTags = reqwest::get(&tags_url)
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| {
+                format!(
+                    "Could not reach Ollama at {}; is `ollama serve` running?",
+                    url
+                )
+            })?
+            .json()
+            .await
+            .with_context(|| format!("Unexpected reply from Ollama: {}", tags_url))?;
+        // `ollama pull nomic-embed-text` lists the model as
+        // `nomic-embed-text:latest`.
+        let pulled = tags
+            .models
+            .iter()
+            .any(|tag| tag.name == model || tag.name.strip_suffix(":latest") == Some(model));
+        if !pulled {
+            bail!(
+                "Ollama at {} has no model {:?}; pull it with `ollama pull {}`",
+                url,
+                model,
+                model
+            );
+        }
+
+        let client: ollama::Client = ollama::Client::builder()
+            .api_key(Nothing)
+            .base_url(url)
+            .build()?;
+        let probe = ollama::EmbeddingModel::new(client.clone(), model, 0);
+        let ndims = probe
+            .embed_text("dimensions")
+            .await
+            .with_context(|| format!("Failed to embed with Ollama model {}", model))?
+            .vec
+            .len();
+        Ok(Embedder::Ollama {
+            model: ollama::EmbeddingModel::new(client, model, ndims),
+            prefixes,
+        })
+    }
+
+    /// The OpenAI `model`, returning vectors cut to `dimensions` when it is
+    /// given. Only the text-embedding-3 models can shorten their vectors,
+    /// and none can lengthen them.
+    pub fn openai(
+        client: &openai::Client,
+        model: &str,
+        dimensions: Option<usize>,
+    ) -> anyhow::Result<Self> {
+        let Some(dimensions) = dimensions else {
+            return Ok(Embedder::OpenAi(client.embedding_model(model)));
+        };
+        let Some(&(_, full)) = OPENAI_DIMENSIONS.iter().find(|(name, _)| *name == model) else {
+            bail!(
+                "--embedding-dimensions needs a text-embedding-3 model, not {}",
+                model
+            );
+        };
+        if dimensions == 0 || dimensions > full {
+            bail!(
+                "--embedding-dimensions must be between 1 and {} for {}",
+                full,
+                model
+            );
+        }
+        Ok(Embedder::OpenAi(
+            client.embedding_model_with_ndims(model, dimensions),
+        ))
+    }
+
+    /// `model` as served at `url` by a server speaking OpenAI's embeddings
+    /// API, like vLLM, LM Studio or text-embeddings-inference, with the key
+    /// in `EMBEDDING_API_KEY` if it needs one. One text is embedded up
+    /// front, so an unreachable server or unknown model fails before any
+    /// chunks are sent, and to learn the model's vector size.
+    pub async fn openai_compatible(
+        url: &str,
+        model: &str,
+        prefixes: Prefixes,
+    ) -> anyhow::Result<Self> {
+        let url = url.trim_end_matches('/');
+        let key = std::env::var("EMBEDDING_API_KEY").unwrap_or_default();
+        let client: openai::Client = openai::Client::builder()
+            .api_key(key)
+            .base_url(url)
+            .build()?;
+        // With no size given, none is sent, which servers that can't shorten
+        // vectors may refuse.
+        let model = client.embedding_model_with_ndims(model, 0);
+        let ndims = model
+            .embed_text("dimensions")
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to embed with {} at {}; is the server running and serving it?",
+                    model.model, url
+                )
+            })?
+            .vec
+            .len();
+        Ok(Embedder::OpenAiCompatible {
+            model,
+            url: url.to_string(),
+            ndims,
+            prefixes,
+        })
+    }
+
+    /// The deployment `model` of the Azure OpenAI resource `client` reaches.
+    pub fn azure(client: &azure::Client, model: &str) -> Self {
+        Embedder::Azure(client.embedding_model(model))
+    }
+
+    /// The Cohere `model`, embedding documents, with the key in
+    /// `COHERE_API_KEY` and the API at `COHERE_BASE_URL`, if set.
+    pub fn cohere(model: &str) -> anyhow::Result<Self> {
+        let key = std::env::var("COHERE_API_KEY")
+            .context("--embedding-provider cohere needs COHERE_API_KEY to be set")?;
+        let mut builder = cohere::Client::builder().api_key(key);
+        if let Ok(url) = std::env::var("COHERE_BASE_URL") {
+            builder = builder.base_url(url);
+        }
+        let client: cohere::Client = builder.build()?;
+        Ok(Embedder::Cohere(
+            client.embedding_model(model, COHERE_DOCUMENT),
+        ))
+    }
+
+    /// The Voyage `model`, embedding documents.
+    pub fn voyage(model: &str) -> anyhow::Result<Self> {
+        Ok(Embedder::Voyage(VoyageModel::new(model)?))
+    }
+
+    /// The Gemini `model`, embedding documents.
+    pub fn gemini(model: &str) -> anyhow::Result<Self> {
+        Ok(Embedder::Gemini(GeminiModel::new(model)?))
+    }
+
+    /// `model` on the Hugging Face Inference API, or the model the Text
+    /// Embeddings Inference server at `endpoint` serves, which is asked for
+    /// a vector up front to learn its size. Texts get `prefixes` before
+    /// them.
+    pub async fn huggingface(
+        model: &str,
+        endpoint: Option<&str>,
+        prefixes: Prefixes,
+    ) -> anyhow::Result<Self> {
+        Ok(Embedder::HuggingFace {
+            model: HuggingFaceModel::new(model, endpoint).await?,
+            prefixes,
+        })
+    }
+
+    /// `model` run locally, from its directory or Hugging Face repo, with
+    /// `prefixes` before the texts it embeds.
+    pub async fn local(model: &str, prefixes: Prefixes) -> anyhow::Result<Self> {
+        Ok(Embedder::Local {
+            model: LocalModel::new(model).await?,
+            prefixes,
+        })
+    }
+
+    /// Which model this is and, where it matters, whether it embeds
+    /// documents or questions: texts embedded under the same key get the
+    /// same vectors.
+    pub fn cache_key(&self) -> String {
+        match self {
+            // Vectors cut short differ from the full ones they start, as
+            // OpenAI normalizes them again.
+            Embedder::OpenAi(model)
+                if OPENAI_DIMENSIONS.contains(&(model.model.as_str(), model.ndims())) =>
+            {
+                format!("openai {}", model.model)
+            }
+            Embedder::OpenAi(model) => format!("openai {} {}", model.model, model.ndims()),
+            Embedder::OpenAiCompatible {
+                model,
+                url,
+                prefixes,
+                ..
+            } => prefixed(format!("openai {} {}", url, model.model), prefixes),
+            Embedder::Cohere(model) => format!("cohere {} {}", model.model, model.input_type),
+            Embedder::Voyage(model) => model.cache_key(),
+            Embedder::Gemini(model) => model.cache_key(),
+            Embedder::HuggingFace { model, prefixes } => prefixed(model.cache_key(), prefixes),
+            Embedder::Azure(model) => format!("azure {}", model.model),
+            Embedder::Ollama { model, prefixes } => {
+                prefixed(format!("ollama {}", model.model), prefixes)
+            }
+            Embedder::Local { model, prefixes } => prefixed(model.cache_key(), prefixes),
+        }
+    }
+
+    /// The longest text the model takes, and how it counts tokens: up to
+    /// `max_tokens` where given, or else the model's own limit where it is
+    /// known. Models served by Hugging Face, Ollama or another server could
+    /// be anything, so they have none unless given one. Local models count
+    /// in their own tokenizer's tokens, their passage prefix included;
+    /// the rest are reckoned in cl100k tokens, near enough to their own.
+    pub fn token_limit(&self, max_tokens: Option<usize>) -> Option<TokenLimit> {
+        let known = match self {
+            Embedder::OpenAi(_) | Embedder::Azure(_) => Some(OPENAI_MAX_TOKENS),
+            Embedder::Cohere(_) => Some(COHERE_MAX_TOKENS),
+            Embedder::Voyage(model) => Some(model.max_tokens()),
+            Embedder::Gemini(model) => Some(model.max_tokens()),
+            Embedder::Local { model, .. } => Some(model.max_tokens()),
+            Embedder::OpenAiCompatible { .. }
+            | Embedder::HuggingFace { .. }
+            | Embedder::Ollama { .. } => None,
+        };
+        let max_tokens = max_tokens.or(known)?;
+        Some(match self {
+            Embedder::Local { model, prefixes } => {
+                let (model, passage) = (model.clone(), prefixes.passage.clone());
+                TokenLimit {
+                    max_tokens,
+                    count: Arc::new(move |text| {
+                        model.count_tokens(&format!("{}{}", passage, text))
+                    }),
+                }
+            }
+            _ => TokenLimit::cl100k(max_tokens),
+        })
+    }
+
+    /// This model as it should embed questions. Cohere's, Voyage's and
+    /// Gemini's embed them differently from the documents they are matched
+    /// against, and open models like e5 are given them under another prefix.
+    pub fn for_queries(&self) -> Self {
+        match self {
+            Embedder::Cohere(model) => {
+                let mut model = model.clone();
+                model.input_type = COHERE_QUERY.to_string();
+                Embedder::Cohere(model)
+            }
+            Embedder::Voyage(model) => Embedder::Voyage(model.for_queries()),
+            Embedder::Gemini(model) => Embedder::Gemini(model.for_queries()),
+            Embedder::OpenAiCompatible {
+                model,
+                url,
+                ndims,
+                prefixes,
+            } => Embedder::OpenAiCompatible {
+                model: model.clone(),
+                url: url.clone(),
+                ndims: *ndims,
+                prefixes: Prefixes {
+                    for_queries: true,
+                    ..prefixes.clone()
+                },
+            },
+            Embedder::HuggingFace { model, prefixes } => Embedder::HuggingFace {
+                model: model.clone(),
+                prefixes: Prefixes {
+                    for_queries: true,
+                    ..prefixes.clone()
+                },
+            },
+            Embedder::Ollama { model, prefixes } => Embedder::Ollama {
+                model: model.clone(),
+                prefixes: Prefixes {
+                    for_queries: true,
+                    ..prefixes.clone()
+                },
+            },
+            Embedder::Local { model, prefixes } => Embedder::Local {
+                model: model.clone(),
+                prefixes: Prefixes {
+                    for_queries: true,
+                    ..prefixes.clone()
+                },
+            },
+            model => model.clone(),
+        }
+    }
+}
+
+/// `key`, with `prefixes` when there are any, as they change every vector.
+fn prefixed(key: String, prefixes: &Prefixes) -> String {
+    match prefixes.prefix() {
+        "" => key,
+        prefix => format!("{} {:?}", key, prefix),
+    }
+}
+
+impl EmbeddingModel for Embedder {
+    const MAX_DOCUMENTS: usize = 1024;
+
+    type Client = openai::Client;
+
+    fn make(client: &Self::Client, model: impl Into<String>, dims: Option<usize>) -> Self {
+        Embedder::OpenAi(openai::EmbeddingModel::make(client, model, dims))
+    }
+
+    fn ndims(&self) -> usize {
+        match self {
+            Embedder::OpenAi(model) => model.ndims(),
+            Embedder::OpenAiCompatible { ndims, .. } => *ndims,
+            Embedder::Cohere(model) => model.ndims(),
+            Embedder::Voyage(model) => model.ndims(),
+            Embedder::Gemini(model) => model.ndims(),
+            Embedder::HuggingFace { model, .. } => model.ndims(),
+            Embedder::Azure(model) => model.ndims(),
+            Embedder::Ollama { model, .. } => model.ndims(),
+            Embedder::Local { model, .. } => model.ndims(),
+        }
+    }
+
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String> + WasmCompatSend,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        match self {
+            Embedder::OpenAi(model) => model.embed_texts(texts).await,
+            Embedder::OpenAiCompatible {
+                model, prefixes, ..
+            } => model.embed_texts(prefixes.apply(texts)).await,
+            // Cohere takes fewer texts at a time than the others.
+            Embedder::Cohere(model) => {
+                let texts: Vec<String> = texts.into_iter().collect();
+                let mut embeddings = Vec::with_capacity(texts.len());
+                for batch in texts.chunks(cohere::EmbeddingModel::<reqwest::Client>::MAX_DOCUMENTS)
+                {
+                    embeddings.extend(model.embed_texts(batch.to_vec()).await?);
+                }
+                Ok(embeddings)
+            }
+            Embedder::Voyage(model) => model.embed_texts(texts.into_iter().collect()).await,
+            Embedder::Gemini(model) => model.embed_texts(texts.into_iter().collect()).await,
+            Embedder::HuggingFace { model, prefixes } => {
+                model.embed_texts(prefixes.apply(texts)).await
+            }
+            Embedder::Azure(model) => model.embed_texts(texts).await,
+            Embedder::Ollama { model, prefixes } => model.embed_texts(prefixes.apply(texts)).await,
+            Embedder::Local { model, prefixes } => model.embed_texts(prefixes.apply(texts)).await,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Vectors kept on disk by embedding model and text, so a chunk that was
+//! embedded before, in this file or any other, isn't embedded again.
+
+use crate::loaders;
+use rig::embeddings::{Embedding, EmbeddingError, EmbeddingModel};
+use rig::wasm_compat::WasmCompatSend;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// `model`, with the vectors it returns saved in
+/// `~/.cache/rag-my-pdf/embeddings` under a hash of `key` and the text.
+#[derive(Clone)]
+pub struct CachedEmbedder<M> {
+    model: M,
+    key: String,
+    /// Nowhere to cache, when there is no cache directory.
+    dir: Option<PathBuf>,
+}
+
+impl<M> CachedEmbedder<M> {
+    /// `key` names the model, and whatever else changes the vectors it
+    /// returns for a text.
+    pub fn new(model: M, key: String) -> Self {
+        let dir = loaders::cache_dir("embeddings")
+            .inspect_err(|e| warn!("Embeddings won't be cached: {:#}", e))
+            .ok();
+        Self { model, key, dir }
+    }
+
+    /// Whether `text` has a saved vector, so embedding it costs nothing.
+    pub fn is_cached(&self, text: &str) -> bool {
+        self.path(text).is_some_and(|path| path.is_file())
+    }
+
+    fn path(&self, text: &str) -> Option<PathBuf> {
+        let name = loaders::sha256_hex(format!("{}\n{}", self.key, text).as_bytes());
+        Some(self.dir.as_ref()?.join(format!("{}.json", name)))
+    }
+}
+
+impl<M: EmbeddingModel> EmbeddingModel for CachedEmbedder<M> {
+    const MAX_DOCUMENTS: usize = M::MAX_DOCUMENTS;
+
+    type Client = M::Client;
+
+    fn make(client: &Self::Client, model: impl Into<String>, dims: Option<usize>) -> Self {
+        let model = model.into();
+        Self::new(M::make(client, model.clone(), dims), model)
+    }
+
+    fn ndims(&self) -> usize {
+        self.model.ndims()
+    }
+
+    /// Only the texts without a saved vector are sent to the model.
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String> + WasmCompatSend,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        let texts: Vec<String> = texts.into_iter().collect();
+        let paths: Vec<Option<PathBuf>> = texts.iter().map(|text| self.path(text)).collect();
+        let mut vectors: Vec<Option<Vec<f64>>> = paths
+            .iter()
+            .map(|path| {
+                let bytes = fs::read(path.as_ref()?).ok()?;
+                serde_json::from_slice(&bytes).ok()
+            })
+            .collect();
+
+        let missing: Vec<usize> = (0..texts.len()).filter(|&i| vectors[i].is_none()).collect();
+        debug!(
+            "{} of {} embeddings cached",
+            texts.len() - missing.len(),
+            texts.len()
+        );
+        if !missing.is_empty() {
+            let embedded = self
+                .model
+                .embed_texts(
+                    missing
+                        .iter()
+                        .map(|&i| texts[i].clone())
+                        .collect::<Vec<_>>(),
+                )
+                .await?;
+            // Each batch is saved as soon as it comes back, so a run that is
+            // interrupted or fails partway picks up where it stopped.
+            for (i, embedding) in missing.into_iter().zip(embedded) {
+                if let Some(path) = &paths[i]
+                    && let Err(e) = write(path, &serde_json::to_vec(&embedding.vec)?)
+                {
+                    warn!("Failed to cache embedding {:?}: {}", path, e);
+                }
+                vectors[i] = Some(embedding.vec);
+            }
+        }
+
+        texts
+            .into_iter()
+            .zip(vectors)
+            .map(|(document, vec)| {
+                let vec = vec.ok_or_else(|| {
+                    EmbeddingError::ResponseError("Fewer embeddings than texts".to_string())
+                })?;
+                Ok(Embedding { document, vec })
+            })
+            .collect()
+    }
+}
+
+/// Writes beside `path` and renames over it, so a run killed mid-write
+/// never leaves half a vector behind.
+fn write(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let partial = path.with_extension("json.part");
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, path)
+}
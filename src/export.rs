@@ -0,0 +1,166 @@
+//! Writing the embedded chunks out for `--export-embeddings`, to analyze
+//! them elsewhere or load them into another vector database, and reading
+//! them back, or vectors made by a batch job of one's own, for
+//! `--import-embeddings`.
+
+use crate::chunking::Chunk;
+use crate::watch::EmbeddedChunk;
+use anyhow::{Context, Result, bail};
+use rig::OneOrMany;
+use rig::embeddings::Embedding;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Serialize)]
+struct ExportedChunk<'a> {
+    #[serde(flatten)]
+    chunk: &'a Chunk,
+    /// The model the vectors came from, so they aren't imported for
+    /// another's questions to be matched against.
+    embedding_model: &'a str,
+    embeddings: Vec<ExportedVector<'a>>,
+}
+
+/// One vector, with the text it was embedded from: the chunk's text or,
+/// with `--summaries`, its summary.
+#[derive(Serialize)]
+struct ExportedVector<'a> {
+    text: &'a str,
+    vector: &'a [f64],
+}
+
+/// A line of an imported file: a chunk as exported, with its vectors
+/// under `embeddings`, or just its `text` and `vector`. The model is only
+/// known for files that name it.
+#[derive(Deserialize)]
+struct ImportedChunk {
+    #[serde(flatten)]
+    chunk: Chunk,
+    embedding_model: Option<String>,
+    #[serde(default)]
+    embeddings: Vec<ImportedVector>,
+    vector: Option<Vec<f64>>,
+}
+
+#[derive(Deserialize)]
+struct ImportedVector {
+    text: Option<String>,
+    vector: Vec<f64>,
+}
+
+/// Checks up front that `path`, given to `flag`, is in a format that can
+/// be read and written, so a long run doesn't end in an error. Only JSON
+/// Lines is.
+pub fn check_path(flag: &str, path: &Path) -> Result<()> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("jsonl" | "ndjson") => Ok(()),
+        Some("parquet") => bail!(
+            "Parquet isn't supported by {}; use JSON Lines (.jsonl) instead",
+            flag
+        ),
+        _ => bail!(
+            "{} takes JSON Lines, so {:?} should end in .jsonl",
+            flag,
+            path
+        ),
+    }
+}
+
+/// Writes one JSON object per chunk to `path`: its text, metadata and
+/// position as the index keeps them, the `embedding_model` its vectors
+/// came from, and the vectors under `embeddings`.
+pub fn export_embeddings(
+    path: &Path,
+    embedding_model: &str,
+    chunks: &[EmbeddedChunk],
+) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create export file: {:?}", path))?;
+    let mut out = BufWriter::new(file);
+    for (chunk, embeddings) in chunks {
+        let exported = ExportedChunk {
+            chunk,
+            embedding_model,
+            embeddings: embeddings
+                .iter()
+                .map(|embedding| ExportedVector {
+                    text: &embedding.document,
+                    vector: &embedding.vec,
+                })
+                .collect(),
+        };
+        serde_json::to_writer(&mut out, &exported)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+        .with_context(|| format!("Failed to write export file: {:?}", path))?;
+    Ok(())
+}
+
+/// Reads chunks and their vectors from a JSON Lines file like the ones
+/// [`export_embeddings`] writes. Questions embedded by `embedding_model`
+/// only mean anything against its own vectors, so a line naming another
+/// model fails the import, as does a vector that isn't `ndims` long when
+/// the model says (`ndims` isn't zero).
+pub fn import_embeddings(
+    path: &Path,
+    embedding_model: &str,
+    ndims: usize,
+) -> Result<Vec<EmbeddedChunk>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut chunks = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {:?}", path))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let imported: ImportedChunk = serde_json::from_str(&line)
+            .with_context(|| format!("Line {} of {:?} isn't an embedded chunk", i + 1, path))?;
+        if let Some(model) = imported
+            .embedding_model
+            .as_deref()
+            .filter(|&model| model != embedding_model)
+        {
+            bail!(
+                "Embedding model mismatch: line {} of {:?} was embedded with {}, but questions are embedded with {}; pass --embedding-model {} (and the --embedding-provider it came from)",
+                i + 1,
+                path,
+                model,
+                embedding_model,
+                model
+            );
+        }
+        let text = imported.chunk.text.clone();
+        let embeddings = imported
+            .embeddings
+            .into_iter()
+            .map(|vector| Embedding {
+                document: vector.text.unwrap_or_else(|| text.clone()),
+                vec: vector.vector,
+            })
+            .chain(imported.vector.map(|vec| Embedding {
+                document: text.clone(),
+                vec,
+            }));
+        let Ok(embeddings) = OneOrMany::many(embeddings) else {
+            bail!("Line {} of {:?} has no vector", i + 1, path);
+        };
+        if let Some(embedding) = embeddings
+            .iter()
+            .find(|e| ndims != 0 && e.vec.len() != ndims)
+        {
+            bail!(
+                "Embedding dimension mismatch: line {} of {:?} has a {}-dimensional vector, but {} embeds questions into {}; pass the --embedding-model (and --embedding-dimensions) the file was embedded with",
+                i + 1,
+                path,
+                embedding.vec.len(),
+                embedding_model,
+                ndims
+            );
+        }
+        chunks.push((imported.chunk, embeddings));
+    }
+    Ok(chunks)
+}
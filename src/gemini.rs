@@ -0,0 +1,141 @@
+//! Embeddings from Google's Gemini API, for `--embedding-provider gemini`.
+
+use crate::retry::{HttpFailure, request_error};
+use anyhow::Context;
+use rig::embeddings::{Embedding, EmbeddingError};
+use serde::Deserialize;
+use serde_json::json;
+
+/// Model used when `--embedding-model` isn't given.
+pub const GEMINI_EMBEDDING_MODEL: &str = "text-embedding-004";
+
+const GEMINI_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// Most texts one `batchEmbedContents` request may hold.
+const MAX_TEXTS: usize = 100;
+
+/// Tokens one text may hold. Gemini counts them with its own tokenizer, so
+/// texts are kept to this share of the limit by OpenAI's count.
+const CONTEXT_LENGTH: usize = 2048;
+const TOKEN_HEADROOM: f64 = 0.8;
+
+/// Sizes of the vectors Gemini's models embed into, and for models not
+/// listed.
+const DIMENSIONS: &[(&str, usize)] = &[("gemini-embedding-001", 3072)];
+const DEFAULT_DIMENSIONS: usize = 768;
+
+/// A Gemini model, embedding either documents or queries: Gemini is told
+/// which, as its `taskType`.
+#[derive(Clone)]
+pub struct GeminiModel {
+    http: reqwest::Client,
+    url: String,
+    key: String,
+    model: String,
+    task_type: &'static str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    embeddings: Vec<ContentEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct ContentEmbedding {
+    values: Vec<f64>,
+}
+
+impl GeminiModel {
+    /// `model`, embedding documents, with the key in `GEMINI_API_KEY` (or
+    /// `GOOGLE_API_KEY`) and the API at `GEMINI_BASE_URL`, if set.
+    pub fn new(model: &str) -> anyhow::Result<Self> {
+        let key = std::env::var("GEMINI_API_KEY")
+            .or_else(|_| std::env::var("GOOGLE_API_KEY"))
+            .context(
+                "--embedding-provider gemini needs GEMINI_API_KEY or GOOGLE_API_KEY to be set",
+            )?;
+        let url = std::env::var("GEMINI_BASE_URL").unwrap_or_else(|_| GEMINI_URL.to_string());
+        Ok(Self {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            key,
+            model: model.trim_start_matches("models/").to_string(),
+            task_type: "RETRIEVAL_DOCUMENT",
+        })
+    }
+
+    /// This model as it should embed questions.
+    pub fn for_queries(&self) -> Self {
+        Self {
+            task_type: "RETRIEVAL_QUERY",
+            ..self.clone()
+        }
+    }
+
+    pub fn cache_key(&self) -> String {
+        format!("gemini {} {}", self.model, self.task_type)
+    }
+
+    pub fn ndims(&self) -> usize {
+        DIMENSIONS
+            .iter()
+            .find(|(model, _)| *model == self.model)
+            .map_or(DEFAULT_DIMENSIONS, |&(_, ndims)| ndims)
+    }
+
+    /// The most tokens, by OpenAI's count, that one text should hold.
+    pub fn max_tokens(&self) -> usize {
+        (CONTEXT_LENGTH as f64 * TOKEN_HEADROOM) as usize
+    }
+
+    /// Embeds `texts` in requests of as many as Gemini takes at a time.
+    pub async fn embed_texts(&self, texts: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(MAX_TEXTS) {
+            embeddings.extend(self.embed_batch(batch.to_vec()).await?);
+        }
+        Ok(embeddings)
+    }
+
+    async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        let model = format!("models/{}", self.model);
+        let requests: Vec<_> = texts
+            .iter()
+            .map(|text| {
+                json!({
+                    "model": model,
+                    "content": { "parts": [{ "text": text }] },
+                    "taskType": self.task_type,
+                })
+            })
+            .collect();
+        let response = self
+            .http
+            .post(format!("{}/{}:batchEmbedContents", self.url, model))
+            .header("x-goog-api-key", &self.key)
+            .json(&json!({ "requests": requests }))
+            .send()
+            .await
+            .map_err(request_error)?;
+        if !response.status().is_success() {
+            return Err(HttpFailure::read(response).await.into());
+        }
+        let body = response.text().await.map_err(request_error)?;
+        let embeddings = serde_json::from_str::<EmbeddingResponse>(&body)?.embeddings;
+        if embeddings.len() != texts.len() {
+            return Err(EmbeddingError::ResponseError(format!(
+                "Gemini returned {} embeddings for {} texts",
+                embeddings.len(),
+                texts.len()
+            )));
+        }
+        Ok(embeddings
+            .into_iter()
+            .zip(texts)
+            .map(|(embedding, document)| Embedding {
+                document,
+                vec: embedding.values,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,440 @@
+//! A hierarchical navigable small world graph (Malkov & Yashunin) over the
+//! in-memory index's vectors, for `--hnsw`: each question walks the graph
+//! from its sparse top layer down to the vectors nearest it, scoring a few
+//! hundred of them rather than every one.
+
+use crate::quantize::{Query, Vector};
+use std::borrow::Borrow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+/// How the graph is built and searched.
+#[derive(Debug, Clone, Copy)]
+pub struct HnswParams {
+    /// Links each vector keeps to its neighbours on each layer (twice as
+    /// many on the bottom one). More find closer vectors, at more memory
+    /// and a slower build.
+    pub m: usize,
+    /// Candidates weighed when linking a vector in. More build a better
+    /// graph, more slowly.
+    pub ef_construction: usize,
+    /// Candidates kept while searching, at least as many as are wanted.
+    /// More find closer vectors, more slowly.
+    pub ef_search: usize,
+}
+
+/// The graph, over vectors known by their place in the list it was built
+/// from, which must be given again to search it, unchanged but for
+/// vectors added at its end.
+pub struct Hnsw {
+    params: HnswParams,
+    /// Each vector's neighbours on each layer it is on, from the bottom.
+    links: Vec<Vec<Vec<usize>>>,
+    /// Where searches start: a vector on the top layer.
+    entry: Option<usize>,
+    /// Vectors taken out of searches' results. They stay in the graph, as
+    /// others are reached through them.
+    removed: Vec<bool>,
+    removed_count: usize,
+}
+
+/// A vector and its score, ordered by the score.
+#[derive(Clone, Copy)]
+struct Scored(f64, usize);
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl Hnsw {
+    /// Links `vectors` into a graph, comparing them by cosine similarity.
+    pub fn build<V: Borrow<Vector>>(vectors: &[V], params: HnswParams) -> Self {
+        let mut graph = Self {
+            params: HnswParams {
+                m: params.m.max(2),
+                ef_construction: params.ef_construction.max(params.m),
+                ..params
+            },
+            links: Vec::with_capacity(vectors.len()),
+            entry: None,
+            removed: Vec::new(),
+            removed_count: 0,
+        };
+        // Vectors are linked by their cosine similarity, the dot product of
+        // their unit-length copies, which are only kept while building.
+        let units: Vec<Vec<f32>> = vectors.iter().map(|vector| unit(vector.borrow())).collect();
+        let similarity = |a: usize, b: usize| dot(&units[a], &units[b]);
+        for node in 0..units.len() {
+            graph.insert(node, &|other| similarity(node, other), &similarity);
+        }
+        graph
+    }
+
+    /// Links in the vectors at the end of `vectors` that the graph doesn't
+    /// have yet, comparing them as they are kept (see
+    /// [`Vector::similarity`]).
+    pub fn add<V: Borrow<Vector>>(&mut self, vectors: &[V]) {
+        let similarity = |a: usize, b: usize| vectors[a].borrow().similarity(vectors[b].borrow());
+        for node in self.links.len()..vectors.len() {
+            self.insert(node, &|other| similarity(node, other), &similarity);
+        }
+    }
+
+    /// Leaves `node` out of searches' results from now on.
+    pub fn remove(&mut self, node: usize) {
+        if !std::mem::replace(&mut self.removed[node], true) {
+            self.removed_count += 1;
+        }
+    }
+
+    /// How many vectors the graph holds, removed ones included.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// How many of the vectors have been removed.
+    pub fn removed(&self) -> usize {
+        self.removed_count
+    }
+
+    /// The `wanted` vectors nearest `query` that the graph leads to, best
+    /// first, by their rough scores (see [`Vector::rough_score`]), leaving
+    /// out removed ones.
+    pub fn search<V: Borrow<Vector>>(
+        &self,
+        vectors: &[V],
+        query: &Query,
+        wanted: usize,
+    ) -> Vec<(f64, usize)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let score = |node: usize| vectors[node].borrow().rough_score(query);
+        let mut nearest = vec![Scored(score(entry), entry)];
+        for layer in (1..self.links[entry].len()).rev() {
+            nearest = self.search_layer(&score, nearest, 1, layer, &|_| true);
+        }
+        let ef = self.params.ef_search.max(wanted);
+        let mut found = self.search_layer(&score, nearest, ef, 0, &|node| !self.removed[node]);
+        found.truncate(wanted);
+        found
+            .into_iter()
+            .map(|Scored(score, node)| (score, node))
+            .collect()
+    }
+
+    /// Links `node` in, on as many layers as it is drawn for. `score` gives
+    /// another vector's similarity to it, and `similarity` any two's.
+    fn insert(
+        &mut self,
+        node: usize,
+        score: &dyn Fn(usize) -> f64,
+        similarity: &dyn Fn(usize, usize) -> f64,
+    ) {
+        let level = self.level(node);
+        self.links.push(vec![Vec::new(); level + 1]);
+        self.removed.push(false);
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+        let top = self.links[entry].len() - 1;
+        let mut nearest = vec![Scored(score(entry), entry)];
+        for layer in (level + 1..=top).rev() {
+            nearest = self.search_layer(score, nearest, 1, layer, &|_| true);
+        }
+        // Removed vectors are linked to like any other, so the graph keeps
+        // leading through them.
+        for layer in (0..=level.min(top)).rev() {
+            let ef = self.params.ef_construction;
+            nearest = self.search_layer(score, nearest, ef, layer, &|_| true);
+            let neighbours = self.select(&nearest, self.params.m, similarity);
+            let most = self.most_links(layer);
+            for &neighbour in &neighbours {
+                let links = &mut self.links[neighbour][layer];
+                links.push(node);
+                if links.len() > most {
+                    let mut scored: Vec<Scored> = links
+                        .iter()
+                        .map(|&other| Scored(similarity(neighbour, other), other))
+                        .collect();
+                    scored.sort_by(|a, b| b.cmp(a));
+                    self.links[neighbour][layer] = self.select(&scored, most, similarity);
+                }
+            }
+            self.links[node][layer] = neighbours;
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// The `ef` vectors nearest by `score` found walking `layer` from
+    /// `entries`, best first, of those `keep` accepts. The others are still
+    /// walked through.
+    fn search_layer(
+        &self,
+        score: &dyn Fn(usize) -> f64,
+        entries: Vec<Scored>,
+        ef: usize,
+        layer: usize,
+        keep: &dyn Fn(usize) -> bool,
+    ) -> Vec<Scored> {
+        let mut visited: HashSet<usize> = entries.iter().map(|entry| entry.1).collect();
+        let mut candidates: BinaryHeap<Scored> = entries.iter().copied().collect();
+        let mut found: BinaryHeap<Reverse<Scored>> = entries
+            .into_iter()
+            .filter(|entry| keep(entry.1))
+            .map(Reverse)
+            .collect();
+        while found.len() > ef {
+            found.pop();
+        }
+        while let Some(candidate) = candidates.pop() {
+            let worst = found.peek().map_or(f64::NEG_INFINITY, |worst| worst.0.0);
+            if candidate.0 < worst && found.len() >= ef {
+                break;
+            }
+            let Some(links) = self.links[candidate.1].get(layer) else {
+                continue;
+            };
+            for &neighbour in links {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let scored = Scored(score(neighbour), neighbour);
+                let worst = found.peek().map_or(f64::NEG_INFINITY, |worst| worst.0.0);
+                if found.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    if keep(neighbour) {
+                        found.push(Reverse(scored));
+                        if found.len() > ef {
+                            found.pop();
+                        }
+                    }
+                }
+            }
+        }
+        let mut found: Vec<Scored> = found.into_iter().map(|Reverse(scored)| scored).collect();
+        found.sort_by(|a, b| b.cmp(a));
+        found
+    }
+
+    /// Up to `m` of the `candidates` (best first) to link to, by the
+    /// paper's heuristic: a candidate nearer one already picked than to the
+    /// vector being linked is passed over, so links reach out in different
+    /// directions. Passed over candidates fill what is left.
+    fn select(
+        &self,
+        candidates: &[Scored],
+        m: usize,
+        similarity: &dyn Fn(usize, usize) -> f64,
+    ) -> Vec<usize> {
+        let mut picked: Vec<usize> = Vec::with_capacity(m);
+        let mut passed = Vec::new();
+        for &Scored(score, candidate) in candidates {
+            if picked.len() == m {
+                break;
+            }
+            if picked
+                .iter()
+                .all(|&other| similarity(other, candidate) < score)
+            {
+                picked.push(candidate);
+            } else {
+                passed.push(candidate);
+            }
+        }
+        let room = m - picked.len();
+        picked.extend(passed.into_iter().take(room));
+        picked
+    }
+
+    fn most_links(&self, layer: usize) -> usize {
+        match layer {
+            0 => self.params.m * 2,
+            _ => self.params.m,
+        }
+    }
+
+    /// The top layer `node` is on: 0 for most, each higher one for a `m`th
+    /// as many. Drawn from a hash of its place, so the same vectors always
+    /// make the same graph.
+    fn level(&self, node: usize) -> usize {
+        let mut x = (node as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^= x >> 31;
+        // In (0, 1], so its logarithm is finite.
+        let uniform = ((x >> 11) + 1) as f64 / (1u64 << 53) as f64;
+        (-uniform.ln() / (self.params.m as f64).ln()) as usize
+    }
+}
+
+/// `vector` scaled to length 1, or left at zero.
+fn unit(vector: &Vector) -> Vec<f32> {
+    let values = vector.values();
+    let norm = values.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let scale = if norm == 0.0 { 0.0 } else { 1.0 / norm };
+    values.iter().map(|x| (x * scale) as f32).collect()
+}
+
+/// Summed in eight lanes, which the compiler turns into vector
+/// instructions.
+fn dot(a: &[f32], b: &[f32]) -> f64 {
+    let mut lanes = [0.0f32; 8];
+    let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+    let rest: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for lane in 0..8 {
+            lanes[lane] += x[lane] * y[lane];
+        }
+    }
+    f64::from(lanes.iter().sum::<f32>() + rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::Quantization;
+
+    const PARAMS: HnswParams = HnswParams {
+        m: 16,
+        ef_construction: 100,
+        ef_search: 64,
+    };
+
+    /// `count` fixed vectors of 32 values between -1 and 1.
+    fn samples(count: usize, seed: u64) -> Vec<Vec<f64>> {
+        let mut x = seed;
+        (0..count)
+            .map(|_| {
+                (0..32)
+                    .map(|_| {
+                        x ^= x << 13;
+                        x ^= x >> 7;
+                        x ^= x << 17;
+                        (x % 2001) as f64 / 1000.0 - 1.0
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The share of the 10 vectors nearest each question that the graph
+    /// finds.
+    fn recall(quantization: Option<Quantization>) -> f64 {
+        let vectors: Vec<Vector> = samples(500, 1)
+            .into_iter()
+            .map(|vec| Vector::new(vec, quantization))
+            .collect();
+        let graph = Hnsw::build(&vectors, PARAMS);
+        let questions = samples(20, 2);
+        let mut found = 0;
+        for question in &questions {
+            let query = Query::new(question.clone());
+            let mut exact: Vec<(f64, usize)> = vectors
+                .iter()
+                .enumerate()
+                .map(|(node, vector)| (vector.rough_score(&query), node))
+                .collect();
+            exact.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let exact: HashSet<usize> = exact.iter().take(10).map(|&(_, node)| node).collect();
+            found += graph
+                .search(&vectors, &query, 10)
+                .iter()
+                .filter(|(_, node)| exact.contains(node))
+                .count();
+        }
+        found as f64 / (questions.len() * 10) as f64
+    }
+
+    #[test]
+    fn finds_the_nearest_full_vectors() {
+        assert!(recall(None) >= 0.95);
+    }
+
+    #[test]
+    fn finds_the_nearest_int8_vectors() {
+        assert!(recall(Some(Quantization::Int8)) >= 0.95);
+    }
+
+    #[test]
+    fn results_are_best_first() {
+        let vectors: Vec<Vector> = samples(200, 3)
+            .into_iter()
+            .map(|vec| Vector::new(vec, None))
+            .collect();
+        let graph = Hnsw::build(&vectors, PARAMS);
+        let query = Query::new(samples(1, 4).remove(0));
+        let found = graph.search(&vectors, &query, 10);
+        assert_eq!(found.len(), 10);
+        assert!(found.windows(2).all(|pair| pair[0].0 >= pair[1].0));
+    }
+
+    #[test]
+    fn an_empty_graph_finds_nothing() {
+        let graph = Hnsw::build::<Vector>(&[], PARAMS);
+        assert_eq!(graph.entry, None);
+        let query = Query::new(vec![1.0; 32]);
+        assert!(graph.search::<Vector>(&[], &query, 10).is_empty());
+    }
+
+    #[test]
+    fn a_single_vector_is_found() {
+        let vectors = vec![Vector::new(vec![1.0; 32], None)];
+        let graph = Hnsw::build(&vectors, PARAMS);
+        assert_eq!(graph.entry, Some(0));
+        let found = graph.search(&vectors, &Query::new(vec![1.0; 32]), 10);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1, 0);
+        assert!((found[0].0 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn added_vectors_are_found_and_removed_ones_are_not() {
+        let mut vectors: Vec<Vector> = samples(300, 5)
+            .into_iter()
+            .map(|vec| Vector::new(vec, None))
+            .collect();
+        let mut graph = Hnsw::build(&vectors, PARAMS);
+        let added = samples(50, 6);
+        vectors.extend(added.iter().map(|vec| Vector::new(vec.clone(), None)));
+        graph.add(&vectors);
+        assert_eq!(graph.len(), 350);
+        for (i, vec) in added.iter().enumerate() {
+            let found = graph.search(&vectors, &Query::new(vec.clone()), 1);
+            assert_eq!(found[0].1, 300 + i);
+        }
+        for node in 0..100 {
+            graph.remove(node);
+        }
+        assert_eq!(graph.removed(), 100);
+        for vec in samples(300, 5).iter().take(100) {
+            let found = graph.search(&vectors, &Query::new(vec.clone()), 10);
+            assert_eq!(found.len(), 10);
+            assert!(found.iter().all(|&(_, node)| node >= 100));
+        }
+    }
+}
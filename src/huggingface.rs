@@ -0,0 +1,115 @@
+//! Embeddings from the Hugging Face Inference API, or a Text Embeddings
+//! Inference (TEI) server, for `--embedding-provider huggingface`. Both take
+//! a list of texts and return one vector for each.
+
+use crate::retry::{HttpFailure, request_error};
+use anyhow::Context;
+use rig::embeddings::{Embedding, EmbeddingError};
+use serde_json::json;
+
+/// Model used when `--embedding-model` isn't given.
+pub const HUGGINGFACE_EMBEDDING_MODEL: &str = "sentence-transformers/all-MiniLM-L6-v2";
+
+const INFERENCE_URL: &str = "https://router.huggingface.co/hf-inference/models";
+
+/// Most texts one request holds; TEI refuses more than 32 by default.
+const MAX_TEXTS: usize = 32;
+
+/// A sentence-transformers model, served by Hugging Face or at a TEI
+/// endpoint, whose vectors are `ndims` long.
+#[derive(Clone)]
+pub struct HuggingFaceModel {
+    http: reqwest::Client,
+    /// Where texts are posted.
+    url: String,
+    key: Option<String>,
+    model: String,
+    ndims: usize,
+}
+
+impl HuggingFaceModel {
+    /// `model` on the Inference API, or whatever model the TEI server at
+    /// `endpoint` serves, with the token in `HF_TOKEN`, if set. One text is
+    /// embedded to learn the vector size, so a wrong name or URL fails up
+    /// front.
+    pub async fn new(model: &str, endpoint: Option<&str>) -> anyhow::Result<Self> {
+        let url = match endpoint {
+            Some(endpoint) => format!("{}/embed", endpoint.trim_end_matches('/')),
+            None => format!("{}/{}/pipeline/feature-extraction", INFERENCE_URL, model),
+        };
+        let mut model = Self {
+            http: reqwest::Client::new(),
+            url,
+            key: std::env::var("HF_TOKEN").ok(),
+            model: model.to_string(),
+            ndims: 0,
+        };
+        model.ndims = model
+            .embed_batch(vec!["dimensions".to_string()])
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to embed with {} at {}; is it a sentence-transformers model, and the server running?",
+                    model.model, model.url
+                )
+            })?
+            .first()
+            .map_or(0, |embedding| embedding.vec.len());
+        Ok(model)
+    }
+
+    pub fn cache_key(&self) -> String {
+        format!("huggingface {} {}", self.url, self.model)
+    }
+
+    pub fn ndims(&self) -> usize {
+        self.ndims
+    }
+
+    /// Embeds `texts` in requests of as many as a TEI server takes by
+    /// default.
+    pub async fn embed_texts(&self, texts: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(MAX_TEXTS) {
+            embeddings.extend(self.embed_batch(batch.to_vec()).await?);
+        }
+        Ok(embeddings)
+    }
+
+    async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        // Texts past the model's input limit are cut short rather than
+        // failing the request.
+        let mut request = self
+            .http
+            .post(&self.url)
+            .json(&json!({ "inputs": texts, "truncate": true }));
+        if let Some(key) = &self.key {
+            request = request.bearer_auth(key);
+        }
+        let response = request.send().await.map_err(request_error)?;
+        if !response.status().is_success() {
+            return Err(HttpFailure::read(response).await.into());
+        }
+        let body = response.text().await.map_err(request_error)?;
+        // Models that aren't sentence-transformers return a vector per
+        // token instead, which won't parse as one per text.
+        let vectors: Vec<Vec<f64>> = serde_json::from_str(&body).map_err(|_| {
+            EmbeddingError::ResponseError(format!(
+                "{} didn't return one vector per text; only sentence-transformers models can embed",
+                self.model
+            ))
+        })?;
+        if vectors.len() != texts.len() {
+            return Err(EmbeddingError::ResponseError(format!(
+                "Hugging Face returned {} embeddings for {} texts",
+                vectors.len(),
+                texts.len()
+            )));
+        }
+        Ok(vectors
+            .into_iter()
+            .zip(texts)
+            .map(|(vec, document)| Embedding { document, vec })
+            .collect())
+    }
+}
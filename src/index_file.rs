@@ -0,0 +1,110 @@
+//! The index kept at `--index-path`: every chunk with its vectors, written
+//! once ingesting is done and read back on later runs in place of
+//! extracting, chunking and embedding the documents again.
+
+use crate::chunking::Chunk;
+use crate::watch::EmbeddedChunk;
+use anyhow::{Context, Result, bail};
+use rig::OneOrMany;
+use rig::embeddings::Embedding;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// What is written: the model the vectors came from and their size, so
+/// they aren't searched with another's questions, and the chunks.
+#[derive(Serialize, Deserialize)]
+struct SavedIndex {
+    embedding_model: String,
+    dimensions: usize,
+    chunks: Vec<SavedChunk>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedChunk {
+    chunk: Chunk,
+    embeddings: Vec<SavedVector>,
+}
+
+/// One vector, with the text it was embedded from: the chunk's text or,
+/// with `--summaries`, its summary.
+#[derive(Serialize, Deserialize)]
+struct SavedVector {
+    text: String,
+    vector: Vec<f64>,
+}
+
+/// Writes `chunks`, embedded by `embedding_model` into `dimensions`, to
+/// `path`. The file is written beside it and renamed over it, so an
+/// interrupted run leaves the last complete index in place.
+pub fn save(
+    path: &Path,
+    embedding_model: &str,
+    dimensions: usize,
+    chunks: &[EmbeddedChunk],
+) -> Result<()> {
+    let saved = SavedIndex {
+        embedding_model: embedding_model.to_string(),
+        dimensions,
+        chunks: chunks
+            .iter()
+            .map(|(chunk, embeddings)| SavedChunk {
+                chunk: chunk.clone(),
+                embeddings: embeddings
+                    .iter()
+                    .map(|embedding| SavedVector {
+                        text: embedding.document.clone(),
+                        vector: embedding.vec.clone(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+    let partial = path.with_extension("part");
+    let json = serde_json::to_vec(&saved)?;
+    fs::write(&partial, json).with_context(|| format!("Failed to write index: {:?}", partial))?;
+    fs::rename(&partial, path).with_context(|| format!("Failed to write index: {:?}", path))?;
+    Ok(())
+}
+
+/// Reads the chunks saved at `path`. They can only be searched with
+/// questions embedded by the model they were, so an index made with a
+/// model other than `embedding_model`, or with vectors other than
+/// `dimensions` long (when that is known, not zero), isn't loaded.
+pub fn load(path: &Path, embedding_model: &str, dimensions: usize) -> Result<Vec<EmbeddedChunk>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read index: {:?}", path))?;
+    let saved: SavedIndex = serde_json::from_slice(&bytes)
+        .with_context(|| format!("Failed to parse index: {:?}", path))?;
+    if saved.embedding_model != embedding_model {
+        bail!(
+            "Embedding model mismatch: the index at {:?} was embedded with {}, but questions are embedded with {}; pass --embedding-model {}, or --reindex to build it again with {}",
+            path,
+            saved.embedding_model,
+            embedding_model,
+            saved.embedding_model,
+            embedding_model
+        );
+    }
+    if dimensions != 0 && saved.dimensions != dimensions {
+        bail!(
+            "Embedding dimension mismatch: the index at {:?} holds {}-dimensional vectors, but {} embeds questions into {}; pass the --embedding-dimensions it was built with, or --reindex to build it again",
+            path,
+            saved.dimensions,
+            embedding_model,
+            dimensions
+        );
+    }
+    saved
+        .chunks
+        .into_iter()
+        .map(|saved| {
+            let embeddings = saved.embeddings.into_iter().map(|vector| Embedding {
+                document: vector.text,
+                vec: vector.vector,
+            });
+            let embeddings = OneOrMany::many(embeddings)
+                .with_context(|| format!("The index at {:?} has a chunk with no vector", path))?;
+            Ok((saved.chunk, embeddings))
+        })
+        .collect()
+}
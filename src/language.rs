@@ -0,0 +1,159 @@
+use crate::chunking::Chunk;
+use crate::loaders::Document;
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use tracing::{debug, info};
+use whatlang::{Detector, Lang};
+
+/// Detection looks at the start of a document; more text rarely changes
+/// the answer but costs time on long documents.
+const SAMPLE_CHARS: usize = 10_000;
+
+/// Documents shorter than this are too short to tell languages apart.
+const MIN_CHARS: usize = 40;
+
+/// Questions are short, so their language is guessed among the languages
+/// of the index alone, and trusted at a lower confidence than documents.
+const MIN_QUESTION_CONFIDENCE: f64 = 0.5;
+
+/// Tags each document with the language its text is written in, as an
+/// ISO 639-3 code under `lang` (`eng`, `deu`, ...). Source code, which
+/// already carries its programming language, and documents too short or
+/// too mixed for a reliable guess are left untagged.
+pub fn detect_languages(documents: &mut [Document]) {
+    let mut counts: HashMap<Lang, usize> = HashMap::new();
+    for document in documents.iter_mut() {
+        if document.metadata.contains_key("language") {
+            continue;
+        }
+        let sample: String = document.text.chars().take(SAMPLE_CHARS).collect();
+        if sample.trim().chars().count() < MIN_CHARS {
+            continue;
+        }
+        match whatlang::detect(&sample) {
+            Some(info) if info.is_reliable() => {
+                *counts.entry(info.lang()).or_default() += 1;
+                document
+                    .metadata
+                    .insert("lang".to_string(), info.lang().code().to_string());
+            }
+            guess => debug!(
+                "No reliable language for a document (best guess {:?})",
+                guess
+            ),
+        }
+    }
+    if !counts.is_empty() {
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        let summary: Vec<String> = counts
+            .iter()
+            .map(|(lang, count)| format!("{} ({})", lang.eng_name(), count))
+            .collect();
+        info!("Detected document languages: {}", summary.join(", "));
+    }
+}
+
+/// The language most of the loaded text is in, by length, when that is
+/// more than half of it.
+pub fn dominant_language(documents: &[Document]) -> Option<Lang> {
+    let mut lengths: HashMap<&str, usize> = HashMap::new();
+    let mut total = 0;
+    for document in documents {
+        total += document.text.len();
+        if let Some(code) = document.metadata.get("lang") {
+            *lengths.entry(code).or_default() += document.text.len();
+        }
+    }
+    let (code, length) = lengths.into_iter().max_by_key(|&(_, length)| length)?;
+    (length * 2 > total)
+        .then(|| Lang::from_code(code))
+        .flatten()
+}
+
+/// Tags each chunk with the language of its own text where that can be told
+/// reliably, so the parts of a bilingual document are told apart; other
+/// chunks keep their document's `lang`.
+pub fn detect_chunk_languages(chunks: &mut [Chunk]) {
+    let mut retagged = 0;
+    for chunk in chunks.iter_mut() {
+        if chunk.metadata.contains_key("language") || chunk.text.trim().chars().count() < MIN_CHARS
+        {
+            continue;
+        }
+        let Some(info) = whatlang::detect(&chunk.text).filter(|info| info.is_reliable()) else {
+            continue;
+        };
+        let code = info.lang().code();
+        if chunk.metadata.get("lang").is_some_and(|lang| lang != code) {
+            retagged += 1;
+        }
+        chunk.metadata.insert("lang".to_string(), code.to_string());
+    }
+    if retagged > 0 {
+        info!(
+            "Tagged {} chunks with a language other than their document's",
+            retagged
+        );
+    }
+}
+
+/// Which chunks retrieval may return, by their `lang`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LanguageRoute {
+    /// Chunks in any language.
+    #[default]
+    Any,
+    /// Only chunks in these languages, as ISO 639-3 codes.
+    Only(Vec<String>),
+    /// Only chunks in the language each question is asked in, when that
+    /// can be told.
+    Question,
+}
+
+impl std::str::FromStr for LanguageRoute {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim() == "auto" {
+            return Ok(LanguageRoute::Question);
+        }
+        let mut codes = Vec::new();
+        for code in s.split(',').map(str::trim).filter(|code| !code.is_empty()) {
+            match Lang::from_code(code) {
+                Some(lang) => codes.push(lang.code().to_string()),
+                None => bail!(
+                    "unknown language code {:?}, expected one like eng or deu",
+                    code
+                ),
+            }
+        }
+        if codes.is_empty() {
+            bail!("expected language codes like eng,deu or auto");
+        }
+        Ok(LanguageRoute::Only(codes))
+    }
+}
+
+impl LanguageRoute {
+    /// The languages chunks retrieved for `question` may be in, out of the
+    /// `indexed` ones, or `None` for any.
+    pub fn languages(&self, question: &str, indexed: &[Lang]) -> Option<Vec<String>> {
+        match self {
+            LanguageRoute::Any => None,
+            LanguageRoute::Only(codes) => Some(codes.clone()),
+            // With a single language indexed there is nothing to choose.
+            LanguageRoute::Question if indexed.len() < 2 => None,
+            LanguageRoute::Question => {
+                let info = Detector::with_allowlist(indexed.to_vec()).detect(question)?;
+                debug!(
+                    "Question is in {} ({:.2} confidence)",
+                    info.lang().eng_name(),
+                    info.confidence()
+                );
+                (info.confidence() >= MIN_QUESTION_CONFIDENCE)
+                    .then(|| vec![info.lang().code().to_string()])
+            }
+        }
+    }
+}
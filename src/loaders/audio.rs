@@ -0,0 +1,35 @@
+use super::{Document, DocumentAudio, LoadOptions, Loader};
+use anyhow::Result;
+use std::path::Path;
+
+pub struct AudioLoader;
+
+impl Loader for AudioLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &[
+            "mp3", "wav", "m4a", "ogg", "oga", "opus", "flac", "aac", "mp4", "m4v", "mov", "mkv",
+            "webm", "avi",
+        ]
+    }
+
+    /// Returns an empty document carrying the recording; it is replaced by
+    /// its transcript before chunking.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "audio".to_string());
+        Ok(vec![Document {
+            audio: Some(DocumentAudio {
+                filename,
+                data: bytes.to_vec(),
+            }),
+            ..Document::default()
+        }])
+    }
+}
@@ -0,0 +1,109 @@
+use super::{Document, LoadOptions, PathFilter, find_loader};
+use anyhow::{Context, Result, bail};
+use futures::TryStreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, ObjectStoreExt};
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+/// Lists every object under an `s3://bucket/prefix` or `gs://bucket/prefix`
+/// URL and loads the supported ones, each tagged with its URL. Credentials
+/// come from the provider's usual environment variables, web identity or
+/// instance metadata.
+pub async fn load_bucket(
+    url: &str,
+    include: &[String],
+    exclude: &[String],
+    options: &LoadOptions,
+) -> Result<Vec<Document>> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .with_context(|| format!("Invalid bucket URL: {}", url))?;
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    let store: Box<dyn ObjectStore> = match scheme {
+        "s3" => Box::new(
+            AmazonS3Builder::from_env()
+                .with_url(url)
+                .build()
+                .with_context(|| format!("Failed to configure S3 access for {}", url))?,
+        ),
+        "gs" => Box::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_url(url)
+                .build()
+                .with_context(|| format!("Failed to configure GCS access for {}", url))?,
+        ),
+        _ => bail!("Unsupported bucket URL {} (expected s3:// or gs://)", url),
+    };
+    let filter = PathFilter::new(include, exclude)?;
+
+    let prefix = ObjectPath::from(prefix.trim_end_matches('/'));
+    let objects: Vec<_> = store
+        .list(Some(&prefix))
+        .try_collect()
+        .await
+        .with_context(|| format!("Failed to list {}", url))?;
+    info!("Found {} objects under {}", objects.len(), url);
+
+    let mut documents = Vec::new();
+    let (mut loaded, mut unsupported, mut excluded, mut failed) = (0, 0, 0, 0);
+    for object in objects {
+        let key = object.location.to_string();
+        let relative = key
+            .strip_prefix(prefix.as_ref())
+            .unwrap_or(&key)
+            .trim_start_matches('/');
+        if !filter.matches(Path::new(relative)) {
+            debug!("Excluded {}", key);
+            excluded += 1;
+            continue;
+        }
+        let Some(loader) = find_loader(Path::new(&key)) else {
+            debug!("Skipping unsupported object {}", key);
+            unsupported += 1;
+            continue;
+        };
+        if let Some(limit) = options.max_download_bytes
+            && object.size > limit
+        {
+            warn!(
+                "Skipping {}: {} MB is over the download limit",
+                key,
+                object.size / (1024 * 1024)
+            );
+            failed += 1;
+            continue;
+        }
+
+        let source = format!("{}://{}/{}", scheme, bucket, key);
+        info!("Loading {}", source);
+        let result = match store.get(&object.location).await {
+            Ok(object) => object.bytes().await.map_err(Into::into),
+            Err(e) => Err(anyhow::Error::from(e)),
+        }
+        .with_context(|| format!("Failed to download {}", source))
+        .and_then(|bytes| loader.load_bytes(Path::new(&key), &bytes, options));
+        match result {
+            Ok(object_documents) => {
+                loaded += 1;
+                documents.extend(
+                    object_documents
+                        .into_iter()
+                        .map(|document| document.with_metadata("source", source.clone())),
+                );
+            }
+            Err(e) => {
+                warn!("Skipping {}: {:#}", source, e);
+                failed += 1;
+            }
+        }
+    }
+
+    info!(
+        "Loaded {} objects from {} (skipped {} unsupported, {} excluded, {} failed)",
+        loaded, url, unsupported, excluded, failed
+    );
+    Ok(documents)
+}
@@ -0,0 +1,384 @@
+use super::{Document, LoadOptions, Loader, extension_of};
+use anyhow::{Context, Result};
+use std::path::Path;
+use tree_sitter::{Language, Node, Parser};
+
+/// Definitions longer than this are split into their members (a class into
+/// its methods, an `impl` block into its functions) so each can be retrieved
+/// on its own.
+const MAX_SEGMENT_LINES: usize = 60;
+
+/// Neighbouring segments are merged while they fit in this many lines, so a
+/// run of one-line declarations does not become a run of tiny documents.
+const MIN_SEGMENT_LINES: usize = 10;
+
+/// How a language's syntax tree maps onto retrievable pieces.
+struct Grammar {
+    name: &'static str,
+    language: Language,
+    /// Node kinds that become a segment of their own: functions, classes,
+    /// methods and other definitions.
+    definitions: &'static [&'static str],
+    /// Definitions whose members are split out when they run long.
+    containers: &'static [&'static str],
+    /// Node kinds that belong to the definition directly below them, like
+    /// doc comments, attributes and decorators.
+    attached: &'static [&'static str],
+}
+
+fn grammar(extension: &str) -> Option<Grammar> {
+    const JS_DEFINITIONS: &[&str] = &[
+        "function_declaration",
+        "generator_function_declaration",
+        "class_declaration",
+        "abstract_class_declaration",
+        "interface_declaration",
+        "type_alias_declaration",
+        "enum_declaration",
+        "internal_module",
+        "export_statement",
+        "lexical_declaration",
+        "method_definition",
+        "abstract_method_signature",
+    ];
+    const JS_CONTAINERS: &[&str] = &[
+        "class_declaration",
+        "abstract_class_declaration",
+        "internal_module",
+    ];
+    const C_DEFINITIONS: &[&str] = &[
+        "function_definition",
+        "type_definition",
+        "struct_specifier",
+        "enum_specifier",
+        "union_specifier",
+        "class_specifier",
+        "namespace_definition",
+        "template_declaration",
+        "preproc_function_def",
+    ];
+
+    Some(match extension {
+        "rs" => Grammar {
+            name: "rust",
+            language: tree_sitter_rust::LANGUAGE.into(),
+            definitions: &[
+                "function_item",
+                "impl_item",
+                "struct_item",
+                "enum_item",
+                "union_item",
+                "trait_item",
+                "mod_item",
+                "macro_definition",
+                "const_item",
+                "static_item",
+                "type_item",
+            ],
+            containers: &["impl_item", "trait_item", "mod_item"],
+            attached: &["line_comment", "block_comment", "attribute_item"],
+        },
+        "py" | "pyi" => Grammar {
+            name: "python",
+            language: tree_sitter_python::LANGUAGE.into(),
+            definitions: &[
+                "function_definition",
+                "class_definition",
+                "decorated_definition",
+            ],
+            containers: &["class_definition"],
+            attached: &["comment"],
+        },
+        "js" | "mjs" | "cjs" | "jsx" => Grammar {
+            name: "javascript",
+            language: tree_sitter_javascript::LANGUAGE.into(),
+            definitions: JS_DEFINITIONS,
+            containers: JS_CONTAINERS,
+            attached: &["comment", "decorator"],
+        },
+        "ts" | "mts" | "cts" => Grammar {
+            name: "typescript",
+            language: tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            definitions: JS_DEFINITIONS,
+            containers: JS_CONTAINERS,
+            attached: &["comment", "decorator"],
+        },
+        "tsx" => Grammar {
+            name: "typescript",
+            language: tree_sitter_typescript::LANGUAGE_TSX.into(),
+            definitions: JS_DEFINITIONS,
+            containers: JS_CONTAINERS,
+            attached: &["comment", "decorator"],
+        },
+        "go" => Grammar {
+            name: "go",
+            language: tree_sitter_go::LANGUAGE.into(),
+            definitions: &[
+                "function_declaration",
+                "method_declaration",
+                "type_declaration",
+            ],
+            containers: &[],
+            attached: &["comment"],
+        },
+        "java" => Grammar {
+            name: "java",
+            language: tree_sitter_java::LANGUAGE.into(),
+            definitions: &[
+                "class_declaration",
+                "interface_declaration",
+                "enum_declaration",
+                "record_declaration",
+                "annotation_type_declaration",
+                "method_declaration",
+                "constructor_declaration",
+            ],
+            containers: &[
+                "class_declaration",
+                "interface_declaration",
+                "enum_declaration",
+                "record_declaration",
+            ],
+            attached: &["line_comment", "block_comment"],
+        },
+        "c" | "h" => Grammar {
+            name: "c",
+            language: tree_sitter_c::LANGUAGE.into(),
+            definitions: C_DEFINITIONS,
+            containers: &[],
+            attached: &["comment"],
+        },
+        "cpp" | "cc" | "cxx" | "hpp" | "hh" | "hxx" => Grammar {
+            name: "cpp",
+            language: tree_sitter_cpp::LANGUAGE.into(),
+            definitions: C_DEFINITIONS,
+            containers: &[
+                "class_specifier",
+                "struct_specifier",
+                "namespace_definition",
+            ],
+            attached: &["comment"],
+        },
+        _ => return None,
+    })
+}
+
+pub struct CodeLoader;
+
+impl Loader for CodeLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &[
+            "rs", "py", "pyi", "js", "mjs", "cjs", "jsx", "ts", "mts", "cts", "tsx", "go", "java",
+            "c", "h", "cpp", "cc", "cxx", "hpp", "hh", "hxx",
+        ]
+    }
+
+    /// Returns one document per top-level definition (or per member of a
+    /// long one), tagged with the language, line range and symbol name.
+    /// Code between definitions, like imports, is kept as its own document.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let source = std::str::from_utf8(bytes)
+            .with_context(|| format!("Source file is not valid UTF-8: {:?}", path))?;
+        let grammar = grammar(&extension_of(path))
+            .with_context(|| format!("Unsupported source file: {:?}", path))?;
+        let mut parser = Parser::new();
+        parser
+            .set_language(&grammar.language)
+            .with_context(|| format!("Failed to load the {} grammar", grammar.name))?;
+        let tree = parser
+            .parse(source, None)
+            .with_context(|| format!("Failed to parse source: {:?}", path))?;
+
+        let lines: Vec<&str> = source.lines().collect();
+        let mut segments = Vec::new();
+        split(
+            &grammar,
+            tree.root_node(),
+            source,
+            None,
+            None,
+            &mut segments,
+        );
+
+        Ok(merge_small(segments)
+            .into_iter()
+            .filter_map(|segment| {
+                let end = segment.end_row.min(lines.len().saturating_sub(1));
+                let text = lines.get(segment.start_row..=end)?.join("\n");
+                if text.trim().is_empty() {
+                    return None;
+                }
+                let document = Document::new(text)
+                    .with_metadata("language", grammar.name)
+                    .with_metadata("lines", format!("{}-{}", segment.start_row + 1, end + 1));
+                Some(match segment.symbol {
+                    Some(symbol) => document.with_metadata("symbol", symbol),
+                    None => document,
+                })
+            })
+            .collect())
+    }
+}
+
+/// A range of source lines (0-based, inclusive) that becomes one document.
+struct Segment {
+    start_row: usize,
+    end_row: usize,
+    symbol: Option<String>,
+}
+
+/// Splits the named children of `parent` into segments: one per definition,
+/// with the comments and attributes directly above it, and one per run of
+/// anything else. `header` is the first row of the enclosing definition when
+/// its members are being split out; the lines above its first member
+/// (signature, docs, fields) stay together as a header segment.
+fn split(
+    grammar: &Grammar,
+    parent: Node,
+    source: &str,
+    scope: Option<&str>,
+    mut header: Option<usize>,
+    segments: &mut Vec<Segment>,
+) {
+    let mut cursor = parent.walk();
+    let children: Vec<Node> = parent.named_children(&mut cursor).collect();
+    // Nodes seen since the last definition.
+    let mut pending: Vec<Node> = Vec::new();
+
+    for child in children {
+        if !grammar.definitions.contains(&child.kind()) {
+            pending.push(child);
+            continue;
+        }
+
+        // Comments and attributes touching the definition belong to it.
+        let mut start_row = child.start_position().row;
+        let mut attached = pending.len();
+        while attached > 0 {
+            let node = pending[attached - 1];
+            if !grammar.attached.contains(&node.kind()) || node.end_position().row + 1 < start_row {
+                break;
+            }
+            start_row = node.start_position().row;
+            attached -= 1;
+        }
+        let before = &pending[..attached];
+        match (header.take(), before.last()) {
+            (Some(header_row), last) => segments.push(Segment {
+                start_row: header_row,
+                end_row: last.map_or(start_row.saturating_sub(1).max(header_row), |last| {
+                    last.end_position().row
+                }),
+                symbol: scope.map(str::to_string),
+            }),
+            (None, Some(last)) => segments.push(Segment {
+                start_row: before[0].start_position().row,
+                end_row: last.end_position().row,
+                symbol: scope.map(str::to_string),
+            }),
+            (None, None) => {}
+        }
+        pending.clear();
+
+        let name = symbol(child, source, 0);
+        let symbol = match (scope, &name) {
+            (Some(scope), Some(name)) => Some(format!("{}.{}", scope, name)),
+            (scope, name) => name.clone().or(scope.map(str::to_string)),
+        };
+        let end_row = child.end_position().row;
+
+        if end_row - start_row >= MAX_SEGMENT_LINES
+            && let Some(body) = body(grammar, child)
+        {
+            split(
+                grammar,
+                body,
+                source,
+                symbol.as_deref(),
+                Some(start_row),
+                segments,
+            );
+            continue;
+        }
+        segments.push(Segment {
+            start_row,
+            end_row,
+            symbol,
+        });
+    }
+
+    let start_row = header.or(pending.first().map(|node| node.start_position().row));
+    let end_row = pending
+        .last()
+        .map_or(parent.end_position().row, |node| node.end_position().row);
+    if let Some(start_row) = start_row {
+        segments.push(Segment {
+            start_row,
+            end_row,
+            symbol: scope.map(str::to_string),
+        });
+    }
+}
+
+fn merge_small(segments: Vec<Segment>) -> Vec<Segment> {
+    let mut merged: Vec<Segment> = Vec::new();
+    for segment in segments {
+        if let Some(last) = merged.last_mut()
+            && segment.start_row <= last.end_row + 2
+            && segment.end_row + 1 - last.start_row <= MIN_SEGMENT_LINES
+        {
+            last.end_row = segment.end_row;
+            last.symbol = match (last.symbol.take(), segment.symbol) {
+                (Some(a), Some(b)) if a != b => Some(format!("{}, {}", a, b)),
+                (a, b) => a.or(b),
+            };
+            continue;
+        }
+        merged.push(segment);
+    }
+    merged
+}
+
+/// The block holding a container's members, looking through wrappers like
+/// decorators and `export`. `None` for functions and other definitions whose
+/// bodies are not split.
+fn body<'tree>(grammar: &Grammar, node: Node<'tree>) -> Option<Node<'tree>> {
+    if grammar.containers.contains(&node.kind()) {
+        return node.child_by_field_name("body");
+    }
+    ["definition", "declaration"]
+        .iter()
+        .find_map(|field| node.child_by_field_name(field))
+        .and_then(|inner| body(grammar, inner))
+}
+
+/// The name a definition introduces, looking through wrappers (decorators,
+/// `export`, declarators) to the identifier. `impl` blocks are named after
+/// their type.
+fn symbol(node: Node, source: &str, depth: usize) -> Option<String> {
+    if depth > 4 {
+        return None;
+    }
+    if node.kind().ends_with("identifier") {
+        return node.utf8_text(source.as_bytes()).ok().map(str::to_string);
+    }
+    if let Some(name) = node.child_by_field_name("name") {
+        return name.utf8_text(source.as_bytes()).ok().map(str::to_string);
+    }
+    for field in ["definition", "declaration", "declarator", "type"] {
+        if let Some(child) = node.child_by_field_name(field) {
+            return symbol(child, source, depth + 1);
+        }
+    }
+    // `const f = () => ...` and Go's `type T struct` name their child.
+    let mut cursor = node.walk();
+    let child = node
+        .named_children(&mut cursor)
+        .find(|child| matches!(child.kind(), "variable_declarator" | "type_spec"));
+    child.and_then(|child| symbol(child, source, depth + 1))
+}
@@ -0,0 +1,182 @@
+use super::{Document, html};
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+/// Pages requested at a time; Confluence may return fewer.
+const PAGE_SIZE: usize = 50;
+
+#[derive(Debug, Deserialize)]
+struct Content {
+    title: String,
+    body: Body,
+    #[serde(rename = "_links")]
+    links: Links,
+}
+
+#[derive(Debug, Deserialize)]
+struct Body {
+    storage: Storage,
+}
+
+#[derive(Debug, Deserialize)]
+struct Storage {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Links {
+    webui: Option<String>,
+}
+
+/// Loads every current page in a Confluence space, given its URL
+/// (`https://example.atlassian.net/wiki/spaces/ENG`, or `.../display/ENG` on
+/// Data Center). Each page becomes a document tagged with its title and link.
+///
+/// Requests are authenticated with `CONFLUENCE_TOKEN`: an API token, sent
+/// with `CONFLUENCE_EMAIL` as basic auth on Confluence Cloud, or a personal
+/// access token sent as a bearer token when no email is set.
+pub async fn load_confluence(space_url: &str) -> Result<Vec<Document>> {
+    let (base, space) = parse_space_url(space_url)?;
+    let token = std::env::var("CONFLUENCE_TOKEN")
+        .context("CONFLUENCE_TOKEN is not set; create an API token to load Confluence spaces")?;
+    let email = std::env::var("CONFLUENCE_EMAIL").ok();
+    let client = reqwest::Client::new();
+
+    let mut documents = Vec::new();
+    let mut start = 0;
+    loop {
+        let request = client
+            .get(format!("{}/rest/api/content", base))
+            .query(&[
+                ("spaceKey", space),
+                ("type", "page"),
+                ("status", "current"),
+                ("expand", "body.storage"),
+            ])
+            .query(&[("start", start), ("limit", PAGE_SIZE)]);
+        let request = match &email {
+            Some(email) => request.basic_auth(email, Some(&token)),
+            None => request.bearer_auth(&token),
+        };
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to list pages in Confluence space {}", space))?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        if !status.is_success() {
+            bail!(
+                "Listing Confluence space {} returned HTTP {}: {}",
+                space,
+                status,
+                String::from_utf8_lossy(&body).trim()
+            );
+        }
+
+        #[derive(Deserialize)]
+        struct Page {
+            results: Vec<Content>,
+            size: usize,
+            #[serde(rename = "_links")]
+            links: PageLinks,
+        }
+        #[derive(Deserialize)]
+        struct PageLinks {
+            next: Option<String>,
+        }
+        let page: Page =
+            serde_json::from_slice(&body).context("Unexpected response from Confluence")?;
+        debug!(
+            "Fetched {} pages from {} at offset {}",
+            page.size, space, start
+        );
+
+        for content in page.results {
+            let text = storage_text(&content.body.storage.value);
+            if text.trim().is_empty() {
+                warn!("Skipping empty Confluence page {:?}", content.title);
+                continue;
+            }
+            let document = Document::new(text)
+                .with_metadata("source", format!("Confluence {}", space))
+                .with_metadata("title", content.title);
+            documents.push(match content.links.webui {
+                Some(webui) => document.with_metadata("url", format!("{}{}", base, webui)),
+                None => document,
+            });
+        }
+        if page.links.next.is_none() || page.size == 0 {
+            break;
+        }
+        start += page.size;
+    }
+
+    info!(
+        "Loaded {} pages from Confluence space {}",
+        documents.len(),
+        space
+    );
+    Ok(documents)
+}
+
+/// Splits a space URL into the site's base URL (up to `/wiki` on Cloud) and
+/// the space key.
+fn parse_space_url(url: &str) -> Result<(&str, &str)> {
+    let (base, rest) = url
+        .split_once("/spaces/")
+        .or_else(|| url.split_once("/display/"))
+        .with_context(|| {
+            format!(
+                "Not a Confluence space URL: {:?} (expected .../spaces/<KEY>)",
+                url
+            )
+        })?;
+    let space = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if space.is_empty() {
+        bail!("No space key in Confluence URL: {:?}", url);
+    }
+    Ok((base.trim_end_matches('/'), space))
+}
+
+/// Flattens a page's storage format to text. Storage format is XHTML with
+/// `ac:` macros: macro parameters (a code block's language, a panel's
+/// colour) are dropped, and the CDATA bodies of code and other plain-text
+/// macros, which an HTML parser would discard, are kept as text.
+fn storage_text(storage: &str) -> String {
+    let mut xhtml = String::with_capacity(storage.len());
+    let mut rest = storage;
+    while let Some(start) = rest.find("<![CDATA[") {
+        xhtml.push_str(&rest[..start]);
+        rest = &rest[start + "<![CDATA[".len()..];
+        let end = rest.find("]]>").unwrap_or(rest.len());
+        xhtml.push_str(
+            &rest[..end]
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+        );
+        rest = rest.get(end + "]]>".len()..).unwrap_or_default();
+    }
+    xhtml.push_str(rest);
+
+    let mut rest = xhtml.as_str();
+    let mut text = String::with_capacity(xhtml.len());
+    while let Some(start) = rest.find("<ac:parameter") {
+        text.push_str(&rest[..start]);
+        let tag_end = rest[start..]
+            .find('>')
+            .map_or(rest.len(), |end| start + end);
+        let end = if rest[..tag_end].ends_with('/') {
+            Some(tag_end + 1)
+        } else {
+            rest[tag_end..]
+                .find("</ac:parameter>")
+                .map(|end| tag_end + end + "</ac:parameter>".len())
+        };
+        rest = end.and_then(|end| rest.get(end..)).unwrap_or_default();
+    }
+    text.push_str(rest);
+
+    html::page_text(&text).1
+}
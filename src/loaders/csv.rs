@@ -0,0 +1,66 @@
+use super::{Document, LoadOptions, Loader, encoding, extension_of};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+pub struct CsvLoader;
+
+impl Loader for CsvLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["csv", "tsv"]
+    }
+
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let delimiter = if extension_of(path) == "tsv" {
+            b'\t'
+        } else {
+            b','
+        };
+        let text = encoding::decode(path, bytes, None);
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(text.as_bytes());
+
+        let rows = reader
+            .records()
+            .map(|record| Ok(record?.iter().map(str::to_string).collect()))
+            .collect::<Result<Vec<Vec<String>>>>()
+            .with_context(|| format!("Failed to parse CSV: {:?}", path))?;
+        Ok(vec![Document::new(rows_to_records(&rows))])
+    }
+}
+
+/// Renders a table as one line per row, with every value labelled by its
+/// column header (`Name: Ada; Born: 1815`), so any chunk cut from the text
+/// still says what its values mean. The first non-empty row is the header.
+pub fn rows_to_records(rows: &[Vec<String>]) -> String {
+    let is_empty = |row: &Vec<String>| row.iter().all(|cell| cell.trim().is_empty());
+    let mut rows = rows.iter().filter(|row| !is_empty(row));
+    let Some(headers) = rows.next() else {
+        return String::new();
+    };
+
+    let mut lines = Vec::new();
+    for (i, row) in rows.enumerate() {
+        let fields: Vec<String> = row
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !value.trim().is_empty())
+            .map(|(column, value)| {
+                let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+                match headers.get(column).map(|h| h.trim()) {
+                    Some(header) if !header.is_empty() => format!("{}: {}", header, value),
+                    _ => format!("Column {}: {}", column + 1, value),
+                }
+            })
+            .collect();
+        lines.push(format!("Row {}: {}", i + 1, fields.join("; ")));
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,91 @@
+use super::{Document, LoadOptions, Loader, xml};
+use anyhow::{Context, Result};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use std::path::Path;
+
+pub struct DocxLoader;
+
+impl Loader for DocxLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["docx"]
+    }
+
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let mut archive = xml::open_archive(path, bytes)?;
+        let document_xml = xml::read_entry(&mut archive, "word/document.xml")?;
+        let text = document_xml_to_text(&document_xml)
+            .with_context(|| format!("Failed to parse DOCX: {:?}", path))?;
+        Ok(vec![Document::new(text)])
+    }
+}
+
+/// Flattens WordprocessingML into text, one line per paragraph. Headings
+/// (paragraphs styled `Title` or `Heading N`, or given an outline level)
+/// become `#` lines, as in Markdown, so the document structure survives.
+fn document_xml_to_text(document_xml: &str) -> Result<String> {
+    let mut reader = Reader::from_str(document_xml);
+    let mut text = String::new();
+    let mut paragraph = String::new();
+    let mut heading: Option<usize> = None;
+    let mut in_text_run = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == "w:t" => in_text_run = true,
+            Event::End(e) => match e.name().as_ref() {
+                "w:t" => in_text_run = false,
+                "w:p" => {
+                    match heading.take() {
+                        Some(level) if !paragraph.trim().is_empty() => {
+                            text.push_str(&"#".repeat(level));
+                            text.push(' ');
+                            text.push_str(paragraph.trim());
+                        }
+                        _ => text.push_str(&paragraph),
+                    }
+                    paragraph.clear();
+                    text.push('\n');
+                }
+                _ => {}
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                "w:tab" => paragraph.push('\t'),
+                "w:br" | "w:cr" => paragraph.push('\n'),
+                "w:pStyle" => {
+                    heading = heading.or(xml::attr(&e, "w:val").and_then(|s| heading_level(&s)))
+                }
+                "w:outlineLvl" => {
+                    let level = xml::attr(&e, "w:val").and_then(|v| v.parse::<usize>().ok());
+                    // Level 9 is Word's "body text".
+                    if let Some(level) = level.filter(|&level| level < 9) {
+                        heading = Some(level + 1);
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(e) if in_text_run => paragraph.push_str(&e.xml10_content()),
+            Event::GeneralRef(e) if in_text_run => paragraph.push_str(&xml::resolve_ref(&e)?),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    text.push_str(&paragraph);
+
+    Ok(text)
+}
+
+/// The heading level of a built-in paragraph style: `HeadingN` is level N,
+/// and `Title` counts as level 1.
+fn heading_level(style: &str) -> Option<usize> {
+    if style == "Title" {
+        return Some(1);
+    }
+    let level: usize = style.strip_prefix("Heading")?.parse().ok()?;
+    (1..=6).contains(&level).then_some(level)
+}
@@ -0,0 +1,311 @@
+use super::{Document, LoadOptions, find_loader};
+use anyhow::{Context, Result, bail};
+use aws_lc_rs::rand::SystemRandom;
+use aws_lc_rs::signature::{RSA_PKCS1_SHA256, RsaKeyPair};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+const FILES_URL: &str = "https://www.googleapis.com/drive/v3/files";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/drive.readonly";
+const FOLDER_TYPE: &str = "application/vnd.google-apps.folder";
+
+/// Google Workspace files have no content of their own; they are exported in
+/// a format one of the loaders reads.
+const EXPORTS: &[(&str, &str, &str)] = &[
+    ("application/vnd.google-apps.document", "text/plain", "txt"),
+    (
+        "application/vnd.google-apps.presentation",
+        "text/plain",
+        "txt",
+    ),
+    ("application/vnd.google-apps.spreadsheet", "text/csv", "csv"),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct File {
+    id: String,
+    name: String,
+    mime_type: String,
+    size: Option<String>,
+    web_view_link: Option<String>,
+}
+
+/// Loads every supported file in a Drive folder and its subfolders: PDFs and
+/// other documents are downloaded, Google Docs and Slides are exported as text
+/// and Sheets as CSV. Each document is tagged with its path in the folder and
+/// its Drive link.
+pub async fn load_drive(
+    folder_id: &str,
+    credentials: Option<&Path>,
+    options: &LoadOptions,
+) -> Result<Vec<Document>> {
+    let token = access_token(credentials).await?;
+    let client = reqwest::Client::new();
+
+    let mut documents = Vec::new();
+    let (mut loaded, mut unsupported, mut failed) = (0, 0, 0);
+    let mut folders = vec![(folder_id.to_string(), String::new())];
+    while let Some((folder, prefix)) = folders.pop() {
+        for file in list_folder(&client, &token, &folder).await? {
+            let path = format!("{}{}", prefix, file.name);
+            if file.mime_type == FOLDER_TYPE {
+                folders.push((file.id, format!("{}/", path)));
+                continue;
+            }
+
+            let export = EXPORTS
+                .iter()
+                .find(|(mime_type, _, _)| *mime_type == file.mime_type);
+            let (request, name) = match export {
+                Some((_, export_type, extension)) => (
+                    client
+                        .get(format!("{}/{}/export", FILES_URL, file.id))
+                        .query(&[("mimeType", export_type)]),
+                    format!("{}.{}", file.name, extension),
+                ),
+                None => (
+                    client
+                        .get(format!("{}/{}", FILES_URL, file.id))
+                        .query(&[("alt", "media"), ("supportsAllDrives", "true")]),
+                    file.name.clone(),
+                ),
+            };
+            let Some(loader) = find_loader(Path::new(&name)) else {
+                debug!(
+                    "Skipping unsupported Drive file {} ({})",
+                    path, file.mime_type
+                );
+                unsupported += 1;
+                continue;
+            };
+            let size = file
+                .size
+                .as_deref()
+                .and_then(|size| size.parse::<u64>().ok());
+            if let (Some(limit), Some(size)) = (options.max_download_bytes, size)
+                && size > limit
+            {
+                warn!(
+                    "Skipping {}: {} MB is over the download limit",
+                    path,
+                    size / (1024 * 1024)
+                );
+                failed += 1;
+                continue;
+            }
+
+            info!("Loading {} from Drive", path);
+            let result = match send(request.bearer_auth(&token)).await {
+                Ok(bytes) => loader.load_bytes(Path::new(&name), &bytes, options),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(file_documents) => {
+                    loaded += 1;
+                    documents.extend(file_documents.into_iter().map(|document| {
+                        let document = document.with_metadata("source", path.clone());
+                        match &file.web_view_link {
+                            Some(link) => document.with_metadata("url", link.clone()),
+                            None => document,
+                        }
+                    }));
+                }
+                Err(e) => {
+                    warn!("Skipping {}: {:#}", path, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    info!(
+        "Loaded {} files from Drive folder {} (skipped {} unsupported, {} failed)",
+        loaded, folder_id, unsupported, failed
+    );
+    Ok(documents)
+}
+
+async fn list_folder(client: &reqwest::Client, token: &str, folder_id: &str) -> Result<Vec<File>> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Page {
+        files: Vec<File>,
+        next_page_token: Option<String>,
+    }
+
+    let query = format!(
+        "'{}' in parents and trashed = false",
+        folder_id.replace('\'', "\\'")
+    );
+    let mut files = Vec::new();
+    let mut page_token = None;
+    loop {
+        let mut request = client.get(FILES_URL).bearer_auth(token).query(&[
+            ("q", query.as_str()),
+            (
+                "fields",
+                "nextPageToken, files(id, name, mimeType, size, webViewLink)",
+            ),
+            ("pageSize", "1000"),
+            ("orderBy", "name"),
+            ("supportsAllDrives", "true"),
+            ("includeItemsFromAllDrives", "true"),
+        ]);
+        if let Some(page_token) = &page_token {
+            request = request.query(&[("pageToken", page_token)]);
+        }
+        let page: Page = parse(
+            &send(request)
+                .await
+                .with_context(|| format!("Failed to list Drive folder {}", folder_id))?,
+        )?;
+        files.extend(page.files);
+        match page.next_page_token {
+            Some(next) => page_token = Some(next),
+            None => return Ok(files),
+        }
+    }
+}
+
+/// Gets an access token for the Drive API: `GOOGLE_DRIVE_ACCESS_TOKEN` when
+/// set, and otherwise one issued for a credentials file. Both service account
+/// keys and authorized user credentials (as written by `gcloud auth
+/// application-default login`) are accepted; without `--drive-credentials`,
+/// `GOOGLE_APPLICATION_CREDENTIALS` and then gcloud's default location are
+/// tried.
+async fn access_token(credentials: Option<&Path>) -> Result<String> {
+    if let Ok(token) = std::env::var("GOOGLE_DRIVE_ACCESS_TOKEN") {
+        return Ok(token);
+    }
+    let path = match credentials {
+        Some(path) => path.to_path_buf(),
+        None => default_credentials()
+            .context("No Google credentials found; pass --drive-credentials or set GOOGLE_DRIVE_ACCESS_TOKEN")?,
+    };
+    debug!("Using Google credentials from {:?}", path);
+    let json =
+        std::fs::read(&path).with_context(|| format!("Failed to read credentials: {:?}", path))?;
+
+    #[derive(Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Credentials {
+        ServiceAccount {
+            client_email: String,
+            private_key: String,
+            token_uri: Option<String>,
+        },
+        AuthorizedUser {
+            client_id: String,
+            client_secret: String,
+            refresh_token: String,
+        },
+    }
+    #[derive(Deserialize)]
+    struct Token {
+        access_token: String,
+    }
+
+    let credentials: Credentials = serde_json::from_slice(&json).with_context(|| {
+        format!(
+            "Unsupported credentials in {:?} (expected a service account key or authorized user credentials)",
+            path
+        )
+    })?;
+    let client = reqwest::Client::new();
+    let request = match credentials {
+        Credentials::ServiceAccount {
+            client_email,
+            private_key,
+            token_uri,
+        } => {
+            let token_uri = token_uri.unwrap_or_else(|| TOKEN_URL.to_string());
+            let assertion = signed_jwt(&client_email, &private_key, &token_uri)?;
+            client.post(token_uri).form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+        }
+        Credentials::AuthorizedUser {
+            client_id,
+            client_secret,
+            refresh_token,
+        } => client.post(TOKEN_URL).form(&[
+            ("grant_type", "refresh_token"),
+            ("client_id", client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+            ("refresh_token", refresh_token.as_str()),
+        ]),
+    };
+    let token: Token = parse(
+        &send(request)
+            .await
+            .context("Failed to get a Google access token")?,
+    )?;
+    Ok(token.access_token)
+}
+
+fn default_credentials() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+        return Some(PathBuf::from(path));
+    }
+    let path = PathBuf::from(std::env::var_os("HOME")?)
+        .join(".config/gcloud/application_default_credentials.json");
+    path.exists().then_some(path)
+}
+
+/// Builds the RS256-signed JWT a service account trades for an access token.
+fn signed_jwt(client_email: &str, private_key: &str, audience: &str) -> Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+    let claims = URL_SAFE_NO_PAD.encode(
+        serde_json::json!({
+            "iss": client_email,
+            "scope": SCOPE,
+            "aud": audience,
+            "iat": now,
+            "exp": now + 3600,
+        })
+        .to_string(),
+    );
+    let message = format!("{}.{}", header, claims);
+
+    let pem: String = private_key
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = STANDARD
+        .decode(pem.trim())
+        .context("Invalid service account private key")?;
+    let key = RsaKeyPair::from_pkcs8(&der)
+        .map_err(|e| anyhow::anyhow!("Invalid service account private key: {}", e))?;
+    let mut signature = vec![0; key.public_modulus_len()];
+    key.sign(
+        &RSA_PKCS1_SHA256,
+        &SystemRandom::new(),
+        message.as_bytes(),
+        &mut signature,
+    )
+    .map_err(|e| anyhow::anyhow!("Failed to sign token request: {}", e))?;
+    Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+async fn send(request: reqwest::RequestBuilder) -> Result<Vec<u8>> {
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.bytes().await?;
+    if !status.is_success() {
+        bail!("HTTP {}: {}", status, String::from_utf8_lossy(&body).trim());
+    }
+    Ok(body.to_vec())
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body).context("Unexpected response from Google")
+}
@@ -0,0 +1,75 @@
+use super::{Document, LoadOptions, Loader, extension_of};
+use anyhow::{Context, Result};
+use mail_parser::mailbox::mbox::MessageIterator;
+use mail_parser::{Message, MessageParser};
+use std::path::Path;
+use tracing::warn;
+
+pub struct EmailLoader;
+
+impl Loader for EmailLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["eml", "mbox"]
+    }
+
+    /// Returns one document per message, tagged with its subject, sender and
+    /// date.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let parser = MessageParser::default();
+        if extension_of(path) != "mbox" {
+            let message = parser
+                .parse(bytes)
+                .with_context(|| format!("Failed to parse email: {:?}", path))?;
+            return Ok(vec![message_document(&message)]);
+        }
+
+        let mut documents = Vec::new();
+        for (i, entry) in MessageIterator::new(bytes).enumerate() {
+            let entry = entry.with_context(|| format!("Failed to read mbox: {:?}", path))?;
+            match parser.parse(entry.contents()) {
+                Some(message) => documents.push(message_document(&message)),
+                None => warn!("Skipping unparseable message {} in {:?}", i + 1, path),
+            }
+        }
+        Ok(documents)
+    }
+}
+
+/// Renders the headers worth searching on above the plain-text body. HTML-only
+/// messages are converted to text.
+fn message_document(message: &Message) -> Document {
+    let subject = message.subject().map(str::to_string);
+    let from = message.from().and_then(|from| from.first()).map(|addr| {
+        match (addr.name(), addr.address()) {
+            (Some(name), Some(address)) => format!("{} <{}>", name, address),
+            (name, address) => name.or(address).unwrap_or_default().to_string(),
+        }
+    });
+    let date = message.date().map(|date| date.to_rfc3339());
+
+    let mut text = String::new();
+    for (label, value) in [("Subject", &subject), ("From", &from), ("Date", &date)] {
+        if let Some(value) = value {
+            text.push_str(&format!("{}: {}\n", label, value));
+        }
+    }
+    let body: Vec<String> = (0..message.text_body_count())
+        .filter_map(|i| message.body_text(i))
+        .map(|body| body.trim().to_string())
+        .collect();
+    text.push('\n');
+    text.push_str(&body.join("\n\n"));
+
+    let mut document = Document::new(text.trim());
+    for (key, value) in [("subject", subject), ("from", from), ("date", date)] {
+        if let Some(value) = value {
+            document = document.with_metadata(key, value);
+        }
+    }
+    document
+}
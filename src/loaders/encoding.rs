@@ -0,0 +1,114 @@
+//! Decoding of text files that are not UTF-8. Older documents are often
+//! Latin-1 or Windows-1252, and some editors save UTF-16; reading those as
+//! UTF-8 either fails or fills the index with mojibake.
+
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_8};
+use std::borrow::Cow;
+use std::path::Path;
+use tracing::info;
+
+/// Decodes text to UTF-8. A byte order mark wins, then a charset the file
+/// declares (like HTML's `<meta charset>`), then valid UTF-8; anything else
+/// is decoded in the encoding its bytes most resemble.
+pub(super) fn decode<'a>(path: &Path, bytes: &'a [u8], declared: Option<&str>) -> Cow<'a, str> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return encoding.decode_without_bom_handling(&bytes[bom_length..]).0;
+    }
+    let declared = declared
+        .and_then(|label| Encoding::for_label(label.trim().as_bytes()))
+        // A page can only declare an ASCII-compatible encoding in itself.
+        .filter(|encoding| encoding.is_ascii_compatible());
+    if let Some(encoding) = declared
+        && encoding != UTF_8
+    {
+        info!("Decoding {:?} as {}", path, encoding.name());
+        return encoding.decode_without_bom_handling(bytes).0;
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, Utf8Detection::Deny);
+    info!(
+        "{:?} is not UTF-8, decoding it as {}",
+        path,
+        encoding.name()
+    );
+    encoding.decode_without_bom_handling(bytes).0
+}
+
+/// The charset an HTML page declares in a `<meta>` tag near its start.
+pub(super) fn html_charset(bytes: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(2048)]).to_lowercase();
+    let start = head.find("charset=")? + "charset=".len();
+    let charset: String = head[start..]
+        .trim_start_matches(['"', '\''])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+        .collect();
+    (!charset.is_empty()).then_some(charset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(bytes: &[u8], declared: Option<&str>) -> String {
+        decode(Path::new("a.txt"), bytes, declared).into_owned()
+    }
+
+    #[test]
+    fn leaves_utf8_alone() {
+        let text = "naïve café".as_bytes();
+        assert!(matches!(
+            decode(Path::new("a.txt"), text, None),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn follows_a_byte_order_mark() {
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain("héllo".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        assert_eq!(decoded(&utf16, Some("iso-8859-1")), "héllo");
+        assert_eq!(decoded(b"\xEF\xBB\xBFhi", None), "hi");
+    }
+
+    #[test]
+    fn follows_a_declared_charset() {
+        assert_eq!(
+            decoded(b"\x93caf\xe9\x94", Some(" windows-1252 ")),
+            "“café”"
+        );
+        // UTF-16 can't declare itself in its own bytes, so it isn't believed.
+        assert_eq!(decoded(b"plain", Some("utf-16")), "plain");
+    }
+
+    #[test]
+    fn guesses_the_encoding_of_other_text() {
+        let latin = b"Le caf\xe9 est tr\xe8s bon, et le th\xe9 aussi. \xc0 bient\xf4t !";
+        assert_eq!(
+            decoded(latin, None),
+            "Le café est très bon, et le thé aussi. À bientôt !"
+        );
+    }
+
+    #[test]
+    fn reads_the_charset_from_a_meta_tag() {
+        assert_eq!(
+            html_charset(b"<html><head><META charset=\"ISO-8859-1\">").as_deref(),
+            Some("iso-8859-1")
+        );
+        assert_eq!(
+            html_charset(b"<meta http-equiv=content-type content='text/html; charset=Shift_JIS'>")
+                .as_deref(),
+            Some("shift_jis")
+        );
+        assert_eq!(html_charset(b"<html><body>no charset</body>"), None);
+    }
+}
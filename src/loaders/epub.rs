@@ -0,0 +1,189 @@
+use super::{Document, LoadOptions, Loader, html, xml};
+use anyhow::{Context, Result};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use scraper::{Html, Selector};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::debug;
+
+pub struct EpubLoader;
+
+impl Loader for EpubLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["epub"]
+    }
+
+    /// Returns one document per spine item, tagged with its chapter title.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let mut archive = xml::open_archive(path, bytes)?;
+
+        let container = xml::read_entry(&mut archive, "META-INF/container.xml")?;
+        let opf_path = rootfile_path(&container)?
+            .with_context(|| format!("EPUB has no rootfile: {:?}", path))?;
+        let opf_dir = xml::parent_dir(&opf_path);
+
+        let package = parse_package(&xml::read_entry(&mut archive, &opf_path)?)
+            .with_context(|| format!("Failed to parse EPUB package: {:?}", path))?;
+
+        let toc = match &package.toc_href {
+            Some((href, is_nav)) => {
+                let toc_path = xml::resolve(opf_dir, href);
+                let toc_dir = xml::parent_dir(&toc_path);
+                let contents = xml::read_entry(&mut archive, &toc_path)?;
+                let entries = if *is_nav {
+                    nav_titles(&contents)
+                } else {
+                    ncx_titles(&contents)?
+                };
+                entries
+                    .into_iter()
+                    .map(|(href, title)| (xml::resolve(toc_dir, &href), title))
+                    .collect()
+            }
+            None => HashMap::new(),
+        };
+
+        let mut documents = Vec::new();
+        for href in &package.spine {
+            let chapter_path = xml::resolve(opf_dir, href);
+            let xhtml = xml::read_entry(&mut archive, &chapter_path)?;
+            let (heading, text) = html::page_text(&xhtml);
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let mut document = Document::new(text);
+            if let Some(title) = toc.get(&chapter_path).cloned().or(heading) {
+                debug!("EPUB chapter {}: {}", chapter_path, title);
+                document = document.with_metadata("chapter", title);
+            }
+            documents.push(document);
+        }
+
+        Ok(documents)
+    }
+}
+
+struct Package {
+    /// Hrefs of the spine items, in reading order.
+    spine: Vec<String>,
+    /// Href of the table of contents, and whether it is an EPUB 3 nav document
+    /// (as opposed to an EPUB 2 NCX file).
+    toc_href: Option<(String, bool)>,
+}
+
+fn rootfile_path(container: &str) -> Result<Option<String>> {
+    let mut reader = Reader::from_str(container);
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == "rootfile" => {
+                return Ok(xml::attr(&e, "full-path"));
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+fn parse_package(opf: &str) -> Result<Package> {
+    let mut reader = Reader::from_str(opf);
+    // id -> (href, media type, properties)
+    let mut manifest: HashMap<String, (String, String, String)> = HashMap::new();
+    let mut spine_ids = Vec::new();
+    let mut ncx_id = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                "item" => {
+                    if let (Some(id), Some(href)) = (xml::attr(&e, "id"), xml::attr(&e, "href")) {
+                        let media_type = xml::attr(&e, "media-type").unwrap_or_default();
+                        let properties = xml::attr(&e, "properties").unwrap_or_default();
+                        manifest.insert(id, (href, media_type, properties));
+                    }
+                }
+                "spine" => ncx_id = xml::attr(&e, "toc"),
+                "itemref" => {
+                    if xml::attr(&e, "linear").as_deref() != Some("no")
+                        && let Some(idref) = xml::attr(&e, "idref")
+                    {
+                        spine_ids.push(idref);
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let nav = manifest
+        .values()
+        .find(|(_, _, properties)| properties.split_whitespace().any(|p| p == "nav"))
+        .map(|(href, _, _)| (href.clone(), true));
+    let ncx = ncx_id
+        .and_then(|id| manifest.get(&id))
+        .or_else(|| {
+            manifest
+                .values()
+                .find(|(_, media_type, _)| media_type == "application/x-dtbncx+xml")
+        })
+        .map(|(href, _, _)| (href.clone(), false));
+
+    Ok(Package {
+        spine: spine_ids
+            .iter()
+            .filter_map(|id| manifest.get(id).map(|(href, _, _)| href.clone()))
+            .collect(),
+        toc_href: nav.or(ncx),
+    })
+}
+
+/// Reads (href, title) pairs from an EPUB 3 navigation document.
+fn nav_titles(nav: &str) -> Vec<(String, String)> {
+    let document = Html::parse_document(nav);
+    let links = Selector::parse("nav a[href]").expect("valid selector");
+    document
+        .select(&links)
+        .filter_map(|a| {
+            let title = a.text().collect::<Vec<_>>().join(" ");
+            let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+            (!title.is_empty()).then(|| (a.attr("href").unwrap_or_default().to_string(), title))
+        })
+        .collect()
+}
+
+/// Reads (href, title) pairs from an EPUB 2 NCX file.
+fn ncx_titles(ncx: &str) -> Result<Vec<(String, String)>> {
+    let mut reader = Reader::from_str(ncx);
+    let mut entries = Vec::new();
+    let mut label = String::new();
+    let mut in_label_text = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.local_name().as_ref() == "text" => {
+                in_label_text = true;
+                label.clear();
+            }
+            Event::End(e) if e.local_name().as_ref() == "text" => in_label_text = false,
+            Event::Text(e) if in_label_text => label.push_str(&e.xml10_content()),
+            Event::GeneralRef(e) if in_label_text => label.push_str(&xml::resolve_ref(&e)?),
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == "content" => {
+                if let Some(src) = xml::attr(&e, "src") {
+                    entries.push((src, label.trim().to_string()));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,227 @@
+use super::{Document, LoadOptions, Loader, encoding};
+use anyhow::{Context, Result, bail};
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::debug;
+
+/// Elements that never contain article content.
+const SKIPPED_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "nav", "header", "footer", "aside", "form",
+    "button", "iframe",
+];
+
+/// Class/id tokens that mark navigation, ads and other page chrome.
+const BOILERPLATE_HINTS: &[&str] = &[
+    "nav",
+    "navbar",
+    "menu",
+    "footer",
+    "header",
+    "sidebar",
+    "breadcrumb",
+    "breadcrumbs",
+    "cookie",
+    "banner",
+    "comments",
+    "comment",
+    "share",
+    "social",
+    "related",
+    "advert",
+    "ads",
+    "promo",
+];
+
+/// Elements that start a new line in the extracted text.
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "br",
+    "li",
+    "ul",
+    "ol",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "pre",
+    "blockquote",
+    "table",
+    "tr",
+    "dl",
+    "dt",
+    "dd",
+    "figcaption",
+];
+
+pub struct HtmlLoader;
+
+impl Loader for HtmlLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["html", "htm"]
+    }
+
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let charset = encoding::html_charset(bytes);
+        let html = encoding::decode(path, bytes, charset.as_deref());
+        Ok(vec![Document::new(extract_main_text(&html))])
+    }
+}
+
+/// Downloads a web page and extracts its main body text.
+pub async fn load_url(url: &str) -> Result<Document> {
+    let response = reqwest::get(url)
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    if !response.status().is_success() {
+        bail!("Fetching {} returned HTTP {}", url, response.status());
+    }
+    let html = response
+        .text()
+        .await
+        .with_context(|| format!("Failed to read response body from {}", url))?;
+
+    Ok(Document::new(extract_main_text(&html)))
+}
+
+/// Readability-style extraction: finds the element holding most of the
+/// paragraph text and flattens it, dropping navigation and other chrome.
+pub fn extract_main_text(html: &str) -> String {
+    let document = Html::parse_document(html);
+
+    let root = ["article", "main", "[role=main]"]
+        .iter()
+        .find_map(|selector| {
+            document
+                .select(&Selector::parse(selector).expect("valid selector"))
+                .max_by_key(|el| text_len(*el))
+        })
+        .or_else(|| densest_paragraph_container(&document))
+        .unwrap_or_else(|| document.root_element());
+    debug!("Extracting HTML content from <{}>", root.value().name());
+
+    let mut text = String::new();
+    flatten(root, &mut text);
+    collapse_blank_lines(&text)
+}
+
+/// Scores each element by the paragraph text of its children (and, at half
+/// weight, grandchildren) and returns the best one.
+fn densest_paragraph_container(document: &Html) -> Option<ElementRef<'_>> {
+    let paragraphs = Selector::parse("p").expect("valid selector");
+    let mut scores: HashMap<_, (ElementRef, usize)> = HashMap::new();
+
+    for p in document.select(&paragraphs) {
+        if is_boilerplate(p) {
+            continue;
+        }
+        let len = text_len(p);
+        let mut ancestors = p.ancestors().filter_map(ElementRef::wrap);
+        if let Some(parent) = ancestors.next() {
+            scores.entry(parent.id()).or_insert((parent, 0)).1 += len;
+        }
+        if let Some(grandparent) = ancestors.next() {
+            scores.entry(grandparent.id()).or_insert((grandparent, 0)).1 += len / 2;
+        }
+    }
+
+    scores
+        .into_values()
+        .max_by_key(|(_, score)| *score)
+        .map(|(el, _)| el)
+}
+
+fn text_len(el: ElementRef) -> usize {
+    el.text().map(|t| t.trim().len()).sum()
+}
+
+fn is_boilerplate(el: ElementRef) -> bool {
+    let value = el.value();
+    if SKIPPED_TAGS.contains(&value.name()) {
+        return true;
+    }
+    value
+        .classes()
+        .chain(value.id())
+        .flat_map(|name| name.split(['-', '_', ' ']))
+        .any(|token| BOILERPLATE_HINTS.contains(&token.to_lowercase().as_str()))
+}
+
+fn flatten(el: ElementRef, out: &mut String) {
+    for child in el.children() {
+        match child.value() {
+            Node::Text(text) => out.push_str(text),
+            Node::Element(element) => {
+                let Some(child) = ElementRef::wrap(child) else {
+                    continue;
+                };
+                if is_boilerplate(child) {
+                    continue;
+                }
+                let block = BLOCK_TAGS.contains(&element.name());
+                if block {
+                    out.push('\n');
+                }
+                flatten(child, out);
+                if block {
+                    out.push('\n');
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collapse_blank_lines(text: &str) -> String {
+    let mut out = String::new();
+    let mut blank = true;
+    for line in text.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            if !blank {
+                out.push('\n');
+            }
+            blank = true;
+        } else {
+            out.push_str(&line);
+            out.push('\n');
+            blank = false;
+        }
+    }
+    out
+}
+
+/// Flattens a whole page without main-content detection, returning its first
+/// heading (or `<title>`) alongside the text. Used for EPUB chapters and
+/// Confluence pages, where each file is already just content.
+pub fn page_text(html: &str) -> (Option<String>, String) {
+    let document = Html::parse_document(html);
+
+    let title = ["h1", "h2", "h3", "title"].iter().find_map(|selector| {
+        document
+            .select(&Selector::parse(selector).expect("valid selector"))
+            .map(|el| el.text().collect::<Vec<_>>().join(" "))
+            .map(|t| t.split_whitespace().collect::<Vec<_>>().join(" "))
+            .find(|t| !t.is_empty())
+    });
+
+    let body = document
+        .select(&Selector::parse("body").expect("valid selector"))
+        .next()
+        .unwrap_or_else(|| document.root_element());
+    let mut text = String::new();
+    flatten(body, &mut text);
+
+    (title, collapse_blank_lines(&text))
+}
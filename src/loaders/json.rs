@@ -0,0 +1,97 @@
+use super::{Document, LoadOptions, Loader, extension_of};
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+use std::path::Path;
+use tracing::warn;
+
+/// Field embedded when `--text-field` is not given.
+const DEFAULT_TEXT_FIELD: &str = "text";
+
+pub struct JsonLoader;
+
+impl Loader for JsonLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["json", "jsonl", "ndjson"]
+    }
+
+    /// Returns one document per record: a line of a `.jsonl` file, or an
+    /// element of a top-level array (or the single object) in a `.json` file.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let records: Vec<Value> = if extension_of(path) == "json" {
+            match serde_json::from_slice(bytes)
+                .with_context(|| format!("Failed to parse JSON: {:?}", path))?
+            {
+                Value::Array(records) => records,
+                record => vec![record],
+            }
+        } else {
+            let text = std::str::from_utf8(bytes)
+                .with_context(|| format!("JSONL file is not valid UTF-8: {:?}", path))?;
+            text.lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(i, line)| {
+                    serde_json::from_str(line).with_context(|| {
+                        format!("Failed to parse line {} of JSONL: {:?}", i + 1, path)
+                    })
+                })
+                .collect::<Result<_>>()?
+        };
+
+        let text_field = options.text_field.as_deref().unwrap_or(DEFAULT_TEXT_FIELD);
+        let mut documents = Vec::new();
+        for (number, record) in (1..).zip(&records) {
+            let text = field(record, text_field).map(render).unwrap_or_default();
+            if text.trim().is_empty() {
+                warn!(
+                    "Skipping record {} in {:?}: no {:?} field",
+                    number, path, text_field
+                );
+                continue;
+            }
+            let mut document = Document::new(text).with_metadata("record", number.to_string());
+            for name in &options.metadata_fields {
+                if let Some(value) = field(record, name).filter(|value| !value.is_null()) {
+                    document = document.with_metadata(name, render(value));
+                }
+            }
+            documents.push(document);
+        }
+        if documents.is_empty() && !records.is_empty() {
+            bail!(
+                "No record in {:?} has a {:?} field (set it with --text-field)",
+                path,
+                text_field
+            );
+        }
+        Ok(documents)
+    }
+}
+
+/// Looks up a field by name; dots reach into nested objects
+/// (`author.name`).
+fn field<'a>(record: &'a Value, name: &str) -> Option<&'a Value> {
+    name.split('.')
+        .try_fold(record, |value, key| value.as_object()?.get(key))
+}
+
+/// Renders strings without quotes, arrays of scalars as comma-separated
+/// lists, and anything else as compact JSON.
+fn render(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items)
+            if items
+                .iter()
+                .all(|item| !item.is_object() && !item.is_array()) =>
+        {
+            items.iter().map(render).collect::<Vec<_>>().join(", ")
+        }
+        other => other.to_string(),
+    }
+}
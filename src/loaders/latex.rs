@@ -0,0 +1,620 @@
+use super::{Document, LoadOptions, Loader, encoding};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tracing::{debug, warn};
+
+pub struct LatexLoader;
+
+impl Loader for LatexLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["tex"]
+    }
+
+    /// Returns one document per section, tagged with its heading path
+    /// (`Method > Datasets`). A file not read from disk (a ZIP entry, a
+    /// download, a bucket object) has nothing it may `\input`, so its
+    /// `\input`s and `\include`s are dropped.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        Ok(convert(&encoding::decode(path, bytes, None), None))
+    }
+
+    /// Like `load_bytes`, but with the files `\input` and `\include` name
+    /// read in, as long as they are inside the main file's directory.
+    fn load(&self, path: &Path, _options: &LoadOptions) -> Result<Vec<Document>> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {:?}", dir))?;
+        Ok(convert(&encoding::decode(path, &bytes, None), Some(&dir)))
+    }
+}
+
+fn convert(source: &str, dir: Option<&Path>) -> Vec<Document> {
+    let source = expand_inputs(source, dir, 0);
+    let (title, body) = match source.split_once("\\begin{document}") {
+        Some((preamble, body)) => (command_argument(preamble, "title"), body),
+        None => (None, source.as_str()),
+    };
+    let body = body.split("\\end{document}").next().unwrap_or_default();
+
+    let mut blocks = Vec::new();
+    if let Some(title) = title {
+        blocks.push(Block::Text(inline_text(&title)));
+    }
+    Converter::new(body).run(&mut blocks);
+    split_sections(blocks)
+}
+
+/// `\input` chains deeper than this are almost certainly cycles.
+const MAX_INPUT_DEPTH: usize = 16;
+
+/// Sectioning commands, from the outermost level in.
+const SECTIONS: &[&str] = &[
+    "part",
+    "chapter",
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+    "subparagraph",
+];
+
+/// Commands dropped along with their arguments, and how many arguments they
+/// take. Optional `[...]` arguments are always skipped.
+const DROPPED: &[(&str, usize)] = &[
+    ("documentclass", 1),
+    ("usepackage", 1),
+    ("label", 1),
+    ("ref", 1),
+    ("eqref", 1),
+    ("pageref", 1),
+    ("cite", 1),
+    ("citep", 1),
+    ("citet", 1),
+    ("includegraphics", 1),
+    ("bibliography", 1),
+    ("bibliographystyle", 1),
+    ("vspace", 1),
+    ("hspace", 1),
+    ("setlength", 2),
+    ("newcommand", 2),
+    ("renewcommand", 2),
+    ("pagestyle", 1),
+    ("thispagestyle", 1),
+];
+
+/// Environments whose contents are not prose.
+const SKIPPED_ENVIRONMENTS: &[&str] = &["comment", "tikzpicture", "thebibliography"];
+
+/// Environments kept verbatim, so formulas and code survive intact.
+const VERBATIM_ENVIRONMENTS: &[&str] = &[
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "gather",
+    "gather*",
+    "multline",
+    "verbatim",
+    "lstlisting",
+    "minted",
+];
+
+enum Block {
+    /// A sectioning command, with its index into `SECTIONS`.
+    Heading(usize, String),
+    Text(String),
+}
+
+/// Removes comments and replaces `\input`/`\include` commands with the
+/// files they name, which are read relative to `dir`, the main file's
+/// directory. With no `dir`, they are only removed.
+fn expand_inputs(source: &str, dir: Option<&Path>, depth: usize) -> String {
+    let mut out = String::new();
+    for line in source.lines() {
+        let mut line = strip_comment(line);
+        while let Some((command, start)) = ["\\input{", "\\include{"]
+            .iter()
+            .filter_map(|command| line.find(command).map(|start| (command, start)))
+            .min_by_key(|&(_, start)| start)
+        {
+            out.push_str(&line[..start]);
+            let rest = &line[start + command.len()..];
+            let Some(end) = rest.find('}') else {
+                line = rest;
+                break;
+            };
+            let name = rest[..end].trim();
+            match dir.map(|dir| (dir, resolve_input(dir, name))) {
+                None => warn!(
+                    "Not including {:?}: only files read from disk include others",
+                    name
+                ),
+                Some((_, None)) => {
+                    warn!(
+                        "Not including {:?}: it is outside the main file's directory",
+                        name
+                    )
+                }
+                Some(_) if depth >= MAX_INPUT_DEPTH => {
+                    warn!("Not including {:?}: \\input nested too deeply", name)
+                }
+                Some((dir, Some(included))) => {
+                    debug!("Including {:?}", included);
+                    match fs::read(&included) {
+                        // Like TeX, resolve nested inputs against the main
+                        // file's directory rather than the including file's.
+                        Ok(contents) => {
+                            let contents = encoding::decode(&included, &contents, None);
+                            out.push_str(&expand_inputs(&contents, Some(dir), depth + 1))
+                        }
+                        Err(e) => warn!("Skipping {:?}: {}", included, e),
+                    }
+                }
+            }
+            line = &rest[end + 1..];
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// The file `\input{name}` reads, as long as it is inside `dir`: `name`
+/// can't be absolute or go up with `..`, and a symlink can't lead out.
+/// `dir` has to be canonical already.
+fn resolve_input(dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = Path::new(name);
+    if name.as_os_str().is_empty()
+        || !name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return None;
+    }
+    let mut included = dir.join(name);
+    if included.extension().is_none() {
+        included.set_extension("tex");
+    }
+    // A file that doesn't exist is reported when it fails to be read.
+    match included.canonicalize() {
+        Ok(resolved) if resolved.starts_with(dir) => Some(resolved),
+        Ok(_) => None,
+        Err(_) => Some(included),
+    }
+}
+
+/// Cuts a line at its first unescaped `%`.
+fn strip_comment(line: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '%' if !escaped => return &line[..i],
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+/// Returns the braced argument of the first use of a command, e.g. the
+/// title from `\title{...}` in the preamble.
+fn command_argument(source: &str, command: &str) -> Option<String> {
+    let start = source.find(&format!("\\{}", command))? + command.len() + 1;
+    let mut converter = Converter::new(&source[start..]);
+    converter.skip_whitespace();
+    converter.read_group()
+}
+
+/// Converts LaTeX to plain text on a single line.
+fn inline_text(source: &str) -> String {
+    let mut blocks = Vec::new();
+    Converter::new(source).run(&mut blocks);
+    blocks
+        .iter()
+        .map(|block| match block {
+            Block::Heading(_, text) | Block::Text(text) => text.as_str(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+struct Converter {
+    chars: Vec<char>,
+    pos: usize,
+    text: String,
+}
+
+impl Converter {
+    fn new(source: &str) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            pos: 0,
+            text: String::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Reads a balanced `{...}` group, returning its raw contents.
+    fn read_group(&mut self) -> Option<String> {
+        self.read_delimited('{', '}')
+    }
+
+    fn skip_optional(&mut self) {
+        self.skip_whitespace();
+        if self.peek() == Some('[') {
+            self.read_delimited('[', ']');
+        }
+    }
+
+    fn read_delimited(&mut self, open: char, close: char) -> Option<String> {
+        if self.peek() != Some(open) {
+            return None;
+        }
+        self.pos += 1;
+        let start = self.pos;
+        let mut depth = 1;
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => self.pos += 1,
+                c if c == open => depth += 1,
+                c if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let group = self.chars[start..self.pos].iter().collect();
+                        self.pos += 1;
+                        return Some(group);
+                    }
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        Some(self.chars[start..].iter().collect())
+    }
+
+    fn read_command_name(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        if self.pos == start && self.peek().is_some() {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Copies everything up to `end` into the text unchanged.
+    fn copy_until(&mut self, end: &str) {
+        while self.peek().is_some() && !self.starts_with(end) {
+            self.text.push(self.chars[self.pos]);
+            self.pos += 1;
+        }
+        self.pos = (self.pos + end.chars().count()).min(self.chars.len());
+    }
+
+    fn skip_until(&mut self, end: &str) {
+        while self.peek().is_some() && !self.starts_with(end) {
+            self.pos += 1;
+        }
+        self.pos = (self.pos + end.chars().count()).min(self.chars.len());
+    }
+
+    fn flush(&mut self, blocks: &mut Vec<Block>) {
+        if !self.text.trim().is_empty() {
+            blocks.push(Block::Text(reflow(&self.text)));
+        }
+        self.text.clear();
+    }
+
+    fn run(mut self, blocks: &mut Vec<Block>) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '\\' => self.command(blocks),
+                '{' | '}' => {}
+                '~' => self.text.push(' '),
+                '$' => {
+                    let end = if self.peek() == Some('$') {
+                        self.pos += 1;
+                        "$$"
+                    } else {
+                        "$"
+                    };
+                    self.text.push('$');
+                    self.copy_until(end);
+                    self.text.push('$');
+                }
+                '`' if self.peek() == Some('`') => {
+                    self.pos += 1;
+                    self.text.push('"');
+                }
+                '\'' if self.peek() == Some('\'') => {
+                    self.pos += 1;
+                    self.text.push('"');
+                }
+                c => self.text.push(c),
+            }
+        }
+        self.flush(blocks);
+    }
+
+    fn command(&mut self, blocks: &mut Vec<Block>) {
+        let name = self.read_command_name();
+        match name.as_str() {
+            "\\" => {
+                self.skip_optional();
+                self.text.push('\n');
+            }
+            "%" | "&" | "_" | "#" | "$" | "{" | "}" => self.text.push_str(&name),
+            " " | "," | ";" => self.text.push(' '),
+            "(" => {
+                self.text.push('$');
+                self.copy_until("\\)");
+                self.text.push('$');
+            }
+            "[" => {
+                self.text.push_str("$$");
+                self.copy_until("\\]");
+                self.text.push_str("$$");
+            }
+            "verb" => {
+                if let Some(delimiter) = self.peek() {
+                    self.pos += 1;
+                    self.copy_until(&delimiter.to_string());
+                }
+            }
+            "item" => {
+                self.skip_optional();
+                self.text.push_str("\n- ");
+            }
+            "footnote" => {
+                if let Some(note) = self.read_group() {
+                    self.text.push_str(&format!(" ({})", inline_text(&note)));
+                }
+            }
+            "begin" => {
+                let Some(environment) = self.read_group() else {
+                    return;
+                };
+                let end = format!("\\end{{{}}}", environment);
+                if SKIPPED_ENVIRONMENTS.contains(&environment.as_str()) {
+                    self.skip_until(&end);
+                } else if VERBATIM_ENVIRONMENTS.contains(&environment.as_str()) {
+                    self.flush(blocks);
+                    self.copy_until(&end);
+                    let code = std::mem::take(&mut self.text);
+                    blocks.push(Block::Text(code.trim_matches('\n').to_string()));
+                } else {
+                    self.skip_optional();
+                    // Column specs are the only argument we see often.
+                    if matches!(environment.as_str(), "tabular" | "array") {
+                        self.read_group();
+                    }
+                    self.text.push_str("\n\n");
+                }
+            }
+            "end" => {
+                self.read_group();
+                self.text.push_str("\n\n");
+            }
+            name => {
+                if let Some(level) = SECTIONS.iter().position(|s| *s == name) {
+                    if self.peek() == Some('*') {
+                        self.pos += 1;
+                    }
+                    self.skip_optional();
+                    self.skip_whitespace();
+                    if let Some(title) = self.read_group() {
+                        self.flush(blocks);
+                        blocks.push(Block::Heading(level, inline_text(&title)));
+                    }
+                } else if let Some((_, args)) = DROPPED.iter().find(|(dropped, _)| *dropped == name)
+                {
+                    if self.peek() == Some('*') {
+                        self.pos += 1;
+                    }
+                    for _ in 0..*args {
+                        self.skip_optional();
+                        self.skip_whitespace();
+                        self.read_group();
+                    }
+                } else if self.peek() == Some('*') {
+                    // Formatting commands like \textbf{...} fall through:
+                    // their braced argument is converted as ordinary text.
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Joins the lines of each paragraph and collapses runs of blank lines,
+/// keeping the explicit breaks from `\\` and list items.
+fn reflow(text: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| {
+            paragraph
+                .lines()
+                .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Starts a new document at every heading that has text under it, tagged
+/// with the titles of the sections it is nested in. Headings are rendered as
+/// Markdown, with the outermost level used in the file as `#`.
+fn split_sections(blocks: Vec<Block>) -> Vec<Document> {
+    let top = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Heading(level, _) => Some(*level),
+            Block::Text(_) => None,
+        })
+        .min()
+        .unwrap_or(0);
+
+    let mut documents = Vec::new();
+    let mut path: Vec<(usize, String)> = Vec::new();
+    let mut text = String::new();
+    let mut has_body = false;
+
+    let mut flush = |path: &[(usize, String)], text: &mut String| {
+        let document = Document::new(text.trim());
+        documents.push(match path {
+            [] => document,
+            path => {
+                let titles: Vec<&str> = path.iter().map(|(_, title)| title.as_str()).collect();
+                document.with_metadata("section", titles.join(" > "))
+            }
+        });
+        text.clear();
+    };
+
+    for block in blocks {
+        match block {
+            Block::Heading(level, title) => {
+                // A heading directly followed by a subheading stays with it.
+                if has_body {
+                    flush(&path, &mut text);
+                    has_body = false;
+                }
+                while path.last().is_some_and(|(outer, _)| *outer >= level) {
+                    path.pop();
+                }
+                let hashes = "#".repeat((level - top + 1).min(6));
+                text.push_str(&format!("{} {}\n\n", hashes, title));
+                path.push((level, title));
+            }
+            Block::Text(block) => {
+                text.push_str(&block);
+                text.push_str("\n\n");
+                has_body = true;
+            }
+        }
+    }
+    if has_body {
+        flush(&path, &mut text);
+    }
+    documents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A main file in `paper/`, next to a secret one level up.
+    fn project(main: &str) -> (tempfile::TempDir, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("paper");
+        fs::create_dir_all(dir.join("chapters")).unwrap();
+        fs::write(root.path().join("secret.tex"), "SECRET").unwrap();
+        fs::write(
+            dir.join("chapters/intro.tex"),
+            "Intro text. \\input{chapters/more}",
+        )
+        .unwrap();
+        fs::write(dir.join("chapters/more.tex"), "More text.").unwrap();
+        let main_path = dir.join("main.tex");
+        fs::write(&main_path, main).unwrap();
+        (root, main_path)
+    }
+
+    fn load(path: &Path) -> String {
+        let documents = LatexLoader.load(path, &LoadOptions::default()).unwrap();
+        documents
+            .iter()
+            .map(|d| d.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn includes_files_under_the_main_ones_directory() {
+        let (_root, main) = project("\\input{chapters/intro} \\include{./chapters/more.tex}");
+        let text = load(&main);
+        assert!(text.contains("Intro text. More text."), "{}", text);
+        assert_eq!(text.matches("More text.").count(), 2);
+    }
+
+    #[test]
+    fn refuses_files_outside_it() {
+        let (root, main) = project("A \\input{../secret} B");
+        assert_eq!(load(&main), "A B");
+        let absolute = root.path().join("secret.tex");
+        fs::write(&main, format!("A \\input{{{}}} B", absolute.display())).unwrap();
+        assert_eq!(load(&main), "A B");
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&absolute, main.with_file_name("link.tex")).unwrap();
+            fs::write(&main, "A \\input{link} B").unwrap();
+            assert_eq!(load(&main), "A B");
+        }
+    }
+
+    #[test]
+    fn includes_nothing_from_memory() {
+        let (_root, main) = project("");
+        let source = "A \\input{chapters/intro} B";
+        let documents = LatexLoader
+            .load_bytes(&main, source.as_bytes(), &LoadOptions::default())
+            .unwrap();
+        assert_eq!(documents[0].text, "A B");
+    }
+
+    #[test]
+    fn splits_sections_and_drops_markup() {
+        let source = "\\documentclass{article}\\title{On \\textbf{Things}}\n\
+            \\begin{document}\n\
+            \\section{Method} % a comment\n\
+            We use 50\\% of \\cite{x} data.\n\
+            \\subsection{Data}\n\
+            See \\ref{tab}~below.\n\
+            \\end{document}";
+        let documents = convert(source, None);
+        let texts: Vec<&str> = documents.iter().map(|d| d.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "On Things",
+                "# Method\n\nWe use 50% of data.",
+                "## Data\n\nSee below."
+            ]
+        );
+        assert_eq!(documents[2].metadata["section"], "Method > Data");
+        assert!(!documents[0].metadata.contains_key("section"));
+    }
+}
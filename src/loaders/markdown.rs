@@ -0,0 +1,89 @@
+use super::{Document, LoadOptions, Loader, encoding};
+use anyhow::Result;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use std::path::Path;
+
+pub struct MarkdownLoader;
+
+impl Loader for MarkdownLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["md", "markdown"]
+    }
+
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let markdown = encoding::decode(path, bytes, None);
+        Ok(vec![Document::new(markdown_to_text(&markdown))])
+    }
+}
+
+/// Renders Markdown to plain text, dropping inline formatting but keeping
+/// headings (as `#` lines), fenced code blocks and table rows on their own
+/// lines so the document structure survives.
+pub fn markdown_to_text(markdown: &str) -> String {
+    let mut out = String::new();
+    let mut in_code_block = false;
+
+    for event in Parser::new_ext(markdown, Options::ENABLE_TABLES) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                out.push_str("\n\n");
+                out.push_str(&"#".repeat(level as usize));
+                out.push(' ');
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                in_code_block = true;
+                out.push_str("\n\n```");
+                if let CodeBlockKind::Fenced(lang) = kind {
+                    out.push_str(&lang);
+                }
+                out.push('\n');
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                if !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                out.push_str("```\n\n");
+            }
+            Event::Start(Tag::Paragraph | Tag::BlockQuote(_) | Tag::Table(_)) => {
+                out.push_str("\n\n")
+            }
+            Event::End(TagEnd::Heading(_) | TagEnd::Paragraph) => out.push_str("\n\n"),
+            Event::Start(Tag::Item) => out.push_str("\n- "),
+            Event::Start(Tag::TableRow | Tag::TableHead) => out.push_str("\n|"),
+            Event::End(TagEnd::TableCell) => out.push_str(" |"),
+            Event::Start(Tag::TableCell) => out.push(' '),
+            Event::Text(text) => out.push_str(&text),
+            Event::Code(code) => {
+                out.push('`');
+                out.push_str(&code);
+                out.push('`');
+            }
+            Event::SoftBreak if !in_code_block => out.push(' '),
+            Event::SoftBreak | Event::HardBreak => out.push('\n'),
+            _ => {}
+        }
+    }
+
+    // Collapse the runs of blank lines introduced between blocks.
+    let mut text = String::new();
+    let mut blank_run = 0;
+    for line in out.lines() {
+        if line.trim().is_empty() {
+            blank_run += 1;
+            if blank_run > 1 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text.trim().to_string()
+}
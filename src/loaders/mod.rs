@@ -0,0 +1,491 @@
+mod audio;
+mod bucket;
+mod code;
+mod confluence;
+mod csv;
+mod docx;
+mod drive;
+mod email;
+mod encoding;
+mod epub;
+mod html;
+mod json;
+mod latex;
+mod markdown;
+mod notion;
+mod odt;
+mod papers;
+mod pdf;
+mod pdf_columns;
+mod pdf_forms;
+mod pdf_headers;
+mod pdf_headings;
+mod pdf_images;
+mod pdf_info;
+mod pdf_layout;
+mod pdf_outline;
+mod pdf_reflow;
+mod pdf_tables;
+mod pptx;
+mod remote;
+mod rtf;
+mod text;
+mod xlsx;
+mod xml;
+mod zip;
+
+pub use bucket::load_bucket;
+pub use confluence::load_confluence;
+pub use drive::load_drive;
+pub use html::load_url;
+pub use papers::{load_arxiv, load_doi};
+pub use remote::{cache_dir, download, is_remote, load_remote, sha256_hex};
+pub use xml::percent_decode;
+
+use crate::progress;
+use anyhow::{Context, Result, bail};
+use glob::Pattern;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+/// A unit of loaded text, plus metadata that every chunk cut from it inherits.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub text: String,
+    pub metadata: BTreeMap<String, String>,
+    /// Figures pulled out of the document, to be captioned before chunking.
+    pub images: Vec<DocumentImage>,
+    /// An audio or video recording, to be transcribed before chunking.
+    pub audio: Option<DocumentAudio>,
+    /// For documents loaded page by page, the page number of each part of
+    /// `text` between form feeds, so chunks can record the pages they span.
+    pub pages: Vec<u32>,
+}
+
+/// An embedded image, encoded in a format vision models accept.
+#[derive(Debug, Clone)]
+pub struct DocumentImage {
+    /// 1-based page number the image appears on.
+    pub page: u32,
+    pub format: ImageFormat,
+    pub data: Vec<u8>,
+}
+
+/// A recording as loaded from disk; the file name tells transcribers its
+/// format.
+#[derive(Debug, Clone)]
+pub struct DocumentAudio {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+}
+
+impl Document {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            metadata: BTreeMap::new(),
+            images: Vec::new(),
+            audio: None,
+            pages: Vec::new(),
+        }
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+/// Options shared by all loaders.
+#[derive(Debug, Default, Clone)]
+pub struct LoadOptions {
+    /// Always OCR PDFs instead of using their text layer.
+    pub force_ocr: bool,
+    /// Password for encrypted PDFs; prompted for interactively when unset.
+    pub pdf_password: Option<String>,
+    /// Detect tables in PDFs and render them as Markdown.
+    pub detect_tables: bool,
+    /// Read multi-column PDF pages column by column rather than straight
+    /// across.
+    pub detect_columns: bool,
+    /// Mark lines set larger than the body text in PDFs as Markdown
+    /// headings.
+    pub detect_headings: bool,
+    /// Keep running headers, footers and page numbers in PDFs instead of
+    /// stripping them.
+    pub keep_headers: bool,
+    /// Extract embedded images from PDFs so they can be captioned.
+    pub extract_images: bool,
+    /// Only extract these PDF pages; all pages when unset.
+    pub pages: Option<PageRanges>,
+    /// JSON field holding the text to embed; `text` when unset.
+    pub text_field: Option<String>,
+    /// JSON fields copied onto each record's chunks as metadata.
+    pub metadata_fields: Vec<String>,
+    /// Largest file to download; unlimited when unset.
+    pub max_download_bytes: Option<u64>,
+}
+
+/// A set of 1-based page ranges, parsed from a list like `10-55,80,100-`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRanges(Vec<(u32, u32)>);
+
+impl PageRanges {
+    pub fn contains(&self, page: u32) -> bool {
+        self.0
+            .iter()
+            .any(|&(first, last)| first <= page && page <= last)
+    }
+
+    /// The ranges in page order, with overlapping and adjacent ones joined,
+    /// so each page is in exactly one.
+    pub fn spans(&self) -> Vec<(u32, u32)> {
+        let mut ranges = self.0.clone();
+        ranges.sort_unstable();
+        let mut spans: Vec<(u32, u32)> = Vec::new();
+        for (first, last) in ranges {
+            match spans.last_mut() {
+                Some(span) if first <= span.1.saturating_add(1) => span.1 = span.1.max(last),
+                _ => spans.push((first, last)),
+            }
+        }
+        spans
+    }
+}
+
+impl std::str::FromStr for PageRanges {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_page = |page: &str| -> Result<u32> {
+            match page.trim().parse::<u32>() {
+                Ok(0) => bail!("pages are numbered from 1"),
+                Ok(page) => Ok(page),
+                Err(_) => bail!("invalid page number {:?}", page.trim()),
+            }
+        };
+
+        let mut ranges = Vec::new();
+        for part in s.split(',').filter(|part| !part.trim().is_empty()) {
+            let range = match part.split_once('-') {
+                Some((first, last)) if last.trim().is_empty() => (parse_page(first)?, u32::MAX),
+                Some((first, last)) => (parse_page(first)?, parse_page(last)?),
+                None => {
+                    let page = parse_page(part)?;
+                    (page, page)
+                }
+            };
+            if range.0 > range.1 {
+                bail!("invalid page range {:?}", part.trim());
+            }
+            ranges.push(range);
+        }
+        if ranges.is_empty() {
+            bail!("no pages given");
+        }
+        Ok(Self(ranges))
+    }
+}
+
+/// Turns a file into one or more documents for the chunking pipeline.
+pub trait Loader {
+    /// Lowercase file extensions (without the dot) handled by this loader.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Parses a file already read into memory. `path` names the file in
+    /// errors and metadata; it need not exist on disk (e.g. a ZIP entry).
+    fn load_bytes(&self, path: &Path, bytes: &[u8], options: &LoadOptions)
+    -> Result<Vec<Document>>;
+
+    fn load(&self, path: &Path, options: &LoadOptions) -> Result<Vec<Document>> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        self.load_bytes(path, &bytes, options)
+    }
+}
+
+fn loaders() -> Vec<Box<dyn Loader>> {
+    vec![
+        Box::new(pdf::PdfLoader),
+        Box::new(docx::DocxLoader),
+        Box::new(epub::EpubLoader),
+        Box::new(html::HtmlLoader),
+        Box::new(markdown::MarkdownLoader),
+        Box::new(text::TextLoader),
+        Box::new(csv::CsvLoader),
+        Box::new(json::JsonLoader),
+        Box::new(code::CodeLoader),
+        Box::new(audio::AudioLoader),
+        Box::new(xlsx::XlsxLoader),
+        Box::new(pptx::PptxLoader),
+        Box::new(email::EmailLoader),
+        Box::new(latex::LatexLoader),
+        Box::new(odt::OdtLoader),
+        Box::new(rtf::RtfLoader),
+        Box::new(zip::ZipLoader),
+    ]
+}
+
+fn extension_of(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default()
+}
+
+fn find_loader(path: &Path) -> Option<Box<dyn Loader>> {
+    let extension = extension_of(path);
+    loaders()
+        .into_iter()
+        .find(|loader| loader.extensions().contains(&extension.as_str()))
+}
+
+/// Returns true if some loader handles this file's extension.
+pub fn is_supported(path: &Path) -> bool {
+    find_loader(path).is_some()
+}
+
+/// Loads a document, picking the loader from the file extension.
+pub fn load_document(path: &Path, options: &LoadOptions) -> Result<Vec<Document>> {
+    loader_for(path)?.load(path, options)
+}
+
+/// Loads a file downloaded to the cache as if it were read from memory, so
+/// that, unlike the user's own files, it can't pull in others on disk the
+/// way a LaTeX source's `\input` does.
+fn load_downloaded(path: &Path, options: &LoadOptions) -> Result<Vec<Document>> {
+    let loader = loader_for(path)?;
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    loader.load_bytes(path, &bytes, options)
+}
+
+fn loader_for(path: &Path) -> Result<Box<dyn Loader>> {
+    match find_loader(path) {
+        Some(loader) => Ok(loader),
+        None => {
+            let supported: Vec<&str> = loaders()
+                .iter()
+                .flat_map(|loader| loader.extensions().iter().copied())
+                .collect();
+            bail!(
+                "Unsupported file type {:?} (supported: {})",
+                path,
+                supported.join(", ")
+            )
+        }
+    }
+}
+
+/// Reads raw text piped into the program, for users who extract text with
+/// their own tools (`pdftotext foo.pdf - | rag-my-pdf --stdin`).
+pub fn load_stdin() -> Result<Document> {
+    let mut bytes = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut bytes)
+        .context("Failed to read text from stdin")?;
+    let text = encoding::decode(Path::new("stdin"), &bytes, None);
+    if text.trim().is_empty() {
+        bail!("No text on stdin");
+    }
+    reattach_terminal();
+    Ok(Document::new(text).with_metadata("source", "stdin"))
+}
+
+/// Points stdin back at the terminal once the piped text is consumed, so the
+/// chat loop can still read questions.
+#[cfg(unix)]
+fn reattach_terminal() {
+    use std::os::fd::AsRawFd;
+    match fs::File::open("/dev/tty") {
+        // SAFETY: both descriptors are open; dup2 only replaces descriptor 0.
+        Ok(tty) => unsafe {
+            libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO);
+        },
+        Err(e) => warn!("No terminal to read questions from: {}", e),
+    }
+}
+
+#[cfg(not(unix))]
+fn reattach_terminal() {}
+
+/// Loads every file matching a shell-style glob as a separate document,
+/// tagging each with its source path.
+pub fn load_glob(pattern: &str, options: &LoadOptions) -> Result<Vec<Document>> {
+    let mut paths: Vec<_> = glob::glob(pattern)
+        .with_context(|| format!("Invalid glob pattern: {}", pattern))?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .collect();
+    if paths.is_empty() {
+        bail!("No files match {}", pattern);
+    }
+    paths.sort();
+
+    let mut documents = Vec::new();
+    let progress = progress::start("Loading", "files", paths.len());
+    for path in paths {
+        info!("Loading {:?}", path);
+        documents.extend(load_tagged(&path, options)?);
+        progress.advance(1);
+    }
+    Ok(documents)
+}
+
+/// Like [`load_document`], but tags every document with its source path so
+/// chunks from different files can be told apart.
+pub fn load_tagged(path: &Path, options: &LoadOptions) -> Result<Vec<Document>> {
+    let source = path.display().to_string();
+    Ok(load_document(path, options)?
+        .into_iter()
+        .map(|document| document.with_metadata("source", source.clone()))
+        .collect())
+}
+
+/// Loads one file found under `root`, tagged with its path and, in a Notion
+/// export, its page. Returns `None` for files that only duplicate others in
+/// the same directory.
+pub fn load_in_dir(
+    root: &Path,
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<Option<Vec<Document>>> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if path
+        .to_str()
+        .is_some_and(|path| notion::is_partial_view(path, |other| Path::new(other).exists()))
+    {
+        debug!("Skipping {:?}; the export also has all its rows", relative);
+        return Ok(None);
+    }
+
+    info!("Loading {:?}", path);
+    let page = notion::page_path(relative);
+    Ok(Some(
+        load_tagged(path, options)?
+            .into_iter()
+            .map(|document| match &page {
+                Some(page) => document.with_metadata("notion_page", page.clone()),
+                None => document,
+            })
+            .collect(),
+    ))
+}
+
+/// `--include`/`--exclude` globs, matched against paths relative to the root
+/// being loaded.
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let compile = |patterns: &[String]| -> Result<Vec<Pattern>> {
+            patterns
+                .iter()
+                .map(|p| Pattern::new(p).with_context(|| format!("Invalid glob pattern: {}", p)))
+                .collect()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// True when the path matches an include glob (or there are none) and no
+    /// exclude glob.
+    pub fn matches(&self, relative: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative)))
+            && !self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+}
+
+/// Walks a directory tree and loads every supported file, optionally limited
+/// by `include`/`exclude` globs matched against paths relative to `root`.
+pub fn load_dir(
+    root: &Path,
+    include: &[String],
+    exclude: &[String],
+    options: &LoadOptions,
+) -> Result<Vec<Document>> {
+    let filter = PathFilter::new(include, exclude)?;
+
+    let mut documents = Vec::new();
+    let (mut loaded, mut unsupported, mut excluded, mut failed) = (0, 0, 0, 0);
+
+    // The files are listed first, so the progress bar knows how many.
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to walk {:?}", root))?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+    let progress = progress::start("Loading", "files", files.len());
+    for path in &files {
+        progress.advance(1);
+        let path = path.as_path();
+        let relative = path.strip_prefix(root).unwrap_or(path);
+
+        if !filter.matches(relative) {
+            debug!("Excluded {:?}", relative);
+            excluded += 1;
+            continue;
+        }
+        if !is_supported(path) {
+            debug!("Skipping unsupported file {:?}", relative);
+            unsupported += 1;
+            continue;
+        }
+
+        match load_in_dir(root, path, options) {
+            Ok(Some(docs)) => {
+                documents.extend(docs);
+                loaded += 1;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Skipping {:?}: {:#}", path, e);
+                failed += 1;
+            }
+        }
+    }
+
+    info!(
+        "Loaded {} files from {:?} (skipped {} unsupported, {} excluded, {} failed)",
+        loaded, root, unsupported, excluded, failed
+    );
+    Ok(documents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_page_ranges() {
+        let ranges: PageRanges = "10-12, 3,20-".parse().unwrap();
+        assert_eq!(ranges, PageRanges(vec![(10, 12), (3, 3), (20, u32::MAX)]));
+        assert!(ranges.contains(3) && ranges.contains(11) && ranges.contains(500));
+        assert!(!ranges.contains(4) && !ranges.contains(13));
+        for bad in ["", "0", "5-2", "a-3"] {
+            assert!(bad.parse::<PageRanges>().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn joins_spans_that_touch() {
+        let ranges: PageRanges = "8-9,1-3,2-5,6,12-".parse().unwrap();
+        assert_eq!(ranges.spans(), [(1, 6), (8, 9), (12, u32::MAX)]);
+        let ranges: PageRanges = "5-,7-9".parse().unwrap();
+        assert_eq!(ranges.spans(), [(5, u32::MAX)]);
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::{Component, Path};
+
+/// Returns the page hierarchy of a file from a Notion export
+/// (`Engineering > Onboarding > Laptop setup`), or `None` for files that are
+/// not exported pages or databases.
+///
+/// Notion names every exported page and database `Title <32 hex digit ID>`,
+/// with a page's children in a folder of the same name, so the hierarchy can
+/// be read back from the path. Folders without an ID, like the export's own
+/// top-level folder, are not pages and are left out.
+pub(super) fn page_path(path: &Path) -> Option<String> {
+    let file = path.file_stem()?.to_str()?;
+    let file = file.strip_suffix("_all").unwrap_or(file);
+    let page = strip_id(file)?;
+
+    let mut titles: Vec<&str> = path
+        .parent()?
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => name.to_str().and_then(strip_id),
+            _ => None,
+        })
+        .collect();
+    titles.push(page);
+    Some(titles.join(" > "))
+}
+
+/// Returns true for a database's view export (`Tasks <id>.csv`) when the
+/// export also holds the database with all its rows (`Tasks <id>_all.csv`),
+/// which makes the view a duplicate.
+pub(super) fn is_partial_view(path: &str, exists: impl Fn(&str) -> bool) -> bool {
+    let Some(stem) = path.strip_suffix(".csv") else {
+        return false;
+    };
+    let name = Path::new(stem).file_name().and_then(|name| name.to_str());
+    name.and_then(strip_id).is_some() && exists(&format!("{}_all.csv", stem))
+}
+
+/// Strips the ` <32 hex digit ID>` Notion appends to exported names,
+/// returning the title.
+fn strip_id(name: &str) -> Option<&str> {
+    let (title, id) = name.rsplit_once(' ')?;
+    (id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit()) && !title.is_empty())
+        .then_some(title)
+}
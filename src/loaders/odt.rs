@@ -0,0 +1,85 @@
+use super::{Document, LoadOptions, Loader, xml};
+use anyhow::{Context, Result};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use std::path::Path;
+
+pub struct OdtLoader;
+
+impl Loader for OdtLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["odt"]
+    }
+
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let mut archive = xml::open_archive(path, bytes)?;
+        let content_xml = xml::read_entry(&mut archive, "content.xml")?;
+        let text = content_xml_to_text(&content_xml)
+            .with_context(|| format!("Failed to parse ODT: {:?}", path))?;
+        Ok(vec![Document::new(text)])
+    }
+}
+
+/// Flattens OpenDocument text into plain text, one line per paragraph or
+/// heading.
+fn content_xml_to_text(content_xml: &str) -> Result<String> {
+    let mut reader = Reader::from_str(content_xml);
+    let mut text = String::new();
+    // Paragraphs can nest (e.g. inside list items or frames), so count them.
+    let mut paragraph_depth = 0usize;
+    // Comments and change-tracking records hold text that is not part of
+    // the document body.
+    let mut skipped_depth = 0usize;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.name().as_ref() {
+                "text:p" | "text:h" => paragraph_depth += 1,
+                "office:annotation" | "text:tracked-changes" | "text:note-citation" => {
+                    skipped_depth += 1
+                }
+                _ => {}
+            },
+            Event::End(e) => match e.name().as_ref() {
+                "text:p" | "text:h" => {
+                    paragraph_depth = paragraph_depth.saturating_sub(1);
+                    if skipped_depth == 0 {
+                        text.push('\n');
+                    }
+                }
+                "office:annotation" | "text:tracked-changes" | "text:note-citation" => {
+                    skipped_depth = skipped_depth.saturating_sub(1)
+                }
+                _ => {}
+            },
+            Event::Empty(e) if paragraph_depth > 0 && skipped_depth == 0 => {
+                match e.name().as_ref() {
+                    "text:tab" => text.push('\t'),
+                    "text:line-break" => text.push('\n'),
+                    "text:s" => {
+                        let count = xml::attr(&e, "text:c")
+                            .and_then(|c| c.parse().ok())
+                            .unwrap_or(1);
+                        text.push_str(&" ".repeat(count));
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(e) if paragraph_depth > 0 && skipped_depth == 0 => {
+                text.push_str(&e.xml10_content())
+            }
+            Event::GeneralRef(e) if paragraph_depth > 0 && skipped_depth == 0 => {
+                text.push_str(&xml::resolve_ref(&e)?)
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(text)
+}
@@ -0,0 +1,133 @@
+use super::remote::{cache_dir, download, get, is_pdf};
+use super::{Document, LoadOptions, load_document};
+use anyhow::{Context, Result, bail};
+use scraper::{Html, Selector};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+const PDF_OR_HTML: &str = "application/pdf, text/html;q=0.9";
+
+/// Downloads an arXiv paper's PDF (or reuses the cached copy) and loads it.
+/// Accepts bare IDs (`2403.12345`, `hep-th/9901001`), `arXiv:` IDs and
+/// abstract or PDF URLs.
+pub async fn load_arxiv(id: &str, options: &LoadOptions) -> Result<Vec<Document>> {
+    let id = id
+        .trim()
+        .trim_start_matches("arXiv:")
+        .trim_start_matches("arxiv:")
+        .trim_start_matches("https://arxiv.org/abs/")
+        .trim_start_matches("https://arxiv.org/pdf/")
+        .trim_end_matches(".pdf");
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '/' | '-'))
+    {
+        bail!("Invalid arXiv ID: {:?}", id);
+    }
+
+    let path = cache_dir("papers")?.join(format!("arxiv-{}.pdf", file_stem(id)));
+    if path.exists() {
+        info!("Using cached arXiv:{} from {:?}", id, path);
+    } else {
+        let url = format!("https://arxiv.org/pdf/{}", id);
+        info!("Downloading arXiv:{} from {}", id, url);
+        let response = get(&url, PDF_OR_HTML).await?;
+        if !is_pdf(&response) {
+            bail!("arXiv did not return a PDF for {:?}", id);
+        }
+        download(response, &path, options.max_download_bytes).await?;
+    }
+    load_cached(&path, &format!("arXiv:{}", id), options)
+}
+
+/// Resolves a DOI and loads the paper behind it: the PDF when the publisher
+/// serves one directly or advertises it in a `citation_pdf_url` tag, and the
+/// landing page's text otherwise. Downloads are cached.
+pub async fn load_doi(doi: &str, options: &LoadOptions) -> Result<Vec<Document>> {
+    let doi = doi
+        .trim()
+        .trim_start_matches("doi:")
+        .trim_start_matches("https://doi.org/")
+        .trim_start_matches("http://dx.doi.org/");
+    if !doi.starts_with("10.") || !doi.contains('/') {
+        bail!("Invalid DOI: {:?}", doi);
+    }
+
+    let dir = cache_dir("papers")?;
+    let stem = format!("doi-{}", file_stem(doi));
+    let cached = ["pdf", "html"]
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", stem, ext)))
+        .find(|path| path.exists());
+    let path = match cached {
+        Some(path) => {
+            info!("Using cached doi:{} from {:?}", doi, path);
+            path
+        }
+        None => download_doi(doi, &dir, &stem, options.max_download_bytes).await?,
+    };
+    load_cached(&path, &format!("doi:{}", doi), options)
+}
+
+async fn download_doi(doi: &str, dir: &Path, stem: &str, limit: Option<u64>) -> Result<PathBuf> {
+    let url = format!("https://doi.org/{}", doi);
+    info!("Resolving doi:{} via {}", doi, url);
+    let response = get(&url, PDF_OR_HTML).await?;
+    if is_pdf(&response) {
+        let path = dir.join(format!("{}.pdf", stem));
+        download(response, &path, limit).await?;
+        return Ok(path);
+    }
+
+    let landing_url = response.url().clone();
+    let html = response
+        .text()
+        .await
+        .with_context(|| format!("Failed to read response body from {}", landing_url))?;
+    if let Some(pdf_url) = citation_pdf_url(&html).and_then(|href| landing_url.join(&href).ok()) {
+        debug!("Landing page links the PDF at {}", pdf_url);
+        match get(pdf_url.as_str(), PDF_OR_HTML).await {
+            Ok(pdf) if is_pdf(&pdf) => {
+                let path = dir.join(format!("{}.pdf", stem));
+                download(pdf, &path, limit).await?;
+                return Ok(path);
+            }
+            Ok(_) => debug!("{} is not a PDF; using the landing page", pdf_url),
+            Err(e) => debug!("Failed to fetch the PDF, using the landing page: {}", e),
+        }
+    }
+    let path = dir.join(format!("{}.html", stem));
+    fs::write(&path, &html).with_context(|| format!("Failed to cache download: {:?}", path))?;
+    Ok(path)
+}
+
+fn load_cached(path: &Path, source: &str, options: &LoadOptions) -> Result<Vec<Document>> {
+    Ok(load_document(path, options)?
+        .into_iter()
+        .map(|document| document.with_metadata("source", source))
+        .collect())
+}
+
+/// The PDF link from the Highwire Press tags most publishers include.
+fn citation_pdf_url(html: &str) -> Option<String> {
+    let selector = Selector::parse(r#"meta[name="citation_pdf_url"]"#).expect("valid selector");
+    Html::parse_document(html)
+        .select(&selector)
+        .find_map(|meta| meta.value().attr("content"))
+        .map(str::to_string)
+}
+
+/// Makes an identifier safe to use as a file name.
+fn file_stem(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,281 @@
+use super::{
+    Document, DocumentImage, LoadOptions, Loader, pdf_columns, pdf_forms, pdf_headers,
+    pdf_headings, pdf_images, pdf_info, pdf_layout, pdf_outline, pdf_reflow, pdf_tables,
+};
+use crate::{ocr, progress};
+use anyhow::{Context, Result, bail};
+use pdf_extract::encryption::DecryptionError;
+use pdf_extract::{Document as PdfDocument, PlainTextOutput, output_doc_page};
+use pdf_layout::PageLayout;
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use std::path::Path;
+use tracing::{info, warn};
+
+pub struct PdfLoader;
+
+impl Loader for PdfLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pdf"]
+    }
+
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let (pdf, needed_password) = open_pdf(path, bytes, options)?;
+        if let Some(ranges) = &options.pages
+            && !pdf
+                .get_pages()
+                .keys()
+                .any(|&page_num| ranges.contains(page_num))
+        {
+            bail!("--pages selects none of the pages in {:?}", path);
+        }
+
+        let images = if options.extract_images {
+            let images = pdf_images::extract_images(&pdf, options.pages.as_ref());
+            info!("Extracted {} images from {:?}", images.len(), path);
+            images
+        } else {
+            Vec::new()
+        };
+
+        let text_pages = if options.force_ocr {
+            None
+        } else {
+            let pages = extract_pages(&pdf, options);
+            let text: Vec<&str> = pages.iter().map(|(_, text)| text.as_str()).collect();
+            if ocr::needs_ocr(&text.join("\n"), pages.len()) {
+                warn!("PDF has little or no extractable text, falling back to OCR");
+                None
+            } else {
+                Some(pages)
+            }
+        };
+        let mut pages = match text_pages {
+            Some(pages) => pages,
+            None => {
+                // pdftoppm would need the password on its command line, where
+                // other users can see it, so it is given a decrypted copy.
+                let bytes = match needed_password {
+                    true => {
+                        let mut copy = Vec::new();
+                        pdf.clone().save_to(&mut copy).with_context(|| {
+                            format!("Failed to write a decrypted copy of {:?}", path)
+                        })?;
+                        Cow::Owned(copy)
+                    }
+                    false => Cow::Borrowed(bytes),
+                };
+                ocr::ocr_pdf(&bytes, options.pages.as_ref())
+                    .with_context(|| format!("Failed to OCR PDF: {:?}", path))?
+            }
+        };
+        clean_pages(&mut pages, options);
+
+        let mut documents = split_sections(&pdf, pages, images);
+        documents.extend(form_documents(&pdf, options));
+        let info = pdf_info::document_info(&pdf);
+        Ok(documents
+            .into_iter()
+            .map(|document| {
+                info.iter().fold(document, |document, (key, value)| {
+                    document.with_metadata(key, value.clone())
+                })
+            })
+            .collect())
+    }
+}
+
+/// Parses a PDF and decrypts it if needed, saying whether it took a user
+/// password to open, which other tools reading `bytes` won't have.
+fn open_pdf(path: &Path, bytes: &[u8], options: &LoadOptions) -> Result<(PdfDocument, bool)> {
+    let mut pdf =
+        PdfDocument::load_mem(bytes).with_context(|| format!("Failed to parse PDF: {:?}", path))?;
+    if !pdf.is_encrypted() {
+        return Ok((pdf, false));
+    }
+
+    // Many PDFs are only protected against editing and open with an empty
+    // user password.
+    if pdf.decrypt("").is_ok() {
+        return Ok((pdf, false));
+    }
+
+    let password = match &options.pdf_password {
+        Some(password) => password.clone(),
+        None if std::io::stdin().is_terminal() => {
+            rpassword::prompt_password(format!("Password for {}: ", path.display()))
+                .context("Failed to read PDF password")?
+        }
+        None => bail!(
+            "PDF is password protected: {:?} (pass --pdf-password to decrypt it)",
+            path
+        ),
+    };
+
+    match pdf.decrypt(&password) {
+        Ok(()) => {
+            info!("Decrypted {:?}", path);
+            Ok((pdf, true))
+        }
+        Err(pdf_extract::Error::Decryption(DecryptionError::IncorrectPassword)) => {
+            bail!("Incorrect password for PDF: {:?}", path)
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to decrypt PDF: {:?}", path)),
+    }
+}
+
+/// Extracts the text layer of each selected page, in page order.
+fn extract_pages(pdf: &PdfDocument, options: &LoadOptions) -> Vec<(u32, String)> {
+    let page_nums: Vec<u32> = pdf
+        .get_pages()
+        .into_keys()
+        .filter(|&page_num| selected(options, page_num))
+        .collect();
+    if options.detect_tables || options.detect_columns || options.detect_headings {
+        return extract_layouts(pdf, page_nums, options);
+    }
+
+    let progress = progress::start("Extracting", "pages", page_nums.len());
+    page_nums
+        .into_iter()
+        .map(|page_num| {
+            let mut text = String::new();
+            let mut output = PlainTextOutput::new(&mut text);
+            if let Err(e) = output_doc_page(pdf, &mut output, page_num) {
+                warn!("Failed to extract text from page {}: {}", page_num, e);
+            }
+            progress.advance(1);
+            (page_num, text)
+        })
+        .collect()
+}
+
+/// Extracts pages with their layout, for the options that need to know
+/// where text sits. Headings are marked over the whole document at once,
+/// since a page on its own can't tell a heading's size from the body's.
+fn extract_layouts(
+    pdf: &PdfDocument,
+    page_nums: Vec<u32>,
+    options: &LoadOptions,
+) -> Vec<(u32, String)> {
+    let progress = progress::start("Extracting", "pages", page_nums.len());
+    let mut layouts: Vec<PageLayout> = page_nums
+        .iter()
+        .map(|&page_num| {
+            let layout = match pdf_layout::page_layout(pdf, page_num) {
+                Ok(layout) if options.detect_columns => pdf_columns::reading_order(&layout),
+                Ok(layout) => layout,
+                Err(e) => {
+                    warn!("Failed to extract text from page {}: {}", page_num, e);
+                    PageLayout::default()
+                }
+            };
+            progress.advance(1);
+            layout
+        })
+        .collect();
+    drop(progress);
+    if options.detect_headings {
+        pdf_headings::mark_headings(&mut layouts);
+    }
+
+    page_nums
+        .into_iter()
+        .zip(layouts)
+        .map(|(page_num, layout)| {
+            let text = if options.detect_tables {
+                pdf_tables::render_with_tables(&layout)
+            } else {
+                pdf_columns::layout_text(&layout)
+            };
+            (page_num, text)
+        })
+        .collect()
+}
+
+/// Returns the filled-in form fields as `name: value` text, one document per
+/// page, kept apart from the page text so a question about a field finds
+/// its value directly.
+fn form_documents(pdf: &PdfDocument, options: &LoadOptions) -> Vec<Document> {
+    let fields = pdf_forms::form_fields(pdf, options.pages.as_ref());
+    if !fields.is_empty() {
+        info!("Extracted {} filled-in form fields", fields.len());
+    }
+    pdf_forms::group_by_page(fields)
+        .into_iter()
+        .map(|(page, text)| {
+            let document = Document::new(text).with_metadata("content", "form fields");
+            match page {
+                Some(page) => document.with_metadata("page", page.to_string()),
+                None => document,
+            }
+        })
+        .collect()
+}
+
+/// Strips running headers and footers (unless asked to keep them), then
+/// rejoins hyphenated words and hard-wrapped lines.
+fn clean_pages(pages: &mut [(u32, String)], options: &LoadOptions) {
+    if !options.keep_headers {
+        pdf_headers::strip_headers(pages);
+    }
+    for (_, text) in pages.iter_mut() {
+        *text = pdf_reflow::reflow(text);
+    }
+}
+
+fn selected(options: &LoadOptions, page_num: u32) -> bool {
+    options
+        .pages
+        .as_ref()
+        .is_none_or(|ranges| ranges.contains(page_num))
+}
+
+/// Groups consecutive pages under the same outline entry into one document
+/// tagged with the section title. PDFs without an outline become a single
+/// document. Images go with the section of the page they appear on.
+fn split_sections(
+    pdf: &PdfDocument,
+    pages: Vec<(u32, String)>,
+    images: Vec<DocumentImage>,
+) -> Vec<Document> {
+    let bookmarks = pdf_outline::bookmarks(pdf);
+    let mut sections: Vec<(Option<String>, Vec<u32>, Vec<String>)> = Vec::new();
+    for (page_num, text) in pages {
+        let section = pdf_outline::section_for(&bookmarks, page_num).map(|b| b.title());
+        match sections.last_mut() {
+            Some((title, page_nums, texts)) if *title == section => {
+                page_nums.push(page_num);
+                texts.push(text);
+            }
+            _ => sections.push((section, vec![page_num], vec![text])),
+        }
+    }
+
+    let mut images = images;
+    sections
+        .into_iter()
+        .map(|(title, page_nums, texts)| {
+            let (section_images, rest) = images
+                .drain(..)
+                .partition(|image| page_nums.contains(&image.page));
+            images = rest;
+            // Pages are kept apart by form feeds, so chunks can be traced
+            // back to the pages they came from.
+            let document = Document {
+                images: section_images,
+                pages: page_nums,
+                ..Document::new(texts.join("\u{c}"))
+            };
+            match title {
+                Some(title) => document.with_metadata("section", title),
+                None => document,
+            }
+        })
+        .collect()
+}
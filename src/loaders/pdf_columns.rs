@@ -0,0 +1,153 @@
+//! Reading-order reconstruction for multi-column pages. Positioned lines
+//! run straight across the page, so a two-column paper reads one line from
+//! each column in turn; this finds the gutters between columns and reads
+//! each column top to bottom instead.
+
+use super::pdf_layout::{Line, PageLayout, Word};
+
+/// Gap between words, in multiples of the font size, wide enough to be a
+/// gutter rather than a space.
+const GUTTER_EMS: f64 = 1.0;
+
+/// Share of a region's lines that must have text on both sides of a gutter
+/// for it to count as columns rather than ragged or indented text.
+const MIN_TWO_SIDED: f64 = 0.3;
+
+/// Share of a region's lines that must not cross the gutter.
+const MIN_SPLIT: f64 = 0.6;
+
+/// A column block needs this many lines; shorter runs are more likely a
+/// table row or a stray label next to the text.
+const MIN_BLOCK_LINES: usize = 3;
+
+/// Three columns are split as a gutter within a column; anything deeper is
+/// more likely a table.
+const MAX_DEPTH: usize = 2;
+
+/// Returns the page's lines in reading order: lines spanning the page
+/// (titles, abstracts, full-width figures) stay where they are, and in
+/// between them each column is read top to bottom, left to right.
+pub fn reading_order(page: &PageLayout) -> PageLayout {
+    PageLayout {
+        lines: order(page.lines.clone(), 0),
+    }
+}
+
+/// Renders lines as text, with a blank line wherever the vertical gap to
+/// the line above is wider than usual line spacing, so paragraph breaks
+/// survive for reflowing.
+pub fn layout_text(page: &PageLayout) -> String {
+    let mut text = String::new();
+    for (i, line) in page.lines.iter().enumerate() {
+        if i > 0 {
+            let gap = line.y - page.lines[i - 1].y;
+            text.push_str(if gap > line.font_size() * 1.8 {
+                "\n\n"
+            } else {
+                "\n"
+            });
+        }
+        text.push_str(&line.text());
+    }
+    text
+}
+
+fn order(lines: Vec<Line>, depth: usize) -> Vec<Line> {
+    if depth >= MAX_DEPTH || lines.len() < MIN_BLOCK_LINES {
+        return lines;
+    }
+    let Some(gutter) = find_gutter(&lines) else {
+        return lines;
+    };
+
+    let mut ordered = Vec::with_capacity(lines.len());
+    let mut block: Vec<Line> = Vec::new();
+    for line in lines {
+        if splits(&line, gutter) {
+            block.push(line);
+        } else {
+            flush(&mut block, gutter, depth, &mut ordered);
+            ordered.push(line);
+        }
+    }
+    flush(&mut block, gutter, depth, &mut ordered);
+    ordered
+}
+
+/// Emits a run of lines split by the gutter: the left column, then the
+/// right, each checked for further columns.
+fn flush(block: &mut Vec<Line>, gutter: f64, depth: usize, ordered: &mut Vec<Line>) {
+    if block.len() < MIN_BLOCK_LINES {
+        ordered.append(block);
+        return;
+    }
+    let (mut left, mut right) = (Vec::new(), Vec::new());
+    for line in block.drain(..) {
+        let (left_words, right_words): (Vec<Word>, Vec<Word>) =
+            line.words.into_iter().partition(|word| word.x1 <= gutter);
+        for (words, side) in [(left_words, &mut left), (right_words, &mut right)] {
+            if !words.is_empty() {
+                side.push(Line { words, y: line.y });
+            }
+        }
+    }
+    ordered.extend(order(left, depth + 1));
+    ordered.extend(order(right, depth + 1));
+}
+
+/// True when no word of the line crosses `x` and any words on either side
+/// of it are separated by a gutter-wide gap.
+fn splits(line: &Line, x: f64) -> bool {
+    let left = line.words.iter().rfind(|word| word.x1 <= x);
+    let right = line.words.iter().find(|word| word.x0 >= x);
+    let crossed = line.words.iter().any(|word| word.x0 < x && word.x1 > x);
+    match (left, right) {
+        _ if crossed => false,
+        (Some(left), Some(right)) => {
+            right.x0 - left.x1 >= left.font_size.max(right.font_size) * GUTTER_EMS
+        }
+        _ => true,
+    }
+}
+
+fn two_sided(line: &Line, x: f64) -> bool {
+    line.words.iter().any(|word| word.x1 <= x) && line.words.iter().any(|word| word.x0 >= x)
+}
+
+/// Finds the x position, in the middle of the text's extent, that the most
+/// lines split at, if enough of them do.
+fn find_gutter(lines: &[Line]) -> Option<f64> {
+    let words = || lines.iter().flat_map(|line| &line.words);
+    let left = words().map(|word| word.x0).fold(f64::INFINITY, f64::min);
+    let right = words()
+        .map(|word| word.x1)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let width = right - left;
+    if !width.is_finite() || width <= 0.0 {
+        return None;
+    }
+
+    // Candidate gutters are the gaps lines already have, not every point.
+    let mut candidates: Vec<f64> = lines
+        .iter()
+        .flat_map(|line| line.words.windows(2))
+        .map(|pair| (pair[0].x1 + pair[1].x0) / 2.0)
+        .filter(|x| *x > left + width * 0.25 && *x < right - width * 0.25)
+        .collect();
+    candidates.sort_by(f64::total_cmp);
+    candidates.dedup_by(|a, b| (*a - *b).abs() < 1.0);
+
+    let count = lines.len() as f64;
+    candidates
+        .into_iter()
+        .map(|x| {
+            let split = lines.iter().filter(|line| splits(line, x)).count();
+            let two_sided = lines.iter().filter(|line| two_sided(line, x)).count();
+            (x, split, two_sided)
+        })
+        .filter(|&(_, split, two_sided)| {
+            split as f64 >= count * MIN_SPLIT && two_sided as f64 >= count * MIN_TWO_SIDED
+        })
+        .max_by_key(|&(_, split, two_sided)| (split, two_sided))
+        .map(|(x, _, _)| x)
+}
@@ -0,0 +1,168 @@
+//! Filled-in values of a PDF's interactive form (AcroForm) fields.
+
+use super::PageRanges;
+use pdf_extract::{Document as PdfDocument, Object, ObjectId, decode_text_string};
+use std::collections::{BTreeMap, HashMap};
+use tracing::debug;
+
+/// Forms nest fields in a tree; anything deeper than this is malformed.
+const MAX_DEPTH: usize = 32;
+
+/// A field that has a value, with the page its widget is drawn on.
+#[derive(Debug)]
+pub struct FormField {
+    pub page: Option<u32>,
+    pub label: String,
+    pub value: String,
+}
+
+/// Returns every form field with a value, in the order the form lists them.
+/// Fields are labelled with their tooltip (which forms usually set to the
+/// question being asked, like "Insured name") and otherwise their full name.
+/// Signature fields and fields on pages outside `pages` are left out.
+pub fn form_fields(pdf: &PdfDocument, pages: Option<&PageRanges>) -> Vec<FormField> {
+    let Some(fields) = pdf
+        .catalog()
+        .and_then(|catalog| catalog.get_deref(b"AcroForm", pdf))
+        .and_then(Object::as_dict)
+        .and_then(|form| form.get_deref(b"Fields", pdf))
+        .and_then(Object::as_array)
+        .ok()
+    else {
+        return Vec::new();
+    };
+
+    // Widgets are found through the pages' annotations, since the `/P` entry
+    // pointing back at the page is optional.
+    let mut widget_pages = HashMap::new();
+    for (page_num, page_id) in pdf.get_pages() {
+        let annotations = pdf
+            .get_dictionary(page_id)
+            .and_then(|page| page.get_deref(b"Annots", pdf))
+            .and_then(Object::as_array);
+        for annotation in annotations.into_iter().flatten() {
+            if let Ok(id) = annotation.as_reference() {
+                widget_pages.insert(id, page_num);
+            }
+        }
+    }
+
+    let mut form_fields = Vec::new();
+    for field in fields {
+        walk(pdf, field, "", None, &widget_pages, 0, &mut form_fields);
+    }
+    debug!("Found {} filled-in form fields", form_fields.len());
+    form_fields.retain(|field| {
+        field
+            .page
+            .is_none_or(|page| pages.is_none_or(|ranges| ranges.contains(page)))
+    });
+    form_fields
+}
+
+/// Renders fields as `label: value` lines, one document's worth per page.
+pub fn group_by_page(fields: Vec<FormField>) -> BTreeMap<Option<u32>, String> {
+    let mut pages: BTreeMap<Option<u32>, String> = BTreeMap::new();
+    for field in fields {
+        let text = pages.entry(field.page).or_default();
+        text.push_str(&format!("{}: {}\n", field.label, field.value));
+    }
+    pages
+}
+
+/// Visits a field and its descendants. A field's name is its partial name
+/// (`/T`) appended to its parent's, and its type is inherited; only the
+/// terminal fields, whose kids are just widgets, carry values.
+fn walk(
+    pdf: &PdfDocument,
+    field: &Object,
+    parent_name: &str,
+    parent_type: Option<&[u8]>,
+    widget_pages: &HashMap<ObjectId, u32>,
+    depth: usize,
+    form_fields: &mut Vec<FormField>,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let Ok((id, Object::Dictionary(dict))) = pdf.dereference(field) else {
+        return;
+    };
+    let name = match dict.get(b"T").ok().and_then(|t| decode_text_string(t).ok()) {
+        Some(partial) if parent_name.is_empty() => partial,
+        Some(partial) => format!("{}.{}", parent_name, partial),
+        None => parent_name.to_string(),
+    };
+    let field_type = dict
+        .get(b"FT")
+        .and_then(Object::as_name)
+        .ok()
+        .or(parent_type);
+    let kids: Vec<&Object> = dict
+        .get_deref(b"Kids", pdf)
+        .and_then(Object::as_array)
+        .map(|kids| kids.iter().collect())
+        .unwrap_or_default();
+
+    let child_fields: Vec<&Object> = kids
+        .iter()
+        .copied()
+        .filter(|kid| {
+            pdf.dereference(kid)
+                .ok()
+                .and_then(|(_, kid)| kid.as_dict().ok())
+                .is_some_and(|kid| kid.has(b"T"))
+        })
+        .collect();
+    if !child_fields.is_empty() {
+        for child in child_fields {
+            walk(
+                pdf,
+                child,
+                &name,
+                field_type,
+                widget_pages,
+                depth + 1,
+                form_fields,
+            );
+        }
+        return;
+    }
+
+    if field_type == Some(b"Sig") {
+        return;
+    }
+    let Some(value) = dict.get_deref(b"V", pdf).ok().and_then(|v| render(pdf, v)) else {
+        return;
+    };
+    let page = id
+        .into_iter()
+        .chain(kids.iter().filter_map(|kid| kid.as_reference().ok()))
+        .find_map(|widget| widget_pages.get(&widget).copied());
+    let label = dict
+        .get(b"TU")
+        .ok()
+        .and_then(|tooltip| decode_text_string(tooltip).ok())
+        .map(|tooltip| tooltip.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|tooltip| !tooltip.is_empty())
+        .unwrap_or(name);
+    form_fields.push(FormField { page, label, value });
+}
+
+/// Text fields hold strings, checkboxes and radio buttons the name of the
+/// selected state (`Yes`, `Off`, ...), and multi-select lists an array.
+fn render(pdf: &PdfDocument, value: &Object) -> Option<String> {
+    let text = match value {
+        Object::String(..) => decode_text_string(value).ok()?,
+        Object::Name(name) => String::from_utf8_lossy(name).into_owned(),
+        Object::Array(values) => values
+            .iter()
+            .filter_map(|value| pdf.dereference(value).ok())
+            .filter_map(|(_, value)| render(pdf, value))
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => return None,
+    };
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
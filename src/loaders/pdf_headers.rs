@@ -0,0 +1,101 @@
+//! Removal of running headers, footers and page numbers: the lines printed
+//! at the top or bottom of most pages, which would otherwise end up in
+//! nearly every chunk.
+
+use std::collections::HashMap;
+use tracing::debug;
+
+/// How many non-blank lines at each end of a page are checked.
+const EDGE_LINES: usize = 3;
+
+/// Fewer pages than this are too few to tell a running header from text
+/// that happens to repeat.
+const MIN_PAGES: usize = 3;
+
+/// A line is a header or footer when it appears at the edge of at least
+/// this share of the pages.
+const MIN_SHARE: f64 = 0.5;
+
+/// Only lines up to this many words have their digits masked; longer ones
+/// are sentences that happen to contain numbers, not page numbers.
+const MAX_NUMBERED_WORDS: usize = 6;
+
+/// Strips lines repeated at the top or bottom of many pages. Short lines are
+/// compared with their digits masked, so `Page 3 of 40` on one page matches
+/// `Page 4 of 40` on the next and bare page numbers match each other.
+pub fn strip_headers(pages: &mut [(u32, String)]) {
+    if pages.len() < MIN_PAGES {
+        return;
+    }
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for (_, text) in pages.iter() {
+        let mut seen: Vec<String> = edge_lines(text).map(|(_, line)| normalize(line)).collect();
+        seen.sort();
+        seen.dedup();
+        for line in seen {
+            *counts.entry(line).or_default() += 1;
+        }
+    }
+    let threshold = ((pages.len() as f64 * MIN_SHARE).ceil() as usize).max(MIN_PAGES);
+    counts.retain(|line, count| *count >= threshold && !line.is_empty());
+    if counts.is_empty() {
+        return;
+    }
+    debug!("Stripping {} running headers and footers", counts.len());
+
+    for (_, text) in pages.iter_mut() {
+        let repeated: Vec<usize> = edge_lines(text)
+            .filter(|(_, line)| counts.contains_key(&normalize(line)))
+            .map(|(index, _)| index)
+            .collect();
+        if repeated.is_empty() {
+            continue;
+        }
+        *text = text
+            .lines()
+            .enumerate()
+            .filter(|(index, _)| !repeated.contains(index))
+            .map(|(_, line)| line)
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+/// The first and last few non-blank lines of a page, with their indices.
+fn edge_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+    let count = lines.len();
+    lines
+        .into_iter()
+        .enumerate()
+        .filter(move |(position, _)| *position < EDGE_LINES || position + EDGE_LINES >= count)
+        .map(|(_, line)| line)
+}
+
+fn normalize(line: &str) -> String {
+    let mask_digits = line.split_whitespace().count() <= MAX_NUMBERED_WORDS;
+    let mut normalized = String::new();
+    for word in line.split_whitespace() {
+        if !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        let mut last_digit = false;
+        for c in word.chars().flat_map(char::to_lowercase) {
+            if mask_digits && c.is_ascii_digit() {
+                if !last_digit {
+                    normalized.push('#');
+                }
+                last_digit = true;
+            } else {
+                normalized.push(c);
+                last_digit = false;
+            }
+        }
+    }
+    normalized
+}
@@ -0,0 +1,74 @@
+//! Heading detection for PDFs by font size. PDFs rarely tag their
+//! headings, but they are nearly always set larger than the body text; this
+//! finds those lines and marks them as Markdown headings so the chunker can
+//! split sections at them.
+
+use super::pdf_layout::{PageLayout, Word};
+
+/// A line set at least this many times the body text size is a heading.
+const MIN_HEADING_SCALE: f64 = 1.2;
+
+/// Headings are short; a longer large line is more likely a pull quote or
+/// a title page blurb.
+const MAX_HEADING_WORDS: usize = 12;
+
+/// Heading sizes beyond the third largest are all treated as level 3.
+const MAX_LEVEL: usize = 3;
+
+/// Marks the headings across a document's pages by prefixing their lines
+/// with `#`, `##` or `###`. Levels rank the heading sizes found, largest
+/// first, so a document's biggest headings are level 1 wherever they fall.
+pub fn mark_headings(pages: &mut [PageLayout]) {
+    let Some(body) = body_size(pages).filter(|&size| size > 0.0) else {
+        return;
+    };
+    let is_heading =
+        |size: f64, words: usize| size >= body * MIN_HEADING_SCALE && words <= MAX_HEADING_WORDS;
+
+    let mut sizes: Vec<f64> = pages
+        .iter()
+        .flat_map(|page| &page.lines)
+        .map(|line| (line.font_size(), line.words.len()))
+        .filter(|&(size, words)| is_heading(size, words))
+        .map(|(size, _)| size)
+        .collect();
+    sizes.sort_by(|a, b| b.total_cmp(a));
+    // Sizes within half a point are the same style, rounded differently.
+    sizes.dedup_by(|a, b| (*a - *b).abs() < 0.5);
+
+    for line in pages.iter_mut().flat_map(|page| &mut page.lines) {
+        let size = line.font_size();
+        if !is_heading(size, line.words.len()) || line.text().starts_with('|') {
+            continue;
+        }
+        let rank = sizes
+            .iter()
+            .position(|&s| (s - size).abs() < 0.5)
+            .unwrap_or_default();
+        let first = &line.words[0];
+        let marker = Word {
+            text: "#".repeat((rank + 1).min(MAX_LEVEL)),
+            x1: first.x0,
+            ..first.clone()
+        };
+        line.words.insert(0, marker);
+    }
+}
+
+/// The size most of the text is set in: the median over all words,
+/// weighted by their length.
+fn body_size(pages: &[PageLayout]) -> Option<f64> {
+    let mut sizes: Vec<(f64, usize)> = pages
+        .iter()
+        .flat_map(|page| &page.lines)
+        .flat_map(|line| &line.words)
+        .map(|word| (word.font_size, word.text.chars().count()))
+        .collect();
+    sizes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let total: usize = sizes.iter().map(|&(_, chars)| chars).sum();
+    let mut seen = 0;
+    sizes.into_iter().find_map(|(size, chars)| {
+        seen += chars;
+        (seen * 2 >= total).then_some(size)
+    })
+}
@@ -0,0 +1,91 @@
+//! Extraction of embedded raster images from PDFs, re-encoded into formats
+//! vision models accept.
+
+use super::{DocumentImage, ImageFormat, PageRanges};
+use flate2::read::ZlibDecoder;
+use pdf_extract::xobject::PdfImage;
+use std::io::Read;
+use tracing::debug;
+
+/// Images smaller than this on either side are icons, bullets or rules
+/// rather than figures.
+const MIN_IMAGE_SIDE: i64 = 64;
+
+/// Returns the embedded images of every selected page, in page order.
+/// Images in encodings we cannot hand to a vision model are skipped.
+pub fn extract_images(
+    pdf: &pdf_extract::Document,
+    pages: Option<&PageRanges>,
+) -> Vec<DocumentImage> {
+    let mut images = Vec::new();
+    for (page_num, page_id) in pdf.get_pages() {
+        if pages.is_some_and(|ranges| !ranges.contains(page_num)) {
+            continue;
+        }
+        let page_images = match pdf.get_page_images(page_id) {
+            Ok(page_images) => page_images,
+            // Pages without an XObject dictionary have no images at all.
+            Err(_) => continue,
+        };
+        for image in page_images {
+            if image.width < MIN_IMAGE_SIDE || image.height < MIN_IMAGE_SIDE {
+                continue;
+            }
+            match encode(&image) {
+                Some((format, data)) => images.push(DocumentImage {
+                    page: page_num,
+                    format,
+                    data,
+                }),
+                None => debug!(
+                    "Skipping image {:?} on page {} ({:?}, {:?})",
+                    image.id, page_num, image.filters, image.color_space
+                ),
+            }
+        }
+    }
+    images
+}
+
+fn encode(image: &PdfImage) -> Option<(ImageFormat, Vec<u8>)> {
+    let filters = image.filters.as_deref().unwrap_or_default();
+    if filters.iter().any(|f| f == "DCTDecode") {
+        // JPEG data can be passed through untouched, as long as it is the
+        // only filter applied.
+        return (filters.len() == 1).then(|| (ImageFormat::Jpeg, image.content.to_vec()));
+    }
+
+    let color = match image.color_space.as_deref() {
+        Some("DeviceRGB") => png::ColorType::Rgb,
+        Some("DeviceGray") => png::ColorType::Grayscale,
+        _ => return None,
+    };
+    if image.bits_per_component != Some(8) {
+        return None;
+    }
+    // lopdf refuses to decode image streams, so inflate them ourselves.
+    // PNG-style predictors (set through DecodeParms) are not undone here.
+    if image.origin_dict.has(b"DecodeParms") {
+        return None;
+    }
+    let pixels = match filters {
+        [] => image.content.to_vec(),
+        [filter] if filter == "FlateDecode" => {
+            let mut pixels = Vec::new();
+            ZlibDecoder::new(image.content)
+                .read_to_end(&mut pixels)
+                .ok()?;
+            pixels
+        }
+        _ => return None,
+    };
+
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, image.width as u32, image.height as u32);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().ok()?;
+    writer.write_image_data(&pixels).ok()?;
+    writer.finish().ok()?;
+    Some((ImageFormat::Png, data))
+}
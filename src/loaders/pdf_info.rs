@@ -0,0 +1,48 @@
+//! Document-level metadata from the PDF info dictionary.
+
+use pdf_extract::{Document as PdfDocument, Object, decode_text_string};
+
+/// Info dictionary entries copied onto documents, and the metadata keys
+/// they are stored under.
+const FIELDS: &[(&[u8], &str)] = &[
+    (b"Title", "title"),
+    (b"Author", "author"),
+    (b"CreationDate", "created"),
+    (b"Keywords", "keywords"),
+];
+
+/// Returns the title, author, creation date (as `YYYY-MM-DD`) and keywords
+/// the PDF declares, skipping any that are missing or blank.
+pub fn document_info(pdf: &PdfDocument) -> Vec<(&'static str, String)> {
+    let Ok(info) = pdf
+        .trailer
+        .get_deref(b"Info", pdf)
+        .and_then(Object::as_dict)
+    else {
+        return Vec::new();
+    };
+    FIELDS
+        .iter()
+        .filter_map(|&(field, key)| {
+            let value = decode_text_string(info.get_deref(field, pdf).ok()?).ok()?;
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            let value = match key {
+                "created" => date(&value),
+                _ => value,
+            };
+            (!value.is_empty()).then_some((key, value))
+        })
+        .collect()
+}
+
+/// Converts a PDF date (`D:20240315093000+01'00'`) to `2024-03-15`. Dates
+/// with less than a day (`D:2024`) are kept as written, minus the prefix.
+fn date(value: &str) -> String {
+    let value = value.strip_prefix("D:").unwrap_or(value);
+    match value.get(..8) {
+        Some(d) if d.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{}-{}-{}", &d[..4], &d[4..6], &d[6..8])
+        }
+        _ => value.to_string(),
+    }
+}
@@ -0,0 +1,165 @@
+//! Positioned text extraction for PDFs. `pdf_extract`'s plain-text output
+//! throws away where characters sit on the page; this keeps it so tables
+//! and other layout can be reconstructed.
+
+use anyhow::Result;
+use pdf_extract::{
+    Document as PdfDocument, MediaBox, OutputDev, OutputError, Transform, output_doc_page,
+};
+
+/// A run of characters on one baseline, in PDF points from the top-left.
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub text: String,
+    pub x0: f64,
+    pub x1: f64,
+    pub y: f64,
+    pub font_size: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    /// Words sorted left to right.
+    pub words: Vec<Word>,
+    pub y: f64,
+}
+
+impl Line {
+    pub fn text(&self) -> String {
+        self.words
+            .iter()
+            .map(|w| w.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn font_size(&self) -> f64 {
+        self.words.iter().map(|w| w.font_size).fold(0.0, f64::max)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PageLayout {
+    /// Lines sorted top to bottom.
+    pub lines: Vec<Line>,
+}
+
+/// Extracts the positioned words of one page, grouped into lines.
+pub fn page_layout(pdf: &PdfDocument, page_num: u32) -> Result<PageLayout> {
+    let mut output = LayoutOutput::default();
+    output_doc_page(pdf, &mut output, page_num)?;
+    Ok(output.finish())
+}
+
+#[derive(Default)]
+struct LayoutOutput {
+    height: f64,
+    words: Vec<Word>,
+    current: Option<Word>,
+}
+
+impl LayoutOutput {
+    fn flush_word(&mut self) {
+        if let Some(word) = self.current.take()
+            && !word.text.trim().is_empty()
+        {
+            self.words.push(word);
+        }
+    }
+
+    fn finish(mut self) -> PageLayout {
+        self.flush_word();
+        let mut words = self.words;
+        words.sort_by(|a, b| a.y.total_cmp(&b.y).then(a.x0.total_cmp(&b.x0)));
+
+        let mut lines: Vec<Line> = Vec::new();
+        for word in words {
+            match lines.last_mut() {
+                Some(line) if (word.y - line.y).abs() <= word.font_size.max(1.0) * 0.5 => {
+                    line.words.push(word)
+                }
+                _ => lines.push(Line {
+                    y: word.y,
+                    words: vec![word],
+                }),
+            }
+        }
+        for line in &mut lines {
+            line.words.sort_by(|a, b| a.x0.total_cmp(&b.x0));
+        }
+
+        PageLayout { lines }
+    }
+}
+
+impl OutputDev for LayoutOutput {
+    fn begin_page(
+        &mut self,
+        _page_num: u32,
+        media_box: &MediaBox,
+        _art_box: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        self.height = media_box.ury - media_box.lly;
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        self.flush_word();
+        Ok(())
+    }
+
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        _spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        // Same scaling pdf_extract's plain-text output uses for font size.
+        let scale_x = font_size * trm.m11 + font_size * trm.m21;
+        let scale_y = font_size * trm.m12 + font_size * trm.m22;
+        let size = (scale_x * scale_y).abs().sqrt();
+        let x = trm.m31;
+        let y = self.height - trm.m32;
+        let end = x + width * size;
+
+        let continues_word = self.current.as_ref().is_some_and(|word| {
+            (word.y - y).abs() <= size * 0.5 && x >= word.x0 && x <= word.x1 + size * 0.1
+        });
+        if !continues_word || char.trim().is_empty() {
+            self.flush_word();
+        }
+        if char.trim().is_empty() {
+            return Ok(());
+        }
+        match &mut self.current {
+            Some(word) => {
+                word.text.push_str(char);
+                word.x1 = end;
+            }
+            None => {
+                self.current = Some(Word {
+                    text: char.to_string(),
+                    x0: x,
+                    x1: end,
+                    y,
+                    font_size: size,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+//! Section titles from the PDF outline (the bookmark tree shown in a
+//! viewer's sidebar), resolved to the pages they point at.
+
+use pdf_extract::{Document as PdfDocument, Object, Outline, decode_text_string};
+use std::collections::HashMap;
+use tracing::debug;
+
+/// An outline entry, with the titles of its ancestors so nested sections
+/// read like `4 Setup > 4.2 Installation`.
+#[derive(Debug, Clone)]
+pub struct Bookmark {
+    pub page: u32,
+    pub path: Vec<String>,
+}
+
+impl Bookmark {
+    pub fn title(&self) -> String {
+        self.path.join(" > ")
+    }
+}
+
+/// Reads the outline in document order. PDFs without one (or with one we
+/// cannot parse) yield no bookmarks.
+pub fn bookmarks(pdf: &PdfDocument) -> Vec<Bookmark> {
+    let outlines = match pdf.get_outlines(None, None, &mut Default::default()) {
+        Ok(Some(outlines)) => outlines,
+        Ok(None) => return Vec::new(),
+        Err(e) => {
+            debug!("No usable PDF outline: {}", e);
+            return Vec::new();
+        }
+    };
+    let page_numbers: HashMap<_, _> = pdf
+        .get_pages()
+        .into_iter()
+        .map(|(page_num, page_id)| (page_id, page_num))
+        .collect();
+
+    let mut bookmarks = Vec::new();
+    walk(&outlines, &page_numbers, &mut Vec::new(), &mut bookmarks);
+    bookmarks
+}
+
+fn walk(
+    outlines: &[Outline],
+    page_numbers: &HashMap<(u32, u16), u32>,
+    path: &mut Vec<String>,
+    bookmarks: &mut Vec<Bookmark>,
+) {
+    // Children follow their parent entry as a nested list, so remember the
+    // last title seen at this level to put in front of them.
+    let depth = path.len();
+    for outline in outlines {
+        match outline {
+            Outline::Destination(destination) => {
+                path.truncate(depth);
+                let Some(title) = destination
+                    .title()
+                    .and_then(|title| decode_text_string(title).ok())
+                    .map(|title| title.split_whitespace().collect::<Vec<_>>().join(" "))
+                else {
+                    continue;
+                };
+                path.push(title);
+                let page = destination
+                    .page()
+                    .and_then(|page| Object::as_reference(page).ok())
+                    .and_then(|page_id| page_numbers.get(&page_id));
+                if let Some(&page) = page {
+                    bookmarks.push(Bookmark {
+                        page,
+                        path: path.clone(),
+                    });
+                }
+            }
+            Outline::SubOutlines(children) => {
+                walk(children, page_numbers, path, bookmarks);
+                path.truncate(depth + 1);
+            }
+        }
+    }
+    path.truncate(depth);
+}
+
+/// Returns the innermost bookmark covering a page: the last one that starts
+/// on or before it.
+pub fn section_for(bookmarks: &[Bookmark], page: u32) -> Option<&Bookmark> {
+    bookmarks
+        .iter()
+        .filter(|bookmark| bookmark.page <= page)
+        .max_by_key(|bookmark| bookmark.page)
+}
@@ -0,0 +1,101 @@
+//! Repair of the line structure in extracted PDF text: words hyphenated
+//! across a line break are rejoined and the hard-wrapped lines of a
+//! paragraph are reflowed into one, so sentences reach the chunker and the
+//! embedding model whole.
+
+/// A line shorter than this share of the paragraph's longest line ends
+/// where the text does rather than where the column does.
+const SHORT_LINE: f64 = 0.7;
+
+/// Reflows each paragraph (a run of non-blank lines) onto a single line,
+/// with paragraphs separated by blank lines. Line breaks are kept before
+/// list items and around Markdown table rows and headings, and after short lines
+/// followed by a capital, which usually end a paragraph or a heading.
+pub fn reflow(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().chain([""]) {
+        let line = line.trim();
+        if !line.is_empty() {
+            lines.push(line);
+            continue;
+        }
+        if lines.is_empty() {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str("\n\n");
+        }
+        reflow_paragraph(&lines, &mut out);
+        lines.clear();
+    }
+    out
+}
+
+fn reflow_paragraph(lines: &[&str], out: &mut String) {
+    let longest = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or_default();
+    let short = (longest as f64 * SHORT_LINE) as usize;
+
+    out.push_str(lines[0]);
+    for pair in lines.windows(2) {
+        let (previous, line) = (pair[0], pair[1]);
+        let starts_lowercase = line.chars().next().is_some_and(char::is_lowercase);
+        let keep_break = is_table_row(previous)
+            || is_table_row(line)
+            || is_heading(previous)
+            || is_heading(line)
+            || is_list_item(line)
+            || (previous.chars().count() < short && !starts_lowercase);
+        if keep_break {
+            out.push('\n');
+        } else if starts_lowercase && is_hyphenated(previous) {
+            out.pop();
+        } else {
+            out.push(' ');
+        }
+        out.push_str(line);
+    }
+}
+
+/// True for a line ending in a word broken with a hyphen, like `informa-`.
+/// Dashes after spaces or other dashes are left alone.
+fn is_hyphenated(line: &str) -> bool {
+    let mut chars = line.chars().rev();
+    chars.next() == Some('-') && chars.next().is_some_and(char::is_alphabetic)
+}
+
+fn is_table_row(line: &str) -> bool {
+    line.starts_with('|')
+}
+
+fn is_heading(line: &str) -> bool {
+    line.starts_with("# ") || line.starts_with("## ") || line.starts_with("### ")
+}
+
+/// Bullets (`•`, `-`, `*`) and numbered or lettered items (`1.`, `2)`,
+/// `(a)`).
+fn is_list_item(line: &str) -> bool {
+    if ["• ", "◦ ", "▪ ", "- ", "* ", "– "]
+        .iter()
+        .any(|bullet| line.starts_with(bullet))
+    {
+        return true;
+    }
+    let Some((marker, _)) = line.split_once(' ') else {
+        return false;
+    };
+    let label = marker
+        .strip_prefix('(')
+        .and_then(|marker| marker.strip_suffix(')'))
+        .or_else(|| marker.strip_suffix('.'))
+        .or_else(|| marker.strip_suffix(')'));
+    label.is_some_and(|label| {
+        (1..=3).contains(&label.len())
+            && (label.chars().all(|c| c.is_ascii_digit())
+                || (label.len() == 1 && label.chars().all(|c| c.is_ascii_lowercase())))
+    })
+}
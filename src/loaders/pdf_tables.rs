@@ -0,0 +1,147 @@
+//! Heuristic table detection over positioned PDF text. Runs of consecutive
+//! lines that split into the same column layout are rendered as Markdown
+//! tables instead of being flattened into word soup.
+
+use super::pdf_layout::{Line, PageLayout};
+
+/// Horizontal gap, in multiples of the font size, that separates two cells.
+const CELL_GAP_EMS: f64 = 1.5;
+/// Vertical gap, in multiples of the font size, after which a row no longer
+/// belongs to the table above it.
+const ROW_GAP_EMS: f64 = 3.0;
+/// Tables hold short cells; longer runs are prose laid out in columns.
+const MAX_WORDS_PER_CELL: f64 = 5.0;
+
+struct Cell {
+    text: String,
+    x0: f64,
+    x1: f64,
+    words: usize,
+}
+
+/// Renders a page as text, replacing detected tables with Markdown tables.
+pub fn render_with_tables(page: &PageLayout) -> String {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < page.lines.len() {
+        match detect_table(&page.lines[i..]) {
+            Some((table, rows)) => {
+                out.push(table);
+                i += rows;
+            }
+            None => {
+                out.push(page.lines[i].text());
+                i += 1;
+            }
+        }
+    }
+    out.join("\n")
+}
+
+fn split_cells(line: &Line) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = Vec::new();
+    for word in &line.words {
+        match cells.last_mut() {
+            Some(cell) if word.x0 - cell.x1 < word.font_size * CELL_GAP_EMS => {
+                cell.text.push(' ');
+                cell.text.push_str(&word.text);
+                cell.x1 = word.x1;
+                cell.words += 1;
+            }
+            _ => cells.push(Cell {
+                text: word.text.clone(),
+                x0: word.x0,
+                x1: word.x1,
+                words: 1,
+            }),
+        }
+    }
+    cells
+}
+
+fn overlaps(a: (f64, f64), b: (f64, f64)) -> bool {
+    a.0 <= b.1 && b.0 <= a.1
+}
+
+/// Tries to read a table starting at the first line, returning it as
+/// Markdown along with the number of lines it spans.
+fn detect_table(lines: &[Line]) -> Option<(String, usize)> {
+    let mut columns: Vec<(f64, f64)> = Vec::new();
+    let mut rows: Vec<Vec<Cell>> = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let cells = split_cells(line);
+        if cells.len() < 2 {
+            break;
+        }
+        if i > 0 && line.y - lines[i - 1].y > line.font_size() * ROW_GAP_EMS {
+            break;
+        }
+
+        // Every cell must fall into a distinct column; cells that line up
+        // with no existing column open a new one.
+        let mut candidate = columns.clone();
+        let mut used = vec![false; candidate.len()];
+        let mut fits = true;
+        for cell in &cells {
+            let span = (cell.x0, cell.x1);
+            let matching: Vec<usize> = (0..candidate.len())
+                .filter(|&c| overlaps(candidate[c], span))
+                .collect();
+            match matching.as_slice() {
+                [] => {
+                    candidate.push(span);
+                    used.push(true);
+                }
+                [c] if !used[*c] => {
+                    used[*c] = true;
+                    candidate[*c] = (candidate[*c].0.min(span.0), candidate[*c].1.max(span.1));
+                }
+                _ => {
+                    fits = false;
+                    break;
+                }
+            }
+        }
+        if !fits {
+            break;
+        }
+        columns = candidate;
+        rows.push(cells);
+    }
+
+    if rows.len() < 2 || columns.len() < 2 {
+        return None;
+    }
+    let cell_count: usize = rows.iter().map(Vec::len).sum();
+    let word_count: usize = rows.iter().flatten().map(|c| c.words).sum();
+    if word_count as f64 / cell_count as f64 > MAX_WORDS_PER_CELL {
+        return None;
+    }
+
+    columns.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let grid: Vec<Vec<String>> = rows
+        .iter()
+        .map(|cells| {
+            let mut row = vec![String::new(); columns.len()];
+            for cell in cells {
+                if let Some(c) = columns
+                    .iter()
+                    .position(|&col| overlaps(col, (cell.x0, cell.x1)))
+                {
+                    row[c] = cell.text.replace('|', "\\|");
+                }
+            }
+            row
+        })
+        .collect();
+
+    let render_row = |row: &[String]| format!("| {} |", row.join(" | "));
+    let mut table = vec![
+        render_row(&grid[0]),
+        format!("|{}", " --- |".repeat(columns.len())),
+    ];
+    table.extend(grid[1..].iter().map(|row| render_row(row)));
+
+    Some((table.join("\n"), rows.len()))
+}
@@ -0,0 +1,195 @@
+use super::{Document, LoadOptions, Loader, xml};
+use anyhow::{Context, Result};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::debug;
+
+pub struct PptxLoader;
+
+impl Loader for PptxLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pptx"]
+    }
+
+    /// Returns one document per slide and one per set of speaker notes, each
+    /// tagged with the slide number and title.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let mut archive = xml::open_archive(path, bytes)?;
+        let parse = |what: &str| format!("Failed to parse PPTX {}: {:?}", what, path);
+
+        let presentation = "ppt/presentation.xml";
+        let slide_ids = slide_ids(&xml::read_entry(&mut archive, presentation)?)
+            .with_context(|| parse("presentation"))?;
+        let targets: HashMap<String, String> = xml::relationships(&xml::read_entry(
+            &mut archive,
+            &xml::rels_path(presentation),
+        )?)
+        .with_context(|| parse("relationships"))?
+        .into_iter()
+        .map(|relationship| (relationship.id, relationship.target))
+        .collect();
+
+        let mut documents = Vec::new();
+        for (number, id) in (1..).zip(slide_ids) {
+            let Some(target) = targets.get(&id) else {
+                continue;
+            };
+            let slide_path = xml::resolve("ppt", target);
+            let shapes = shapes(&xml::read_entry(&mut archive, &slide_path)?)
+                .with_context(|| parse(&format!("slide {}", number)))?;
+
+            let (titles, body): (Vec<_>, Vec<_>) = shapes.into_iter().partition(|shape| {
+                matches!(shape.placeholder.as_deref(), Some("title" | "ctrTitle"))
+            });
+            let title = titles
+                .iter()
+                .map(|shape| shape.text.split_whitespace().collect::<Vec<_>>().join(" "))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let tag = |document: Document| {
+                let document = document.with_metadata("slide", number.to_string());
+                if title.is_empty() {
+                    document
+                } else {
+                    document.with_metadata("title", title.clone())
+                }
+            };
+
+            let mut text = title.clone();
+            for shape in &body {
+                text.push('\n');
+                text.push_str(&shape.text);
+            }
+            if !text.trim().is_empty() {
+                documents.push(tag(Document::new(text.trim())));
+            }
+
+            let notes = notes(&mut archive, &slide_path)
+                .with_context(|| parse(&format!("notes for slide {}", number)))?;
+            if let Some(notes) = notes {
+                debug!("Slide {} has speaker notes", number);
+                documents.push(tag(Document::new(notes)).with_metadata("content", "speaker notes"));
+            }
+        }
+        Ok(documents)
+    }
+}
+
+struct Shape {
+    /// Placeholder type (`title`, `body`, `sldNum`, ...), if the shape is one.
+    placeholder: Option<String>,
+    text: String,
+}
+
+/// Reads the relationship ids of the slides, in presentation order.
+fn slide_ids(presentation: &str) -> Result<Vec<String>> {
+    let mut reader = Reader::from_str(presentation);
+    let mut ids = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == "sldId" => {
+                ids.extend(xml::attr(&e, "r:id"));
+            }
+            Event::Eof => return Ok(ids),
+            _ => {}
+        }
+    }
+}
+
+/// Reads the text of each shape on a slide, one line per paragraph.
+fn shapes(slide: &str) -> Result<Vec<Shape>> {
+    let mut reader = Reader::from_str(slide);
+    let mut shapes = Vec::new();
+    let mut current: Option<Shape> = None;
+    let mut in_text_run = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == "p:ph" => {
+                if let Some(shape) = &mut current {
+                    // Placeholders without a type are body text.
+                    shape.placeholder =
+                        Some(xml::attr(&e, "type").unwrap_or_else(|| "body".to_string()));
+                }
+            }
+            Event::Start(e) => match e.name().as_ref() {
+                "p:sp" => {
+                    current = Some(Shape {
+                        placeholder: None,
+                        text: String::new(),
+                    })
+                }
+                "a:t" => in_text_run = current.is_some(),
+                _ => {}
+            },
+            Event::Empty(e) if e.name().as_ref() == "a:br" => {
+                if let Some(shape) = &mut current {
+                    shape.text.push('\n');
+                }
+            }
+            Event::End(e) => match e.name().as_ref() {
+                "a:t" => in_text_run = false,
+                "a:p" => {
+                    if let Some(shape) = &mut current {
+                        shape.text.push('\n');
+                    }
+                }
+                "p:sp" => {
+                    if let Some(shape) = current.take()
+                        && !shape.text.trim().is_empty()
+                    {
+                        shapes.push(Shape {
+                            text: shape.text.trim().to_string(),
+                            ..shape
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(e) if in_text_run => {
+                if let Some(shape) = &mut current {
+                    shape.text.push_str(&e.xml10_content());
+                }
+            }
+            Event::GeneralRef(e) if in_text_run => {
+                if let Some(shape) = &mut current {
+                    shape.text.push_str(&xml::resolve_ref(&e)?);
+                }
+            }
+            Event::Eof => return Ok(shapes),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the speaker notes of a slide, if it has any.
+fn notes(archive: &mut xml::Archive, slide_path: &str) -> Result<Option<String>> {
+    // Slides without notes or other relationships may have no rels part.
+    let Ok(rels) = xml::read_entry(archive, &xml::rels_path(slide_path)) else {
+        return Ok(None);
+    };
+    let Some(notes) = xml::relationships(&rels)?
+        .into_iter()
+        .find(|relationship| relationship.kind == "notesSlide")
+    else {
+        return Ok(None);
+    };
+
+    let notes_path = xml::resolve(xml::parent_dir(slide_path), &notes.target);
+    // Only the body placeholder holds the notes; the rest of the notes page
+    // repeats the slide image, number and header/footer.
+    let text = shapes(&xml::read_entry(archive, &notes_path)?)?
+        .into_iter()
+        .filter(|shape| shape.placeholder.as_deref() == Some("body"))
+        .map(|shape| shape.text)
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok((!text.is_empty()).then_some(text))
+}
@@ -0,0 +1,187 @@
+use super::{Document, LoadOptions, find_loader, load_downloaded};
+use anyhow::{Context, Result, bail};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Returns true for arguments that should be downloaded rather than read
+/// from disk.
+pub fn is_remote(location: &str) -> bool {
+    location.starts_with("https://") || location.starts_with("http://")
+}
+
+/// Downloads a document (or reuses the cached copy from an earlier run) and
+/// loads it with the loader for its type, tagged with the URL as its source.
+/// The type comes from the URL's extension, or the response's content type
+/// when the URL has none.
+pub async fn load_remote(url: &str, options: &LoadOptions) -> Result<Vec<Document>> {
+    let dir = cache_dir("downloads")?;
+    let stem = sha256_hex(url.as_bytes());
+    let cached = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read cache directory: {:?}", dir))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|path| {
+            path.file_stem().is_some_and(|s| *s == *stem)
+                && path.extension().is_some_and(|ext| ext != "part")
+        });
+
+    let path = match cached {
+        Some(path) => {
+            info!("Using cached copy of {} from {:?}", url, path);
+            path
+        }
+        None => {
+            info!("Downloading {}", url);
+            let response = get(url, "*/*").await?;
+            let name = url_file_name(response.url());
+            let extension = match Path::new(&name).extension() {
+                Some(ext) if find_loader(Path::new(&name)).is_some() => {
+                    ext.to_string_lossy().to_lowercase()
+                }
+                _ => extension_for(&response)
+                    .with_context(|| format!("Unsupported document type at {}", url))?
+                    .to_string(),
+            };
+            let path = dir.join(format!("{}.{}", stem, extension));
+            download(response, &path, options.max_download_bytes).await?;
+            path
+        }
+    };
+
+    Ok(load_downloaded(&path, options)?
+        .into_iter()
+        .map(|document| document.with_metadata("source", url))
+        .collect())
+}
+
+/// Directory downloads of the given kind are cached in:
+/// `$XDG_CACHE_HOME/rag-my-pdf/<kind>`, falling back to `~/.cache`.
+pub fn cache_dir(kind: &str) -> Result<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    let dir = base.join("rag-my-pdf").join(kind);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create cache directory: {:?}", dir))?;
+    Ok(dir)
+}
+
+/// The SHA-256 digest of `bytes` in hex, which files in the caches and
+/// chunks in the stores are named by.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub(super) async fn get(url: &str, accept: &str) -> Result<reqwest::Response> {
+    // Some servers turn away clients without a user agent.
+    let response = reqwest::Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()?
+        .get(url)
+        .header(ACCEPT, accept)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    if !response.status().is_success() {
+        bail!("Fetching {} returned HTTP {}", url, response.status());
+    }
+    Ok(response)
+}
+
+/// Streams a response body to `path`, giving up once it exceeds `limit`
+/// bytes. The file only appears once the download is complete.
+pub async fn download(
+    mut response: reqwest::Response,
+    path: &Path,
+    limit: Option<u64>,
+) -> Result<()> {
+    let url = response.url().to_string();
+    let too_large = |size: u64| {
+        format!(
+            "{} is larger than the download limit of {} MB (raise it with --max-download-mb)",
+            url,
+            size / (1024 * 1024)
+        )
+    };
+    if let (Some(limit), Some(length)) = (limit, response.content_length())
+        && length > limit
+    {
+        bail!(too_large(limit));
+    }
+
+    let partial = path.with_extension("part");
+    let mut file =
+        fs::File::create(&partial).with_context(|| format!("Failed to create {:?}", partial))?;
+    let mut written = 0u64;
+    let result = async {
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("Failed to download {}", url))?
+        {
+            written += chunk.len() as u64;
+            if let Some(limit) = limit
+                && written > limit
+            {
+                bail!(too_large(limit));
+            }
+            file.write_all(&chunk)
+                .with_context(|| format!("Failed to write {:?}", partial))?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, path).with_context(|| format!("Failed to cache download: {:?}", path))?;
+    debug!("Cached {} bytes at {:?}", written, path);
+    Ok(())
+}
+
+pub(super) fn is_pdf(response: &reqwest::Response) -> bool {
+    extension_for(response) == Some("pdf")
+}
+
+/// Maps a response's content type to the extension of the loader for it.
+fn extension_for(response: &reqwest::Response) -> Option<&'static str> {
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())?;
+    let mime = content_type.split(';').next()?.trim();
+    Some(match mime {
+        "application/pdf" | "application/x-pdf" => "pdf",
+        "text/html" | "application/xhtml+xml" => "html",
+        "text/plain" => "txt",
+        "text/markdown" => "md",
+        "text/csv" => "csv",
+        "application/json" => "json",
+        "application/epub+zip" => "epub",
+        "application/zip" => "zip",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => "docx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation" => "pptx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => "xlsx",
+        _ => return None,
+    })
+}
+
+/// The last path segment of a URL, after any redirects.
+fn url_file_name(url: &reqwest::Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or_default()
+        .to_string()
+}
@@ -0,0 +1,262 @@
+use super::{Document, LoadOptions, Loader};
+use anyhow::{Result, bail};
+use std::path::Path;
+
+pub struct RtfLoader;
+
+impl Loader for RtfLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["rtf"]
+    }
+
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        if !bytes.starts_with(b"{\\rtf") {
+            bail!("Not an RTF document: {:?}", path);
+        }
+        Ok(vec![Document::new(rtf_to_text(bytes))])
+    }
+}
+
+/// Destination groups holding metadata, styles or binary data rather than
+/// document text.
+const SKIPPED_DESTINATIONS: &[&str] = &[
+    "fonttbl",
+    "colortbl",
+    "stylesheet",
+    "listtable",
+    "listoverridetable",
+    "revtbl",
+    "rsidtbl",
+    "info",
+    "pict",
+    "object",
+    "header",
+    "headerl",
+    "headerr",
+    "headerf",
+    "footer",
+    "footerl",
+    "footerr",
+    "footerf",
+    "themedata",
+    "colorschememapping",
+    "datastore",
+    "latentstyles",
+    "generator",
+    "xmlnstbl",
+];
+
+#[derive(Clone, Copy)]
+struct GroupState {
+    skip: bool,
+    /// Number of fallback characters that follow each `\u` escape (`\ucN`).
+    unicode_skip: usize,
+}
+
+/// Extracts the text of an RTF document, one line per paragraph. Text in
+/// non-Unicode escapes is decoded as Windows-1252, the default code page.
+fn rtf_to_text(rtf: &[u8]) -> String {
+    let mut text = String::new();
+    let mut stack: Vec<GroupState> = Vec::new();
+    let mut state = GroupState {
+        skip: false,
+        unicode_skip: 1,
+    };
+    // Fallback characters still to drop after a `\u` escape.
+    let mut pending_skip = 0usize;
+    // Characters outside the BMP arrive as two `\u` escapes.
+    let mut high_surrogate: Option<u16> = None;
+    let mut i = 0;
+
+    while i < rtf.len() {
+        let byte = rtf[i];
+        match byte {
+            b'{' => {
+                stack.push(state);
+                // `{\*\dest ...}` marks a destination readers may ignore.
+                if rtf[i + 1..].starts_with(b"\\*") {
+                    state.skip = true;
+                }
+                i += 1;
+            }
+            b'}' => {
+                if let Some(outer) = stack.pop() {
+                    state = outer;
+                }
+                i += 1;
+            }
+            b'\\' => {
+                i += 1;
+                let Some(&next) = rtf.get(i) else { break };
+                if next.is_ascii_alphabetic() {
+                    let start = i;
+                    while i < rtf.len() && rtf[i].is_ascii_alphabetic() {
+                        i += 1;
+                    }
+                    let word = std::str::from_utf8(&rtf[start..i]).unwrap_or_default();
+                    let num_start = i;
+                    if i < rtf.len() && rtf[i] == b'-' {
+                        i += 1;
+                    }
+                    while i < rtf.len() && rtf[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                    let param: Option<i32> = std::str::from_utf8(&rtf[num_start..i])
+                        .ok()
+                        .and_then(|n| n.parse().ok());
+                    // A single space after a control word is its delimiter.
+                    if i < rtf.len() && rtf[i] == b' ' {
+                        i += 1;
+                    }
+
+                    if SKIPPED_DESTINATIONS.contains(&word) {
+                        state.skip = true;
+                    }
+                    if word == "bin" {
+                        // Raw binary data follows; jump over it.
+                        i += param.unwrap_or(0).max(0) as usize;
+                        continue;
+                    }
+                    if state.skip {
+                        continue;
+                    }
+                    match word {
+                        "par" | "line" | "sect" | "page" | "row" => text.push('\n'),
+                        "tab" | "cell" => text.push('\t'),
+                        "emdash" => text.push('—'),
+                        "endash" => text.push('–'),
+                        "lquote" => text.push('‘'),
+                        "rquote" => text.push('’'),
+                        "ldblquote" => text.push('“'),
+                        "rdblquote" => text.push('”'),
+                        "bullet" => text.push('•'),
+                        "uc" => state.unicode_skip = param.unwrap_or(1).max(0) as usize,
+                        "u" => {
+                            // Negative values encode code units above 32767.
+                            let unit = param.unwrap_or(0) as i16 as u16;
+                            match (high_surrogate.take(), unit) {
+                                (_, 0xD800..=0xDBFF) => high_surrogate = Some(unit),
+                                (Some(high), 0xDC00..=0xDFFF) => text.extend(
+                                    char::decode_utf16([high, unit])
+                                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)),
+                                ),
+                                (_, unit) => text.push(
+                                    char::from_u32(unit as u32)
+                                        .unwrap_or(char::REPLACEMENT_CHARACTER),
+                                ),
+                            }
+                            pending_skip = state.unicode_skip;
+                        }
+                        _ => {}
+                    }
+                } else {
+                    i += 1;
+                    if next == b'\'' {
+                        let hex = rtf
+                            .get(i..i + 2)
+                            .and_then(|hex| std::str::from_utf8(hex).ok());
+                        if let Some(value) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                            i += 2;
+                            if pending_skip > 0 {
+                                pending_skip -= 1;
+                            } else if !state.skip {
+                                text.push(windows_1252(value));
+                            }
+                        }
+                        continue;
+                    }
+                    if state.skip {
+                        continue;
+                    }
+                    match next {
+                        b'\\' | b'{' | b'}' => text.push(next as char),
+                        b'~' => text.push('\u{A0}'),
+                        b'_' => text.push('‑'),
+                        b'\n' | b'\r' => text.push('\n'),
+                        _ => {}
+                    }
+                }
+            }
+            b'\r' | b'\n' => i += 1,
+            _ => {
+                i += 1;
+                if pending_skip > 0 {
+                    pending_skip -= 1;
+                } else if !state.skip {
+                    text.push(windows_1252(byte));
+                }
+            }
+        }
+    }
+
+    text
+}
+
+/// Decodes a byte in the Windows-1252 code page.
+fn windows_1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž',
+        '\u{8F}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}',
+        'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9F => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(rtf: &str) -> String {
+        rtf_to_text(rtf.as_bytes())
+    }
+
+    #[test]
+    fn keeps_paragraphs_and_drops_control_words() {
+        assert_eq!(
+            text(r"{\rtf1\ansi {\b Bold} text\par Next\tab cell\par}"),
+            "Bold text\nNext\tcell\n"
+        );
+    }
+
+    #[test]
+    fn skips_tables_and_ignorable_destinations() {
+        assert_eq!(
+            text(
+                r"{\rtf1{\fonttbl{\f0 Arial;}}{\colortbl;\red0;}{\*\generator Word;}{\*\unknown x}Body}"
+            ),
+            "Body"
+        );
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        // `\'e9` is é in Windows-1252, and `\'93`/`\'94` its curly quotes.
+        assert_eq!(
+            text(r"{\rtf1 caf\'e9 \'93quoted\'94 \{braces\}\~\emdash}"),
+            "café “quoted” {braces}\u{A0}—"
+        );
+    }
+
+    #[test]
+    fn decodes_unicode_escapes_without_their_fallbacks() {
+        assert_eq!(text(r"{\rtf1 \u8364?5 \uc2\u-3988??}"), "€5 \u{F06C}");
+        // A character outside the BMP, as two surrogates.
+        assert_eq!(text(r"{\rtf1 \u-10179?\u-8704?}"), "😀");
+    }
+
+    #[test]
+    fn turns_away_other_files() {
+        let error = RtfLoader
+            .load_bytes(Path::new("a.rtf"), b"plain text", &LoadOptions::default())
+            .unwrap_err();
+        assert!(error.to_string().contains("Not an RTF document"));
+    }
+}
@@ -0,0 +1,20 @@
+use super::{Document, LoadOptions, Loader, encoding};
+use anyhow::Result;
+use std::path::Path;
+
+pub struct TextLoader;
+
+impl Loader for TextLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt", "text"]
+    }
+
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        Ok(vec![Document::new(encoding::decode(path, bytes, None))])
+    }
+}
@@ -0,0 +1,178 @@
+use super::{Document, LoadOptions, Loader, csv, xml};
+use anyhow::{Context, Result};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::debug;
+
+pub struct XlsxLoader;
+
+impl Loader for XlsxLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["xlsx"]
+    }
+
+    /// Returns one document per worksheet, tagged with the sheet name.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        _options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let mut archive = xml::open_archive(path, bytes)?;
+        let parse = |what: &str| format!("Failed to parse XLSX {}: {:?}", what, path);
+
+        let sheets = workbook_sheets(&xml::read_entry(&mut archive, "xl/workbook.xml")?)
+            .with_context(|| parse("workbook"))?;
+        let targets: HashMap<String, String> = xml::relationships(&xml::read_entry(
+            &mut archive,
+            "xl/_rels/workbook.xml.rels",
+        )?)
+        .with_context(|| parse("relationships"))?
+        .into_iter()
+        .map(|relationship| (relationship.id, relationship.target))
+        .collect();
+        // Workbooks without any text cells have no shared strings table.
+        let shared_strings = match xml::read_entry(&mut archive, "xl/sharedStrings.xml") {
+            Ok(contents) => shared_strings(&contents).with_context(|| parse("shared strings"))?,
+            Err(_) => Vec::new(),
+        };
+
+        let mut documents = Vec::new();
+        for (name, relationship_id) in sheets {
+            let Some(target) = targets.get(&relationship_id) else {
+                continue;
+            };
+            let sheet_path = xml::resolve("xl", target);
+            let rows = sheet_rows(
+                &xml::read_entry(&mut archive, &sheet_path)?,
+                &shared_strings,
+            )
+            .with_context(|| parse(&format!("sheet {:?}", name)))?;
+            let text = csv::rows_to_records(&rows);
+            if text.is_empty() {
+                debug!("Skipping empty sheet {:?}", name);
+                continue;
+            }
+            documents.push(Document::new(text).with_metadata("sheet", name));
+        }
+        Ok(documents)
+    }
+}
+
+/// Reads (sheet name, relationship id) pairs in workbook order.
+fn workbook_sheets(workbook: &str) -> Result<Vec<(String, String)>> {
+    let mut reader = Reader::from_str(workbook);
+    let mut sheets = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == "sheet" => {
+                if let (Some(name), Some(id)) = (xml::attr(&e, "name"), xml::attr(&e, "r:id")) {
+                    sheets.push((name, id));
+                }
+            }
+            Event::Eof => return Ok(sheets),
+            _ => {}
+        }
+    }
+}
+
+fn shared_strings(contents: &str) -> Result<Vec<String>> {
+    let mut reader = Reader::from_str(contents);
+    let mut strings = Vec::new();
+    let mut current = String::new();
+    let mut in_text = false;
+    // Phonetic guides (`rPh`) repeat the string's reading; skip them.
+    let mut in_phonetic = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                "si" => current.clear(),
+                "t" => in_text = !in_phonetic,
+                "rPh" => in_phonetic = true,
+                _ => {}
+            },
+            Event::End(e) => match e.local_name().as_ref() {
+                "si" => strings.push(std::mem::take(&mut current)),
+                "t" => in_text = false,
+                "rPh" => in_phonetic = false,
+                _ => {}
+            },
+            Event::Empty(e) if e.local_name().as_ref() == "si" => strings.push(String::new()),
+            Event::Text(e) if in_text => current.push_str(&e.xml10_content()),
+            Event::GeneralRef(e) if in_text => current.push_str(&xml::resolve_ref(&e)?),
+            Event::Eof => return Ok(strings),
+            _ => {}
+        }
+    }
+}
+
+/// Reads the cell values of a worksheet as rows of strings, placing each
+/// cell in its column even when the cells before it are missing.
+fn sheet_rows(sheet: &str, shared_strings: &[String]) -> Result<Vec<Vec<String>>> {
+    let mut reader = Reader::from_str(sheet);
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut cell: Option<(Option<usize>, String)> = None;
+    let mut value = String::new();
+    let mut in_value = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                "row" => rows.push(Vec::new()),
+                "c" => {
+                    let column = xml::attr(&e, "r").and_then(|r| column_index(&r));
+                    cell = Some((column, xml::attr(&e, "t").unwrap_or_default()));
+                    value.clear();
+                }
+                // `v` holds the value; `t` holds inline strings.
+                "v" | "t" => in_value = cell.is_some(),
+                _ => {}
+            },
+            Event::End(e) => match e.local_name().as_ref() {
+                "v" | "t" => in_value = false,
+                "c" => {
+                    if let (Some((column, kind)), Some(row)) = (cell.take(), rows.last_mut()) {
+                        let text = match kind.as_str() {
+                            "s" => value
+                                .trim()
+                                .parse::<usize>()
+                                .ok()
+                                .and_then(|i| shared_strings.get(i))
+                                .cloned()
+                                .unwrap_or_default(),
+                            "b" => if value.trim() == "1" { "TRUE" } else { "FALSE" }.to_string(),
+                            _ => value.clone(),
+                        };
+                        let column = column.unwrap_or(row.len());
+                        if row.len() <= column {
+                            row.resize(column + 1, String::new());
+                        }
+                        row[column] = text;
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(e) if in_value => value.push_str(&e.xml10_content()),
+            Event::GeneralRef(e) if in_value => value.push_str(&xml::resolve_ref(&e)?),
+            Event::Eof => return Ok(rows),
+            _ => {}
+        }
+    }
+}
+
+/// Converts the column letters of a cell reference (`C7`) to a 0-based index.
+fn column_index(reference: &str) -> Option<usize> {
+    let letters: Vec<u8> = reference
+        .bytes()
+        .take_while(|b| b.is_ascii_alphabetic())
+        .collect();
+    if letters.is_empty() {
+        return None;
+    }
+    let index = letters.iter().fold(0usize, |index, letter| {
+        index * 26 + (letter.to_ascii_uppercase() - b'A') as usize + 1
+    });
+    Some(index - 1)
+}
@@ -0,0 +1,127 @@
+//! Helpers shared by the loaders for zip-packaged XML formats.
+
+use anyhow::{Context, Result};
+use quick_xml::escape::unescape;
+use quick_xml::events::{BytesRef, BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+pub type Archive<'a> = ZipArchive<Cursor<&'a [u8]>>;
+
+pub fn open_archive<'a>(path: &Path, bytes: &'a [u8]) -> Result<Archive<'a>> {
+    ZipArchive::new(Cursor::new(bytes))
+        .with_context(|| format!("Failed to read archive: {:?}", path))
+}
+
+pub fn read_entry(archive: &mut Archive, name: &str) -> Result<String> {
+    let mut contents = String::new();
+    archive
+        .by_name(name)
+        .with_context(|| format!("Archive is missing {}", name))?
+        .read_to_string(&mut contents)
+        .with_context(|| format!("Failed to read {} from archive", name))?;
+    Ok(contents)
+}
+
+/// Returns the unescaped value of an attribute, if present.
+pub fn attr(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|a| a.normalized_value(XmlVersion::Implicit1_0).ok())
+        .map(|v| v.into_owned())
+}
+
+/// Resolves a character or predefined entity reference (`&amp;`, `&#160;`).
+pub fn resolve_ref(reference: &BytesRef) -> Result<String> {
+    Ok(match reference.resolve_char_ref()? {
+        Some(c) => c.to_string(),
+        None => unescape(&format!("&{};", reference.xml10_content()))?.into_owned(),
+    })
+}
+
+/// An entry of an Office Open XML `.rels` part.
+pub struct Relationship {
+    pub id: String,
+    /// Last segment of the relationship type URI, e.g. `slide` or `notesSlide`.
+    pub kind: String,
+    pub target: String,
+}
+
+pub fn relationships(rels: &str) -> Result<Vec<Relationship>> {
+    let mut reader = Reader::from_str(rels);
+    let mut relationships = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == "Relationship" => {
+                if let (Some(id), Some(target)) = (attr(&e, "Id"), attr(&e, "Target")) {
+                    let kind = attr(&e, "Type").unwrap_or_default();
+                    relationships.push(Relationship {
+                        id,
+                        kind: kind.rsplit('/').next().unwrap_or_default().to_string(),
+                        target,
+                    });
+                }
+            }
+            Event::Eof => return Ok(relationships),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the `.rels` part holding the relationships of another part,
+/// e.g. `ppt/slides/_rels/slide1.xml.rels` for `ppt/slides/slide1.xml`.
+pub fn rels_path(part: &str) -> String {
+    match part.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/_rels/{}.rels", dir, name),
+        None => format!("_rels/{}.rels", part),
+    }
+}
+
+/// Returns the directory of a path inside an archive.
+pub fn parent_dir(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+/// Resolves an href relative to a directory inside the archive, dropping any
+/// fragment and percent-encoding. Hrefs starting with `/` are relative to the
+/// archive root.
+pub fn resolve(base_dir: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let base_dir = if href.starts_with('/') { "" } else { base_dir };
+    let mut parts: Vec<&str> = base_dir.split('/').filter(|p| !p.is_empty()).collect();
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    percent_decode(&parts.join("/"))
+}
+
+/// `s` with its `%XX` escapes decoded.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,88 @@
+use super::{Document, LoadOptions, Loader, find_loader, notion, xml};
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+pub struct ZipLoader;
+
+impl Loader for ZipLoader {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["zip"]
+    }
+
+    /// Loads every supported file in the archive, one entry at a time and
+    /// without extracting anything to disk. Documents are tagged with the
+    /// path of the entry they came from, and pages of a Notion export with
+    /// their place in the page hierarchy.
+    fn load_bytes(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        options: &LoadOptions,
+    ) -> Result<Vec<Document>> {
+        let mut archive = xml::open_archive(path, bytes)?;
+        let names: Vec<String> = archive
+            .file_names()
+            .filter_map(|name| name.ok().map(|name| name.into_owned()))
+            .collect();
+        let mut documents = Vec::new();
+        let (mut loaded, mut unsupported, mut failed) = (0, 0, 0);
+
+        for i in 0..archive.len() {
+            let mut entry = archive
+                .by_index(i)
+                .with_context(|| format!("Failed to read entry {} of {:?}", i, path))?;
+            if !entry.is_file() {
+                continue;
+            }
+            let name = entry
+                .name()
+                .with_context(|| format!("Invalid entry name in {:?}", path))?
+                .into_owned();
+            // macOS resource forks ride along in many archives.
+            if name.starts_with("__MACOSX/") {
+                continue;
+            }
+            if notion::is_partial_view(&name, |other| names.iter().any(|n| n == other)) {
+                debug!("Skipping {}; the export also has all its rows", name);
+                continue;
+            }
+            let entry_path = Path::new(&name);
+            let Some(loader) = find_loader(entry_path) else {
+                debug!("Skipping unsupported entry {}", name);
+                unsupported += 1;
+                continue;
+            };
+
+            let mut contents = Vec::with_capacity(entry.size() as usize);
+            let result = entry
+                .read_to_end(&mut contents)
+                .with_context(|| format!("Failed to read {} from {:?}", name, path))
+                .and_then(|_| loader.load_bytes(entry_path, &contents, options));
+            match result {
+                Ok(entry_documents) => {
+                    loaded += 1;
+                    let page = notion::page_path(entry_path);
+                    documents.extend(entry_documents.into_iter().map(|document| {
+                        let document = document.with_metadata("entry", name.clone());
+                        match &page {
+                            Some(page) => document.with_metadata("notion_page", page.clone()),
+                            None => document,
+                        }
+                    }));
+                }
+                Err(e) => {
+                    warn!("Failed to load {} from {:?}: {:#}", name, path, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        info!(
+            "Loaded {} files from {:?} (skipped {} unsupported, {} failed)",
+            loaded, path, unsupported, failed
+        );
+        Ok(documents)
+    }
+}
@@ -0,0 +1,558 @@
+//! A BERT encoder, the architecture of sentence-transformers models like
+//! all-MiniLM-L6-v2 and bge-small-en-v1.5, run on the CPU from the weights
+//! in the model's `model.safetensors`.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// What `config.json` says of the model's shape.
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub model_type: Option<String>,
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    pub max_position_embeddings: usize,
+    #[serde(default = "default_type_vocab_size")]
+    pub type_vocab_size: usize,
+    #[serde(default = "default_layer_norm_eps")]
+    pub layer_norm_eps: f64,
+    #[serde(default = "default_hidden_act")]
+    pub hidden_act: String,
+}
+
+fn default_type_vocab_size() -> usize {
+    2
+}
+
+fn default_layer_norm_eps() -> f64 {
+    1e-12
+}
+
+fn default_hidden_act() -> String {
+    "gelu".to_string()
+}
+
+/// The activation between each layer's feed-forward matrices.
+#[derive(Debug, Clone, Copy)]
+enum Activation {
+    Gelu,
+    /// GELU's tanh approximation, as `gelu_new` and `gelu_pytorch_tanh`.
+    GeluTanh,
+    Relu,
+}
+
+/// A matrix of `outputs` rows of `inputs` weights, and a bias for each
+/// output.
+struct Linear {
+    weight: Vec<f32>,
+    bias: Vec<f32>,
+    inputs: usize,
+}
+
+struct LayerNorm {
+    weight: Vec<f32>,
+    bias: Vec<f32>,
+    eps: f32,
+}
+
+struct Layer {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    attention_output: Linear,
+    attention_norm: LayerNorm,
+    intermediate: Linear,
+    output: Linear,
+    output_norm: LayerNorm,
+}
+
+pub struct Bert {
+    words: Vec<f32>,
+    positions: Vec<f32>,
+    /// The embedding of the first token type, the only one a single text
+    /// uses.
+    token_type: Vec<f32>,
+    embedding_norm: LayerNorm,
+    layers: Vec<Layer>,
+    hidden: usize,
+    heads: usize,
+    max_positions: usize,
+    activation: Activation,
+}
+
+impl Bert {
+    /// The model `config` describes, with the weights in `safetensors`,
+    /// the contents of a `model.safetensors` file.
+    pub fn new(config: &Config, safetensors: &[u8]) -> Result<Self> {
+        if config
+            .model_type
+            .as_deref()
+            .is_some_and(|kind| kind != "bert")
+        {
+            bail!(
+                "only BERT models run locally, and this is a {} model",
+                config.model_type.as_deref().unwrap_or_default()
+            );
+        }
+        let activation = match config.hidden_act.as_str() {
+            "gelu" => Activation::Gelu,
+            "gelu_new" | "gelu_pytorch_tanh" => Activation::GeluTanh,
+            "relu" => Activation::Relu,
+            other => bail!("Unsupported activation {:?}", other),
+        };
+        if config.num_attention_heads == 0
+            || !config
+                .hidden_size
+                .is_multiple_of(config.num_attention_heads)
+        {
+            bail!(
+                "{} attention heads don't divide a hidden size of {}",
+                config.num_attention_heads,
+                config.hidden_size
+            );
+        }
+        let tensors = Tensors::new(safetensors)?;
+        let (hidden, eps) = (config.hidden_size, config.layer_norm_eps as f32);
+        let linear = |name: &str, inputs: usize, outputs: usize| -> Result<Linear> {
+            Ok(Linear {
+                weight: tensors.get(&format!("{}.weight", name), &[outputs, inputs])?,
+                bias: tensors.get(&format!("{}.bias", name), &[outputs])?,
+                inputs,
+            })
+        };
+        let norm = |name: &str| -> Result<LayerNorm> {
+            Ok(LayerNorm {
+                weight: tensors.get_norm(name, "weight", "gamma", hidden)?,
+                bias: tensors.get_norm(name, "bias", "beta", hidden)?,
+                eps,
+            })
+        };
+        let layers = (0..config.num_hidden_layers)
+            .map(|i| {
+                let layer = format!("encoder.layer.{}", i);
+                Ok(Layer {
+                    query: linear(&format!("{}.attention.self.query", layer), hidden, hidden)?,
+                    key: linear(&format!("{}.attention.self.key", layer), hidden, hidden)?,
+                    value: linear(&format!("{}.attention.self.value", layer), hidden, hidden)?,
+                    attention_output: linear(
+                        &format!("{}.attention.output.dense", layer),
+                        hidden,
+                        hidden,
+                    )?,
+                    attention_norm: norm(&format!("{}.attention.output.LayerNorm", layer))?,
+                    intermediate: linear(
+                        &format!("{}.intermediate.dense", layer),
+                        hidden,
+                        config.intermediate_size,
+                    )?,
+                    output: linear(
+                        &format!("{}.output.dense", layer),
+                        config.intermediate_size,
+                        hidden,
+                    )?,
+                    output_norm: norm(&format!("{}.output.LayerNorm", layer))?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let mut token_type = tensors.get(
+            "embeddings.token_type_embeddings.weight",
+            &[config.type_vocab_size, hidden],
+        )?;
+        token_type.truncate(hidden);
+        Ok(Self {
+            words: tensors.get(
+                "embeddings.word_embeddings.weight",
+                &[config.vocab_size, hidden],
+            )?,
+            positions: tensors.get(
+                "embeddings.position_embeddings.weight",
+                &[config.max_position_embeddings, hidden],
+            )?,
+            token_type,
+            embedding_norm: norm("embeddings.LayerNorm")?,
+            layers,
+            hidden,
+            heads: config.num_attention_heads,
+            max_positions: config.max_position_embeddings,
+            activation,
+        })
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    pub fn max_positions(&self) -> usize {
+        self.max_positions
+    }
+
+    /// The last layer's output for the tokens `ids`, `hidden_size` values
+    /// a token. Ids past the vocabulary are read as the first token.
+    pub fn forward(&self, ids: &[u32]) -> Vec<f32> {
+        let (n, hidden) = (ids.len().min(self.max_positions), self.hidden);
+        let mut x = Vec::with_capacity(n * hidden);
+        for (position, &id) in ids[..n].iter().enumerate() {
+            let id = id as usize;
+            let id = if (id + 1) * hidden <= self.words.len() {
+                id
+            } else {
+                0
+            };
+            let word = &self.words[id * hidden..(id + 1) * hidden];
+            let place = &self.positions[position * hidden..(position + 1) * hidden];
+            x.extend(
+                word.iter()
+                    .zip(place)
+                    .zip(&self.token_type)
+                    .map(|((w, p), t)| w + p + t),
+            );
+        }
+        self.embedding_norm.apply(&mut x);
+
+        for layer in &self.layers {
+            let context = self.attention(layer, &x, n);
+            let mut attended = layer.attention_output.forward(&context);
+            add(&mut attended, &x);
+            layer.attention_norm.apply(&mut attended);
+
+            let mut inner = layer.intermediate.forward(&attended);
+            for value in &mut inner {
+                *value = self.activation.apply(*value);
+            }
+            let mut output = layer.output.forward(&inner);
+            add(&mut output, &attended);
+            layer.output_norm.apply(&mut output);
+            x = output;
+        }
+        x
+    }
+
+    /// Every token's mix of the values of the tokens it attends to, head by
+    /// head.
+    fn attention(&self, layer: &Layer, x: &[f32], n: usize) -> Vec<f32> {
+        let hidden = self.hidden;
+        let size = hidden / self.heads;
+        let scale = 1.0 / (size as f32).sqrt();
+        let (query, key, value) = (
+            layer.query.forward(x),
+            layer.key.forward(x),
+            layer.value.forward(x),
+        );
+        let mut context = vec![0.0; n * hidden];
+        let mut scores = vec![0.0; n];
+        for head in 0..self.heads {
+            // Where the head's part of a token's row starts and ends.
+            let at =
+                |token: usize| token * hidden + head * size..token * hidden + (head + 1) * size;
+            for i in 0..n {
+                let q = &query[at(i)];
+                for (j, score) in scores.iter_mut().enumerate() {
+                    *score = dot(q, &key[at(j)]) * scale;
+                }
+                softmax(&mut scores);
+                let mixed = &mut context[at(i)];
+                for (j, &weight) in scores.iter().enumerate() {
+                    for (m, v) in mixed.iter_mut().zip(&value[at(j)]) {
+                        *m += weight * v;
+                    }
+                }
+            }
+        }
+        context
+    }
+}
+
+impl Linear {
+    /// `x`, rows of `inputs` values, through the matrix.
+    fn forward(&self, x: &[f32]) -> Vec<f32> {
+        let outputs = self.bias.len();
+        let mut y = Vec::with_capacity(x.len() / self.inputs * outputs);
+        // Four rows at a time, so each row of weights is read once for all
+        // four.
+        let mut rows = x.chunks_exact(4 * self.inputs);
+        for block in &mut rows {
+            let (a, rest) = block.split_at(self.inputs);
+            let (b, rest) = rest.split_at(self.inputs);
+            let (c, d) = rest.split_at(self.inputs);
+            let mut out = vec![0.0; 4 * outputs];
+            for (o, (weights, bias)) in self
+                .weight
+                .chunks_exact(self.inputs)
+                .zip(&self.bias)
+                .enumerate()
+            {
+                let [p, q, r, s] = dot4(weights, [a, b, c, d]);
+                out[o] = p + bias;
+                out[outputs + o] = q + bias;
+                out[2 * outputs + o] = r + bias;
+                out[3 * outputs + o] = s + bias;
+            }
+            y.extend(out);
+        }
+        for row in rows.remainder().chunks_exact(self.inputs) {
+            for (weights, bias) in self.weight.chunks_exact(self.inputs).zip(&self.bias) {
+                y.push(dot(row, weights) + bias);
+            }
+        }
+        y
+    }
+}
+
+impl LayerNorm {
+    /// Normalizes each row of `x` in place.
+    fn apply(&self, x: &mut [f32]) {
+        for row in x.chunks_exact_mut(self.weight.len()) {
+            let n = row.len() as f32;
+            let mean = row.iter().sum::<f32>() / n;
+            let variance = row.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n;
+            let scale = 1.0 / (variance + self.eps).sqrt();
+            for ((v, w), b) in row.iter_mut().zip(&self.weight).zip(&self.bias) {
+                *v = (*v - mean) * scale * w + b;
+            }
+        }
+    }
+}
+
+impl Activation {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Activation::Gelu => {
+                let x = f64::from(x);
+                (0.5 * x * (1.0 + erf(x / std::f64::consts::SQRT_2))) as f32
+            }
+            Activation::GeluTanh => {
+                let inner = (2.0 / std::f32::consts::PI).sqrt() * (x + 0.044715 * x.powi(3));
+                0.5 * x * (1.0 + inner.tanh())
+            }
+            Activation::Relu => x.max(0.0),
+        }
+    }
+}
+
+/// The error function, to within 1.2e-7 (Numerical Recipes' `erfc`
+/// approximation).
+fn erf(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let erfc = t * polynomial.exp();
+    if x >= 0.0 { 1.0 - erfc } else { erfc - 1.0 }
+}
+
+fn add(x: &mut [f32], other: &[f32]) {
+    for (x, y) in x.iter_mut().zip(other) {
+        *x += y;
+    }
+}
+
+fn softmax(x: &mut [f32]) {
+    let largest = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0.0;
+    for v in x.iter_mut() {
+        *v = (*v - largest).exp();
+        sum += *v;
+    }
+    for v in x.iter_mut() {
+        *v /= sum;
+    }
+}
+
+/// Summed in eight lanes, which the compiler can keep in vector registers.
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let (a8, b8) = (a.chunks_exact(8), b.chunks_exact(8));
+    let tail: f32 = a8
+        .remainder()
+        .iter()
+        .zip(b8.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    let mut sums = [0.0f32; 8];
+    for (x, y) in a8.zip(b8) {
+        for lane in 0..8 {
+            sums[lane] += x[lane] * y[lane];
+        }
+    }
+    sums.iter().sum::<f32>() + tail
+}
+
+/// The dot products of `weights` with four rows at once.
+fn dot4(weights: &[f32], rows: [&[f32]; 4]) -> [f32; 4] {
+    let mut sums = [[0.0f32; 8]; 4];
+    let whole = weights.len() / 8 * 8;
+    for start in (0..whole).step_by(8) {
+        let w = &weights[start..start + 8];
+        for (sum, row) in sums.iter_mut().zip(rows) {
+            let x = &row[start..start + 8];
+            for lane in 0..8 {
+                sum[lane] += w[lane] * x[lane];
+            }
+        }
+    }
+    let mut totals = [0.0; 4];
+    for ((total, sum), row) in totals.iter_mut().zip(sums).zip(rows) {
+        *total = sum.iter().sum::<f32>() + dot(&weights[whole..], &row[whole..]);
+    }
+    totals
+}
+
+/// The tensors of a safetensors file: a little-endian length, a JSON header
+/// naming each tensor's type, shape and place, and their bytes.
+struct Tensors<'a> {
+    header: HashMap<String, Value>,
+    data: &'a [u8],
+}
+
+#[derive(Deserialize)]
+struct TensorInfo {
+    dtype: String,
+    shape: Vec<usize>,
+    data_offsets: (usize, usize),
+}
+
+impl<'a> Tensors<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self> {
+        let length = bytes
+            .get(..8)
+            .map(|length| u64::from_le_bytes(length.try_into().unwrap()) as usize)
+            .context("The weights file is too short to be safetensors")?;
+        let header = bytes
+            .get(8..8 + length)
+            .context("The weights file's header runs past its end")?;
+        let header = serde_json::from_slice(header)
+            .context("The weights file's header isn't safetensors JSON")?;
+        Ok(Self {
+            header,
+            data: &bytes[8 + length..],
+        })
+    }
+
+    /// The tensor `name`, which has to be `shape`, as 32-bit floats.
+    /// Models saved from a whole `BertModel` name their tensors under
+    /// `bert.`.
+    fn get(&self, name: &str, shape: &[usize]) -> Result<Vec<f32>> {
+        let prefixed = format!("bert.{}", name);
+        let info = self
+            .header
+            .get(name)
+            .or_else(|| self.header.get(&prefixed))
+            .with_context(|| format!("The weights have no tensor {}", name))?;
+        let info: TensorInfo = serde_json::from_value(info.clone())
+            .with_context(|| format!("The weights describe {} oddly", name))?;
+        if info.shape != shape {
+            bail!(
+                "The tensor {} is {:?}, where the config implies {:?}",
+                name,
+                info.shape,
+                shape
+            );
+        }
+        let (start, end) = info.data_offsets;
+        let bytes = self
+            .data
+            .get(start..end)
+            .with_context(|| format!("The tensor {} runs past the end of the weights", name))?;
+        let values: Vec<f32> = match info.dtype.as_str() {
+            "F32" => bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            "F16" => bytes
+                .chunks_exact(2)
+                .map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+                .collect(),
+            "BF16" => bytes
+                .chunks_exact(2)
+                .map(|b| f32::from_bits(u32::from(u16::from_le_bytes([b[0], b[1]])) << 16))
+                .collect(),
+            dtype => bail!("The tensor {} is {}, not floats", name, dtype),
+        };
+        if values.len() != shape.iter().product::<usize>() {
+            bail!("The tensor {} holds the wrong number of values", name);
+        }
+        Ok(values)
+    }
+
+    /// A layer norm's `weight` or `bias`, which older models call `gamma`
+    /// and `beta`.
+    fn get_norm(&self, name: &str, field: &str, old: &str, size: usize) -> Result<Vec<f32>> {
+        let old = format!("{}.{}", name, old);
+        if self.header.contains_key(&old) || self.header.contains_key(&format!("bert.{}", old)) {
+            return self.get(&old, &[size]);
+        }
+        self.get(&format!("{}.{}", name, field), &[size])
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = u32::from((bits >> 10) & 0x1f);
+    let fraction = u32::from(bits & 0x3ff);
+    let magnitude = match exponent {
+        0 => fraction as f32 / (1u32 << 24) as f32,
+        0x1f if fraction == 0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => f32::from_bits(((exponent + 112) << 23) | (fraction << 13)),
+    };
+    sign * magnitude
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erf_matches_known_values() {
+        for (x, expected) in [
+            (0.0, 0.0),
+            (0.5, 0.520_499_877_8),
+            (1.0, 0.842_700_792_9),
+            (2.0, 0.995_322_265_0),
+            (-1.0, -0.842_700_792_9),
+        ] {
+            assert!((erf(x) - expected).abs() < 2e-7, "erf({})", x);
+        }
+    }
+
+    #[test]
+    fn half_floats_convert() {
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3555), 0.333_251_95);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+    }
+
+    #[test]
+    fn blocked_rows_match_single_ones() {
+        let inputs = 11;
+        let linear = Linear {
+            weight: (0..3 * inputs).map(|i| (i as f32 * 0.37).sin()).collect(),
+            bias: vec![0.5, -0.25, 0.0],
+            inputs,
+        };
+        let x: Vec<f32> = (0..6 * inputs).map(|i| (i as f32 * 0.11).cos()).collect();
+        let y = linear.forward(&x);
+        assert_eq!(y.len(), 6 * 3);
+        for (row, outputs) in x.chunks(inputs).zip(y.chunks(3)) {
+            for ((weights, bias), output) in
+                linear.weight.chunks(inputs).zip(&linear.bias).zip(outputs)
+            {
+                let expected: f32 = row.iter().zip(weights).map(|(a, b)| a * b).sum::<f32>() + bias;
+                assert!((output - expected).abs() < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,625 @@
+//! Embeddings computed on this machine, for `--embedding-provider local`: a
+//! BERT sentence-transformers model (all-MiniLM-L6-v2, bge-small-en-v1.5 and
+//! the like) run in-process from its weights, so no text is sent anywhere
+//! and no server has to be running. The model is read from a directory
+//! holding its files, or downloaded once from Hugging Face and cached.
+
+mod bert;
+mod wordpiece;
+
+use crate::loaders::{cache_dir, download};
+use anyhow::{Context, Result, bail};
+use bert::{Bert, Config};
+use rig::embeddings::{Embedding, EmbeddingError};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::info;
+use wordpiece::WordPiece;
+
+/// Model used when `--embedding-model` isn't given.
+pub const LOCAL_EMBEDDING_MODEL: &str = "sentence-transformers/all-MiniLM-L6-v2";
+
+/// Where models are downloaded from, unless `HF_ENDPOINT` says otherwise.
+const HUGGINGFACE_URL: &str = "https://huggingface.co";
+
+/// The files a model can't be run without.
+const REQUIRED_FILES: &[&str] = &["config.json", "vocab.txt", "model.safetensors"];
+
+/// Files that say how the model's tokenizer, input limit and pooling are
+/// set, when they differ from BERT's.
+const OPTIONAL_FILES: &[&str] = &[
+    "tokenizer_config.json",
+    "sentence_bert_config.json",
+    "1_Pooling/config.json",
+];
+
+/// Written to a model's cache directory once all its files have been
+/// fetched, or found missing from the repo.
+const COMPLETE_MARKER: &str = ".complete";
+
+/// How a text's token vectors are made into one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pooling {
+    /// Their mean, as all-MiniLM-L6-v2 and most sentence-transformers
+    /// models are trained with.
+    Mean,
+    /// The `[CLS]` token's, as the bge models are.
+    Cls,
+}
+
+#[derive(Deserialize)]
+struct TokenizerConfig {
+    #[serde(default = "uncased")]
+    do_lower_case: bool,
+}
+
+fn uncased() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct SentenceBertConfig {
+    max_seq_length: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct PoolingConfig {
+    #[serde(default)]
+    pooling_mode_cls_token: bool,
+}
+
+/// A model run on this machine, shared by the copies that embed documents
+/// and questions.
+#[derive(Clone)]
+pub struct LocalModel {
+    model: String,
+    encoder: Arc<Encoder>,
+}
+
+struct Encoder {
+    bert: Bert,
+    tokenizer: WordPiece,
+    pooling: Pooling,
+    /// Tokens read of each text, `[CLS]` and `[SEP]` included; the rest is
+    /// cut off.
+    max_len: usize,
+}
+
+impl LocalModel {
+    /// The model in the directory `model`, or else the Hugging Face repo
+    /// `model` names, like `BAAI/bge-small-en-v1.5`, downloaded on first
+    /// use to `~/.cache/rag-my-pdf/models` (or `$XDG_CACHE_HOME`).
+    pub async fn new(model: &str) -> Result<Self> {
+        let dir = match Path::new(model).is_dir() {
+            true => PathBuf::from(model),
+            false => fetch(model).await?,
+        };
+        let encoder = tokio::task::spawn_blocking(move || Encoder::load(&dir))
+            .await?
+            .with_context(|| format!("Failed to load the local model {}", model))?;
+        info!(
+            "Loaded {} locally: {} dimensions, {:?} pooling, up to {} tokens a text",
+            model,
+            encoder.bert.hidden_size(),
+            encoder.pooling,
+            encoder.max_len
+        );
+        Ok(Self {
+            model: model.to_string(),
+            encoder: Arc::new(encoder),
+        })
+    }
+
+    pub fn cache_key(&self) -> String {
+        format!("local {}", self.model)
+    }
+
+    pub fn ndims(&self) -> usize {
+        self.encoder.bert.hidden_size()
+    }
+
+    /// The most tokens of a text the model reads, besides `[CLS]` and
+    /// `[SEP]`.
+    pub fn max_tokens(&self) -> usize {
+        self.encoder.max_len - 2
+    }
+
+    /// `text`'s length in the model's tokens.
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.encoder.tokenizer.count(text)
+    }
+
+    /// Embeds `texts` on blocking threads, as many at once as there are
+    /// cores, so the runtime goes on with other work meanwhile.
+    pub async fn embed_texts(&self, texts: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        let encoder = self.encoder.clone();
+        let vectors = tokio::task::spawn_blocking(move || {
+            let vectors = encoder.embed_all(&texts);
+            texts
+                .into_iter()
+                .zip(vectors)
+                .map(|(document, vec)| Embedding { document, vec })
+                .collect()
+        })
+        .await
+        .map_err(|e| EmbeddingError::ProviderError(e.to_string()))?;
+        Ok(vectors)
+    }
+}
+
+impl Encoder {
+    fn load(dir: &Path) -> Result<Self> {
+        let read = |name: &str| {
+            std::fs::read(dir.join(name)).with_context(|| format!("Failed to read {}", name))
+        };
+        let optional = |name: &str| -> Result<Option<Vec<u8>>> {
+            let path = dir.join(name);
+            match path.exists() {
+                true => Ok(Some(
+                    std::fs::read(&path).with_context(|| format!("Failed to read {}", name))?,
+                )),
+                false => Ok(None),
+            }
+        };
+        let config: Config =
+            serde_json::from_slice(&read("config.json")?).context("Unexpected config.json")?;
+        let bert = Bert::new(&config, &read("model.safetensors")?)?;
+
+        let lowercase = match optional("tokenizer_config.json")? {
+            Some(json) => {
+                let config: TokenizerConfig =
+                    serde_json::from_slice(&json).context("Unexpected tokenizer_config.json")?;
+                config.do_lower_case
+            }
+            None => true,
+        };
+        let vocab = String::from_utf8(read("vocab.txt")?).context("vocab.txt isn't UTF-8")?;
+        let tokenizer = WordPiece::new(&vocab, lowercase)?;
+
+        let max_len = match optional("sentence_bert_config.json")? {
+            Some(json) => {
+                let config: SentenceBertConfig = serde_json::from_slice(&json)
+                    .context("Unexpected sentence_bert_config.json")?;
+                config.max_seq_length
+            }
+            None => None,
+        };
+        let max_len = max_len.unwrap_or(512).min(bert.max_positions()).max(2);
+        let pooling = match optional("1_Pooling/config.json")? {
+            Some(json) => {
+                let config: PoolingConfig =
+                    serde_json::from_slice(&json).context("Unexpected 1_Pooling/config.json")?;
+                match config.pooling_mode_cls_token {
+                    true => Pooling::Cls,
+                    false => Pooling::Mean,
+                }
+            }
+            None => Pooling::Mean,
+        };
+        Ok(Self {
+            bert,
+            tokenizer,
+            pooling,
+            max_len,
+        })
+    }
+
+    /// The vectors of `texts`, shared out among the machine's cores.
+    fn embed_all(&self, texts: &[String]) -> Vec<Vec<f64>> {
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(texts.len())
+            .max(1);
+        let share = texts.len().div_ceil(threads).max(1);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = texts
+                .chunks(share)
+                .map(|texts| {
+                    scope.spawn(move || {
+                        texts
+                            .iter()
+                            .map(|text| self.embed(text))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect()
+        })
+    }
+
+    /// `text`'s vector, pooled from its tokens' and scaled to length 1, as
+    /// sentence-transformers models are compared by cosine.
+    fn embed(&self, text: &str) -> Vec<f64> {
+        let ids = self.tokenizer.encode(text, self.max_len);
+        let states = self.bert.forward(&ids);
+        let hidden = self.bert.hidden_size();
+        let mut pooled: Vec<f64> = match self.pooling {
+            Pooling::Cls => states[..hidden].iter().map(|&v| f64::from(v)).collect(),
+            Pooling::Mean => {
+                let mut sums = vec![0.0; hidden];
+                for token in states.chunks_exact(hidden) {
+                    for (sum, &v) in sums.iter_mut().zip(token) {
+                        *sum += f64::from(v);
+                    }
+                }
+                let tokens = (states.len() / hidden) as f64;
+                sums.into_iter().map(|sum| sum / tokens).collect()
+            }
+        };
+        let norm = pooled.iter().map(|v| v * v).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            for v in &mut pooled {
+                *v /= norm;
+            }
+        }
+        pooled
+    }
+}
+
+/// The directory the Hugging Face repo `model` is cached in, downloading
+/// its files there first unless an earlier run has. The files only some
+/// models have are fetched where they exist.
+async fn fetch(model: &str) -> Result<PathBuf> {
+    if !model.contains('/') {
+        bail!(
+            "--embedding-provider local needs a Hugging Face repo like {}, or a directory holding a model's files; {:?} is neither",
+            LOCAL_EMBEDDING_MODEL,
+            model
+        );
+    }
+    let dir = cache_dir("models")?.join(model.replace('/', "--"));
+    let endpoint = std::env::var("HF_ENDPOINT").unwrap_or_else(|_| HUGGINGFACE_URL.to_string());
+    let token = std::env::var("HF_TOKEN").ok();
+    fetch_into(&dir, &endpoint, token.as_deref(), model).await?;
+    Ok(dir)
+}
+
+/// Downloads the files of `model` from `endpoint` to `dir` that aren't
+/// there yet. Once every file has been tried, a marker naming the ones the
+/// repo doesn't have is written, and until it is, a later run tries again:
+/// a download cut short mustn't leave a bge model without the pooling
+/// config that says it pools by `[CLS]`.
+async fn fetch_into(dir: &Path, endpoint: &str, token: Option<&str>, model: &str) -> Result<()> {
+    let marker = dir.join(COMPLETE_MARKER);
+    if marker.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir.join("1_Pooling"))
+        .with_context(|| format!("Failed to create {:?}", dir))?;
+    let client = reqwest::Client::new();
+    info!("Downloading {} from {} to {:?}", model, endpoint, dir);
+    let mut missing = Vec::new();
+    for (&file, required) in REQUIRED_FILES
+        .iter()
+        .map(|file| (file, true))
+        .chain(OPTIONAL_FILES.iter().map(|file| (file, false)))
+    {
+        let path = dir.join(file);
+        if path.exists() {
+            continue;
+        }
+        let url = format!(
+            "{}/{}/resolve/main/{}",
+            endpoint.trim_end_matches('/'),
+            model,
+            file
+        );
+        let mut request = client.get(&url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch {}", url))?;
+        match response.status() {
+            status if status.is_success() => download(response, &path, None).await?,
+            reqwest::StatusCode::NOT_FOUND if !required => missing.push(file),
+            reqwest::StatusCode::NOT_FOUND => bail!(
+                "{} has no {}; only BERT models with safetensors weights run locally",
+                model,
+                file
+            ),
+            status => bail!("Fetching {} returned HTTP {}", url, status),
+        }
+    }
+    let mut contents = String::from("Not in the repo:\n");
+    for file in missing {
+        contents.push_str(file);
+        contents.push('\n');
+    }
+    std::fs::write(&marker, contents).with_context(|| format!("Failed to write {:?}", marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOCAB: &str =
+        "[PAD]\n[UNK]\n[CLS]\n[SEP]\nhello\nworld\n,\n!\nun\n##aff\n##able\ncafe\n東\n京\n";
+    const HIDDEN: usize = 8;
+    const LAYERS: usize = 2;
+    const INTERMEDIATE: usize = 16;
+    const POSITIONS: usize = 16;
+
+    /// The tensors of a tiny BERT with fixed weights, in the order their
+    /// values are made.
+    fn tensors() -> Vec<(String, Vec<usize>)> {
+        let mut tensors = vec![
+            (
+                "embeddings.word_embeddings.weight".to_string(),
+                vec![14, HIDDEN],
+            ),
+            (
+                "embeddings.position_embeddings.weight".to_string(),
+                vec![POSITIONS, HIDDEN],
+            ),
+            (
+                "embeddings.token_type_embeddings.weight".to_string(),
+                vec![2, HIDDEN],
+            ),
+            ("embeddings.LayerNorm.weight".to_string(), vec![HIDDEN]),
+            ("embeddings.LayerNorm.bias".to_string(), vec![HIDDEN]),
+        ];
+        for layer in 0..LAYERS {
+            let mut add = |name: &str, shape: Vec<usize>| {
+                tensors.push((format!("encoder.layer.{}.{}", layer, name), shape));
+            };
+            for name in ["query", "key", "value"] {
+                add(
+                    &format!("attention.self.{}.weight", name),
+                    vec![HIDDEN, HIDDEN],
+                );
+                add(&format!("attention.self.{}.bias", name), vec![HIDDEN]);
+            }
+            add("attention.output.dense.weight", vec![HIDDEN, HIDDEN]);
+            add("attention.output.dense.bias", vec![HIDDEN]);
+            add("attention.output.LayerNorm.weight", vec![HIDDEN]);
+            add("attention.output.LayerNorm.bias", vec![HIDDEN]);
+            add("intermediate.dense.weight", vec![INTERMEDIATE, HIDDEN]);
+            add("intermediate.dense.bias", vec![INTERMEDIATE]);
+            add("output.dense.weight", vec![HIDDEN, INTERMEDIATE]);
+            add("output.dense.bias", vec![HIDDEN]);
+            add("output.LayerNorm.weight", vec![HIDDEN]);
+            add("output.LayerNorm.bias", vec![HIDDEN]);
+        }
+        tensors
+    }
+
+    /// Writes the tiny model's files to a fresh directory.
+    fn tiny_model() -> tempfile::TempDir {
+        let model = tempfile::tempdir().unwrap();
+        let dir = model.path();
+        let mut header = serde_json::Map::new();
+        let mut data = Vec::new();
+        for (k, (name, shape)) in tensors().into_iter().enumerate() {
+            let start = data.len();
+            let offset = if name.ends_with("LayerNorm.weight") {
+                1.0
+            } else {
+                0.0
+            };
+            for i in 0..shape.iter().product::<usize>() {
+                let value = offset + 0.5 * (0.37 * i as f64 + 1.3 * k as f64).sin();
+                data.extend((value as f32).to_le_bytes());
+            }
+            header.insert(
+                name,
+                serde_json::json!({"dtype": "F32", "shape": shape, "data_offsets": [start, data.len()]}),
+            );
+        }
+        let header = serde_json::to_vec(&header).unwrap();
+        let mut weights = (header.len() as u64).to_le_bytes().to_vec();
+        weights.extend(header);
+        weights.extend(data);
+        std::fs::write(dir.join("model.safetensors"), weights).unwrap();
+        let config = serde_json::json!({
+            "model_type": "bert",
+            "vocab_size": 14,
+            "hidden_size": HIDDEN,
+            "num_hidden_layers": LAYERS,
+            "num_attention_heads": 2,
+            "intermediate_size": INTERMEDIATE,
+            "max_position_embeddings": POSITIONS,
+        });
+        std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+        std::fs::write(dir.join("vocab.txt"), VOCAB).unwrap();
+        model
+    }
+
+    fn assert_near(vec: &[f64], expected: &[f64]) {
+        assert_eq!(vec.len(), expected.len());
+        for (value, expected) in vec.iter().zip(expected) {
+            assert!(
+                (value - expected).abs() < 1e-4,
+                "{:?} != {:?}",
+                vec,
+                expected
+            );
+        }
+    }
+
+    // The expected vectors are from a separate, plain Python BERT run on
+    // the same weights.
+    #[test]
+    fn matches_a_reference_bert() {
+        let model = tiny_model();
+        let dir = model.path();
+        let mut encoder = Encoder::load(dir).unwrap();
+        assert_eq!(encoder.pooling, Pooling::Mean);
+        assert_eq!(encoder.max_len, POSITIONS);
+        assert_near(
+            &encoder.embed("Hello, world!"),
+            &[
+                -0.188143, -0.252229, 0.357014, -0.217425, 0.444679, -0.461516, 0.107159, -0.551389,
+            ],
+        );
+        assert_near(
+            &encoder.embed("unaffable 東京"),
+            &[
+                -0.136106, -0.203872, 0.369838, -0.258021, 0.394924, -0.539026, 0.120256, -0.524958,
+            ],
+        );
+        encoder.pooling = Pooling::Cls;
+        assert_near(
+            &encoder.embed("Hello, world!"),
+            &[
+                -0.158299, -0.200766, 0.414934, -0.051729, 0.474821, -0.34236, -0.034868, -0.644909,
+            ],
+        );
+    }
+
+    #[test]
+    fn reads_the_sentence_transformers_settings() {
+        let model = tiny_model();
+        let dir = model.path();
+        std::fs::create_dir_all(dir.join("1_Pooling")).unwrap();
+        std::fs::write(
+            dir.join("1_Pooling/config.json"),
+            r#"{"pooling_mode_cls_token": true}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("sentence_bert_config.json"),
+            r#"{"max_seq_length": 4}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("tokenizer_config.json"),
+            r#"{"do_lower_case": false}"#,
+        )
+        .unwrap();
+        let encoder = Encoder::load(dir).unwrap();
+        assert_eq!(encoder.pooling, Pooling::Cls);
+        assert_eq!(encoder.max_len, 4);
+        assert_eq!(encoder.tokenizer.encode("Hello world", 512), [2, 1, 5, 3]);
+        let vectors = encoder.embed_all(&["hello".to_string(), "world".to_string()]);
+        assert_eq!(vectors.len(), 2);
+        assert_ne!(vectors[0], vectors[1]);
+    }
+
+    /// A Hugging Face endpoint serving `files` of the repo `org/tiny`, and
+    /// the paths it has been asked for.
+    async fn serving(
+        files: Vec<(&'static str, Vec<u8>)>,
+    ) -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = asked.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut connection, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let mut buf = [0; 1024];
+                    match connection.read(&mut buf).await.unwrap() {
+                        0 => break,
+                        n => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let file = path
+                    .strip_prefix("/org/tiny/resolve/main/")
+                    .unwrap_or_default();
+                log.lock().unwrap().push(file.to_string());
+                let (status, body) = match files.iter().find(|(name, _)| *name == file) {
+                    Some((_, body)) => ("200 OK", body.clone()),
+                    None => ("404 Not Found", Vec::new()),
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                connection.write_all(head.as_bytes()).await.unwrap();
+                connection.write_all(&body).await.unwrap();
+            }
+        });
+        (endpoint, asked)
+    }
+
+    fn model_files() -> Vec<(&'static str, Vec<u8>)> {
+        let model = tiny_model();
+        REQUIRED_FILES
+            .iter()
+            .map(|&file| (file, std::fs::read(model.path().join(file)).unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetches_each_file_once() {
+        let mut files = model_files();
+        files.push((
+            "1_Pooling/config.json",
+            br#"{"pooling_mode_cls_token": true}"#.to_vec(),
+        ));
+        let (endpoint, asked) = serving(files).await;
+        let dir = tempfile::tempdir().unwrap();
+        fetch_into(dir.path(), &endpoint, None, "org/tiny")
+            .await
+            .unwrap();
+        assert_eq!(
+            asked.lock().unwrap().len(),
+            REQUIRED_FILES.len() + OPTIONAL_FILES.len()
+        );
+        let marker = std::fs::read_to_string(dir.path().join(COMPLETE_MARKER)).unwrap();
+        assert!(marker.contains("tokenizer_config.json"));
+        assert!(!marker.contains("1_Pooling"));
+        assert_eq!(Encoder::load(dir.path()).unwrap().pooling, Pooling::Cls);
+
+        // The files missing from the repo aren't asked for again.
+        fetch_into(dir.path(), &endpoint, None, "org/tiny")
+            .await
+            .unwrap();
+        assert_eq!(
+            asked.lock().unwrap().len(),
+            REQUIRED_FILES.len() + OPTIONAL_FILES.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn finishes_a_download_cut_short() {
+        let mut files = model_files();
+        files.push((
+            "1_Pooling/config.json",
+            br#"{"pooling_mode_cls_token": true}"#.to_vec(),
+        ));
+        let (endpoint, asked) = serving(files.clone()).await;
+        // The weights came down, but not the pooling config.
+        let dir = tempfile::tempdir().unwrap();
+        for (file, body) in &files[..REQUIRED_FILES.len()] {
+            std::fs::write(dir.path().join(file), body).unwrap();
+        }
+        fetch_into(dir.path(), &endpoint, None, "org/tiny")
+            .await
+            .unwrap();
+        assert_eq!(*asked.lock().unwrap(), OPTIONAL_FILES);
+        assert_eq!(Encoder::load(dir.path()).unwrap().pooling, Pooling::Cls);
+    }
+
+    #[tokio::test]
+    async fn fails_without_a_required_file() {
+        let (endpoint, _) = serving(Vec::new()).await;
+        let dir = tempfile::tempdir().unwrap();
+        let error = fetch_into(dir.path(), &endpoint, None, "org/tiny")
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("has no config.json"),
+            "{}",
+            error
+        );
+        assert!(!dir.path().join(COMPLETE_MARKER).exists());
+    }
+}
@@ -0,0 +1,211 @@
+//! The WordPiece tokenizer BERT models read text through. Text is split at
+//! whitespace and punctuation and around CJK ideographs, lowercased and
+//! stripped of accents for uncased models, and each word is cut into the
+//! longest pieces the vocabulary has.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Longest word cut into pieces; longer ones are unknown.
+const MAX_WORD_CHARS: usize = 100;
+
+pub struct WordPiece {
+    vocab: HashMap<String, u32>,
+    lowercase: bool,
+    unknown: u32,
+    cls: u32,
+    sep: u32,
+}
+
+impl WordPiece {
+    /// The tokenizer for `vocab`, the contents of a `vocab.txt`, one token
+    /// per line in id order.
+    pub fn new(vocab: &str, lowercase: bool) -> Result<Self> {
+        let vocab: HashMap<String, u32> = vocab
+            .lines()
+            .enumerate()
+            .map(|(id, token)| (token.trim_end_matches('\r').to_string(), id as u32))
+            .collect();
+        let id = |token: &str| {
+            vocab
+                .get(token)
+                .copied()
+                .with_context(|| format!("The vocabulary has no {} token", token))
+        };
+        Ok(Self {
+            unknown: id("[UNK]")?,
+            cls: id("[CLS]")?,
+            sep: id("[SEP]")?,
+            vocab,
+            lowercase,
+        })
+    }
+
+    /// The ids of `text`'s tokens, between `[CLS]` and `[SEP]`, cut off at
+    /// `max_len` ids in all.
+    pub fn encode(&self, text: &str, max_len: usize) -> Vec<u32> {
+        let mut ids = vec![self.cls];
+        for word in self.words(text) {
+            if ids.len() >= max_len - 1 {
+                break;
+            }
+            self.pieces(&word, &mut ids);
+        }
+        ids.truncate(max_len - 1);
+        ids.push(self.sep);
+        ids
+    }
+
+    /// How many tokens `text` comes to, all of it, without `[CLS]` and
+    /// `[SEP]`.
+    pub fn count(&self, text: &str) -> usize {
+        let mut ids = Vec::new();
+        for word in self.words(text) {
+            self.pieces(&word, &mut ids);
+        }
+        ids.len()
+    }
+
+    /// `text` split into words, with each punctuation mark and CJK
+    /// ideograph a word of its own.
+    fn words(&self, text: &str) -> Vec<String> {
+        let text: String = if self.lowercase {
+            text.to_lowercase()
+                .nfd()
+                .filter(|&c| !is_combining_mark(c))
+                .collect()
+        } else {
+            text.to_string()
+        };
+        let mut words = Vec::new();
+        let mut word = String::new();
+        for c in text.chars() {
+            if c == '\0' || c == '\u{fffd}' || (c.is_control() && !c.is_whitespace()) {
+                continue;
+            }
+            if c.is_whitespace() || is_punctuation(c) || is_cjk(c) {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                if !c.is_whitespace() {
+                    words.push(c.to_string());
+                }
+            } else {
+                word.push(c);
+            }
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+        words
+    }
+
+    /// Adds the ids of `word`'s pieces to `ids`: the longest start of it
+    /// in the vocabulary, then the longest `##` continuation, and so on.
+    /// A word that can't be cut up that way is unknown as a whole.
+    fn pieces(&self, word: &str, ids: &mut Vec<u32>) {
+        if word.chars().count() > MAX_WORD_CHARS {
+            ids.push(self.unknown);
+            return;
+        }
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while start < word.len() {
+            let mut end = word.len();
+            let id = loop {
+                if end == start {
+                    ids.push(self.unknown);
+                    return;
+                }
+                let piece = match start {
+                    0 => word[..end].to_string(),
+                    _ => format!("##{}", &word[start..end]),
+                };
+                if let Some(&id) = self.vocab.get(&piece) {
+                    break id;
+                }
+                end -= word[start..end]
+                    .chars()
+                    .next_back()
+                    .map_or(1, char::len_utf8);
+            };
+            pieces.push(id);
+            start = end;
+        }
+        ids.extend(pieces);
+    }
+}
+
+/// ASCII punctuation and symbols, which BERT splits off too, and the
+/// punctuation of Latin-1, the General Punctuation block and CJK text.
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation()
+        || matches!(c, '¡' | '§' | '«' | '¶' | '·' | '»' | '¿')
+        || matches!(c as u32,
+            0x2010..=0x2027
+            | 0x2030..=0x205E
+            | 0x3001..=0x3003
+            | 0x3008..=0x3011
+            | 0x3014..=0x301F
+            | 0xFF01..=0xFF0F
+            | 0xFF1A..=0xFF20
+            | 0xFF3B..=0xFF40
+            | 0xFF5B..=0xFF65)
+}
+
+/// The CJK ideographs BERT's tokenizer treats as words of their own.
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x4E00..=0x9FFF
+        | 0x3400..=0x4DBF
+        | 0x20000..=0x2A6DF
+        | 0x2A700..=0x2B73F
+        | 0x2B740..=0x2B81F
+        | 0x2B820..=0x2CEAF
+        | 0xF900..=0xFAFF
+        | 0x2F800..=0x2FA1F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOCAB: &str =
+        "[PAD]\n[UNK]\n[CLS]\n[SEP]\nhello\nworld\n,\n!\nun\n##aff\n##able\ncafe\n東\n京";
+
+    fn encode(text: &str) -> Vec<u32> {
+        WordPiece::new(VOCAB, true).unwrap().encode(text, 512)
+    }
+
+    #[test]
+    fn splits_words_and_punctuation() {
+        assert_eq!(encode("Hello, world!"), [2, 4, 6, 5, 7, 3]);
+        assert_eq!(encode("  hello\tWORLD\n"), [2, 4, 5, 3]);
+    }
+
+    #[test]
+    fn cuts_words_into_the_longest_pieces() {
+        assert_eq!(encode("unaffable"), [2, 8, 9, 10, 3]);
+        // No piece covers "##x", so the whole word is unknown.
+        assert_eq!(encode("unaffablex hello"), [2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn strips_accents_and_splits_ideographs() {
+        assert_eq!(encode("Café"), [2, 11, 3]);
+        assert_eq!(encode("東京"), [2, 12, 13, 3]);
+        let cased = WordPiece::new(VOCAB, false).unwrap();
+        assert_eq!(cased.encode("Hello", 512), [2, 1, 3]);
+    }
+
+    #[test]
+    fn keeps_within_the_length() {
+        assert_eq!(encode(""), [2, 3]);
+        let tokenizer = WordPiece::new(VOCAB, true).unwrap();
+        assert_eq!(tokenizer.encode("hello world hello", 3), [2, 4, 3]);
+        assert_eq!(tokenizer.encode("unaffable", 4), [2, 8, 9, 3]);
+        assert_eq!(tokenizer.count("hello world hello unaffable"), 6);
+    }
+}
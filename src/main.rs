@@ -1,64 +1,483 @@
-use anyhow::{Context, Result};
-use pdf_extract::extract_text;
+mod captions;
+mod chat;
+mod chunking;
+mod clean;
+mod cost;
+mod dedup;
+mod embedder;
+mod embedding_cache;
+mod export;
+mod gemini;
+mod hnsw;
+mod huggingface;
+mod index_file;
+mod language;
+mod loaders;
+mod local;
+mod manifest;
+mod ocr;
+mod preview;
+mod progress;
+mod propositions;
+mod quantize;
+mod rate_limit;
+mod redact;
+mod retry;
+mod store;
+mod summaries;
+mod transcribe;
+mod voyage;
+mod watch;
+
+use anyhow::{Context, Result, bail};
+use chunking::{ChunkOptions, ChunkUnit, Chunking, Overlap};
+use embedder::{Embedder, EmbeddingProvider};
+use embedding_cache::CachedEmbedder;
+use language::LanguageRoute;
+use loaders::{Document, LoadOptions, PageRanges};
+use regex::RegexBuilder;
+use retry::{RetryPolicy, Retrying};
+use rig::OneOrMany;
+use rig::agent::AgentBuilder;
 use rig::client::{CompletionClient, EmbeddingsClient};
-use rig::embeddings::EmbeddingsBuilder;
+use rig::embeddings::{Embedding, EmbeddingModel, EmbeddingsBuilder};
 use rig::integrations::cli_chatbot::ChatBotBuilder;
-use rig::vector_store::in_memory_store::InMemoryVectorStore;
 use rig::{client::ProviderClient, providers::openai};
-use std::path::Path;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{debug, info, warn};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
-fn load_pdf_content<P: AsRef<Path>>(file_path: P) -> Result<String> {
-    extract_text(file_path.as_ref())
-        .with_context(|| format!("Failed to extract text from PDF: {:?}", file_path.as_ref()))
-}
+use clap::{ArgGroup, Parser};
+use futures::{StreamExt, TryStreamExt, stream};
 
-fn chunk_text(text: &str, chunk_size: usize, overlap: usize) -> Vec<String> {
-    let words: Vec<&str> = text.split_whitespace().collect();
-    let mut chunks = Vec::new();
-    let mut start = 0;
+/// Chunks retrieved for each question.
+const CONTEXT_CHUNKS: usize = 2;
 
-    while start < words.len() {
-        let end = (start + chunk_size).min(words.len());
-        let chunk = words[start..end].join(" ");
-        chunks.push(chunk);
+#[derive(Parser)]
+#[command(name = "rag-my-pdf")]
+#[command(version, about = "PDF RAG chatbot using OpenAI", long_about = None)]
+#[command(group(ArgGroup::new("tree").multiple(true).args(["dir", "watch", "bucket"])))]
+struct Cli {
+    /// Path to a document to load (PDF, DOCX, ODT, RTF, PPTX, EPUB, Markdown, HTML, LaTeX,
+    /// text, CSV, XLSX, JSON/JSONL, EML, mbox, source code, audio/video or a ZIP of any of
+    /// these).
+    /// Repeat the flag or pass a comma-separated list to load several; `-` reads stdin
+    #[arg(short, long, visible_alias = "file", value_delimiter = ',')]
+    pdf: Vec<String>,
 
-        if end >= words.len() {
-            break;
-        }
+    /// Read raw document text from stdin instead of extracting it from a file
+    #[arg(long)]
+    stdin: bool,
 
-        start += chunk_size - overlap;
-    }
+    /// Directory to walk recursively, loading every supported file
+    #[arg(long)]
+    dir: Option<String>,
 
-    chunks
-}
+    /// Directory to load like --dir, then keep watching while the chat runs, indexing files
+    /// as they are added or changed and dropping deleted ones
+    #[arg(long, value_name = "DIR")]
+    watch: Option<String>,
 
-use clap::Parser;
+    /// ID of a Google Drive folder to load, including its subfolders (repeatable)
+    #[arg(long, value_name = "FOLDER_ID")]
+    drive_folder: Vec<String>,
 
-#[derive(Parser)]
-#[command(name = "rag-my-pdf")]
-#[command(version, about = "PDF RAG chatbot using OpenAI", long_about = None)]
-struct Cli {
-    /// Path to the PDF file to load
-    #[arg(short, long)]
-    pdf: Option<String>,
+    /// Google service account key or authorized user credentials for --drive-folder
+    #[arg(long, value_name = "PATH")]
+    drive_credentials: Option<PathBuf>,
+
+    /// URL of a Confluence space to load every page of, e.g.
+    /// `https://example.atlassian.net/wiki/spaces/ENG` (repeatable). Authenticates with
+    /// the CONFLUENCE_TOKEN (and, on Confluence Cloud, CONFLUENCE_EMAIL) environment variables
+    #[arg(long, value_name = "URL")]
+    confluence_space: Vec<String>,
+
+    /// Recursively load every supported object under an `s3://bucket/prefix` or
+    /// `gs://bucket/prefix` URL (repeatable)
+    #[arg(long, visible_aliases = ["s3", "gcs"], value_name = "URL")]
+    bucket: Vec<String>,
+
+    /// Only load files under --dir, --watch or --bucket whose relative path matches this glob
+    /// (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "tree")]
+    include: Vec<String>,
+
+    /// Skip files under --dir, --watch or --bucket whose relative path matches this glob
+    /// (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "tree")]
+    exclude: Vec<String>,
+
+    /// URL of a web page to load
+    #[arg(long)]
+    url: Option<String>,
+
+    /// arXiv ID of a paper to download and load, e.g. `2403.12345` (repeatable)
+    #[arg(long, value_name = "ID", value_delimiter = ',')]
+    arxiv: Vec<String>,
+
+    /// DOI of a paper to download and load (repeatable)
+    #[arg(long, value_delimiter = ',')]
+    doi: Vec<String>,
+
+    /// Largest file to download from a URL, in megabytes
+    #[arg(long, default_value_t = 100, value_name = "MB")]
+    max_download_mb: u64,
+
+    /// Glob of files to load, each as a separate document (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    input: Vec<String>,
+
+    /// Always run OCR instead of using the PDF's text layer
+    #[arg(long)]
+    ocr: bool,
+
+    /// Detect tables in PDFs and keep them as Markdown tables
+    #[arg(long)]
+    tables: bool,
+
+    /// Detect multi-column PDF layouts (like two-column papers) and read each column in turn
+    #[arg(long)]
+    columns: bool,
+
+    /// Keep running headers, footers and page numbers repeated across PDF pages
+    #[arg(long)]
+    keep_headers: bool,
+
+    /// Caption images embedded in PDFs with a vision model and index the captions
+    #[arg(long)]
+    caption_images: bool,
+
+    /// Vision model used by --caption-images
+    #[arg(long, default_value = "gpt-4o-mini")]
+    vision_model: String,
+
+    /// Where chunks and questions are embedded: the OpenAI API, an Azure OpenAI deployment (see
+    /// --azure-endpoint), the Cohere API (with COHERE_API_KEY), the Voyage AI API (with
+    /// VOYAGE_API_KEY), Google's Gemini API (with GEMINI_API_KEY), a sentence-transformers model
+    /// on Hugging Face (with HF_TOKEN) or a Text Embeddings Inference server (see
+    /// --huggingface-url), a model served by Ollama, or a BERT sentence-transformers model run
+    /// in this process (`local`, see --embedding-model); the last two send no document text out
+    /// for embedding
+    #[arg(long, value_enum, default_value_t = EmbeddingProvider::Openai)]
+    embedding_provider: EmbeddingProvider,
+
+    /// Embedding model [default: text-embedding-3-small, embed-english-v3.0 with
+    /// --embedding-provider cohere, voyage-3 with --embedding-provider voyage,
+    /// text-embedding-004 with --embedding-provider gemini,
+    /// sentence-transformers/all-MiniLM-L6-v2 with --embedding-provider huggingface, or
+    /// all-minilm with --embedding-provider ollama]. With --embedding-provider local, a
+    /// directory holding the model's files or a Hugging Face repo to download them from once
+    #[arg(long, value_name = "MODEL")]
+    embedding_model: Option<String>,
+
+    /// Cut OpenAI's text-embedding-3 vectors to this many dimensions (like 256 or 512), which
+    /// keeps the index smaller and searches it faster for a little less accuracy
+    #[arg(long, value_name = "N")]
+    embedding_dimensions: Option<usize>,
+
+    /// Embed with a server speaking OpenAI's embeddings API instead of OpenAI, like vLLM, LM
+    /// Studio or text-embeddings-inference, at this URL (like http://localhost:8000/v1), with
+    /// --embedding-model naming the model it serves. A key, if it needs one, goes in
+    /// EMBEDDING_API_KEY
+    #[arg(long, value_name = "URL")]
+    embedding_base_url: Option<String>,
+
+    /// Split chunks longer than this many tokens before they are embedded, for models served by
+    /// Hugging Face, Ollama or --embedding-base-url, whose limit isn't known, or to change the
+    /// known limit of any other (and of --ensemble-model's) [default: the model's limit where
+    /// known, or else none]
+    #[arg(long, value_name = "N")]
+    max_embed_tokens: Option<usize>,
+
+    /// Put this before every question a model served by Hugging Face, Ollama or
+    /// --embedding-base-url, or run locally, embeds [default: what the model is known to want, like `query: ` for e5 models or
+    /// `search_query: ` for nomic-embed-text]
+    #[arg(long, value_name = "TEXT")]
+    query_prefix: Option<String>,
+
+    /// Put this before every chunk a model served by Hugging Face, Ollama or
+    /// --embedding-base-url, or run locally, embeds [default: what the model is known to want, like `passage: ` for e5 models]
+    #[arg(long, value_name = "TEXT")]
+    passage_prefix: Option<String>,
+
+    /// Embed every chunk with this model as well, and fuse the two models' rankings of the
+    /// chunks for each question (reciprocal rank fusion). Different models miss different
+    /// questions, so this finds more of what is asked about, at twice the embedding calls
+    #[arg(long, value_name = "MODEL")]
+    ensemble_model: Option<String>,
+
+    /// Where --ensemble-model is served; the choices are those of --embedding-provider
+    #[arg(long, value_enum, default_value_t = EmbeddingProvider::Openai)]
+    ensemble_provider: EmbeddingProvider,
+
+    /// Keep the index's vectors in memory as bytes (int8) or bits (binary) instead of 64-bit
+    /// floats, for large collections. Candidates found with them are scored again against the
+    /// full question vector, so little is lost
+    #[arg(long, value_enum, value_name = "TYPE")]
+    quantize: Option<quantize::Quantization>,
+
+    /// Search the in-memory index through an HNSW graph rather than scoring every vector, for
+    /// large collections: much faster questions, at a small chance of missing a close chunk
+    #[arg(long)]
+    hnsw: bool,
+
+    /// Links each vector keeps in the --hnsw graph. More find closer chunks, at more memory and
+    /// a slower build
+    #[arg(long, value_name = "N", default_value_t = 16, requires = "hnsw")]
+    hnsw_m: usize,
+
+    /// Candidates the --hnsw graph weighs for each question. More find closer chunks, more
+    /// slowly
+    #[arg(long, value_name = "N", default_value_t = 64, requires = "hnsw")]
+    hnsw_ef: usize,
+
+    /// Candidates weighed when linking each vector into the --hnsw graph. More build a better
+    /// graph, more slowly
+    #[arg(long, value_name = "N", default_value_t = 100, requires = "hnsw")]
+    hnsw_ef_construction: usize,
+
+    /// Chunks sent to the embedding API per request. A failing request is retried a chunk at
+    /// a time, so one bad chunk is left out rather than failing the run
+    #[arg(long, value_name = "N", default_value_t = 256)]
+    embed_batch_size: usize,
+
+    /// Embedding requests in flight at once. Raise it to embed large documents faster, lower
+    /// it if the provider's rate limits are hit
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    embed_concurrency: usize,
+
+    /// Times a failed API call is tried again, when it failed for a reason that passes: a rate
+    /// limit, an overloaded server or a dropped connection
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    max_retries: u32,
+
+    /// Wait before the first retry, in milliseconds, doubled for each retry after it (with some
+    /// jitter) unless the API says how long to wait
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    retry_delay: u64,
+
+    /// Most API requests to send a minute, across embedding, captioning, proposition, summary,
+    /// transcription and chat calls. Set it to your account's limit to be kept under it
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    rpm: Option<u32>,
+
+    /// Most tokens to send a minute, as estimated from the text of each request
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    tpm: Option<u32>,
+
+    /// Ollama server used by --embedding-provider ollama [default: OLLAMA_API_BASE_URL, or
+    /// http://localhost:11434]
+    #[arg(long, value_name = "URL")]
+    ollama_url: Option<String>,
+
+    /// Text Embeddings Inference server, or Hugging Face Inference Endpoint, used by
+    /// --embedding-provider huggingface instead of the serverless Inference API, like
+    /// http://localhost:8080. It embeds with whatever model it serves
+    #[arg(long, value_name = "URL")]
+    huggingface_url: Option<String>,
+
+    /// Azure OpenAI resource used by --chat-provider azure and --embedding-provider azure, like
+    /// https://my-resource.openai.azure.com [default: AZURE_OPENAI_ENDPOINT]
+    #[arg(long, value_name = "URL")]
+    azure_endpoint: Option<String>,
+
+    /// Azure OpenAI API version [default: AZURE_OPENAI_API_VERSION, or 2024-10-21]
+    #[arg(long, value_name = "VERSION")]
+    azure_api_version: Option<String>,
+
+    /// Transcription model used for audio and video files
+    #[arg(long, default_value = "whisper-1")]
+    transcription_model: String,
+
+    /// Transcribe audio locally with whisper.cpp using this ggml model file
+    #[arg(long, value_name = "PATH")]
+    whisper_cpp_model: Option<PathBuf>,
+
+    /// Only index these PDF pages, e.g. `10-55,80`
+    #[arg(long, value_name = "RANGES")]
+    pages: Option<PageRanges>,
+
+    /// JSON/JSONL field holding the text to embed
+    #[arg(long, value_name = "FIELD")]
+    text_field: Option<String>,
+
+    /// JSON/JSONL fields to attach to chunks as metadata (comma-separated)
+    #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+    metadata_fields: Vec<String>,
+
+    /// Password for encrypted PDFs (prompted for when omitted)
+    #[arg(long)]
+    pdf_password: Option<String>,
+
+    /// Mask email addresses, phone numbers, social security numbers and names before any text
+    /// is sent to OpenAI
+    #[arg(long)]
+    redact_pii: bool,
+
+    /// Remove boilerplate lines (copyright notices, "this page intentionally left blank", page
+    /// counters) and normalize Unicode and whitespace before chunking
+    #[arg(long)]
+    clean: bool,
+
+    /// File of extra --clean rules: one regular expression per line, and every line of text
+    /// one matches is removed
+    #[arg(long, value_name = "FILE", requires = "clean")]
+    clean_rules: Option<PathBuf>,
+
+    /// Retrieve only chunks in these languages, as ISO 639-3 codes like eng,deu, or with auto
+    /// in the language each question is asked in; chunks whose language isn't known are always
+    /// retrieved
+    #[arg(long, value_name = "CODES")]
+    lang: Option<LanguageRoute>,
+
+    /// Index only one copy of near-duplicate chunks, such as the unchanged pages of several
+    /// revisions of a report, noting where the other copies came from
+    #[arg(long)]
+    dedup: bool,
+
+    /// Re-chunk and re-embed every file, instead of reusing the embeddings of files unchanged
+    /// since an earlier run
+    #[arg(long)]
+    reindex: bool,
 
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
 
-    /// OpenAI model to use
+    /// OpenAI model to use (with --chat-provider azure, the name of its deployment)
     #[arg(short, long, default_value = "gpt-3.5-turbo")]
     model: String,
 
-    /// Chunk size in words
+    /// Where the chat models answering, captioning and summing up are called: the OpenAI API,
+    /// or an Azure OpenAI resource (with --azure-endpoint and AZURE_OPENAI_API_KEY or
+    /// AZURE_OPENAI_AD_TOKEN)
+    #[arg(long, value_enum, default_value_t = chat::ChatProvider::Openai)]
+    chat_provider: chat::ChatProvider,
+
+    /// How to cut documents into chunks
+    #[arg(long, value_enum, default_value_t = Chunking::Words)]
+    chunking: Chunking,
+
+    /// Chunk size, in --chunk-unit
     #[arg(long, default_value = "500")]
     chunk_size: usize,
 
-    /// Overlap between chunks in words
-    #[arg(long, default_value = "50")]
-    chunk_overlap: usize,
+    /// What --chunk-size, --chunk-overlap and --min-chunk-size count [default: words, or
+    /// tokens with --chunking tokens]
+    #[arg(long, value_enum, value_name = "UNIT")]
+    chunk_unit: Option<ChunkUnit>,
+
+    /// Overlap between chunks in --chunk-unit, or in sentences with --chunking sentences, or
+    /// as a percentage of --chunk-size, like 10% [default: 50 (a tenth of smaller chunks), 1
+    /// sentence]
+    #[arg(long)]
+    chunk_overlap: Option<Overlap>,
+
+    /// Smallest chunk, in the same unit as --chunk-size; smaller chunks, like the last few
+    /// words of a document, are merged into the chunk before them
+    #[arg(long, default_value = "0")]
+    min_chunk_size: usize,
+
+    /// Also cut documents into larger parent chunks of this size, in the same unit as
+    /// --chunk-size: chunks are retrieved as usual, but the model reads the parent each falls in
+    #[arg(long, value_name = "SIZE")]
+    parent_chunk_size: Option<usize>,
+
+    /// Similarity between neighbouring sentences below which --chunking semantic starts a new
+    /// chunk [default: the least similar tenth of each document's sentence pairs]
+    #[arg(long, value_name = "SIMILARITY")]
+    semantic_threshold: Option<f64>,
+
+    /// JSON file of chunk settings by file type or path: an array of objects with a "match"
+    /// glob, like "*.rs", and any of "chunking", "chunk_unit", "chunk_size", "chunk_overlap" and
+    /// "min_chunk_size", used for the documents whose source matches instead of the flags
+    #[arg(long, value_name = "FILE")]
+    chunk_profiles: Option<PathBuf>,
+
+    /// Regular expression marking where chunks start, like "^Article \\d+" for contracts or
+    /// "^Q\\d+:" for questionnaires: documents are cut before every match, and only pieces
+    /// longer than --chunk-size are chunked further with --chunking
+    #[arg(long, value_name = "PATTERN")]
+    split_regex: Option<String>,
+
+    /// Chunk within the sections marked by headings (Markdown, DOCX heading styles, large
+    /// text in PDFs) and start each chunk with its section path, like "Chapter 3 > Installation"
+    #[arg(long)]
+    sections: bool,
+
+    /// Start every chunk with a line naming its document, section and page, like
+    /// "Document: Annual Report | Section: Results | Page: 14", before it is embedded
+    #[arg(long)]
+    chunk_headers: bool,
+
+    /// Have a chat model rewrite every chunk as short, self-contained factual statements and
+    /// index those instead. Costs a chat call per chunk; answers are cached
+    #[arg(long)]
+    propositions: bool,
+
+    /// Chat model used by --propositions
+    #[arg(long, default_value = "gpt-4o-mini")]
+    proposition_model: String,
+
+    /// Have a chat model sum up every chunk in a sentence, embedded alongside its text or
+    /// instead of it, and shown to the model with the chunk. Costs a chat call per chunk;
+    /// answers are cached
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, default_missing_value = "alongside")]
+    summaries: Option<summaries::SummaryMode>,
+
+    /// Chat model used by --summaries
+    #[arg(long, default_value = "gpt-4o-mini")]
+    summary_model: String,
+
+    /// Don't ask before embedding, however much the estimate printed beforehand comes to
+    #[arg(long, short = 'y')]
+    yes: bool,
+
+    /// Only load and chunk the documents, and print the chunks with their sizes and metadata
+    /// instead of starting the chatbot. Calls no API, so images aren't captioned and audio is
+    /// only transcribed with --whisper-cpp-model
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "text")]
+    preview_chunks: Option<preview::PreviewFormat>,
+
+    /// Embed the documents, write every chunk with its text, metadata and vectors to this JSON
+    /// Lines file, and exit instead of starting the chatbot
+    #[arg(long, value_name = "PATH")]
+    export_embeddings: Option<PathBuf>,
+
+    /// Add the chunks and vectors in a JSON Lines file, as --export-embeddings writes them, to
+    /// the index without chunking or embedding them. Questions are embedded with
+    /// --embedding-model, which must be the model the vectors came from
+    #[arg(long, value_name = "PATH")]
+    import_embeddings: Option<PathBuf>,
+
+    /// Save the index, every chunk with its vectors, to this file once the documents are
+    /// embedded, and load it from there on later runs instead of extracting and embedding
+    /// anything. Pass --reindex to build it again from the documents
+    #[arg(long, value_name = "PATH")]
+    index_path: Option<PathBuf>,
+
+    /// Where the index is kept: in memory, built again each run from the documents and the
+    /// caches, or in a vector database, which other runs and processes can search too
+    #[arg(long, value_enum, default_value_t = store::StoreKind::Memory)]
+    store: store::StoreKind,
+
+    /// URL of the --store database, the path of a SQLite one, or a Postgres connection string
+    /// [default: http://localhost:6333 for Qdrant, rag-my-pdf.db for SQLite, DATABASE_URL for
+    /// Postgres, the host Pinecone serves the index at, http://localhost:19530 for Milvus,
+    /// redis://localhost:6379 for Redis, http://localhost:8000 for Chroma]
+    #[arg(long, value_name = "URL")]
+    store_url: Option<String>,
+
+    /// Collection (or SQLite or Postgres table) of the --store database the chunks are kept in
+    #[arg(long, value_name = "NAME", default_value = store::DEFAULT_COLLECTION)]
+    store_collection: String,
+
+    /// Namespace of the Pinecone index the chunks are kept in, so several corpora or tenants can
+    /// share one index [default: the index's default namespace]
+    #[arg(long, value_name = "NAME")]
+    store_namespace: Option<String>,
 }
 
 #[tokio::main]
@@ -67,59 +486,514 @@ async fn main() -> Result<()> {
 
     // Initialize tracing/logging
     let log_level = if cli.verbose { "debug" } else { "info" };
+    // A preview prints the chunks to stdout, so logs go to stderr instead.
+    let previewing = cli.preview_chunks.is_some();
+    let log_writer = move || -> Box<dyn std::io::Write> {
+        progress::hide();
+        if previewing {
+            Box::new(std::io::stderr())
+        } else {
+            Box::new(std::io::stdout())
+        }
+    };
     tracing_subscriber::registry()
-        .with(fmt::layer().with_target(false).pretty())
+        .with(
+            fmt::layer()
+                .with_target(false)
+                .with_writer(log_writer)
+                .pretty(),
+        )
         .with(EnvFilter::new(log_level))
         .init();
 
     info!("Starting RAG PDF Chatbot");
     debug!("Using model: {}", cli.model);
 
-    // This requires the `OPENAI_API_KEY` environment variable to be set.
-    info!("Initializing OpenAI client");
-    let openai_client = openai::Client::from_env();
+    if cli.embed_batch_size == 0 {
+        bail!("--embed-batch-size must be at least 1");
+    }
+    if cli.embed_concurrency == 0 {
+        bail!("--embed-concurrency must be at least 1");
+    }
+    if let Some(path) = &cli.export_embeddings {
+        export::check_path("--export-embeddings", path)?;
+    }
+    if let Some(path) = &cli.import_embeddings {
+        export::check_path("--import-embeddings", path)?;
+    }
+    let stored = cli.store != store::StoreKind::Memory;
+    if stored {
+        let memory_only = [
+            ("--ensemble-model", cli.ensemble_model.is_some()),
+            ("--quantize", cli.quantize.is_some()),
+            ("--hnsw", cli.hnsw),
+            ("--watch", cli.watch.is_some()),
+            ("--index-path", cli.index_path.is_some()),
+        ];
+        if let Some((flag, _)) = memory_only.iter().find(|(_, given)| *given) {
+            bail!("{} only works with the in-memory index, not --store", flag);
+        }
+    } else if cli.store_url.is_some() {
+        bail!("--store-url needs a --store database");
+    }
+    if cli.store_namespace.is_some() && cli.store != store::StoreKind::Pinecone {
+        bail!("--store-namespace only works with --store pinecone");
+    }
+    let retry = RetryPolicy {
+        retries: cli.max_retries,
+        delay: Duration::from_millis(cli.retry_delay),
+        limiter: rate_limit::RateLimiter::new(cli.rpm, cli.tpm),
+    };
+
+    // Bad chunk settings are caught before anything is loaded.
+    let mut chunk_options = ChunkOptions {
+        chunking: cli.chunking,
+        unit: cli.chunking.unit(cli.chunk_unit)?,
+        size: cli.chunk_size,
+        overlap: cli.chunking.overlap(cli.chunk_size, cli.chunk_overlap)?,
+        min_size: cli.min_chunk_size,
+        semantic_threshold: cli.semantic_threshold,
+        sections: cli.sections,
+        headers: cli.chunk_headers,
+        parent_size: cli.parent_chunk_size,
+        split: cli
+            .split_regex
+            .as_deref()
+            .map(|pattern| RegexBuilder::new(pattern).multi_line(true).build())
+            .transpose()
+            .context("Invalid --split-regex")?,
+        profiles: Vec::new(),
+    };
+    chunk_options.validate()?;
+    if let Some(path) = &cli.chunk_profiles {
+        chunk_options.profiles =
+            chunking::load_profiles(path, &chunk_options, cli.chunk_unit, cli.chunk_overlap)?;
+    }
+    let clean_rules = cli
+        .clean
+        .then(|| clean::CleanRules::load(cli.clean_rules.as_deref()))
+        .transpose()?;
 
-    // Load document from PDF if provided, otherwise use default
-    let document: String = if let Some(pdf_path) = cli.pdf.clone() {
-        info!("Loading PDF from: {}", pdf_path);
-        load_pdf_content(&pdf_path)?
+    // Load document if provided, otherwise use default
+    let load_options = LoadOptions {
+        force_ocr: cli.ocr,
+        pdf_password: cli.pdf_password.clone(),
+        detect_tables: cli.tables,
+        detect_columns: cli.columns,
+        detect_headings: cli.sections,
+        keep_headers: cli.keep_headers,
+        extract_images: cli.caption_images,
+        pages: cli.pages.clone(),
+        text_field: cli.text_field.clone(),
+        metadata_fields: cli.metadata_fields.clone(),
+        max_download_bytes: Some(cli.max_download_mb.saturating_mul(1024 * 1024)),
+    };
+    // A saved index holds everything ingesting would make, so nothing is
+    // loaded for it.
+    let index_saved = !cli.reindex && cli.index_path.as_ref().is_some_and(|path| path.exists());
+    let mut documents = if index_saved {
+        Vec::new()
     } else {
-        warn!("No PDF provided, using default document");
-        String::from("The answer to life is 42 by the way")
+        load_documents(&cli, &load_options).await?
+    };
+    // A database already holds the chunks of earlier runs.
+    if documents.is_empty() && cli.import_embeddings.is_none() && !index_saved && !stored {
+        warn!("No document provided, using default document");
+        documents.push(Document::new("The answer to life is 42 by the way"));
+    }
+    let transcriber = match &cli.whisper_cpp_model {
+        Some(model) => transcribe::Transcriber::WhisperCpp {
+            model: model.clone(),
+        },
+        None => transcribe::Transcriber::OpenAi {
+            model: cli.transcription_model.clone(),
+        },
+    };
+
+    if let Some(format) = cli.preview_chunks {
+        let clean_rules = clean_rules.as_ref();
+        return preview_chunks(
+            documents,
+            &chunk_options,
+            clean_rules,
+            &transcriber,
+            &retry,
+            &cli,
+            format,
+        )
+        .await;
+    }
+
+    // With OpenAI, this requires the `OPENAI_API_KEY` environment variable
+    // to be set.
+    info!("Initializing {:?} chat client", cli.chat_provider);
+    let azure = chat::AzureSettings {
+        endpoint: cli.azure_endpoint.clone(),
+        api_version: cli.azure_api_version.clone(),
     };
+    let chat_client = chat::ChatClient::new(cli.chat_provider, &azure)?;
+    let embedding_model_name = cli
+        .embedding_model
+        .as_deref()
+        .unwrap_or(cli.embedding_provider.default_model());
+
+    info!(
+        "Creating embedding model {} ({:?})",
+        embedding_model_name, cli.embedding_provider
+    );
+    if cli.embedding_dimensions.is_some() && cli.embedding_provider != EmbeddingProvider::Openai {
+        bail!("--embedding-dimensions only works with --embedding-provider openai");
+    }
+    if cli.embedding_base_url.is_some() {
+        if cli.embedding_provider != EmbeddingProvider::Openai {
+            bail!("--embedding-base-url only works with --embedding-provider openai");
+        }
+        if cli.embedding_dimensions.is_some() {
+            bail!(
+                "--embedding-dimensions only works with OpenAI's own API, not --embedding-base-url"
+            );
+        }
+    }
+    let huggingface = cli.embedding_provider == EmbeddingProvider::Huggingface;
+    if cli.huggingface_url.is_some() && !huggingface {
+        bail!("--huggingface-url only works with --embedding-provider huggingface");
+    }
+    let self_hosted = matches!(
+        cli.embedding_provider,
+        EmbeddingProvider::Ollama | EmbeddingProvider::Local
+    ) || cli.embedding_base_url.is_some()
+        || (huggingface && cli.huggingface_url.is_some());
+    // Open models, wherever they are served, may want prefixes.
+    let open_model = self_hosted || huggingface;
+    if !open_model && (cli.query_prefix.is_some() || cli.passage_prefix.is_some()) {
+        bail!(
+            "--query-prefix and --passage-prefix only work with --embedding-provider huggingface, ollama or local, or --embedding-base-url"
+        );
+    }
+    let prefixes = embedder::Prefixes::new(
+        embedding_model_name,
+        cli.query_prefix.as_deref(),
+        cli.passage_prefix.as_deref(),
+    );
+    let embedder = create_embedder(
+        &cli,
+        &azure,
+        cli.embedding_provider,
+        embedding_model_name,
+        cli.embedding_dimensions,
+        cli.embedding_base_url.as_deref(),
+        prefixes.clone(),
+    )
+    .await?;
+    let ensemble = match &cli.ensemble_model {
+        Some(model) => {
+            info!(
+                "Creating ensemble embedding model {} ({:?})",
+                model, cli.ensemble_provider
+            );
+            let prefixes = embedder::Prefixes::new(model, None, None);
+            let ensemble = create_embedder(
+                &cli,
+                &azure,
+                cli.ensemble_provider,
+                model,
+                None,
+                None,
+                prefixes,
+            )
+            .await?;
+            Some(ensemble)
+        }
+        None => None,
+    };
+    let store = store::Store::open(
+        cli.store,
+        cli.store_url.as_deref(),
+        &cli.store_collection,
+        cli.store_namespace.as_deref(),
+        embedding_model_name,
+        embedder.ndims(),
+    )
+    .await?;
+    // Chunks must fit both models of an ensemble.
+    let token_limits: Vec<chunking::TokenLimit> = [Some(&embedder), ensemble.as_ref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|embedder| embedder.token_limit(cli.max_embed_tokens))
+        .collect();
+
+    // Files unchanged since an earlier run with the same settings keep the
+    // chunks and embeddings they were given then.
+    let settings = format!(
+        "{:?}",
+        (
+            cli.embedding_provider,
+            embedding_model_name,
+            cli.embedding_dimensions,
+            (
+                cli.embedding_base_url
+                    .as_deref()
+                    .or(cli.huggingface_url.as_deref())
+                    .map(|url| url.trim_end_matches('/')),
+                open_model.then_some(&prefixes),
+                cli.max_embed_tokens,
+            ),
+            &chunk_options,
+            &load_options,
+            cli.caption_images.then_some(&cli.vision_model),
+            cli.propositions.then_some(&cli.proposition_model),
+            cli.summaries.map(|mode| (mode, &cli.summary_model)),
+            &transcriber,
+            cli.redact_pii,
+            &clean_rules,
+        )
+    );
+    let mut manifest = manifest::Manifest::open(
+        &settings,
+        &format!("{:?} {}", cli.embedding_provider, embedding_model_name),
+        embedder.ndims(),
+        cli.reindex,
+    )?;
+    let (mut unchanged, reused) = manifest.reuse(&mut documents);
+
+    let ingest = Ingest {
+        chat_client: chat_client.clone(),
+        captions: cli.caption_images.then(|| cli.vision_model.clone()),
+        transcriber,
+        clean_rules,
+        redact_pii: cli.redact_pii,
+        chunk_options,
+        propositions: cli.propositions.then(|| cli.proposition_model.clone()),
+        token_limits,
+        summaries: cli.summaries.map(|_| cli.summary_model.clone()),
+        summary_only: cli.summaries == Some(summaries::SummaryMode::Instead),
+        near_copies: cli.dedup,
+        batch_size: cli.embed_batch_size,
+        concurrency: cli.embed_concurrency,
+        retry: retry.clone(),
+    };
+    ingest.prepare(&mut documents).await?;
+    language::detect_languages(&mut unchanged);
+    if cli.redact_pii {
+        redact::redact_documents(&mut unchanged);
+    }
+
+    let embedding_model = CachedEmbedder::new(
+        Retrying::new(embedder.clone(), retry.clone()),
+        embedder.cache_key(),
+    );
+    let ensemble_model = ensemble.as_ref().map(|ensemble| {
+        CachedEmbedder::new(
+            Retrying::new(ensemble.clone(), retry.clone()),
+            ensemble.cache_key(),
+        )
+    });
 
     // Chunk the text
+    let options = &ingest.chunk_options;
     info!(
-        "Chunking text (size: {}, overlap: {})",
-        cli.chunk_size, cli.chunk_overlap
+        "Chunking text by {:?} (size: {}, overlap: {}, in {:?})",
+        options.chunking, options.size, options.overlap, options.unit
     );
-    let chunks = chunk_text(&document, cli.chunk_size, cli.chunk_overlap);
-    info!("Created {} chunks from document", chunks.len());
+    let chunks = ingest.chunk(&documents, &embedding_model).await?;
     debug!(
         "First chunk preview: {}...",
-        chunks.first().map(|c| &c[..c.len().min(100)]).unwrap_or("")
+        chunks
+            .first()
+            .map(|c| c.text.chars().take(100).collect::<String>())
+            .unwrap_or_default()
     );
 
-    info!("Creating embedding model");
-    let embedding_model = openai_client.embedding_model("text-embedding-ada-002");
+    // Only texts not embedded before cost anything. Vectors are cached as
+    // each batch comes back, so a run that stopped partway resumes here.
+    let texts: HashSet<&str> = chunks
+        .iter()
+        .flat_map(|chunk| {
+            let text =
+                (!ingest.summary_only || chunk.summary.is_none()).then_some(chunk.text.as_str());
+            text.into_iter().chain(chunk.summary.as_deref())
+        })
+        .collect();
+    let pending: HashSet<&str> = texts
+        .iter()
+        .copied()
+        .filter(|text| !embedding_model.is_cached(text))
+        .collect();
+    if pending.len() < texts.len() {
+        info!(
+            "{} of {} texts were embedded by an earlier run and come from the cache",
+            texts.len() - pending.len(),
+            texts.len()
+        );
+    }
+    let context_tokens = chunks
+        .iter()
+        .map(|chunk| rate_limit::estimate_tokens(&chunk.text))
+        .sum::<usize>()
+        .checked_div(chunks.len())
+        .unwrap_or_default()
+        * CONTEXT_CHUNKS;
+    cost::confirm(
+        self_hosted,
+        embedding_model_name,
+        pending
+            .iter()
+            .map(|text| rate_limit::estimate_tokens(text))
+            .sum(),
+        &cli.model,
+        context_tokens,
+        cli.yes,
+    )?;
 
     info!("Building embeddings from {} chunks", chunks.len());
-    let mut embeddings_builder = EmbeddingsBuilder::new(embedding_model.clone());
-    for chunk in chunks.iter() {
-        embeddings_builder = embeddings_builder.document(chunk.clone())?;
+    let (mut embeddings, failed) = ingest
+        .embed(&embedding_model, &chunks)
+        .await
+    .context("Embedding stopped partway; the chunks embedded so far are cached, so running again resumes from there")?;
+    // Files with chunks left out are embedded again next time.
+    for source in &failed {
+        manifest.forget(source);
+    }
+    manifest.record(&embeddings);
+    if let Err(e) = manifest.save() {
+        warn!("Failed to save the index manifest: {:#}", e);
+    }
+    embeddings.extend(reused);
+    if let Some(path) = &cli.import_embeddings {
+        let imported = export::import_embeddings(path, embedding_model_name, embedder.ndims())?;
+        info!(
+            "Imported {} embedded chunks from {:?}",
+            imported.len(),
+            path
+        );
+        embeddings.extend(imported);
+    }
+    if let Some(path) = cli.index_path.as_ref().filter(|_| index_saved) {
+        let saved = index_file::load(path, embedding_model_name, embedder.ndims())?;
+        info!("Loaded {} embedded chunks from {:?}", saved.len(), path);
+        // The chunks carry their documents' metadata, which is all the
+        // documents are needed for from here on.
+        documents.extend(saved.iter().map(|(chunk, _)| Document {
+            text: chunk.text.clone(),
+            metadata: chunk.metadata.clone(),
+            ..Document::default()
+        }));
+        embeddings.extend(saved);
+    }
+    if cli.dedup {
+        embeddings = dedup::remove_duplicates(embeddings);
+    }
+    if let Some(path) = cli.index_path.as_ref().filter(|_| !index_saved) {
+        index_file::save(path, embedding_model_name, embedder.ndims(), &embeddings)?;
+        info!("Saved {} embedded chunks to {:?}", embeddings.len(), path);
+    }
+    let chunk_count = embeddings.len();
+    documents.extend(unchanged);
+
+    if let Some(path) = &cli.export_embeddings {
+        export::export_embeddings(path, embedding_model_name, &embeddings)?;
+        info!("Exported {} chunks to {:?}", chunk_count, path);
+        return Ok(());
     }
-    let embeddings = embeddings_builder.build().await?;
 
+    // The ensemble model embeds the same chunks, reused and imported ones
+    // included; the vectors it embedded before come from the cache.
+    let mut models = vec![Retrying::new(embedder.for_queries(), retry.clone())];
+    let mut spaces = vec![embeddings];
+    if let (Some(ensemble), Some(ensemble_model)) = (&ensemble, &ensemble_model) {
+        let chunks: Vec<chunking::Chunk> =
+            spaces[0].iter().map(|(chunk, _)| chunk.clone()).collect();
+        info!("Building ensemble embeddings from {} chunks", chunks.len());
+        let (embedded, _) = ingest
+            .embed(ensemble_model, &chunks)
+            .await
+        .context("Embedding stopped partway; the chunks embedded so far are cached, so running again resumes from there")?;
+        models.push(Retrying::new(ensemble.for_queries(), retry.clone()));
+        spaces.push(embedded);
+    }
+
+    let route = cli.lang.clone().unwrap_or_default();
+    let store_index = match store {
+        Some(store) => {
+            if !spaces[0].is_empty() {
+                store.replace(&spaces[0]).await?;
+                info!(
+                    "Wrote {} chunks to the {:?} collection {}",
+                    spaces[0].len(),
+                    cli.store,
+                    cli.store_collection
+                );
+            }
+            let model = models[0].clone();
+            Some(store::StoreIndex::new(
+                model,
+                store,
+                route.clone(),
+                &spaces[0],
+            ))
+        }
+        None => None,
+    };
     debug!("Creating vector store and index");
-    let vector_store = InMemoryVectorStore::from_documents(embeddings);
-    let index = vector_store.index(embedding_model);
+    let hnsw = cli.hnsw.then_some(hnsw::HnswParams {
+        m: cli.hnsw_m,
+        ef_construction: cli.hnsw_ef_construction,
+        ef_search: cli.hnsw_ef,
+    });
+    let index = store_index
+        .is_none()
+        .then(|| watch::LiveIndex::new(models, spaces, route, cli.quantize, hnsw));
+    if let (Some(dir), Some(index)) = (&cli.watch, &index) {
+        let root = PathBuf::from(dir);
+        let filter = loaders::PathFilter::new(&cli.include, &cli.exclude)?;
+        let models: Vec<_> = std::iter::once(embedding_model.clone())
+            .chain(ensemble_model.clone())
+            .collect();
+        let load = {
+            let root = root.clone();
+            move |path: PathBuf| {
+                let (root, load_options) = (root.clone(), load_options.clone());
+                let (ingest, models) = (ingest.clone(), models.clone());
+                async move {
+                    let documents =
+                        loaders::load_in_dir(&root, &path, &load_options)?.unwrap_or_default();
+                    ingest.ingest(documents, &models).await
+                }
+            }
+        };
+        let index = index.clone();
+        tokio::spawn(async move {
+            if let Err(e) = watch::watch(root, filter, index, load).await {
+                warn!("Stopped watching for changes: {:#}", e);
+            }
+        });
+    }
 
     info!("Initializing RAG agent with model: {}", cli.model);
-    let rag_agent = openai_client
-            .agent(&cli.model)
-            .preamble("You are a helpful assistant that answers questions based on the given context from the provided PDF document.")
-            .dynamic_context(2, index)
-            .build();
+    let mut preamble = String::from(
+        "You are a helpful assistant that answers questions based on the given context from the provided documents. When a context passage names its source, say which document your answer came from, and cite its page when it has one, like (p. 14).",
+    );
+    if let Some(lang) = language::dominant_language(&documents)
+        && lang != whatlang::Lang::Eng
+    {
+        preamble.push_str(&format!(
+            " The documents are in {0}; answer in {0} unless the user writes in another language.",
+            lang.eng_name()
+        ));
+    }
+    if let Some(catalog) = describe_documents(&documents) {
+        preamble.push_str("\n\n");
+        preamble.push_str(&catalog);
+    }
+    // Answers are retried and rate limited like every other call.
+    let chat_model = Retrying::new(chat_client.completion_model(&cli.model), retry.clone());
+    let agent = AgentBuilder::new(chat_model).preamble(&preamble);
+    let rag_agent = match (index, store_index) {
+        (Some(index), _) => agent.dynamic_context(CONTEXT_CHUNKS, index),
+        (_, Some(index)) => agent.dynamic_context(CONTEXT_CHUNKS, index),
+        (None, None) => agent,
+    }
+    .build();
 
     info!("Starting chatbot interface");
     let chatbot = ChatBotBuilder::new().agent(rag_agent).build();
@@ -127,16 +1001,472 @@ async fn main() -> Result<()> {
     // Print welcome message
     println!("           Welcome to RAG PDF Chatbot!");
     println!();
-    println!("Loaded {} chunks from your document", chunks.len());
+    println!("Loaded {} chunks from your document", chunk_count);
     println!("Using model: {}", cli.model);
-    if let Some(pdf_path) = cli.pdf {
-        println!("Ask me anything about the document {}", pdf_path);
+    let mut sources: Vec<&str> = cli
+        .pdf
+        .iter()
+        .filter(|p| *p != "-")
+        .chain(&cli.dir)
+        .chain(&cli.watch)
+        .chain(&cli.bucket)
+        .chain(&cli.drive_folder)
+        .chain(&cli.confluence_space)
+        .chain(&cli.url)
+        .chain(&cli.arxiv)
+        .chain(&cli.doi)
+        .chain(&cli.input)
+        .map(String::as_str)
+        .collect();
+    if cli.stdin || cli.pdf.iter().any(|p| p == "-") {
+        sources.insert(0, "stdin");
+    }
+    if !sources.is_empty() {
+        println!("Ask me anything about {}", sources.join(", "));
     }
     println!("Type 'exit' or press Ctrl+C to quit\n");
 
+    progress::disable();
     chatbot.run().await?;
 
     info!("Chatbot session ended");
 
     Ok(())
 }
+
+/// The steps between loading documents and embedding their chunks, shared
+/// by the documents loaded at startup and the files `--watch` sees change.
+#[derive(Clone)]
+struct Ingest {
+    chat_client: chat::ChatClient,
+    /// The vision model images are captioned with, with --caption-images.
+    captions: Option<String>,
+    transcriber: transcribe::Transcriber,
+    clean_rules: Option<clean::CleanRules>,
+    redact_pii: bool,
+    chunk_options: ChunkOptions,
+    /// The model chunks are rewritten as propositions with, with
+    /// --propositions.
+    propositions: Option<String>,
+    /// The limits of the models chunks are embedded with.
+    token_limits: Vec<chunking::TokenLimit>,
+    /// The model chunks are summarized with, with --summaries.
+    summaries: Option<String>,
+    summary_only: bool,
+    near_copies: bool,
+    batch_size: usize,
+    concurrency: usize,
+    retry: RetryPolicy,
+}
+
+impl Ingest {
+    /// Captions, transcribes, cleans, tags with their language and redacts
+    /// the documents, as the command line asks.
+    async fn prepare(&self, documents: &mut Vec<Document>) -> Result<()> {
+        if let Some(vision_model) = &self.captions {
+            captions::caption_images(&self.chat_client, vision_model, &self.retry, documents)
+                .await?;
+        }
+        transcribe::transcribe_audio(&self.transcriber, &self.retry, documents).await?;
+        if let Some(rules) = &self.clean_rules {
+            clean::clean_documents(documents, rules);
+        }
+        language::detect_languages(documents);
+        if self.redact_pii {
+            redact::redact_documents(documents);
+        }
+        Ok(())
+    }
+
+    /// Cuts prepared documents into the chunks to embed: tagged with their
+    /// language, rewritten as propositions, split to the models' limits and
+    /// summarized, as the command line asks. `model` is the one
+    /// `--chunking semantic` embeds sentences with.
+    async fn chunk<M: EmbeddingModel>(
+        &self,
+        documents: &[Document],
+        model: &M,
+    ) -> Result<Vec<chunking::Chunk>> {
+        let mut chunks = chunking::chunk_documents(documents, &self.chunk_options, model).await?;
+        info!("Created {} chunks from document", chunks.len());
+        language::detect_chunk_languages(&mut chunks);
+        if let Some(model) = &self.propositions {
+            chunks = propositions::rewrite_as_propositions(
+                &self.chat_client,
+                model,
+                &self.retry,
+                chunks,
+            )
+            .await?;
+        }
+        if !self.token_limits.is_empty() {
+            chunks = chunking::split_over_limit(chunks, &self.token_limits);
+        }
+        if let Some(model) = &self.summaries {
+            summaries::summarize_chunks(&self.chat_client, model, &self.retry, &mut chunks).await?;
+        }
+        Ok(chunks)
+    }
+
+    /// [`embed_chunks`] with the command line's settings.
+    async fn embed<M: EmbeddingModel + Clone>(
+        &self,
+        model: &M,
+        chunks: &[chunking::Chunk],
+    ) -> Result<(Vec<watch::EmbeddedChunk>, BTreeSet<String>)> {
+        embed_chunks(
+            model,
+            chunks,
+            self.near_copies,
+            self.summary_only,
+            self.batch_size,
+            self.concurrency,
+        )
+        .await
+    }
+
+    /// Prepares and chunks the documents and embeds the chunks with each
+    /// of `models`, returning each one's embedded chunks. The first model
+    /// is the one semantic chunking uses.
+    async fn ingest<M: EmbeddingModel + Clone>(
+        &self,
+        mut documents: Vec<Document>,
+        models: &[M],
+    ) -> Result<Vec<Vec<watch::EmbeddedChunk>>> {
+        self.prepare(&mut documents).await?;
+        let Some(first) = models.first() else {
+            return Ok(Vec::new());
+        };
+        let chunks = self.chunk(&documents, first).await?;
+        let mut spaces = Vec::with_capacity(models.len());
+        for model in models {
+            let (embedded, _) = self.embed(model, &chunks).await?;
+            spaces.push(embedded);
+        }
+        Ok(spaces)
+    }
+}
+
+/// Embeds the chunks, in order. Only the first of several chunks with the
+/// same text (or, with `near_copies`, nearly the same) is sent to the
+/// model; its copies share its embedding. With `summary_only`, chunks are
+/// embedded by their summaries rather than their text.
+///
+/// Chunks are sent `batch_size` at a time, with up to `concurrency`
+/// requests at once. A batch that fails is tried
+/// again a chunk at a time, and the chunks that still fail are left out
+/// with a warning; only when every chunk of a batch fails on its own is the
+/// error returned, as then the API rather than the chunks is likely at
+/// fault. Returns the embedded chunks and the sources of any left out.
+async fn embed_chunks<M: EmbeddingModel + Clone>(
+    model: &M,
+    chunks: &[chunking::Chunk],
+    near_copies: bool,
+    summary_only: bool,
+    batch_size: usize,
+    concurrency: usize,
+) -> Result<(Vec<watch::EmbeddedChunk>, BTreeSet<String>)> {
+    if chunks.is_empty() {
+        return Ok((Vec::new(), BTreeSet::new()));
+    }
+    let copies = dedup::find_copies(chunks, near_copies);
+    let originals: Vec<chunking::Chunk> = chunks
+        .iter()
+        .zip(&copies)
+        .filter(|(_, copy)| copy.is_none())
+        .map(|(chunk, _)| chunk.clone())
+        .collect();
+    let batches = originals.len().div_ceil(batch_size);
+    let progress = progress::start("Embedding", "chunks", originals.len());
+    let progress = &progress;
+    let requests: Vec<_> = originals
+        .chunks(batch_size)
+        .enumerate()
+        .map(|(i, batch)| async move {
+            let embedded = embed_or_split(
+                model,
+                batch,
+                summary_only,
+                format!("{} of {}", i + 1, batches),
+            )
+            .await;
+            let tokens = batch
+                .iter()
+                .map(|chunk| rate_limit::estimate_tokens(&chunk.text))
+                .sum();
+            progress.advance_tokens(batch.len(), tokens);
+            embedded
+        })
+        .collect();
+    let embedded: Vec<HashMap<String, OneOrMany<Embedding>>> = stream::iter(requests)
+        .buffer_unordered(concurrency)
+        .try_collect()
+        .await?;
+    let embedded: HashMap<_, _> = embedded.into_iter().flatten().collect();
+
+    let mut failed = BTreeSet::new();
+    let mut embedded_chunks = Vec::new();
+    for (i, (chunk, copy)) in chunks.iter().zip(&copies).enumerate() {
+        match embedded.get(&chunks[copy.unwrap_or(i)].text) {
+            Some(embeddings) => embedded_chunks.push((chunk.clone(), embeddings.clone())),
+            None => {
+                failed.insert(source(chunk).to_string());
+            }
+        }
+    }
+    if !failed.is_empty() {
+        warn!(
+            "{} chunks couldn't be embedded and are left out of the index",
+            chunks.len() - embedded_chunks.len()
+        );
+    }
+    Ok((embedded_chunks, failed))
+}
+
+/// Embeds a batch, or as many of its chunks as can be embedded one at a
+/// time when the batch fails as a whole; `name` says which batch it is.
+async fn embed_or_split<M: EmbeddingModel + Clone>(
+    model: &M,
+    batch: &[chunking::Chunk],
+    summary_only: bool,
+    name: String,
+) -> Result<HashMap<String, OneOrMany<Embedding>>> {
+    debug!("Embedding batch {}", name);
+    let e = match embed_batch(model, batch, summary_only).await {
+        Ok(embedded) => return Ok(embedded),
+        Err(e) => e,
+    };
+    if let [chunk] = batch {
+        warn!(
+            "Skipping chunk {} of {}: {:#}",
+            chunk.index,
+            source(chunk),
+            e
+        );
+        return Ok(HashMap::new());
+    }
+    warn!(
+        "Embedding batch {} failed, trying its chunks one at a time: {:#}",
+        name, e
+    );
+    let mut embedded = HashMap::new();
+    let mut last_error = None;
+    for chunk in batch {
+        match embed_batch(model, std::slice::from_ref(chunk), summary_only).await {
+            Ok(found) => embedded.extend(found),
+            Err(e) => {
+                warn!(
+                    "Skipping chunk {} of {}: {:#}",
+                    chunk.index,
+                    source(chunk),
+                    e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+    match last_error {
+        Some(e) if embedded.is_empty() => Err(e.context("No chunk of the batch could be embedded")),
+        _ => Ok(embedded),
+    }
+}
+
+/// One request's worth of chunks, by text; originals have distinct texts.
+async fn embed_batch<M: EmbeddingModel + Clone>(
+    model: &M,
+    batch: &[chunking::Chunk],
+    summary_only: bool,
+) -> Result<HashMap<String, OneOrMany<Embedding>>> {
+    let batch = batch.iter().cloned();
+    // The builder returns chunks in any order.
+    Ok(if summary_only {
+        EmbeddingsBuilder::new(model.clone())
+            .documents(batch.map(summaries::SummaryOnly))?
+            .build()
+            .await?
+            .into_iter()
+            .map(|(summaries::SummaryOnly(chunk), embeddings)| (chunk.text, embeddings))
+            .collect()
+    } else {
+        EmbeddingsBuilder::new(model.clone())
+            .documents(batch)?
+            .build()
+            .await?
+            .into_iter()
+            .map(|(chunk, embeddings)| (chunk.text, embeddings))
+            .collect()
+    })
+}
+
+fn source(chunk: &chunking::Chunk) -> &str {
+    chunk.source().unwrap_or("the documents")
+}
+
+/// The embedding model `provider` serves as `model`, its vectors cut to
+/// `dimensions` or served from `base_url` when those are given. Only open
+/// models, served by Hugging Face or self-hosted, take `prefixes`.
+async fn create_embedder(
+    cli: &Cli,
+    azure: &chat::AzureSettings,
+    provider: EmbeddingProvider,
+    model: &str,
+    dimensions: Option<usize>,
+    base_url: Option<&str>,
+    prefixes: embedder::Prefixes,
+) -> Result<Embedder> {
+    Ok(match provider {
+        EmbeddingProvider::Openai => match base_url {
+            Some(url) => Embedder::openai_compatible(url, model, prefixes).await?,
+            None => Embedder::openai(&openai::Client::from_env(), model, dimensions)?,
+        },
+        EmbeddingProvider::Cohere => Embedder::cohere(model)?,
+        EmbeddingProvider::Voyage => Embedder::voyage(model)?,
+        EmbeddingProvider::Gemini => Embedder::gemini(model)?,
+        EmbeddingProvider::Huggingface => {
+            Embedder::huggingface(model, cli.huggingface_url.as_deref(), prefixes).await?
+        }
+        EmbeddingProvider::Azure => Embedder::azure(&azure.client()?, model),
+        EmbeddingProvider::Ollama => {
+            let url = cli
+                .ollama_url
+                .clone()
+                .or_else(|| std::env::var("OLLAMA_API_BASE_URL").ok())
+                .unwrap_or_else(|| embedder::OLLAMA_URL.to_string());
+            Embedder::ollama(&url, model, prefixes).await?
+        }
+        EmbeddingProvider::Local => Embedder::local(model, prefixes).await?,
+    })
+}
+
+/// Loads every document the command line names, from files, stdin, URLs
+/// and the document stores.
+async fn load_documents(cli: &Cli, load_options: &LoadOptions) -> Result<Vec<Document>> {
+    let mut documents: Vec<Document> = Vec::new();
+    if cli.stdin || cli.pdf.iter().any(|p| p == "-") {
+        info!("Reading document text from stdin");
+        documents.push(loaders::load_stdin()?);
+    }
+    for pdf_path in cli.pdf.iter().filter(|p| *p != "-") {
+        info!("Loading document from: {}", pdf_path);
+        if loaders::is_remote(pdf_path) {
+            documents.extend(loaders::load_remote(pdf_path, load_options).await?);
+        } else {
+            documents.extend(loaders::load_tagged(Path::new(pdf_path), load_options)?);
+        }
+    }
+    if let Some(url) = &cli.url {
+        info!("Fetching web page: {}", url);
+        documents.push(loaders::load_url(url).await?);
+    }
+    for id in &cli.arxiv {
+        documents.extend(loaders::load_arxiv(id, load_options).await?);
+    }
+    for doi in &cli.doi {
+        documents.extend(loaders::load_doi(doi, load_options).await?);
+    }
+    // A watched directory is loaded like --dir to begin with.
+    for dir in cli.dir.iter().chain(&cli.watch) {
+        info!("Loading directory: {}", dir);
+        documents.extend(loaders::load_dir(
+            Path::new(dir),
+            &cli.include,
+            &cli.exclude,
+            load_options,
+        )?);
+    }
+    for folder in &cli.drive_folder {
+        info!("Loading Google Drive folder: {}", folder);
+        documents.extend(
+            loaders::load_drive(folder, cli.drive_credentials.as_deref(), load_options).await?,
+        );
+    }
+    for space in &cli.confluence_space {
+        info!("Loading Confluence space: {}", space);
+        documents.extend(loaders::load_confluence(space).await?);
+    }
+    for bucket in &cli.bucket {
+        info!("Loading bucket: {}", bucket);
+        documents
+            .extend(loaders::load_bucket(bucket, &cli.include, &cli.exclude, load_options).await?);
+    }
+    for pattern in &cli.input {
+        info!("Loading files matching: {}", pattern);
+        documents.extend(loaders::load_glob(pattern, load_options)?);
+    }
+    Ok(documents)
+}
+
+/// Chunks the documents as a full run would, short of the steps that call
+/// an API, and prints the chunks instead of embedding them.
+async fn preview_chunks(
+    mut documents: Vec<Document>,
+    chunk_options: &ChunkOptions,
+    clean_rules: Option<&clean::CleanRules>,
+    transcriber: &transcribe::Transcriber,
+    retry: &RetryPolicy,
+    cli: &Cli,
+    format: preview::PreviewFormat,
+) -> Result<()> {
+    let semantic = |options: &ChunkOptions| options.chunking == Chunking::Semantic;
+    if semantic(chunk_options) || chunk_options.profiles.iter().any(|p| semantic(&p.options)) {
+        bail!("--chunking semantic embeds every sentence, so it can't be previewed");
+    }
+    if let transcribe::Transcriber::WhisperCpp { .. } = transcriber {
+        transcribe::transcribe_audio(transcriber, retry, &mut documents).await?;
+    } else if documents.iter().any(|d| d.audio.is_some()) {
+        warn!("Audio is only transcribed in a preview with --whisper-cpp-model");
+    }
+    if let Some(rules) = clean_rules {
+        clean::clean_documents(&mut documents, rules);
+    }
+    language::detect_languages(&mut documents);
+    if cli.redact_pii {
+        redact::redact_documents(&mut documents);
+    }
+
+    // Only semantic chunking calls the embedding model, so it needs no key.
+    let client: openai::Client = openai::Client::new("")?;
+    let embedding_model = client.embedding_model(embedder::OPENAI_EMBEDDING_MODEL);
+    let mut chunks = chunking::chunk_documents(&documents, chunk_options, &embedding_model).await?;
+    language::detect_chunk_languages(&mut chunks);
+    preview::print_chunks(&chunks, format, chunk_options.unit)
+}
+
+/// Most documents listed in the preamble; past this the list costs more
+/// context than it is worth.
+const MAX_DESCRIBED_DOCUMENTS: usize = 20;
+
+/// Lists the title, author, date and keywords of each loaded document that
+/// declares them, so the model knows what it is talking about before any
+/// context is retrieved.
+fn describe_documents(documents: &[Document]) -> Option<String> {
+    let mut seen = std::collections::HashSet::new();
+    let lines: Vec<String> = documents
+        .iter()
+        .filter(|document| seen.insert(document.metadata.get("source")))
+        .filter_map(|document| {
+            let field = |key: &str| document.metadata.get(key);
+            let title = field("title");
+            let author = field("author");
+            if title.is_none() && author.is_none() {
+                return None;
+            }
+            let mut line = format!("- {}", field("source").map_or("document", String::as_str));
+            if let Some(title) = title {
+                line.push_str(&format!(": \"{}\"", title));
+            }
+            if let Some(author) = author {
+                line.push_str(&format!(" by {}", author));
+            }
+            if let Some(created) = field("created") {
+                line.push_str(&format!(", created {}", created));
+            }
+            if let Some(keywords) = field("keywords") {
+                line.push_str(&format!(" (keywords: {})", keywords));
+            }
+            Some(line)
+        })
+        .take(MAX_DESCRIBED_DOCUMENTS)
+        .collect();
+    (!lines.is_empty()).then(|| format!("The loaded documents are:\n{}", lines.join("\n")))
+}
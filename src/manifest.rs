@@ -0,0 +1,397 @@
+//! A record of the files indexed by earlier runs, so later runs only
+//! re-chunk and re-embed the files that changed.
+
+use crate::chunking::Chunk;
+use crate::loaders::{self, Document};
+use crate::watch::EmbeddedChunk;
+use anyhow::{Context, Result};
+use rig::OneOrMany;
+use rig::embeddings::Embedding;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// What is saved between runs: the embedding model and the size of its
+/// vectors, each file's content hash and the ids of the chunks cut from
+/// it, and the chunks themselves with their embeddings.
+#[derive(Default, Serialize, Deserialize)]
+struct Saved {
+    /// Empty in manifests saved before the model was recorded.
+    #[serde(default)]
+    embedding_model: String,
+    /// Zero in manifests saved before the size was recorded.
+    #[serde(default)]
+    dimensions: usize,
+    files: BTreeMap<String, FileEntry>,
+    chunks: BTreeMap<String, SavedChunk>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileEntry {
+    hash: String,
+    chunk_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedChunk {
+    chunk: Chunk,
+    vectors: Vec<Vec<f64>>,
+}
+
+pub struct Manifest {
+    path: PathBuf,
+    saved: Saved,
+    /// Content hashes of the local files loaded this run, by source.
+    hashes: HashMap<String, String>,
+    /// Sources whose chunks were reused rather than embedded again.
+    reused: BTreeSet<String>,
+    /// Embed every file again, only recording the results.
+    reindex: bool,
+}
+
+impl Manifest {
+    /// Opens the manifest kept for these settings (everything that changes
+    /// how files are chunked and embedded), or an empty one on the first run
+    /// with them. Vectors from one embedding model can't be searched with
+    /// another, or with the same model cut to another size, so a manifest
+    /// recording a model other than `embedding_model` or vectors of other
+    /// than `dimensions` is started afresh, as is one that can't be read.
+    /// With `reindex`, nothing saved is reused, but the manifest is still
+    /// brought up to date.
+    pub fn open(
+        settings: &str,
+        embedding_model: &str,
+        dimensions: usize,
+        reindex: bool,
+    ) -> Result<Self> {
+        let path = loaders::cache_dir("index")?.join(format!(
+            "{}.json",
+            &loaders::sha256_hex(settings.as_bytes())[..16]
+        ));
+        Ok(Self::at(path, embedding_model, dimensions, reindex))
+    }
+
+    /// The manifest at `path`, opened as [`Manifest::open`] does.
+    fn at(path: PathBuf, embedding_model: &str, dimensions: usize, reindex: bool) -> Self {
+        let mut saved: Saved = match fs::read(&path) {
+            // A manifest only saves work, so a damaged one costs a
+            // re-embedding rather than the run.
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!(
+                    "Index manifest {:?} is unreadable ({}); embedding every file again",
+                    path, e
+                );
+                Saved::default()
+            }),
+            Err(_) => Saved::default(),
+        };
+        if !saved.embedding_model.is_empty() && saved.embedding_model != embedding_model {
+            warn!(
+                "Index manifest {:?} was embedded with {}, not {}; embedding every file again",
+                path, saved.embedding_model, embedding_model
+            );
+            saved = Saved::default();
+        } else if saved.dimensions != 0 && saved.dimensions != dimensions {
+            warn!(
+                "Index manifest {:?} holds {}-dimensional vectors, not {}; embedding every file again",
+                path, saved.dimensions, dimensions
+            );
+            saved = Saved::default();
+        }
+        saved.embedding_model = embedding_model.to_string();
+        saved.dimensions = dimensions;
+        debug!("Index manifest {:?} has {} files", path, saved.files.len());
+        Self {
+            path,
+            saved,
+            hashes: HashMap::new(),
+            reused: BTreeSet::new(),
+            reindex,
+        }
+    }
+
+    /// Takes out the documents of local files that are unchanged since they
+    /// were last indexed, returning them along with their saved chunks. The
+    /// documents left in `documents` need chunking and embedding.
+    pub fn reuse(&mut self, documents: &mut Vec<Document>) -> (Vec<Document>, Vec<EmbeddedChunk>) {
+        let mut unchanged = Vec::new();
+        let mut chunks = Vec::new();
+        for document in std::mem::take(documents) {
+            let Some(source) = document.metadata.get("source").cloned() else {
+                documents.push(document);
+                continue;
+            };
+            if !self.reused.contains(&source) {
+                match self.saved_chunks(&source) {
+                    Some(saved) => {
+                        chunks.extend(saved);
+                        self.reused.insert(source);
+                    }
+                    None => {
+                        documents.push(document);
+                        continue;
+                    }
+                }
+            }
+            unchanged.push(document);
+        }
+        if !self.reused.is_empty() {
+            info!(
+                "Reusing {} chunks from {} unchanged files",
+                chunks.len(),
+                self.reused.len()
+            );
+        }
+        (unchanged, chunks)
+    }
+
+    /// The saved chunks of a source, when it is a local file whose content
+    /// still has the hash it was indexed with.
+    fn saved_chunks(&mut self, source: &str) -> Option<Vec<EmbeddedChunk>> {
+        if !self.hashes.contains_key(source) {
+            let bytes = fs::read(Path::new(source)).ok()?;
+            self.hashes
+                .insert(source.to_string(), loaders::sha256_hex(&bytes));
+        }
+        let entry = self.saved.files.get(source).filter(|_| !self.reindex)?;
+        if entry.hash != self.hashes[source] {
+            debug!("{} changed since it was indexed", source);
+            return None;
+        }
+        entry
+            .chunk_ids
+            .iter()
+            .map(|id| {
+                let saved = self.saved.chunks.get(id)?;
+                // A summary's vector comes after the text's, or alone with
+                // `--summaries=instead`.
+                let text = saved.chunk.text.clone();
+                let documents = match &saved.chunk.summary {
+                    Some(summary) if saved.vectors.len() == 1 => vec![summary.clone()],
+                    Some(summary) => vec![text, summary.clone()],
+                    None => vec![text],
+                };
+                let embeddings = saved.vectors.iter().zip(documents.into_iter().cycle()).map(
+                    |(vec, document)| Embedding {
+                        document,
+                        vec: vec.clone(),
+                    },
+                );
+                Some((saved.chunk.clone(), OneOrMany::many(embeddings).ok()?))
+            })
+            .collect()
+    }
+
+    /// Leaves what was saved for `source` as it was, so a file whose chunks
+    /// couldn't all be embedded this run isn't taken as indexed next time.
+    pub fn forget(&mut self, source: &str) {
+        self.hashes.remove(source);
+    }
+
+    /// Records the freshly embedded chunks of every local file loaded this
+    /// run, replacing what was saved for them before.
+    pub fn record(&mut self, chunks: &[EmbeddedChunk]) {
+        let mut by_source: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (chunk, embeddings) in chunks {
+            let Some(source) = chunk.source() else {
+                continue;
+            };
+            let id = chunk_id(chunk);
+            self.saved.chunks.insert(
+                id.clone(),
+                SavedChunk {
+                    chunk: chunk.clone(),
+                    vectors: embeddings.iter().map(|e| e.vec.clone()).collect(),
+                },
+            );
+            by_source.entry(source).or_default().push(id);
+        }
+        for (source, hash) in &self.hashes {
+            if self.reused.contains(source) {
+                continue;
+            }
+            let chunk_ids = by_source.remove(source.as_str()).unwrap_or_default();
+            self.saved.files.insert(
+                source.clone(),
+                FileEntry {
+                    hash: hash.clone(),
+                    chunk_ids,
+                },
+            );
+        }
+    }
+
+    /// Writes the manifest back, forgetting files that no longer exist and
+    /// chunks no file refers to any more.
+    pub fn save(mut self) -> Result<()> {
+        self.saved
+            .files
+            .retain(|source, _| Path::new(source).is_file());
+        let referenced: BTreeSet<&String> = self
+            .saved
+            .files
+            .values()
+            .flat_map(|entry| &entry.chunk_ids)
+            .collect();
+        self.saved.chunks.retain(|id, _| referenced.contains(id));
+
+        // Written beside the manifest and renamed over it, so an interrupted
+        // run leaves the last complete one in place.
+        let partial = self.path.with_extension("json.part");
+        let json = serde_json::to_vec(&self.saved)?;
+        fs::write(&partial, json)
+            .with_context(|| format!("Failed to write index manifest: {:?}", partial))?;
+        fs::rename(&partial, &self.path)
+            .with_context(|| format!("Failed to write index manifest: {:?}", self.path))?;
+        debug!("Saved index manifest {:?}", self.path);
+        Ok(())
+    }
+}
+
+/// Chunks are identified by their content, so an unchanged chunk keeps its
+/// id (and embedding) wherever it turns up.
+fn chunk_id(chunk: &Chunk) -> String {
+    let json = serde_json::to_string(chunk).unwrap_or_default();
+    loaders::sha256_hex(json.as_bytes())[..32].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory holding `a.txt` and `b.txt`, and where their manifest is
+    /// kept.
+    fn files() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "alpha").unwrap();
+        fs::write(dir.path().join("b.txt"), "beta").unwrap();
+        let manifest = dir.path().join("manifest.json");
+        (dir, manifest)
+    }
+
+    fn open(path: &Path, reindex: bool) -> Manifest {
+        Manifest::at(path.to_path_buf(), "model", 2, reindex)
+    }
+
+    fn documents(dir: &Path, names: &[&str]) -> Vec<Document> {
+        names
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                Document::new(fs::read_to_string(&path).unwrap())
+                    .with_metadata("source", path.to_string_lossy())
+            })
+            .collect()
+    }
+
+    /// Each document as one chunk with a vector of its own.
+    fn embedded(documents: &[Document]) -> Vec<EmbeddedChunk> {
+        documents
+            .iter()
+            .enumerate()
+            .map(|(i, document)| {
+                let chunk = Chunk {
+                    text: document.text.clone(),
+                    metadata: document.metadata.clone(),
+                    index: 0,
+                    char_range: 0..document.text.len(),
+                    parent: None,
+                    summary: None,
+                    document: 0,
+                    header: None,
+                };
+                let embedding = Embedding {
+                    document: document.text.clone(),
+                    vec: vec![i as f64, 1.0],
+                };
+                (chunk, OneOrMany::one(embedding))
+            })
+            .collect()
+    }
+
+    /// Runs `names` through the manifest at `path` as a run would,
+    /// embedding what it doesn't reuse, and returns the names reused.
+    fn run(dir: &Path, path: &Path, names: &[&str], reindex: bool) -> Vec<String> {
+        let mut manifest = open(path, reindex);
+        let mut documents = documents(dir, names);
+        let (unchanged, reused) = manifest.reuse(&mut documents);
+        assert_eq!(unchanged.len(), reused.len());
+        manifest.record(&embedded(&documents));
+        manifest.save().unwrap();
+        reused.iter().map(|(chunk, _)| chunk.text.clone()).collect()
+    }
+
+    #[test]
+    fn reuses_files_unchanged_since_the_last_run() {
+        let (dir, path) = files();
+        assert!(run(dir.path(), &path, &["a.txt", "b.txt"], false).is_empty());
+        let mut manifest = open(&path, false);
+        let mut documents = documents(dir.path(), &["a.txt", "b.txt"]);
+        let (unchanged, reused) = manifest.reuse(&mut documents);
+        assert!(documents.is_empty());
+        assert_eq!(unchanged.len(), 2);
+        assert_eq!(reused, embedded(&unchanged));
+    }
+
+    #[test]
+    fn embeds_changed_files_again() {
+        let (dir, path) = files();
+        run(dir.path(), &path, &["a.txt", "b.txt"], false);
+        fs::write(dir.path().join("a.txt"), "alpha, changed").unwrap();
+        assert_eq!(run(dir.path(), &path, &["a.txt", "b.txt"], false), ["beta"]);
+        // The new text was recorded in turn.
+        assert_eq!(
+            run(dir.path(), &path, &["a.txt", "b.txt"], false),
+            ["alpha, changed", "beta"]
+        );
+    }
+
+    #[test]
+    fn forgets_files_left_partly_embedded() {
+        let (dir, path) = files();
+        let mut manifest = open(&path, false);
+        let mut documents = documents(dir.path(), &["a.txt", "b.txt"]);
+        manifest.reuse(&mut documents);
+        manifest.forget(&documents[0].metadata["source"]);
+        manifest.record(&embedded(&documents));
+        manifest.save().unwrap();
+        assert_eq!(run(dir.path(), &path, &["a.txt", "b.txt"], false), ["beta"]);
+    }
+
+    #[test]
+    fn drops_deleted_files_and_their_chunks() {
+        let (dir, path) = files();
+        run(dir.path(), &path, &["a.txt", "b.txt"], false);
+        fs::remove_file(dir.path().join("b.txt")).unwrap();
+        open(&path, false).save().unwrap();
+        let saved = open(&path, false).saved;
+        assert_eq!(saved.files.len(), 1);
+        assert_eq!(saved.chunks.len(), 1);
+        assert!(saved.files.keys().all(|source| source.ends_with("a.txt")));
+    }
+
+    #[test]
+    fn reindexing_reuses_nothing_but_records_everything() {
+        let (dir, path) = files();
+        run(dir.path(), &path, &["a.txt"], false);
+        assert!(run(dir.path(), &path, &["a.txt", "b.txt"], true).is_empty());
+        assert_eq!(
+            run(dir.path(), &path, &["a.txt", "b.txt"], false),
+            ["alpha", "beta"]
+        );
+    }
+
+    #[test]
+    fn starts_afresh_from_a_manifest_it_cant_use() {
+        let (dir, path) = files();
+        fs::write(&path, "{ not json").unwrap();
+        assert!(run(dir.path(), &path, &["a.txt"], false).is_empty());
+        assert_eq!(run(dir.path(), &path, &["a.txt"], false), ["alpha"]);
+        // Another model's vectors can't be searched with this one's.
+        let mut manifest = Manifest::at(path.clone(), "other", 2, false);
+        let mut documents = documents(dir.path(), &["a.txt"]);
+        assert!(manifest.reuse(&mut documents).1.is_empty());
+    }
+}
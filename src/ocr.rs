@@ -0,0 +1,143 @@
+use crate::loaders::PageRanges;
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info};
+
+/// Minimum average number of alphanumeric characters per page before the
+/// extracted text layer is considered usable.
+const MIN_CHARS_PER_PAGE: usize = 50;
+
+/// Returns true when the text extracted from a PDF is too sparse or too noisy
+/// to be worth embedding, which usually means the document is a scan.
+pub fn needs_ocr(text: &str, page_count: usize) -> bool {
+    let visible: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let alphanumeric = visible.iter().filter(|c| c.is_alphanumeric()).count();
+
+    if alphanumeric < MIN_CHARS_PER_PAGE * page_count.max(1) {
+        return true;
+    }
+
+    // Broken font encodings tend to produce runs of symbols and replacement
+    // characters rather than words.
+    alphanumeric * 2 < visible.len()
+}
+
+/// Rasterizes the pages of the PDF `pages` selects (every page when unset)
+/// with `pdftoppm` and runs `tesseract` on each image, returning each
+/// page's number and recognized text, in page order. `pdf` is handed to
+/// `pdftoppm` as it is, so an encrypted document has to be decrypted first.
+pub fn ocr_pdf(pdf: &[u8], pages: Option<&PageRanges>) -> Result<Vec<(u32, String)>> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("rag-my-pdf-ocr-");
+    // The copy of the PDF in it may be a decrypted one.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o700));
+    }
+    let work_dir = builder
+        .tempdir()
+        .context("Failed to create OCR work directory")?;
+    ocr_pdf_in(pdf, pages, work_dir.path())
+}
+
+fn ocr_pdf_in(
+    pdf: &[u8],
+    pages: Option<&PageRanges>,
+    work_dir: &Path,
+) -> Result<Vec<(u32, String)>> {
+    // The PDF may not be on disk (e.g. inside a ZIP), so hand pdftoppm a copy.
+    let path = work_dir.join("input.pdf");
+    fs::write(&path, pdf).with_context(|| format!("Failed to write {:?}", path))?;
+
+    info!("Rendering PDF pages for OCR");
+    // One run per span, so pages --pages leaves out are never rendered.
+    let spans = match pages {
+        Some(pages) => pages.spans().into_iter().map(Some).collect(),
+        None => vec![None],
+    };
+    for span in spans {
+        let mut pdftoppm = Command::new("pdftoppm");
+        if let Some((first, last)) = span {
+            pdftoppm.args(["-f", &first.to_string()]);
+            if last != u32::MAX {
+                pdftoppm.args(["-l", &last.to_string()]);
+            }
+        }
+        let status = pdftoppm
+            .args(["-r", "300", "-png"])
+            .arg(&path)
+            .arg(work_dir.join("page"))
+            .status()
+            .context("Failed to run `pdftoppm`; install poppler-utils to enable OCR")?;
+        if !status.success() {
+            bail!("`pdftoppm` failed to render {:?}", path);
+        }
+    }
+
+    // pdftoppm names each image `page-N.png`, its page number padded to
+    // the same width in every run.
+    let mut images: Vec<(u32, PathBuf)> = fs::read_dir(work_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "png"))
+        .filter_map(|p| Some((page_number(&p)?, p)))
+        .collect();
+    images.sort();
+
+    info!("Running OCR on {} pages", images.len());
+    images
+        .into_iter()
+        .map(|(page, image)| {
+            debug!("OCR: {:?}", image);
+            let output = Command::new("tesseract")
+                .arg(&image)
+                .arg("stdout")
+                .output()
+                .context("Failed to run `tesseract`; install tesseract-ocr to enable OCR")?;
+            if !output.status.success() {
+                bail!(
+                    "`tesseract` failed on {:?}: {}",
+                    image,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Ok((page, String::from_utf8_lossy(&output.stdout).into_owned()))
+        })
+        .collect()
+}
+
+/// The page number in the name of an image `pdftoppm` wrote.
+fn page_number(image: &Path) -> Option<u32> {
+    image
+        .file_stem()?
+        .to_str()?
+        .rsplit_once('-')?
+        .1
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_page_numbers_from_image_names() {
+        assert_eq!(page_number(Path::new("/tmp/x/page-7.png")), Some(7));
+        assert_eq!(page_number(Path::new("/tmp/x/page-012.png")), Some(12));
+        assert_eq!(page_number(Path::new("/tmp/x-1/input.png")), None);
+    }
+
+    #[test]
+    fn needs_ocr_for_sparse_or_garbled_text() {
+        assert!(needs_ocr("", 1));
+        assert!(needs_ocr(&"word ".repeat(20), 3));
+        assert!(!needs_ocr(&"word ".repeat(20), 1));
+        assert!(needs_ocr(
+            &format!("{}{}", "ab".repeat(30), "\u{fffd}#".repeat(40)),
+            1
+        ));
+    }
+}
@@ -0,0 +1,84 @@
+//! Printing chunks for `--preview-chunks`, to tune the chunking settings
+//! without embedding anything.
+
+use crate::chunking::{Chunk, ChunkUnit};
+use anyhow::Result;
+use serde::Serialize;
+
+/// How `--preview-chunks` prints the chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PreviewFormat {
+    /// Each chunk's position, sizes and metadata above its text
+    Text,
+    /// A JSON array of the chunks, with their sizes
+    Json,
+}
+
+#[derive(Serialize)]
+struct PreviewedChunk<'a> {
+    #[serde(flatten)]
+    chunk: &'a Chunk,
+    words: usize,
+    chars: usize,
+    tokens: usize,
+}
+
+/// Prints the chunks to stdout, followed in text form by a summary of
+/// their sizes in `unit`.
+pub fn print_chunks(chunks: &[Chunk], format: PreviewFormat, unit: ChunkUnit) -> Result<()> {
+    let bpe = tiktoken_rs::cl100k_base_singleton();
+    let previewed: Vec<PreviewedChunk> = chunks
+        .iter()
+        .map(|chunk| PreviewedChunk {
+            chunk,
+            words: chunk.text.split_whitespace().count(),
+            chars: chunk.text.chars().count(),
+            tokens: bpe.count_ordinary(&chunk.text),
+        })
+        .collect();
+
+    if format == PreviewFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&previewed)?);
+        return Ok(());
+    }
+    for preview in &previewed {
+        let chunk = preview.chunk;
+        println!(
+            "=== {} #{}: {} words, {} characters, {} tokens, at {}..{}",
+            chunk.source().unwrap_or("document"),
+            chunk.index,
+            preview.words,
+            preview.chars,
+            preview.tokens,
+            chunk.char_range.start,
+            chunk.char_range.end
+        );
+        for (key, value) in chunk.metadata.iter().filter(|(key, _)| *key != "source") {
+            println!("{}: {}", key, value);
+        }
+        if let Some(parent) = &chunk.parent {
+            println!(
+                "parent: #{}, {} words",
+                parent.index,
+                parent.text.split_whitespace().count()
+            );
+        }
+        println!("\n{}\n", chunk.text);
+    }
+    let sizes: Vec<usize> = previewed
+        .iter()
+        .map(|preview| unit.count(&preview.chunk.text))
+        .collect();
+    match (sizes.iter().min(), sizes.iter().max()) {
+        (Some(min), Some(max)) => println!(
+            "{} chunks of {} to {} {}, {} on average",
+            sizes.len(),
+            min,
+            max,
+            unit.name(),
+            sizes.iter().sum::<usize>() / sizes.len()
+        ),
+        _ => println!("No chunks"),
+    }
+    Ok(())
+}
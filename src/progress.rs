@@ -0,0 +1,183 @@
+//! Progress bars on stderr for the slow parts of ingesting (extracting
+//! pages, loading files, chunking, and the API calls per chunk), so a long
+//! run shows it is getting somewhere. Bars are only drawn when stderr is a
+//! terminal, and only once a step has taken long enough to be worth one.
+
+use std::io::{IsTerminal, Write};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+
+/// Steps done sooner than this never show a bar.
+const SHOW_AFTER: Duration = Duration::from_millis(500);
+
+/// Time between redraws, which also keeps the time left up to date while
+/// a slow unit of work holds up the count.
+const REDRAW_EVERY: Duration = Duration::from_millis(100);
+
+/// Width of the bar itself, in characters.
+const WIDTH: usize = 30;
+
+/// The bars of the steps under way, innermost last; only the innermost is
+/// drawn.
+static BARS: Mutex<Bars> = Mutex::new(Bars {
+    stack: Vec::new(),
+    drawn: None,
+    disabled: false,
+});
+
+struct Bars {
+    stack: Vec<Bar>,
+    /// When a bar was last drawn, while one is on screen.
+    drawn: Option<Instant>,
+    disabled: bool,
+}
+
+struct Bar {
+    label: String,
+    unit: &'static str,
+    done: usize,
+    total: usize,
+    /// Tokens of the work done, for the steps that call an API.
+    tokens: Option<usize>,
+    started: Instant,
+}
+
+/// A step's bar, taken down when this is dropped.
+pub struct Progress {
+    depth: usize,
+}
+
+/// Starts a bar for a step of `total` `unit`s, like 400 chunks, under any
+/// step already under way.
+pub fn start(label: impl Into<String>, unit: &'static str, total: usize) -> Progress {
+    let mut bars = BARS.lock().unwrap();
+    bars.stack.push(Bar {
+        label: label.into(),
+        unit,
+        done: 0,
+        total,
+        tokens: None,
+        started: Instant::now(),
+    });
+    static TICKER: Once = Once::new();
+    TICKER.call_once(|| {
+        std::thread::spawn(|| {
+            loop {
+                std::thread::sleep(REDRAW_EVERY);
+                BARS.lock().unwrap().draw();
+            }
+        });
+    });
+    Progress {
+        depth: bars.stack.len(),
+    }
+}
+
+impl Progress {
+    /// Counts `done` more units finished.
+    pub fn advance(&self, done: usize) {
+        self.update(done, None);
+    }
+
+    /// Counts `done` more units finished, which took `tokens` tokens.
+    pub fn advance_tokens(&self, done: usize, tokens: usize) {
+        self.update(done, Some(tokens));
+    }
+
+    fn update(&self, done: usize, tokens: Option<usize>) {
+        let mut bars = BARS.lock().unwrap();
+        let Some(bar) = bars.stack.get_mut(self.depth - 1) else {
+            return;
+        };
+        bar.done = (bar.done + done).min(bar.total);
+        if let Some(tokens) = tokens {
+            *bar.tokens.get_or_insert(0) += tokens;
+        }
+        if bars.stack.len() == self.depth {
+            bars.draw();
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        let mut bars = BARS.lock().unwrap();
+        bars.stack.truncate(self.depth - 1);
+        bars.clear();
+    }
+}
+
+/// Takes down the bar on screen, if any, so a log line can be written in
+/// its place; it is drawn again at the next update.
+pub fn hide() {
+    BARS.lock().unwrap().clear();
+}
+
+/// Stops drawing bars for good, once the terminal is taken by the chat.
+pub fn disable() {
+    let mut bars = BARS.lock().unwrap();
+    bars.clear();
+    bars.disabled = true;
+}
+
+impl Bars {
+    fn draw(&mut self) {
+        let Some(bar) = self.stack.last() else {
+            return;
+        };
+        let now = Instant::now();
+        if self.disabled
+            || now - bar.started < SHOW_AFTER
+            || self.drawn.is_some_and(|drawn| now - drawn < REDRAW_EVERY)
+            || !std::io::stderr().is_terminal()
+        {
+            return;
+        }
+        let line = bar.render(now);
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", line);
+        let _ = stderr.flush();
+        self.drawn = Some(now);
+    }
+
+    fn clear(&mut self) {
+        if self.drawn.take().is_some() {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+impl Bar {
+    fn render(&self, now: Instant) -> String {
+        let filled = (WIDTH * self.done).checked_div(self.total).unwrap_or(WIDTH);
+        let mut line = format!(
+            "{} [{}{}] {}/{} {}",
+            self.label,
+            "#".repeat(filled),
+            "-".repeat(WIDTH - filled),
+            self.done,
+            self.total,
+            self.unit
+        );
+        if let Some(tokens) = self.tokens {
+            line.push_str(&format!(", {} tokens", tokens));
+        }
+        if self.done > 0 && self.done < self.total {
+            let elapsed = now - self.started;
+            let left = elapsed.mul_f64((self.total - self.done) as f64 / self.done as f64);
+            line.push_str(&format!(", about {} left", duration(left)));
+        }
+        line
+    }
+}
+
+fn duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
@@ -0,0 +1,151 @@
+//! Rewriting chunks as propositions for `--propositions`: short statements
+//! that each carry one fact and make sense on their own, which match
+//! questions far more precisely than a dense passage does.
+
+use crate::chat::ChatClient;
+use crate::chunking::Chunk;
+use crate::loaders;
+use crate::progress;
+use crate::rate_limit::estimate_tokens;
+use crate::retry::RetryPolicy;
+use anyhow::{Context, Result};
+use rig::client::CompletionClient;
+use rig::completion::Prompt;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+const PROPOSITIONS_PROMPT: &str = "Rewrite the passage you are given as a list of propositions: \
+short factual statements that each make sense on their own. Give every statement its subject \
+by name, replacing pronouns and phrases like \"this method\" with what they refer to, and keep \
+numbers, names and dates exactly as written. Leave out no fact, and add none. Answer with the \
+propositions only, one per line.";
+
+/// Has a chat model rewrite every chunk into propositions, each indexed as
+/// a chunk of its own with the metadata and place in the document of the
+/// chunk it came from. Answers are cached by model and chunk text, so only
+/// new or changed chunks cost a call; a chunk that can't be rewritten is
+/// kept as it is.
+pub async fn rewrite_as_propositions(
+    client: &ChatClient,
+    model: &str,
+    retry: &RetryPolicy,
+    chunks: Vec<Chunk>,
+) -> Result<Vec<Chunk>> {
+    if chunks.is_empty() {
+        return Ok(chunks);
+    }
+    info!(
+        "Rewriting {} chunks as propositions with {}",
+        chunks.len(),
+        model
+    );
+    let dir = loaders::cache_dir("propositions")?;
+    let agent = client.agent(model).preamble(PROPOSITIONS_PROMPT).build();
+
+    let (total, mut cached) = (chunks.len(), 0);
+    let mut rewritten: Vec<Chunk> = Vec::new();
+    // Propositions are numbered among their document's chunks in turn.
+    let mut next_index: HashMap<(Option<String>, usize), usize> = HashMap::new();
+    let progress = progress::start("Rewriting", "chunks", total);
+    for chunk in chunks {
+        let document = (chunk.source().map(str::to_string), chunk.document);
+        let index = next_index.entry(document).or_default();
+        let path = dir.join(format!(
+            "{}.json",
+            loaders::sha256_hex(format!("{}\n{}", model, chunk.text).as_bytes())
+        ));
+        let propositions = match read_cached(&path) {
+            Some(propositions) => {
+                cached += 1;
+                propositions
+            }
+            None => match retry
+                .run(
+                    "Rewriting as propositions",
+                    estimate_tokens(&chunk.text),
+                    || agent.prompt(chunk.text.as_str()).into_future(),
+                )
+                .await
+            {
+                Ok(answer) => {
+                    let propositions = parse_propositions(&answer);
+                    let json = serde_json::to_vec(&propositions)?;
+                    fs::write(&path, json).with_context(|| {
+                        format!("Failed to write cached propositions: {:?}", path)
+                    })?;
+                    propositions
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to rewrite chunk {} as propositions: {}",
+                        chunk.index, e
+                    );
+                    Vec::new()
+                }
+            },
+        };
+        progress.advance(1);
+        if propositions.is_empty() {
+            rewritten.push(Chunk {
+                index: *index,
+                ..chunk
+            });
+            *index += 1;
+            continue;
+        }
+        debug!(
+            "Chunk {} became {} propositions",
+            chunk.index,
+            propositions.len()
+        );
+        for text in propositions {
+            // A proposition restates its chunk, header and all.
+            rewritten.push(Chunk {
+                text,
+                index: *index,
+                header: None,
+                ..chunk.clone()
+            });
+            *index += 1;
+        }
+    }
+    info!(
+        "Rewrote {} chunks as {} propositions ({} cached)",
+        total,
+        rewritten.len(),
+        cached
+    );
+    Ok(rewritten)
+}
+
+/// One proposition per line of the answer, without the bullets or numbers
+/// models like to put in front.
+fn parse_propositions(answer: &str) -> Vec<String> {
+    answer
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            let line = line
+                .strip_prefix(['-', '*', '•'])
+                .unwrap_or(line)
+                .trim_start();
+            match line.split_once(['.', ')']) {
+                Some((number, rest))
+                    if !number.is_empty()
+                        && number.chars().all(|c| c.is_ascii_digit())
+                        && rest.starts_with(' ') =>
+                {
+                    rest.trim().to_string()
+                }
+                _ => line.to_string(),
+            }
+        })
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+fn read_cached(path: &Path) -> Option<Vec<String>> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
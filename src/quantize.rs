@@ -0,0 +1,349 @@
+//! Smaller vectors for `--quantize`: the index keeps each chunk's vectors
+//! as bytes or bits rather than 64-bit floats, finds candidates with those,
+//! and scores the best of them again against the question's full vector.
+
+/// How the vectors the index keeps in memory are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Quantization {
+    /// One signed byte per dimension, scaled to the vector's largest
+    /// value: an eighth of the memory, with scores barely changed
+    Int8,
+    /// One bit per dimension, its sign: a sixty-fourth of the memory, for
+    /// models with many dimensions
+    Binary,
+}
+
+/// Candidates searched for by quantized vectors, as a multiple of the
+/// chunks wanted, before the full scoring picks among them.
+pub const RESCORE_FACTOR: usize = 4;
+
+/// One vector, as the index keeps it.
+pub enum Vector {
+    Full(Vec<f64>),
+    /// The bytes times `scale` give back the vector, near enough; `norm` is
+    /// the length of that vector.
+    Int8 {
+        values: Vec<i8>,
+        scale: f32,
+        norm: f32,
+    },
+    /// The signs of the dimensions, a set bit for a positive one, packed
+    /// 64 to a word.
+    Binary {
+        bits: Vec<u64>,
+        len: usize,
+    },
+}
+
+/// A question's vector, with the quantized forms it is compared to
+/// quantized vectors in.
+pub struct Query {
+    vec: Vec<f64>,
+    norm: f64,
+    int8: Vec<i8>,
+    bits: Vec<u64>,
+}
+
+impl Query {
+    pub fn new(vec: Vec<f64>) -> Self {
+        let norm = norm(&vec);
+        let (int8, _) = to_int8(&vec);
+        let bits = to_bits(&vec);
+        Self {
+            vec,
+            norm,
+            int8,
+            bits,
+        }
+    }
+}
+
+impl Vector {
+    pub fn new(vec: Vec<f64>, quantization: Option<Quantization>) -> Self {
+        match quantization {
+            None => Vector::Full(vec),
+            Some(Quantization::Int8) => {
+                let (values, scale) = to_int8(&vec);
+                let norm = values
+                    .iter()
+                    .map(|&value| (f64::from(value) * f64::from(scale)).powi(2))
+                    .sum::<f64>()
+                    .sqrt() as f32;
+                Vector::Int8 {
+                    values,
+                    scale,
+                    norm,
+                }
+            }
+            Some(Quantization::Binary) => Vector::Binary {
+                bits: to_bits(&vec),
+                len: vec.len(),
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Vector::Full(vec) => vec.len(),
+            Vector::Int8 { values, .. } => values.len(),
+            Vector::Binary { len, .. } => *len,
+        }
+    }
+
+    /// The vector as floats again, as near as it is kept: binary vectors
+    /// give +1 or -1 for each dimension.
+    pub fn values(&self) -> Vec<f64> {
+        match self {
+            Vector::Full(vec) => vec.clone(),
+            Vector::Int8 { values, scale, .. } => values
+                .iter()
+                .map(|&value| f64::from(value) * f64::from(*scale))
+                .collect(),
+            Vector::Binary { bits, len } => (0..*len)
+                .map(|i| {
+                    if bits[i / 64] & (1 << (i % 64)) != 0 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// The cosine similarity of this vector and `other`, kept the same way,
+    /// from their quantized forms.
+    pub fn similarity(&self, other: &Vector) -> f64 {
+        match (self, other) {
+            // In one pass, as graphs compare vectors many times over.
+            (Vector::Full(a), Vector::Full(b)) => {
+                let (mut dot, mut a_squares, mut b_squares) = (0.0, 0.0, 0.0);
+                for (x, y) in a.iter().zip(b) {
+                    dot += x * y;
+                    a_squares += x * x;
+                    b_squares += y * y;
+                }
+                ratio(dot, (a_squares * b_squares).sqrt())
+            }
+            (
+                Vector::Int8 {
+                    values: a,
+                    scale: a_scale,
+                    norm: a_norm,
+                },
+                Vector::Int8 {
+                    values: b,
+                    scale: b_scale,
+                    norm: b_norm,
+                },
+            ) => {
+                let dot: i64 = a
+                    .iter()
+                    .zip(b)
+                    .map(|(&x, &y)| i64::from(x) * i64::from(y))
+                    .sum();
+                ratio(
+                    dot as f64 * f64::from(*a_scale) * f64::from(*b_scale),
+                    f64::from(*a_norm) * f64::from(*b_norm),
+                )
+            }
+            (Vector::Binary { bits: a, len }, Vector::Binary { bits: b, .. }) => {
+                let differing: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+                1.0 - 2.0 * f64::from(differing) / *len as f64
+            }
+            (a, b) => {
+                let (a, b) = (a.values(), b.values());
+                let dot: f64 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+                ratio(dot, norm(&a) * norm(&b))
+            }
+        }
+    }
+
+    /// A quick score for ranking vectors against `query`, from the
+    /// quantized forms alone. Only comparable between vectors quantized
+    /// the same way.
+    pub fn rough_score(&self, query: &Query) -> f64 {
+        match self {
+            Vector::Full(_) => self.score(query),
+            Vector::Int8 {
+                values,
+                scale,
+                norm,
+            } => {
+                let dot: i64 = values
+                    .iter()
+                    .zip(&query.int8)
+                    .map(|(&a, &b)| i64::from(a) * i64::from(b))
+                    .sum();
+                ratio(dot as f64 * f64::from(*scale), f64::from(*norm))
+            }
+            Vector::Binary { bits, len } => {
+                let differing: u32 = bits
+                    .iter()
+                    .zip(&query.bits)
+                    .map(|(a, b)| (a ^ b).count_ones())
+                    .sum();
+                1.0 - 2.0 * f64::from(differing) / *len as f64
+            }
+        }
+    }
+
+    /// The cosine similarity of `query`'s full vector and this one, as far
+    /// as this one is kept.
+    pub fn score(&self, query: &Query) -> f64 {
+        match self {
+            Vector::Full(vec) => {
+                let dot: f64 = vec.iter().zip(&query.vec).map(|(a, b)| a * b).sum();
+                ratio(dot, norm(vec) * query.norm)
+            }
+            Vector::Int8 {
+                values,
+                scale,
+                norm,
+            } => {
+                let dot: f64 = values
+                    .iter()
+                    .zip(&query.vec)
+                    .map(|(&a, b)| f64::from(a) * b)
+                    .sum();
+                ratio(dot * f64::from(*scale), f64::from(*norm) * query.norm)
+            }
+            // Each dimension is taken as +1 or -1.
+            Vector::Binary { bits, len } => {
+                let dot: f64 = query
+                    .vec
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        if bits[i / 64] & (1 << (i % 64)) != 0 {
+                            *value
+                        } else {
+                            -value
+                        }
+                    })
+                    .sum();
+                ratio(dot, (*len as f64).sqrt() * query.norm)
+            }
+        }
+    }
+}
+
+/// Nothing is similar to a vector of zeros.
+fn ratio(dot: f64, lengths: f64) -> f64 {
+    if lengths == 0.0 { 0.0 } else { dot / lengths }
+}
+
+fn norm(vec: &[f64]) -> f64 {
+    vec.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+/// `vec` as bytes, and what each byte is worth.
+fn to_int8(vec: &[f64]) -> (Vec<i8>, f32) {
+    let largest = vec.iter().fold(0.0f64, |largest, x| largest.max(x.abs()));
+    if largest == 0.0 {
+        return (vec![0; vec.len()], 1.0);
+    }
+    let scale = largest / 127.0;
+    let values = vec.iter().map(|x| (x / scale).round() as i8).collect();
+    (values, scale as f32)
+}
+
+fn to_bits(vec: &[f64]) -> Vec<u64> {
+    let mut bits = vec![0u64; vec.len().div_ceil(64)];
+    for (i, x) in vec.iter().enumerate() {
+        if *x > 0.0 {
+            bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fixed vector of `len` values between -1 and 1.
+    fn sample(len: usize, seed: u64) -> Vec<f64> {
+        (0..len as u64)
+            .map(|i| {
+                let x = (i + 1).wrapping_mul(seed.wrapping_mul(0x9E37_79B9) + 0x7F4A_7C15);
+                (x % 2001) as f64 / 1000.0 - 1.0
+            })
+            .collect()
+    }
+
+    fn cosine(a: &[f64], b: &[f64]) -> f64 {
+        let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        dot / (norm(a) * norm(b))
+    }
+
+    #[test]
+    fn int8_values_round_trip() {
+        let vec = sample(100, 1);
+        let values = Vector::new(vec.clone(), Some(Quantization::Int8)).values();
+        let largest = vec.iter().fold(0.0f64, |largest, x| largest.max(x.abs()));
+        for (value, original) in values.iter().zip(&vec) {
+            // Within half a step of the scale.
+            assert!((value - original).abs() <= largest / 127.0 / 2.0 + 1e-6);
+        }
+    }
+
+    #[test]
+    fn binary_values_keep_the_signs() {
+        let vec = sample(100, 2);
+        let values = Vector::new(vec.clone(), Some(Quantization::Binary)).values();
+        assert_eq!(values.len(), vec.len());
+        for (value, original) in values.iter().zip(&vec) {
+            assert_eq!(*value, if *original > 0.0 { 1.0 } else { -1.0 });
+        }
+    }
+
+    #[test]
+    fn int8_scores_stay_near_the_full_cosine() {
+        for seed in 1..20 {
+            let (vec, question) = (sample(384, seed), sample(384, seed + 100));
+            let query = Query::new(question.clone());
+            let full = cosine(&vec, &question);
+            let int8 = Vector::new(vec, Some(Quantization::Int8));
+            assert!((int8.score(&query) - full).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn full_scores_are_the_cosine() {
+        let (vec, question) = (sample(50, 3), sample(50, 4));
+        let full = Vector::new(vec.clone(), None);
+        let score = full.score(&Query::new(question.clone()));
+        assert!((score - cosine(&vec, &question)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zero_vectors_score_zero() {
+        let zero = vec![0.0; 16];
+        let query = Query::new(sample(16, 5));
+        for quantization in [None, Some(Quantization::Int8)] {
+            let vector = Vector::new(zero.clone(), quantization);
+            assert_eq!(vector.score(&query), 0.0);
+            assert_eq!(vector.rough_score(&query), 0.0);
+        }
+        let vector = Vector::new(sample(16, 6), None);
+        assert_eq!(vector.score(&Query::new(zero)), 0.0);
+    }
+
+    #[test]
+    fn binary_handles_partial_words() {
+        for len in [1, 63, 65, 100, 130] {
+            let vec = sample(len, 7);
+            let binary = Vector::new(vec.clone(), Some(Quantization::Binary));
+            assert_eq!(binary.len(), len);
+            assert_eq!(binary.values().len(), len);
+            // The same vector agrees with itself in every dimension.
+            assert_eq!(binary.rough_score(&Query::new(vec.clone())), 1.0);
+            let opposite: Vec<f64> = vec.iter().map(|x| -x).collect();
+            let score = binary.rough_score(&Query::new(opposite));
+            let zeros = vec.iter().filter(|&&x| x == 0.0).count();
+            // Zero dimensions are negative either way, so they still agree.
+            assert_eq!(score, -1.0 + 2.0 * zeros as f64 / len as f64);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Client-side pacing of API calls for `--rpm` and `--tpm`, so an account
+//! with low rate limits is kept under them rather than failing mid-ingest.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Buckets of requests and tokens shared by every copy, refilled at the
+/// allowed rate per minute and holding at most a minute's worth, so a
+/// burst after a quiet spell stays within a minute's limit.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    buckets: Option<Arc<Mutex<Buckets>>>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    refilled: Instant,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    per_minute: f64,
+    available: f64,
+}
+
+impl Bucket {
+    fn new(per_minute: u32) -> Self {
+        let per_minute = f64::from(per_minute);
+        Self {
+            per_minute,
+            available: per_minute,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.available =
+            (self.available + self.per_minute * elapsed.as_secs_f64() / 60.0).min(self.per_minute);
+    }
+
+    /// How long until `amount` is available; no longer than it takes to
+    /// fill up, so an amount over the limit waits for a full bucket.
+    fn wait(&self, amount: f64) -> Duration {
+        let missing = amount.min(self.per_minute) - self.available;
+        Duration::from_secs_f64(missing.max(0.0) * 60.0 / self.per_minute)
+    }
+}
+
+impl RateLimiter {
+    /// Up to `rpm` requests and `tpm` tokens a minute; no limit where
+    /// neither is given.
+    pub fn new(rpm: Option<u32>, tpm: Option<u32>) -> Self {
+        if rpm.is_none() && tpm.is_none() {
+            return Self::default();
+        }
+        let buckets = Buckets {
+            requests: rpm.map(Bucket::new),
+            tokens: tpm.map(Bucket::new),
+            refilled: Instant::now(),
+        };
+        Self {
+            buckets: Some(Arc::new(Mutex::new(buckets))),
+        }
+    }
+
+    /// Waits until a request of about `tokens` tokens may be sent, and
+    /// takes it out of the buckets. A request larger than `--tpm` waits for
+    /// a full bucket and leaves it in debt, which the following requests
+    /// wait out.
+    pub async fn acquire(&self, tokens: usize) {
+        let Some(buckets) = &self.buckets else {
+            return;
+        };
+        loop {
+            let wait = {
+                let mut buckets = buckets.lock().unwrap();
+                buckets.refill();
+                let wait = buckets.wait(tokens as f64);
+                if wait.is_zero() {
+                    buckets.take(tokens as f64);
+                    return;
+                }
+                wait
+            };
+            debug!("Waiting {:.1}s for the rate limit", wait.as_secs_f64());
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl Buckets {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.refilled;
+        self.refilled = now;
+        for bucket in [&mut self.requests, &mut self.tokens].into_iter().flatten() {
+            bucket.refill(elapsed);
+        }
+    }
+
+    fn wait(&self, tokens: f64) -> Duration {
+        let requests = self.requests.as_ref().map(|bucket| bucket.wait(1.0));
+        let tokens = self.tokens.as_ref().map(|bucket| bucket.wait(tokens));
+        requests.into_iter().chain(tokens).max().unwrap_or_default()
+    }
+
+    fn take(&mut self, tokens: f64) {
+        if let Some(bucket) = &mut self.requests {
+            bucket.available -= 1.0;
+        }
+        if let Some(bucket) = &mut self.tokens {
+            bucket.available -= tokens;
+        }
+    }
+}
+
+/// About how many tokens `text` is, as OpenAI's models count them.
+pub fn estimate_tokens(text: &str) -> usize {
+    tiktoken_rs::cl100k_base_singleton().count_ordinary(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buckets(rpm: Option<u32>, tpm: Option<u32>) -> Buckets {
+        Buckets {
+            requests: rpm.map(Bucket::new),
+            tokens: tpm.map(Bucket::new),
+            refilled: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn refills_at_the_rate_and_no_further() {
+        let mut bucket = Bucket::new(60);
+        bucket.available = 0.0;
+        bucket.refill(Duration::from_secs(10));
+        assert_eq!(bucket.available, 10.0);
+        bucket.refill(Duration::from_secs(600));
+        assert_eq!(bucket.available, 60.0);
+    }
+
+    #[test]
+    fn waits_for_what_is_missing() {
+        let mut bucket = Bucket::new(60);
+        assert!(bucket.wait(60.0).is_zero());
+        bucket.available = 20.0;
+        assert_eq!(bucket.wait(50.0), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn a_request_over_the_limit_waits_for_a_full_bucket_and_leaves_it_in_debt() {
+        let mut buckets = buckets(None, Some(600));
+        assert!(buckets.wait(1500.0).is_zero());
+        buckets.take(1500.0);
+        // 900 tokens of debt, and 100 more for the next request, come back
+        // in 100 seconds at 10 a second.
+        assert_eq!(buckets.wait(100.0), Duration::from_secs(100));
+        // However large the next one, it waits no longer than the debt and
+        // a full bucket.
+        assert_eq!(buckets.wait(5000.0), Duration::from_secs(150));
+    }
+
+    #[test]
+    fn waits_for_the_emptier_bucket() {
+        let mut buckets = buckets(Some(2), Some(600));
+        buckets.take(10.0);
+        buckets.take(10.0);
+        // No requests are left, and the next comes after half a minute,
+        // though there are tokens to spare.
+        assert_eq!(buckets.wait(10.0), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn does_not_wait_without_limits() {
+        let limiter = RateLimiter::new(None, None);
+        assert!(limiter.buckets.is_none());
+        for _ in 0..1000 {
+            limiter.acquire(1_000_000).await;
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::loaders::Document;
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+use tracing::info;
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}\b").unwrap()
+});
+
+/// US social security numbers, written with dashes or spaces.
+static SSN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b\d{3}[- ]\d{2}[- ]\d{4}\b").unwrap());
+
+/// Phone numbers in the usual groupings, with or without a country code:
+/// `(555) 123-4567`, `+44 20 7946 0958`, `555.123.4567`.
+static PHONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{2,4}\)[ .-]?|\b\d{2,4}[ .-])\d{3,4}[ .-]\d{3,4}\b")
+        .unwrap()
+});
+
+/// A title followed by one or two capitalised words: `Dr. Jane Smith`.
+static TITLED_NAME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"\b(Mr|Mrs|Ms|Miss|Mx|Dr|Prof|Sir|Dame)(\.?\s+)\p{Lu}[\p{L}'-]+(?:\s+\p{Lu}[\p{L}'-]+)?",
+    )
+    .unwrap()
+});
+
+/// Phone numbers are at least this many digits; shorter digit groups are
+/// more often figures like `10 000 000`.
+const MIN_PHONE_DIGITS: usize = 9;
+
+/// Metadata that identifies where a chunk came from rather than what it
+/// says, and is left as is.
+const KEPT_METADATA: &[&str] = &["source", "url"];
+
+/// Masks personal data in the documents' text and metadata before any of
+/// it is sent to the embedding or chat APIs: email addresses, phone
+/// numbers, social security numbers and people's names become `[EMAIL]`,
+/// `[PHONE]`, `[SSN]` and `[NAME]`. Names are only recognised after a title
+/// like `Dr.` or `Ms.`: there is no named-entity recognition, so a name
+/// written without one is left as is.
+pub fn redact_documents(documents: &mut [Document]) {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for document in documents.iter_mut() {
+        // Page by page, so a match across a page break can't remove the
+        // form feed between them.
+        let pages: Vec<String> = document
+            .text
+            .split('\u{c}')
+            .map(|page| redact(page, &mut counts))
+            .collect();
+        document.text = pages.join("\u{c}");
+        for (key, value) in document.metadata.iter_mut() {
+            if !KEPT_METADATA.contains(&key.as_str()) {
+                *value = redact(value, &mut counts);
+            }
+        }
+    }
+    if !counts.is_empty() {
+        let summary: Vec<String> = counts
+            .iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect();
+        info!("Redacted {}", summary.join(", "));
+    }
+}
+
+fn redact(text: &str, counts: &mut BTreeMap<&'static str, usize>) -> String {
+    // Emails go first so the names in addresses aren't masked on their own,
+    // and SSNs before phone numbers, which they also look like.
+    let text = EMAIL.replace_all(text, |_: &Captures| mask(counts, "emails", "[EMAIL]"));
+    let text = SSN.replace_all(&text, |_: &Captures| mask(counts, "SSNs", "[SSN]"));
+    let text = PHONE.replace_all(&text, |caps: &Captures| {
+        let digits = caps[0].chars().filter(char::is_ascii_digit).count();
+        if digits < MIN_PHONE_DIGITS {
+            caps[0].to_string()
+        } else {
+            mask(counts, "phone numbers", "[PHONE]")
+        }
+    });
+    let text = TITLED_NAME.replace_all(&text, |caps: &Captures| {
+        format!(
+            "{}{}{}",
+            &caps[1],
+            &caps[2],
+            mask(counts, "names", "[NAME]")
+        )
+    });
+    text.into_owned()
+}
+
+fn mask(counts: &mut BTreeMap<&'static str, usize>, kind: &'static str, mask: &str) -> String {
+    *counts.entry(kind).or_default() += 1;
+    mask.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redacted(text: &str) -> String {
+        redact(text, &mut BTreeMap::new())
+    }
+
+    #[test]
+    fn masks_contact_details() {
+        assert_eq!(
+            redacted("Write to jane.doe@example.com or call (555) 123-4567."),
+            "Write to [EMAIL] or call [PHONE]."
+        );
+        assert_eq!(redacted("Her SSN is 123-45-6789."), "Her SSN is [SSN].");
+        assert_eq!(redacted("Call +44 20 7946 0958"), "Call [PHONE]");
+    }
+
+    #[test]
+    fn keeps_figures_that_look_like_phone_numbers() {
+        assert_eq!(
+            redacted("Sales rose to 10 000 000."),
+            "Sales rose to 10 000 000."
+        );
+    }
+
+    #[test]
+    fn masks_titled_names_and_keeps_the_title() {
+        assert_eq!(
+            redacted("Dr. Jane Smith met Ms Ortiz."),
+            "Dr. [NAME] met Ms [NAME]."
+        );
+    }
+
+    #[test]
+    fn keeps_capitalised_phrases_without_a_title() {
+        for text in [
+            "Grace Period",
+            "Mark Down",
+            "the Grace Hopper Award",
+            "Will Power",
+        ] {
+            assert_eq!(redacted(text), text);
+        }
+    }
+
+    #[test]
+    fn counts_what_it_masks() {
+        let mut counts = BTreeMap::new();
+        redact("a@b.io, c@d.io and Mr. Brown", &mut counts);
+        assert_eq!(counts.get("emails"), Some(&2));
+        assert_eq!(counts.get("names"), Some(&1));
+    }
+}
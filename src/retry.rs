@@ -0,0 +1,536 @@
+//! Retries for API calls that fail for reasons that pass, like rate limits,
+//! overloaded servers and dropped connections, so a long ingest outlasts a
+//! flaky network.
+
+use crate::chat::relay;
+use crate::rate_limit::{RateLimiter, estimate_tokens};
+use futures::{StreamExt, stream};
+use regex::Regex;
+use reqwest::StatusCode;
+use rig::completion::{
+    CompletionError, CompletionModel, CompletionRequest, CompletionResponse, PromptError,
+};
+use rig::embeddings::{Embedding, EmbeddingError, EmbeddingModel};
+use rig::http_client;
+use rig::streaming::StreamingCompletionResponse;
+use rig::wasm_compat::WasmCompatSend;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tracing::warn;
+
+/// Longest wait taken on an API's say-so.
+const MAX_REQUESTED_DELAY: Duration = Duration::from_secs(300);
+
+/// What errors look like when trying again may help, for those that carry
+/// no status or connection error to go by, only text: the HTTP statuses for
+/// rate limits, timeouts and server trouble, and failed connections.
+const TRANSIENT: &[&str] = &[
+    "408 Request Timeout",
+    "429 Too Many Requests",
+    "500 Internal Server Error",
+    "502 Bad Gateway",
+    "503 Service Unavailable",
+    "504 Gateway Timeout",
+    "rate limit",
+    "overloaded",
+    "error sending request",
+    "connection",
+    "timed out",
+];
+
+/// How long the API asked to be left alone, as said in an error's text: a
+/// `Retry-After` header quoted in it, or OpenAI's "Please try again in 1.5s".
+static REQUESTED_DELAY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:retry-after:\s*|try again in )(\d+(?:\.\d+)?)(ms|s)?").unwrap()
+});
+
+/// `--max-retries` and `--retry-delay`, and the `--rpm` and `--tpm` limits
+/// every try is held to.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Tries after the first.
+    pub retries: u32,
+    /// The wait before the first retry, doubled for each one after.
+    pub delay: Duration,
+    pub limiter: RateLimiter,
+}
+
+impl RetryPolicy {
+    /// Calls `call` until it succeeds, fails with an error retrying won't
+    /// fix, or has been retried `retries` times. Between tries, it waits as
+    /// long as the error asks, or else for a doubling delay with jitter, so
+    /// concurrent requests that failed together don't retry together. Each
+    /// try first waits its turn under the rate limits, as a request of
+    /// about `tokens` tokens. `what` names the call in warnings.
+    pub async fn run<T, E, F, Fut>(&self, what: &str, tokens: usize, mut call: F) -> Result<T, E>
+    where
+        E: Failure,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            self.limiter.acquire(tokens).await;
+            let e = match call().await {
+                Ok(result) => return Ok(result),
+                Err(e) => e,
+            };
+            let message = format!("{:#}", e);
+            let (transient, requested) = assess(e.error(), &message);
+            if attempt == self.retries || !transient {
+                return Err(e);
+            }
+            let delay = requested.unwrap_or_else(|| self.backoff(attempt));
+            attempt += 1;
+            warn!(
+                "{} failed, retrying in {:.1}s ({} of {}): {}",
+                what,
+                delay.as_secs_f64(),
+                attempt,
+                self.retries,
+                message
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Between half and all of `delay` doubled `attempt` times.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let full = self.delay.saturating_mul(2u32.saturating_pow(attempt));
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        full.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+/// A response one of our own HTTP clients was turned down with, kept as
+/// its status and `Retry-After` header rather than only as text, so
+/// [`RetryPolicy::run`] can tell whether to try again, and when.
+#[derive(Debug)]
+pub struct HttpFailure {
+    pub status: StatusCode,
+    /// How long the `Retry-After` header asked to wait, where it gave a
+    /// number of seconds.
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl HttpFailure {
+    /// The failure `response` reports, its body read in full.
+    pub async fn read(response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+            .map(|seconds| Duration::from_secs_f64(seconds).min(MAX_REQUESTED_DELAY));
+        let body = response.text().await.unwrap_or_default();
+        Self {
+            status,
+            retry_after,
+            body,
+        }
+    }
+}
+
+impl Display for HttpFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.status)?;
+        if let Some(delay) = self.retry_after {
+            write!(f, " (Retry-After: {}s)", delay.as_secs_f64())?;
+        }
+        if !self.body.is_empty() {
+            write!(f, ": {}", self.body)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for HttpFailure {}
+
+impl From<HttpFailure> for EmbeddingError {
+    fn from(failure: HttpFailure) -> Self {
+        EmbeddingError::HttpError(http_client::Error::Instance(Box::new(failure)))
+    }
+}
+
+/// A request that got no response, or whose response couldn't be read, as
+/// an embedding error, with the connection error kept for
+/// [`RetryPolicy::run`] to look at.
+pub fn request_error(error: reqwest::Error) -> EmbeddingError {
+    EmbeddingError::HttpError(http_client::Error::Instance(Box::new(error)))
+}
+
+/// Errors [`RetryPolicy::run`] looks through for the HTTP status or failed
+/// connection behind them.
+pub trait Failure: Display {
+    fn error(&self) -> &(dyn Error + 'static);
+}
+
+impl Failure for EmbeddingError {
+    fn error(&self) -> &(dyn Error + 'static) {
+        self
+    }
+}
+
+impl Failure for CompletionError {
+    fn error(&self) -> &(dyn Error + 'static) {
+        self
+    }
+}
+
+impl Failure for PromptError {
+    fn error(&self) -> &(dyn Error + 'static) {
+        self
+    }
+}
+
+impl Failure for anyhow::Error {
+    fn error(&self) -> &(dyn Error + 'static) {
+        self.as_ref()
+    }
+}
+
+/// Whether a call that failed with `error` (shown as `message`) may succeed
+/// if tried again, and how long the API asked to be left alone first,
+/// where it did. The HTTP status decides where there is one, and a
+/// connection that failed or timed out is always worth another try; only
+/// errors with neither are judged by their text.
+fn assess(error: &(dyn Error + 'static), message: &str) -> (bool, Option<Duration>) {
+    for cause in std::iter::successors(Some(error), |&cause| cause.source()) {
+        if let Some(failure) = cause.downcast_ref::<HttpFailure>() {
+            let delay = failure
+                .retry_after
+                .or_else(|| requested_delay(&failure.body));
+            return (is_transient_status(failure.status), delay);
+        }
+        if let Some(
+            http_client::Error::InvalidStatusCode(status)
+            | http_client::Error::InvalidStatusCodeWithMessage(status, _),
+        ) = cause.downcast_ref::<http_client::Error>()
+        {
+            return (is_transient_status(*status), requested_delay(message));
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if let Some(status) = e.status() {
+                return (is_transient_status(status), requested_delay(message));
+            }
+            if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() {
+                return (true, None);
+            }
+            if e.is_builder() || e.is_decode() || e.is_redirect() {
+                return (false, None);
+            }
+        }
+    }
+    (is_transient(message), requested_delay(message))
+}
+
+/// Rate limits, timeouts and server trouble, but not a server that doesn't
+/// do what was asked at all.
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || (status.is_server_error()
+            && status != StatusCode::NOT_IMPLEMENTED
+            && status != StatusCode::HTTP_VERSION_NOT_SUPPORTED)
+}
+
+fn is_transient(message: &str) -> bool {
+    let message = message.to_lowercase();
+    TRANSIENT
+        .iter()
+        .any(|transient| message.contains(&transient.to_lowercase()))
+}
+
+fn requested_delay(message: &str) -> Option<Duration> {
+    let captures = REQUESTED_DELAY.captures(message)?;
+    let amount: f64 = captures[1].parse().ok()?;
+    let seconds = match captures.get(2).map(|unit| unit.as_str()) {
+        Some("ms") => amount / 1000.0,
+        _ => amount,
+    };
+    Some(Duration::from_secs_f64(seconds).min(MAX_REQUESTED_DELAY))
+}
+
+/// `model`, with every request made under `policy`.
+#[derive(Clone)]
+pub struct Retrying<M> {
+    model: M,
+    policy: RetryPolicy,
+}
+
+impl<M> Retrying<M> {
+    pub fn new(model: M, policy: RetryPolicy) -> Self {
+        Self { model, policy }
+    }
+}
+
+impl<M: EmbeddingModel + Sync> EmbeddingModel for Retrying<M> {
+    const MAX_DOCUMENTS: usize = M::MAX_DOCUMENTS;
+
+    type Client = M::Client;
+
+    fn make(client: &Self::Client, model: impl Into<String>, dims: Option<usize>) -> Self {
+        let policy = RetryPolicy {
+            retries: 0,
+            delay: Duration::ZERO,
+            limiter: RateLimiter::default(),
+        };
+        Self::new(M::make(client, model, dims), policy)
+    }
+
+    fn ndims(&self) -> usize {
+        self.model.ndims()
+    }
+
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String> + WasmCompatSend,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        let texts: Vec<String> = texts.into_iter().collect();
+        let tokens = texts.iter().map(|text| estimate_tokens(text)).sum();
+        self.policy
+            .run("Embedding request", tokens, || {
+                self.model.embed_texts(texts.clone())
+            })
+            .await
+    }
+}
+
+impl<M: CompletionModel + 'static> CompletionModel for Retrying<M> {
+    type Response = M::Response;
+    type StreamingResponse = M::StreamingResponse;
+
+    type Client = M::Client;
+
+    fn make(client: &Self::Client, model: impl Into<String>) -> Self {
+        let policy = RetryPolicy {
+            retries: 0,
+            delay: Duration::ZERO,
+            limiter: RateLimiter::default(),
+        };
+        Self::new(M::make(client, model), policy)
+    }
+
+    async fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<CompletionResponse<Self::Response>, CompletionError> {
+        self.policy
+            .run("Chat request", request_tokens(&request), || {
+                self.model.completion(request.clone())
+            })
+            .await
+    }
+
+    /// Only the request is retried: once the answer has started streaming,
+    /// a failure ends it.
+    async fn stream(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<StreamingCompletionResponse<Self::StreamingResponse>, CompletionError> {
+        let (response, first) = self
+            .policy
+            .run("Chat request", request_tokens(&request), || async {
+                let mut response = self.model.stream(request.clone()).await?;
+                // A request the API turns down, for a rate limit say, fails
+                // as the first thing streamed.
+                match response.next().await {
+                    Some(Err(e)) => Err(e),
+                    first => Ok((response, first)),
+                }
+            })
+            .await?;
+        Ok(relay(stream::iter(first).chain(response), |response| {
+            response
+        }))
+    }
+}
+
+/// About how many tokens `request` sends: its preamble, documents and
+/// messages, the last as JSON.
+fn request_tokens(request: &CompletionRequest) -> usize {
+    let messages = serde_json::to_string(&request.chat_history).unwrap_or_default();
+    request
+        .preamble
+        .iter()
+        .chain(request.documents.iter().map(|document| &document.text))
+        .chain([&messages])
+        .map(|text| estimate_tokens(text))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn failure(status: StatusCode, body: &str) -> EmbeddingError {
+        HttpFailure {
+            status,
+            retry_after: None,
+            body: body.to_string(),
+        }
+        .into()
+    }
+
+    fn assessed<E: Failure>(e: E) -> (bool, Option<Duration>) {
+        assess(e.error(), &format!("{:#}", e))
+    }
+
+    #[test]
+    fn goes_by_the_status_of_our_own_requests() {
+        assert_eq!(
+            assessed(failure(StatusCode::TOO_MANY_REQUESTS, "")),
+            (true, None)
+        );
+        assert!(assessed(failure(StatusCode::BAD_GATEWAY, "")).0);
+        // The status decides, whatever the body says.
+        assert!(!assessed(failure(StatusCode::BAD_REQUEST, "connection rate limit")).0);
+        assert!(!assessed(failure(StatusCode::NOT_IMPLEMENTED, "")).0);
+        let asked = HttpFailure {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            retry_after: Some(Duration::from_secs(7)),
+            body: "Please try again in 2s".to_string(),
+        };
+        assert_eq!(
+            assessed(EmbeddingError::from(asked)),
+            (true, Some(Duration::from_secs(7)))
+        );
+        // Wrapped further, as transcription errors are.
+        let wrapped = anyhow::Error::new(HttpFailure {
+            status: StatusCode::UNAUTHORIZED,
+            retry_after: None,
+            body: "timed out".to_string(),
+        })
+        .context("Transcription failed");
+        assert!(!assessed(wrapped).0);
+    }
+
+    #[test]
+    fn goes_by_the_status_rig_reports() {
+        let status = |status, message: &str| {
+            CompletionError::HttpError(http_client::Error::InvalidStatusCodeWithMessage(
+                status,
+                message.to_string(),
+            ))
+        };
+        assert_eq!(
+            assessed(status(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Rate limit reached. Please try again in 1.5s."
+            )),
+            (true, Some(Duration::from_millis(1500)))
+        );
+        assert!(!assessed(status(StatusCode::UNAUTHORIZED, "overloaded")).0);
+        let prompt = PromptError::CompletionError(CompletionError::HttpError(
+            http_client::Error::InvalidStatusCode(StatusCode::GATEWAY_TIMEOUT),
+        ));
+        assert!(assessed(prompt).0);
+    }
+
+    #[tokio::test]
+    async fn retries_connections_that_fail() {
+        // A port nothing listens on any more.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let e = reqwest::get(format!("http://127.0.0.1:{}", port))
+            .await
+            .unwrap_err();
+        assert_eq!(assessed(request_error(e)), (true, None));
+    }
+
+    #[test]
+    fn reads_errors_with_only_text() {
+        let text = |message: &str| assessed(EmbeddingError::ProviderError(message.to_string()));
+        assert_eq!(text("503 Service Unavailable"), (true, None));
+        assert_eq!(
+            text("Server overloaded (Retry-After: 3)"),
+            (true, Some(Duration::from_secs(3)))
+        );
+        assert!(!text("Invalid API key").0);
+    }
+
+    #[test]
+    fn caps_requested_delays() {
+        assert_eq!(
+            requested_delay("try again in 250ms"),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            requested_delay("Retry-After: 86400"),
+            Some(MAX_REQUESTED_DELAY)
+        );
+        assert_eq!(requested_delay("try again later"), None);
+    }
+
+    /// Answers every request on `listener` with `response`, as is.
+    async fn answer(listener: TcpListener, response: &'static str) {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = [0; 4096];
+            let _ = socket.read(&mut request).await;
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_the_failure_a_server_answers_with() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(answer(
+            listener,
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 2\r\nContent-Length: 4\r\nConnection: close\r\n\r\nslow",
+        ));
+        let failure = HttpFailure::read(reqwest::get(url).await.unwrap()).await;
+        assert_eq!(failure.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(failure.retry_after, Some(Duration::from_secs(2)));
+        assert_eq!(failure.body, "slow");
+        assert_eq!(
+            failure.to_string(),
+            "429 Too Many Requests (Retry-After: 2s): slow"
+        );
+    }
+
+    fn policy(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            delay: Duration::ZERO,
+            limiter: RateLimiter::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn tries_again_only_while_it_may_help() {
+        let calls = AtomicU32::new(0);
+        let flaky = || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(failure(StatusCode::SERVICE_UNAVAILABLE, "")),
+                _ => Ok("done"),
+            }
+        };
+        assert_eq!(policy(2).run("Test", 0, flaky).await.unwrap(), "done");
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 3);
+
+        assert!(policy(1).run("Test", 0, flaky).await.is_err());
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 2);
+
+        let refused = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(failure(StatusCode::FORBIDDEN, ""))
+        };
+        assert!(policy(5).run("Test", 0, refused).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
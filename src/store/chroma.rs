@@ -0,0 +1,396 @@
+//! Chunks kept in a Chroma collection, through the HTTP API a Chroma
+//! server (`chroma run`) serves. Records are written the way Chroma's own
+//! clients write them, the chunk's text as the document and its metadata
+//! as the record's, so Python tooling reads them as it would any other;
+//! records written by that tooling are read back as chunks the same way.
+
+use super::{chunk_name, point_id};
+use crate::progress;
+use crate::watch::EmbeddedChunk;
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeSet;
+use tracing::{debug, info};
+
+/// Where Chroma serves its API, unless `--store-url` says otherwise.
+const CHROMA_URL: &str = "http://localhost:8000";
+
+/// Records written per request.
+const UPSERT_BATCH: usize = 128;
+
+/// The language chunks in no known language are given, as Chroma can't
+/// filter by a field that is missing.
+const UNDETERMINED: &str = "und";
+
+/// How a collection compares vectors, which is what its distances mean.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Space {
+    Cosine,
+    InnerProduct,
+    /// Squared Euclidean distance, Chroma's default.
+    L2,
+}
+
+pub struct Chroma {
+    http: reqwest::Client,
+    /// The collection's URL.
+    url: String,
+    token: Option<String>,
+    space: Space,
+    embedding_model: String,
+}
+
+impl Chroma {
+    /// `collection` on the server at `url`, in the tenant and database in
+    /// `CHROMA_TENANT` and `CHROMA_DATABASE` (Chroma's defaults otherwise),
+    /// with the token in `CHROMA_API_KEY`, if set, created for vectors
+    /// compared by cosine similarity if it doesn't exist yet.
+    pub async fn open(
+        url: Option<&str>,
+        collection: &str,
+        embedding_model: &str,
+        dimensions: usize,
+    ) -> Result<Self> {
+        check_name(collection)?;
+        let url = url.unwrap_or(CHROMA_URL).trim_end_matches('/');
+        let tenant = std::env::var("CHROMA_TENANT").unwrap_or("default_tenant".to_string());
+        let database = std::env::var("CHROMA_DATABASE").unwrap_or("default_database".to_string());
+        let mut store = Self {
+            http: reqwest::Client::new(),
+            url: format!(
+                "{}/api/v2/tenants/{}/databases/{}/collections",
+                url, tenant, database
+            ),
+            token: std::env::var("CHROMA_API_KEY").ok(),
+            space: Space::Cosine,
+            embedding_model: embedding_model.to_string(),
+        };
+        let info = store
+            .call(
+                "",
+                json!({
+                    "name": collection,
+                    "metadata": { "hnsw:space": "cosine" },
+                    "get_or_create": true,
+                }),
+            )
+            .await
+            .with_context(|| {
+                format!("Could not open Chroma collection {} at {}", collection, url)
+            })?;
+        let id = info["id"]
+            .as_str()
+            .context("Unexpected reply from Chroma")?
+            .to_string();
+        store.url = format!("{}/{}", store.url, id);
+        // Collections made elsewhere keep the space they were made with.
+        let space = info
+            .pointer("/configuration_json/hnsw/space")
+            .or_else(|| info.pointer("/metadata/hnsw:space"))
+            .and_then(Value::as_str)
+            .unwrap_or("l2");
+        store.space = match space {
+            "cosine" => Space::Cosine,
+            "ip" => Space::InnerProduct,
+            _ => Space::L2,
+        };
+        debug!(
+            "Opened Chroma collection {} ({}), comparing by {:?}",
+            collection, id, store.space
+        );
+        if let Some(size) = info["dimension"]
+            .as_u64()
+            .filter(|&size| size as usize != dimensions)
+        {
+            bail!(
+                "Embedding dimension mismatch: Chroma collection {} holds {}-dimensional vectors, but {} embeds into {}; pass another --store-collection",
+                collection,
+                size,
+                embedding_model,
+                dimensions
+            );
+        }
+        let records = store
+            .call("/get", json!({ "limit": 1, "include": ["metadatas"] }))
+            .await?;
+        if let Some(model) = records
+            .pointer("/metadatas/0/embedding_model")
+            .and_then(Value::as_str)
+            .filter(|&model| model != embedding_model)
+        {
+            bail!(
+                "Embedding model mismatch: Chroma collection {} was embedded with {}, but questions are embedded with {}; pass --embedding-model {}, or another --store-collection",
+                collection,
+                model,
+                embedding_model,
+                model
+            );
+        }
+        if store.space != Space::Cosine {
+            info!(
+                "Chroma collection {} compares vectors by {:?}; scores are converted to cosine similarity",
+                collection, store.space
+            );
+        }
+        Ok(store)
+    }
+
+    pub async fn replace(&self, chunks: &[EmbeddedChunk]) -> Result<()> {
+        let sources: BTreeSet<&str> = chunks.iter().filter_map(|(c, _)| c.source()).collect();
+        if !sources.is_empty() {
+            self.call(
+                "/delete",
+                json!({ "where": { "source": { "$in": sources } } }),
+            )
+            .await
+            .context("Failed to remove old chunks from Chroma")?;
+        }
+        let mut records = Vec::new();
+        for (chunk, embeddings) in chunks {
+            let value = serde_json::to_value(chunk)?;
+            let mut metadata: Map<String, Value> = chunk
+                .metadata
+                .iter()
+                .map(|(key, text)| (key.clone(), json!(text)))
+                .collect();
+            metadata
+                .entry("lang")
+                .or_insert_with(|| json!(UNDETERMINED));
+            metadata.insert("index".to_string(), json!(chunk.index));
+            metadata.insert("chunk".to_string(), json!(value.to_string()));
+            metadata.insert("embedding_model".to_string(), json!(self.embedding_model));
+            for (i, embedding) in embeddings.iter().enumerate() {
+                records.push((
+                    point_id(&value, i),
+                    &embedding.vec,
+                    &chunk.text,
+                    metadata.clone(),
+                ));
+            }
+        }
+        let progress = progress::start("Writing to Chroma", "records", records.len());
+        for batch in records.chunks(UPSERT_BATCH) {
+            let ids: Vec<_> = batch.iter().map(|record| &record.0).collect();
+            let embeddings: Vec<_> = batch.iter().map(|record| record.1).collect();
+            let documents: Vec<_> = batch.iter().map(|record| record.2).collect();
+            let metadatas: Vec<_> = batch.iter().map(|record| &record.3).collect();
+            self.call(
+                "/upsert",
+                json!({
+                    "ids": ids,
+                    "embeddings": embeddings,
+                    "documents": documents,
+                    "metadatas": metadatas,
+                }),
+            )
+            .await
+            .context("Failed to write chunks to Chroma")?;
+            progress.advance(batch.len());
+        }
+        debug!("Wrote {} records to Chroma", records.len());
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        query: Vec<f64>,
+        wanted: usize,
+        languages: Option<&[String]>,
+    ) -> Result<Vec<(f64, String, Value)>> {
+        let mut request = json!({
+            "query_embeddings": [query],
+            "n_results": wanted,
+            "include": ["documents", "metadatas", "distances"],
+        });
+        // Chunks in no known language are never passed over.
+        if let Some(languages) = languages {
+            let mut languages = languages.to_vec();
+            languages.push(UNDETERMINED.to_string());
+            request["where"] = json!({ "lang": { "$in": languages } });
+        }
+        let found = self
+            .call("/query", request)
+            .await
+            .context("Failed to search Chroma")?;
+        // One list of results per query embedding, and this is the only one.
+        let distances = found.pointer("/distances/0").and_then(Value::as_array);
+        let documents = found.pointer("/documents/0").and_then(Value::as_array);
+        let metadatas = found.pointer("/metadatas/0").and_then(Value::as_array);
+        let (Some(distances), Some(documents), Some(metadatas)) = (distances, documents, metadatas)
+        else {
+            return Ok(Vec::new());
+        };
+        Ok(distances
+            .iter()
+            .zip(documents)
+            .zip(metadatas)
+            .filter_map(|((distance, document), metadata)| {
+                let chunk = record_chunk(document, metadata)?;
+                Some((
+                    self.similarity(distance.as_f64()?),
+                    chunk_name(&chunk),
+                    chunk,
+                ))
+            })
+            .collect())
+    }
+
+    /// A distance in the collection's space as cosine similarity, taking
+    /// the vectors to be normalized, as embedding models' are.
+    fn similarity(&self, distance: f64) -> f64 {
+        match self.space {
+            Space::Cosine | Space::InnerProduct => 1.0 - distance,
+            Space::L2 => 1.0 - distance / 2.0,
+        }
+    }
+
+    /// Sends `body` to `path` under the collection (or, before it is open,
+    /// the database's collections) and returns the reply.
+    async fn call(&self, path: &str, body: Value) -> Result<Value> {
+        let mut request = self.http.post(format!("{}{}", self.url, path)).json(&body);
+        if let Some(token) = &self.token {
+            request = request.header("x-chroma-token", token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("Chroma returned {}: {}", status, body);
+        }
+        if body.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(&body).context("Unexpected reply from Chroma")
+    }
+}
+
+/// The chunk a record holds: the one this tool wrote, or, for a record
+/// written by other tooling, its document and metadata.
+fn record_chunk(document: &Value, metadata: &Value) -> Option<Value> {
+    if let Some(chunk) = metadata.get("chunk").and_then(Value::as_str) {
+        return serde_json::from_str(chunk).ok();
+    }
+    let metadata: Map<String, Value> = metadata
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| {
+            let text = match value {
+                Value::String(text) => text.clone(),
+                value => value.to_string(),
+            };
+            (key.clone(), json!(text))
+        })
+        .collect();
+    Some(json!({ "text": document.as_str()?, "metadata": metadata }))
+}
+
+/// Chroma takes names of 3 to 512 letters, digits, dots, dashes and
+/// underscores, starting and ending with a letter or digit.
+fn check_name(name: &str) -> Result<()> {
+    let valid = (3..=512).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+        && !name.contains("..");
+    if !valid {
+        bail!(
+            "{:?} can't name a Chroma collection: use 3 to 512 letters, digits, dots, dashes and underscores, starting and ending with a letter or digit",
+            name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::fake::serving;
+
+    #[test]
+    fn takes_only_names_chroma_takes() {
+        for name in ["rag-my-pdf", "docs.v2", "a_b"] {
+            assert!(check_name(name).is_ok(), "{}", name);
+        }
+        for name in ["ab", "-docs", "docs_", "a..b", "my docs"] {
+            assert!(check_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn reads_records_written_by_other_tooling() {
+        let written = json!({ "text": "one", "index": 2, "metadata": { "source": "a.txt" } });
+        assert_eq!(
+            record_chunk(
+                &json!("ignored"),
+                &json!({ "chunk": written.to_string(), "source": "a.txt" })
+            ),
+            Some(written)
+        );
+        assert_eq!(
+            record_chunk(&json!("two"), &json!({ "source": "b.txt", "page": 3 })),
+            Some(json!({ "text": "two", "metadata": { "source": "b.txt", "page": "3" } }))
+        );
+        assert_eq!(record_chunk(&Value::Null, &json!({})), None);
+    }
+
+    #[tokio::test]
+    async fn scores_collections_made_elsewhere_by_cosine_similarity() {
+        let chunk = json!({ "text": "one", "index": 0, "metadata": { "source": "a.txt" } });
+        let (url, sent) = serving(vec![
+            (
+                200,
+                json!({ "id": "c1", "configuration_json": { "hnsw": { "space": "l2" } } }),
+            ),
+            (200, json!({ "metadatas": [] })),
+            (
+                200,
+                json!({
+                    "distances": [[0.5]],
+                    "documents": [["one"]],
+                    "metadatas": [[{ "chunk": chunk.to_string() }]],
+                }),
+            ),
+        ])
+        .await;
+        let store = Chroma::open(Some(&url), "test", "model", 2).await.unwrap();
+        assert_eq!(store.space, Space::L2);
+        let found = store
+            .search(vec![1.0, 0.0], 1, Some(&["en".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(found, [(0.75, "a.txt#0".to_string(), chunk)]);
+        let sent = sent.lock().unwrap();
+        assert!(
+            sent[2].1.ends_with("/collections/c1/query"),
+            "{}",
+            sent[2].1
+        );
+        assert_eq!(
+            sent[2].2["where"],
+            json!({ "lang": { "$in": ["en", UNDETERMINED] } })
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_collections_of_another_model() {
+        let (url, _) = serving(vec![
+            (
+                200,
+                json!({ "id": "c1", "metadata": { "hnsw:space": "cosine" } }),
+            ),
+            (
+                200,
+                json!({ "metadatas": [{ "embedding_model": "other" }] }),
+            ),
+        ])
+        .await;
+        let error = Chroma::open(Some(&url), "test", "model", 2)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("model mismatch"), "{}", error);
+    }
+}
@@ -0,0 +1,377 @@
+//! Chunks kept in a Milvus (or Zilliz Cloud) collection, through its
+//! RESTful API (v2). Entities carry the chunk as JSON text, with the fields
+//! searches are filtered by beside it, in the collection's dynamic fields.
+
+use super::{chunk_name, point_id};
+use crate::progress;
+use crate::watch::EmbeddedChunk;
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use tracing::{debug, info};
+
+/// Where Milvus serves its API, unless `--store-url` says otherwise.
+const MILVUS_URL: &str = "http://localhost:19530";
+
+/// Entities written per request.
+const UPSERT_BATCH: usize = 128;
+
+pub struct Milvus {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    collection: String,
+    embedding_model: String,
+}
+
+impl Milvus {
+    /// `collection` on the server at `url`, with the token in
+    /// `MILVUS_TOKEN` (a Zilliz Cloud API key, or `user:password`), if set,
+    /// created for `dimensions`-long vectors compared by cosine similarity
+    /// if it doesn't exist yet. Milvus names hold only letters, digits and
+    /// underscores, so any other character in `collection` becomes `_`.
+    pub async fn open(
+        url: Option<&str>,
+        collection: &str,
+        embedding_model: &str,
+        dimensions: usize,
+    ) -> Result<Self> {
+        let url = url.unwrap_or(MILVUS_URL).trim_end_matches('/');
+        let name: String = collection
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let store = Self {
+            http: reqwest::Client::new(),
+            url: url.to_string(),
+            token: std::env::var("MILVUS_TOKEN").ok(),
+            collection: name.clone(),
+            embedding_model: embedding_model.to_string(),
+        };
+        let has = store
+            .call("/collections/has", json!({}))
+            .await
+            .with_context(|| format!("Could not reach Milvus at {}; is it running?", url))?;
+        if has["has"] != true {
+            info!("Creating Milvus collection {}", name);
+            store
+                .call(
+                    "/collections/create",
+                    json!({
+                        "schema": {
+                            "autoId": false,
+                            "enableDynamicField": true,
+                            "fields": [
+                                {
+                                    "fieldName": "id",
+                                    "dataType": "VarChar",
+                                    "isPrimary": true,
+                                    "elementTypeParams": { "max_length": 64 },
+                                },
+                                {
+                                    "fieldName": "vector",
+                                    "dataType": "FloatVector",
+                                    "elementTypeParams": { "dim": dimensions },
+                                },
+                            ],
+                        },
+                        "indexParams": [{
+                            "fieldName": "vector",
+                            "indexName": "vector",
+                            "metricType": "COSINE",
+                            "indexType": "AUTOINDEX",
+                        }],
+                    }),
+                )
+                .await
+                .with_context(|| format!("Failed to create Milvus collection {}", name))?;
+            return Ok(store);
+        }
+        let info = store
+            .call("/collections/describe", json!({}))
+            .await
+            .with_context(|| format!("Failed to open Milvus collection {}", name))?;
+        let size = info["fields"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|field| field["name"] == "vector")
+            .and_then(|field| {
+                field["params"]
+                    .as_array()?
+                    .iter()
+                    .find(|param| param["key"] == "dim")
+            })
+            .and_then(|param| match &param["value"] {
+                Value::String(text) => text.parse().ok(),
+                value => value.as_u64(),
+            })
+            .with_context(|| {
+                format!(
+                    "Milvus collection {} has no `vector` field; pass another --store-collection",
+                    name
+                )
+            })?;
+        if size as usize != dimensions {
+            bail!(
+                "Embedding dimension mismatch: Milvus collection {} holds {}-dimensional vectors, but {} embeds into {}; pass another --store-collection",
+                name,
+                size,
+                embedding_model,
+                dimensions
+            );
+        }
+        if let Some(metric) = info["indexes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|index| index["fieldName"] == "vector")
+            .and_then(|index| index["metricType"].as_str())
+            .filter(|&metric| metric != "COSINE")
+        {
+            bail!(
+                "Milvus collection {} compares vectors by {}, not cosine similarity; pass another --store-collection",
+                name,
+                metric
+            );
+        }
+        store
+            .call("/collections/load", json!({}))
+            .await
+            .with_context(|| format!("Failed to load Milvus collection {}", name))?;
+        let entities = store
+            .call(
+                "/entities/query",
+                json!({ "filter": "", "limit": 1, "outputFields": ["embedding_model"] }),
+            )
+            .await?;
+        if let Some(model) = entities
+            .pointer("/0/embedding_model")
+            .and_then(Value::as_str)
+            .filter(|&model| model != embedding_model)
+        {
+            bail!(
+                "Embedding model mismatch: Milvus collection {} was embedded with {}, but questions are embedded with {}; pass --embedding-model {}, or another --store-collection",
+                name,
+                model,
+                embedding_model,
+                model
+            );
+        }
+        Ok(store)
+    }
+
+    pub async fn replace(&self, chunks: &[EmbeddedChunk]) -> Result<()> {
+        let sources: BTreeSet<&str> = chunks.iter().filter_map(|(c, _)| c.source()).collect();
+        if !sources.is_empty() {
+            self.call(
+                "/entities/delete",
+                json!({ "filter": format!("source in {}", json!(sources)) }),
+            )
+            .await
+            .context("Failed to remove old chunks from Milvus")?;
+        }
+        let mut entities = Vec::new();
+        for (chunk, embeddings) in chunks {
+            let value = serde_json::to_value(chunk)?;
+            for (i, embedding) in embeddings.iter().enumerate() {
+                // Every entity has a language, empty when it isn't known, as
+                // filters can't test for a missing field.
+                entities.push(json!({
+                    "id": point_id(&value, i),
+                    "vector": embedding.vec,
+                    "chunk": value.to_string(),
+                    "source": chunk.source().unwrap_or_default(),
+                    "lang": chunk.metadata.get("lang").map_or("", String::as_str),
+                    "embedding_model": self.embedding_model,
+                }));
+            }
+        }
+        let progress = progress::start("Writing to Milvus", "entities", entities.len());
+        for batch in entities.chunks(UPSERT_BATCH) {
+            self.call("/entities/upsert", json!({ "data": batch }))
+                .await
+                .context("Failed to write chunks to Milvus")?;
+            progress.advance(batch.len());
+        }
+        debug!("Wrote {} entities to Milvus", entities.len());
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        query: Vec<f64>,
+        wanted: usize,
+        languages: Option<&[String]>,
+    ) -> Result<Vec<(f64, String, Value)>> {
+        let mut request = json!({
+            "data": [query],
+            "annsField": "vector",
+            "limit": wanted,
+            "outputFields": ["chunk"],
+        });
+        // Chunks in no known language are never passed over.
+        if let Some(languages) = languages {
+            let mut languages = languages.to_vec();
+            languages.push(String::new());
+            request["filter"] = json!(format!("lang in {}", json!(languages)));
+        }
+        let found = self
+            .call("/entities/search", request)
+            .await
+            .context("Failed to search Milvus")?;
+        let found = found.as_array().cloned().unwrap_or_default();
+        Ok(found
+            .into_iter()
+            .filter_map(|entity| {
+                let score = entity.get("distance")?.as_f64()?;
+                let chunk: Value = serde_json::from_str(entity.get("chunk")?.as_str()?).ok()?;
+                Some((score, chunk_name(&chunk), chunk))
+            })
+            .collect())
+    }
+
+    /// Sends `body`, for the collection, to `path` under the API and
+    /// returns the `data` of the reply.
+    async fn call(&self, path: &str, mut body: Value) -> Result<Value> {
+        body["collectionName"] = json!(self.collection);
+        let mut request = self
+            .http
+            .post(format!("{}/v2/vectordb{}", self.url, path))
+            .json(&body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("Milvus returned {}: {}", status, body);
+        }
+        // Milvus answers errors with a code in a successful response.
+        let mut reply: Value =
+            serde_json::from_str(&body).context("Unexpected reply from Milvus")?;
+        if reply["code"].as_i64().is_some_and(|code| code != 0) {
+            bail!(
+                "Milvus returned {}: {}",
+                reply["code"],
+                reply["message"].as_str().unwrap_or(&body)
+            );
+        }
+        Ok(reply["data"].take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::fake::serving;
+
+    fn ok(data: Value) -> (u16, Value) {
+        (200, json!({ "code": 0, "data": data }))
+    }
+
+    /// What `/collections/describe` answers with for a collection of
+    /// `dim`-long vectors compared by `metric`.
+    fn described(dim: &str, metric: &str) -> (u16, Value) {
+        ok(json!({
+            "fields": [
+                { "name": "id", "params": [{ "key": "max_length", "value": "64" }] },
+                { "name": "vector", "params": [{ "key": "dim", "value": dim }] },
+            ],
+            "indexes": [{ "fieldName": "vector", "metricType": metric }],
+        }))
+    }
+
+    #[tokio::test]
+    async fn creates_a_missing_collection_with_a_valid_name() {
+        let (url, sent) = serving(vec![ok(json!({ "has": false })), ok(json!({}))]).await;
+        Milvus::open(Some(&url), "rag-my.pdf", "model", 3)
+            .await
+            .unwrap();
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent[1].1, "/v2/vectordb/collections/create");
+        assert_eq!(sent[1].2["collectionName"], "rag_my_pdf");
+        assert_eq!(
+            sent[1].2.pointer("/schema/fields/1/elementTypeParams/dim"),
+            Some(&json!(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn opens_a_collection_made_for_the_model() {
+        let (url, sent) = serving(vec![
+            ok(json!({ "has": true })),
+            described("3", "COSINE"),
+            ok(json!({})),
+            ok(json!([{ "embedding_model": "model" }])),
+        ])
+        .await;
+        Milvus::open(Some(&url), "test", "model", 3).await.unwrap();
+        let paths: Vec<String> = sent.lock().unwrap().iter().map(|s| s.1.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/v2/vectordb/collections/has",
+                "/v2/vectordb/collections/describe",
+                "/v2/vectordb/collections/load",
+                "/v2/vectordb/entities/query",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_collections_it_cant_search() {
+        for (described, expected) in [
+            (described("4", "COSINE"), "dimension mismatch"),
+            (described("3", "L2"), "not cosine similarity"),
+        ] {
+            let (url, _) = serving(vec![ok(json!({ "has": true })), described]).await;
+            let error = Milvus::open(Some(&url), "test", "model", 3)
+                .await
+                .err()
+                .unwrap();
+            assert!(error.to_string().contains(expected), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_error_code() {
+        let (url, _) = serving(vec![(
+            200,
+            json!({ "code": 1100, "message": "collection not found" }),
+        )])
+        .await;
+        let error = Milvus::open(Some(&url), "test", "model", 3)
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            format!("{:#}", error).contains("Milvus returned 1100: collection not found"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn searches_in_the_languages_asked_for() {
+        let chunk = json!({ "text": "one", "index": 1, "metadata": { "source": "a.txt" } });
+        let (url, sent) = serving(vec![ok(json!([
+            { "distance": 0.9, "chunk": chunk.to_string() },
+        ]))])
+        .await;
+        let store = Milvus {
+            http: reqwest::Client::new(),
+            url,
+            token: None,
+            collection: "test".to_string(),
+            embedding_model: "model".to_string(),
+        };
+        let found = store
+            .search(vec![1.0, 0.0], 2, Some(&["en".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(found, [(0.9, "a.txt#1".to_string(), chunk)]);
+        assert_eq!(sent.lock().unwrap()[0].2["filter"], r#"lang in ["en",""]"#);
+    }
+}
@@ -0,0 +1,379 @@
+//! Vector databases the index can be kept in instead of memory, for
+//! `--store`. The chunks are written to the database once they are
+//! embedded, replacing what was kept for their files before, and each
+//! question is answered from what the database holds, which may include
+//! chunks written by other runs and other processes.
+
+mod chroma;
+mod milvus;
+mod pgvector;
+mod pinecone;
+mod qdrant;
+mod redis;
+mod sqlite;
+
+use crate::language::LanguageRoute;
+use crate::loaders::sha256_hex;
+use crate::watch::{self, EmbeddedChunk};
+use anyhow::{Context, Result, bail};
+use chroma::Chroma;
+use milvus::Milvus;
+use pgvector::Pgvector;
+use pinecone::Pinecone;
+use qdrant::Qdrant;
+use redis::Redis;
+use rig::embeddings::EmbeddingModel;
+use rig::vector_store::request::Filter;
+use rig::vector_store::{VectorSearchRequest, VectorStoreError, VectorStoreIndex};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlite::Sqlite;
+use std::collections::{BTreeSet, HashSet};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tracing::debug;
+use whatlang::Lang;
+
+/// Collection chunks are kept in when `--store-collection` isn't given.
+pub const DEFAULT_COLLECTION: &str = "rag-my-pdf";
+
+/// A chunk embedded more than once (with `--summaries`) can match more than
+/// once, and chunks that share a parent come back as one, so this many
+/// times as many matches are asked for as chunks are wanted.
+const FETCH_FACTOR: usize = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StoreKind {
+    /// In memory, built again from the documents (and the caches) each run
+    #[default]
+    Memory,
+    /// A Qdrant collection (https://qdrant.tech), at --store-url, with the
+    /// key in QDRANT_API_KEY if it needs one
+    Qdrant,
+    /// A table in a SQLite database file (rag-my-pdf.db, unless --store-url
+    /// names another), written with the sqlite3 tool, vectors and all. It
+    /// has no vector index: the whole table is read into memory when opened
+    /// and each question is scored against every row, a brute-force scan
+    /// that suits smaller collections
+    Sqlite,
+    /// A Postgres table searched with pgvector, in the database at
+    /// --store-url or DATABASE_URL, through the psql tool
+    #[value(alias = "postgres")]
+    Pgvector,
+    /// A Pinecone index (https://pinecone.io), named by --store-collection,
+    /// with the key in PINECONE_API_KEY, in the --store-namespace given
+    Pinecone,
+    /// A Milvus collection (https://milvus.io), or a Zilliz Cloud one, at
+    /// --store-url, with the token in MILVUS_TOKEN if it needs one
+    #[value(alias = "zilliz")]
+    Milvus,
+    /// Redis hashes searched with a RediSearch HNSW index, as Redis Stack
+    /// serves them, at --store-url
+    Redis,
+    /// A Chroma collection (https://trychroma.com), on the server at
+    /// --store-url, with the token in CHROMA_API_KEY if it needs one
+    Chroma,
+}
+
+/// A vector database holding the chunks, in the collection named when it
+/// was opened.
+pub enum Store {
+    Qdrant(Qdrant),
+    Sqlite(Sqlite),
+    Pgvector(Pgvector),
+    Pinecone(Pinecone),
+    Milvus(Milvus),
+    Redis(Redis),
+    Chroma(Chroma),
+}
+
+impl Store {
+    /// Opens `collection` in the database `kind` names, at `url` (a path,
+    /// for SQLite, or a connection string, for Postgres) or where it is
+    /// served by default, creating it for vectors `dimensions` long if it
+    /// doesn't exist. Where the database has namespaces (Pinecone), the
+    /// chunks are kept in `namespace`. A collection holding vectors of another size,
+    /// or from a model other than `embedding_model`, isn't opened, as its
+    /// chunks couldn't be found with this model's questions. Returns `None`
+    /// for the in-memory index.
+    pub async fn open(
+        kind: StoreKind,
+        url: Option<&str>,
+        collection: &str,
+        namespace: Option<&str>,
+        embedding_model: &str,
+        dimensions: usize,
+    ) -> Result<Option<Self>> {
+        Ok(match kind {
+            StoreKind::Memory => None,
+            StoreKind::Qdrant => Some(Store::Qdrant(
+                Qdrant::open(url, collection, embedding_model, dimensions).await?,
+            )),
+            StoreKind::Sqlite => Some(Store::Sqlite(
+                Sqlite::open(url, collection, embedding_model, dimensions).await?,
+            )),
+            StoreKind::Pgvector => Some(Store::Pgvector(
+                Pgvector::open(url, collection, embedding_model, dimensions).await?,
+            )),
+            StoreKind::Pinecone => Some(Store::Pinecone(
+                Pinecone::open(url, collection, namespace, embedding_model, dimensions).await?,
+            )),
+            StoreKind::Milvus => Some(Store::Milvus(
+                Milvus::open(url, collection, embedding_model, dimensions).await?,
+            )),
+            StoreKind::Redis => Some(Store::Redis(
+                Redis::open(url, collection, embedding_model, dimensions).await?,
+            )),
+            StoreKind::Chroma => Some(Store::Chroma(
+                Chroma::open(url, collection, embedding_model, dimensions).await?,
+            )),
+        })
+    }
+
+    /// Writes `chunks`, first removing what was kept for their sources, so
+    /// chunks of a file that changed don't linger.
+    pub async fn replace(&self, chunks: &[EmbeddedChunk]) -> Result<()> {
+        match self {
+            Store::Qdrant(store) => store.replace(chunks).await,
+            Store::Sqlite(store) => store.replace(chunks).await,
+            Store::Pgvector(store) => store.replace(chunks).await,
+            Store::Pinecone(store) => store.replace(chunks).await,
+            Store::Milvus(store) => store.replace(chunks).await,
+            Store::Redis(store) => store.replace(chunks).await,
+            Store::Chroma(store) => store.replace(chunks).await,
+        }
+    }
+
+    /// The `wanted` chunks nearest `query`, best first, with their scores
+    /// and ids, as JSON. With `languages`, only chunks in them (or in no
+    /// known language) are searched.
+    async fn search(
+        &self,
+        query: Vec<f64>,
+        wanted: usize,
+        languages: Option<&[String]>,
+    ) -> Result<Vec<(f64, String, Value)>> {
+        match self {
+            Store::Qdrant(store) => store.search(query, wanted, languages).await,
+            Store::Sqlite(store) => store.search(query, wanted, languages).await,
+            Store::Pgvector(store) => store.search(query, wanted, languages).await,
+            Store::Pinecone(store) => store.search(query, wanted, languages).await,
+            Store::Milvus(store) => store.search(query, wanted, languages).await,
+            Store::Redis(store) => store.search(query, wanted, languages).await,
+            Store::Chroma(store) => store.search(query, wanted, languages).await,
+        }
+    }
+}
+
+/// An index over a [`Store`], answering questions embedded by `model`.
+pub struct StoreIndex<M> {
+    model: M,
+    store: Store,
+    route: LanguageRoute,
+    /// The languages of the chunks written this run, which are all that
+    /// questions are told apart by.
+    languages: Vec<Lang>,
+}
+
+impl<M: EmbeddingModel> StoreIndex<M> {
+    /// An index of `store`, retrieving only chunks in the languages `route`
+    /// picks for each question out of those of `chunks`.
+    pub fn new(model: M, store: Store, route: LanguageRoute, chunks: &[EmbeddedChunk]) -> Self {
+        let languages: BTreeSet<&str> = chunks
+            .iter()
+            .filter_map(|(chunk, _)| chunk.metadata.get("lang"))
+            .map(String::as_str)
+            .collect();
+        Self {
+            model,
+            store,
+            route,
+            languages: languages.into_iter().filter_map(Lang::from_code).collect(),
+        }
+    }
+}
+
+impl<M: EmbeddingModel + Sync> VectorStoreIndex for StoreIndex<M> {
+    type Filter = Filter<Value>;
+
+    /// As with the in-memory index, chunks in the wrong language for the
+    /// question are passed over, and chunks with a parent come back as the
+    /// parent, once.
+    async fn top_n<T: for<'a> Deserialize<'a> + Send>(
+        &self,
+        req: VectorSearchRequest<Self::Filter>,
+    ) -> Result<Vec<(f64, String, T)>, VectorStoreError> {
+        let languages = self.route.languages(req.query(), &self.languages);
+        let samples = req.samples() as usize;
+        let query = self.model.embed_text(req.query()).await?.vec;
+        let mut found = self
+            .store
+            .search(query, samples * FETCH_FACTOR, languages.as_deref())
+            .await
+            .map_err(|e| VectorStoreError::DatastoreError(e.into()))?;
+        // A chunk matched by more than one of its vectors counts once, at
+        // its best.
+        let mut seen = HashSet::new();
+        found.retain(|(_, _, chunk)| seen.insert(chunk.to_string()));
+        debug!(
+            "Closest chunks: {}",
+            found
+                .iter()
+                .map(|(score, id, _)| format!("{} ({:.3})", id, score))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        watch::pick(found, None, samples)
+            .into_iter()
+            .map(|(score, id, chunk)| Ok((score, id, serde_json::from_value(chunk)?)))
+            .collect()
+    }
+
+    async fn top_n_ids(
+        &self,
+        req: VectorSearchRequest<Self::Filter>,
+    ) -> Result<Vec<(f64, String)>, VectorStoreError> {
+        let found = self.top_n::<Value>(req).await?;
+        Ok(found
+            .into_iter()
+            .map(|(score, id, _)| (score, id))
+            .collect())
+    }
+}
+
+/// What a chunk is known by in the stores: its id, from its content and
+/// which of its vectors this is, shaped as a UUID, which every store takes.
+fn point_id(chunk: &Value, vector: usize) -> String {
+    let hex = sha256_hex(format!("{}#{}", chunk, vector).as_bytes());
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// How a chunk is named in the debug log and the agent's context: its
+/// source and place in it.
+fn chunk_name(chunk: &Value) -> String {
+    let source = chunk
+        .pointer("/metadata/source")
+        .and_then(Value::as_str)
+        .unwrap_or("document");
+    let index = chunk.get("index").and_then(Value::as_u64).unwrap_or(0);
+    format!("{}#{}", source, index)
+}
+
+/// Runs `command`, a database's command line tool, with `script` on its
+/// stdin, and returns what it printed. `install` says what to install to
+/// have the tool. The tool runs on a blocking thread, so the runtime goes on
+/// with other work while it does.
+async fn run_script(mut command: Command, script: String, install: &str) -> Result<String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let install = install.to_string();
+    tokio::task::spawn_blocking(move || {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run `{}`; install {}", program, install))?;
+        child
+            .stdin
+            .take()
+            .with_context(|| format!("Failed to write to `{}`", program))?
+            .write_all(script.as_bytes())?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!(
+                "`{}` failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    })
+    .await?
+}
+
+/// `name` as an SQL identifier.
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// `text` as an SQL string literal.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+/// A fake JSON API for the stores' tests.
+#[cfg(test)]
+mod fake {
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A request the server was sent: its method, path and query, and body.
+    pub type Sent = (String, String, Value);
+
+    /// A server answering each request in turn with the next of `replies`,
+    /// a status and a body (none for null), at the URL returned along with
+    /// the requests it has been sent.
+    pub async fn serving(replies: Vec<(u16, Value)>) -> (String, Arc<Mutex<Vec<Sent>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let log = sent.clone();
+        tokio::spawn(async move {
+            for (status, body) in replies {
+                let (mut connection, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let end = loop {
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                    let mut buf = [0; 4096];
+                    match connection.read(&mut buf).await.unwrap() {
+                        0 => return,
+                        n => request.extend_from_slice(&buf[..n]),
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..end]).to_string();
+                let length: usize = head
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.trim().parse().ok())
+                    .unwrap_or(0);
+                while request.len() < end + length {
+                    let mut buf = [0; 4096];
+                    match connection.read(&mut buf).await.unwrap() {
+                        0 => break,
+                        n => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let mut words = head.split(' ');
+                let method = words.next().unwrap_or_default().to_string();
+                let path = words.next().unwrap_or_default().to_string();
+                let received = serde_json::from_slice(&request[end..]).unwrap_or(Value::Null);
+                log.lock().unwrap().push((method, path, received));
+                let body = if body.is_null() {
+                    String::new()
+                } else {
+                    body.to_string()
+                };
+                let reply = format!(
+                    "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                connection.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (url, sent)
+    }
+}
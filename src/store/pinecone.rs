@@ -0,0 +1,438 @@
+//! Chunks kept in a Pinecone index, through its REST API. Pinecone keeps
+//! only flat metadata, so each vector carries its chunk as JSON text, with
+//! the fields searches are filtered by beside it.
+
+use super::{chunk_name, point_id};
+use crate::loaders::sha256_hex;
+use crate::progress;
+use crate::watch::EmbeddedChunk;
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Pinecone's control plane, which finds (and creates) indexes by name.
+const PINECONE_API: &str = "https://api.pinecone.io";
+
+/// The version of the API the requests are written for.
+const API_VERSION: &str = "2025-01";
+
+/// Where indexes are created when they don't exist: the serverless region
+/// every plan, the free one included, can use.
+const CLOUD: &str = "aws";
+const REGION: &str = "us-east-1";
+
+/// Vectors written per request; Pinecone takes at most 2MB at once.
+const UPSERT_BATCH: usize = 100;
+
+/// Vectors deleted per request, the most Pinecone takes.
+const DELETE_BATCH: usize = 1000;
+
+pub struct Pinecone {
+    http: reqwest::Client,
+    /// The index's own host, which serves its vectors.
+    host: String,
+    key: String,
+    /// The namespace of the index the chunks are kept in; the empty one
+    /// is Pinecone's default.
+    namespace: String,
+    embedding_model: String,
+}
+
+impl Pinecone {
+    /// The index `name`, with the key in `PINECONE_API_KEY`, created as a
+    /// serverless index for `dimensions`-long vectors compared by cosine
+    /// similarity if it doesn't exist yet. With `host`, the index served
+    /// there is used without looking it up. Chunks are kept in
+    /// `namespace`, so one index can hold several corpora apart.
+    pub async fn open(
+        host: Option<&str>,
+        name: &str,
+        namespace: Option<&str>,
+        embedding_model: &str,
+        dimensions: usize,
+    ) -> Result<Self> {
+        let key =
+            std::env::var("PINECONE_API_KEY").context("--store pinecone needs PINECONE_API_KEY")?;
+        let mut store = Self {
+            http: reqwest::Client::new(),
+            host: String::new(),
+            key,
+            namespace: namespace.unwrap_or_default().to_string(),
+            embedding_model: embedding_model.to_string(),
+        };
+        store.host = match host {
+            Some(host) => host.trim_end_matches('/').to_string(),
+            None => store.find(name, dimensions).await?,
+        };
+        let stats = store
+            .call(reqwest::Method::POST, "/describe_index_stats", json!({}))
+            .await
+            .with_context(|| format!("Failed to open Pinecone index {}", name))?;
+        if let Some(size) = stats
+            .get("dimension")
+            .and_then(Value::as_u64)
+            .filter(|&size| size as usize != dimensions)
+        {
+            bail!(
+                "Embedding dimension mismatch: Pinecone index {} holds {}-dimensional vectors, but {} embeds into {}; pass another --store-collection",
+                name,
+                size,
+                embedding_model,
+                dimensions
+            );
+        }
+        let ids = store.list("", Some(1)).await?;
+        if let Some(id) = ids.first() {
+            let fetched = store
+                .get(
+                    "/vectors/fetch",
+                    &[("ids", id), ("namespace", &store.namespace)],
+                )
+                .await?;
+            if let Some(model) = fetched
+                .pointer(&format!("/vectors/{}/metadata/embedding_model", id))
+                .and_then(Value::as_str)
+                .filter(|&model| model != embedding_model)
+            {
+                bail!(
+                    "Embedding model mismatch: Pinecone index {} was embedded with {}, but questions are embedded with {}; pass --embedding-model {}, or another --store-namespace",
+                    name,
+                    model,
+                    embedding_model,
+                    model
+                );
+            }
+        }
+        Ok(store)
+    }
+
+    pub async fn replace(&self, chunks: &[EmbeddedChunk]) -> Result<()> {
+        // Serverless indexes can't delete by metadata, so a file's vectors
+        // are found by the prefix their ids share.
+        let sources: BTreeSet<&str> = chunks.iter().filter_map(|(c, _)| c.source()).collect();
+        let mut old = Vec::new();
+        for source in sources {
+            old.extend(self.list(&source_prefix(Some(source)), None).await?);
+        }
+        for batch in old.chunks(DELETE_BATCH) {
+            self.call(
+                reqwest::Method::POST,
+                "/vectors/delete",
+                json!({ "ids": batch, "namespace": self.namespace }),
+            )
+            .await
+            .context("Failed to remove old chunks from Pinecone")?;
+        }
+        let mut vectors = Vec::new();
+        for (chunk, embeddings) in chunks {
+            let value = serde_json::to_value(chunk)?;
+            let mut metadata = json!({
+                "chunk": value.to_string(),
+                "embedding_model": self.embedding_model,
+            });
+            for field in ["source", "lang"] {
+                if let Some(text) = chunk.metadata.get(field) {
+                    metadata[field] = json!(text);
+                }
+            }
+            for (i, embedding) in embeddings.iter().enumerate() {
+                vectors.push(json!({
+                    "id": format!("{}{}", source_prefix(chunk.source()), point_id(&value, i)),
+                    "values": embedding.vec,
+                    "metadata": metadata,
+                }));
+            }
+        }
+        let progress = progress::start("Writing to Pinecone", "vectors", vectors.len());
+        for batch in vectors.chunks(UPSERT_BATCH) {
+            self.call(
+                reqwest::Method::POST,
+                "/vectors/upsert",
+                json!({ "vectors": batch, "namespace": self.namespace }),
+            )
+            .await
+            .context("Failed to write chunks to Pinecone")?;
+            progress.advance(batch.len());
+        }
+        debug!(
+            "Wrote {} vectors to Pinecone, removing {}",
+            vectors.len(),
+            old.len()
+        );
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        query: Vec<f64>,
+        wanted: usize,
+        languages: Option<&[String]>,
+    ) -> Result<Vec<(f64, String, Value)>> {
+        let mut request = json!({
+            "vector": query,
+            "topK": wanted,
+            "includeMetadata": true,
+            "namespace": self.namespace,
+        });
+        // Chunks in no known language are never passed over.
+        if let Some(languages) = languages {
+            request["filter"] = json!({
+                "$or": [{ "lang": { "$in": languages } }, { "lang": { "$exists": false } }]
+            });
+        }
+        let found = self
+            .call(reqwest::Method::POST, "/query", request)
+            .await
+            .context("Failed to search Pinecone")?;
+        let matches = found["matches"].as_array().cloned().unwrap_or_default();
+        Ok(matches
+            .into_iter()
+            .filter_map(|found| {
+                let score = found.get("score")?.as_f64()?;
+                let chunk: Value =
+                    serde_json::from_str(found.pointer("/metadata/chunk")?.as_str()?).ok()?;
+                Some((score, chunk_name(&chunk), chunk))
+            })
+            .collect())
+    }
+
+    /// The host of the index `name`, which is created, and waited on until
+    /// it is ready, if it doesn't exist.
+    async fn find(&self, name: &str, dimensions: usize) -> Result<String> {
+        let url = format!("{}/indexes/{}", PINECONE_API, name);
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .send()
+            .await
+            .context("Could not reach Pinecone")?;
+        let mut index = if response.status() == reqwest::StatusCode::NOT_FOUND {
+            info!("Creating Pinecone index {}", name);
+            let response = self
+                .request(reqwest::Method::POST, &format!("{}/indexes", PINECONE_API))
+                .json(&json!({
+                    "name": name,
+                    "dimension": dimensions,
+                    "metric": "cosine",
+                    "spec": { "serverless": { "cloud": CLOUD, "region": REGION } },
+                }))
+                .send()
+                .await?;
+            reply(response)
+                .await
+                .with_context(|| format!("Failed to create Pinecone index {}", name))?
+        } else {
+            reply(response)
+                .await
+                .with_context(|| format!("Failed to look up Pinecone index {}", name))?
+        };
+        while index.pointer("/status/ready") != Some(&Value::Bool(true)) {
+            debug!("Waiting for Pinecone index {} to be ready", name);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            index = reply(self.request(reqwest::Method::GET, &url).send().await?).await?;
+        }
+        if index["metric"] != "cosine" {
+            bail!(
+                "Pinecone index {} compares vectors by {}, not cosine similarity; pass another --store-collection",
+                name,
+                index["metric"]
+            );
+        }
+        let host = index["host"]
+            .as_str()
+            .with_context(|| format!("Pinecone didn't say where index {} is served", name))?;
+        Ok(format!("https://{}", host))
+    }
+
+    /// The ids in the namespace starting with `prefix`, all of them unless
+    /// `limit` says how many are wanted.
+    async fn list(&self, prefix: &str, limit: Option<usize>) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let limit_text = limit.map(|limit| limit.to_string());
+            let mut query = vec![("namespace", self.namespace.as_str())];
+            if !prefix.is_empty() {
+                query.push(("prefix", prefix));
+            }
+            if let Some(limit) = &limit_text {
+                query.push(("limit", limit));
+            }
+            if let Some(token) = &token {
+                query.push(("paginationToken", token));
+            }
+            let page = self
+                .get("/vectors/list", &query)
+                .await
+                .context("Failed to list the vectors in Pinecone")?;
+            ids.extend(
+                page["vectors"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|vector| vector["id"].as_str().map(String::from)),
+            );
+            token = page
+                .pointer("/pagination/next")
+                .and_then(Value::as_str)
+                .map(String::from);
+            if token.is_none() || limit.is_some_and(|limit| ids.len() >= limit) {
+                return Ok(ids);
+            }
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, url)
+            .header("Api-Key", &self.key)
+            .header("X-Pinecone-API-Version", API_VERSION)
+    }
+
+    /// Sends `body` to `path` on the index's host and returns the reply.
+    async fn call(&self, method: reqwest::Method, path: &str, body: Value) -> Result<Value> {
+        let url = format!("{}{}", self.host, path);
+        reply(self.request(method, &url).json(&body).send().await?).await
+    }
+
+    /// Asks `path` on the index's host with `query` and returns the reply.
+    async fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<Value> {
+        let url = format!("{}{}", self.host, path);
+        let request = self.request(reqwest::Method::GET, &url).query(query);
+        reply(request.send().await?).await
+    }
+}
+
+/// The start of the ids of a file's vectors, from a digest of its name, so
+/// they can be listed when it is written again.
+fn source_prefix(source: Option<&str>) -> String {
+    match source {
+        Some(source) => format!("{}#", &sha256_hex(source.as_bytes())[..16]),
+        None => String::new(),
+    }
+}
+
+async fn reply(response: reqwest::Response) -> Result<Value> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        bail!("Pinecone returned {}: {}", status, body);
+    }
+    if body.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_str(&body).context("Unexpected reply from Pinecone")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::Chunk;
+    use crate::store::fake::serving;
+    use rig::OneOrMany;
+    use rig::embeddings::Embedding;
+
+    fn store(host: &str) -> Pinecone {
+        Pinecone {
+            http: reqwest::Client::new(),
+            host: host.to_string(),
+            key: "key".to_string(),
+            namespace: "docs".to_string(),
+            embedding_model: "model".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_every_page_of_ids() {
+        let (host, sent) = serving(vec![
+            (
+                200,
+                json!({ "vectors": [{ "id": "a" }], "pagination": { "next": "more" } }),
+            ),
+            (200, json!({ "vectors": [{ "id": "b" }] })),
+        ])
+        .await;
+        let ids = store(&host).list("p#", None).await.unwrap();
+        assert_eq!(ids, ["a", "b"]);
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent[0].1, "/vectors/list?namespace=docs&prefix=p%23");
+        assert_eq!(
+            sent[1].1,
+            "/vectors/list?namespace=docs&prefix=p%23&paginationToken=more"
+        );
+    }
+
+    #[tokio::test]
+    async fn replaces_a_files_vectors_by_their_prefix() {
+        let (host, sent) = serving(vec![
+            (200, json!({ "vectors": [{ "id": "old" }] })),
+            (200, json!({})),
+            (200, json!({ "upsertedCount": 1 })),
+        ])
+        .await;
+        let chunk = Chunk {
+            text: "one".to_string(),
+            metadata: [
+                ("source".to_string(), "a.txt".to_string()),
+                ("lang".to_string(), "en".to_string()),
+            ]
+            .into(),
+            index: 0,
+            char_range: 0..0,
+            parent: None,
+            summary: None,
+            document: 0,
+            header: None,
+        };
+        let embedding = Embedding {
+            document: "one".to_string(),
+            vec: vec![1.0, 0.0],
+        };
+        store(&host)
+            .replace(&[(chunk.clone(), OneOrMany::one(embedding))])
+            .await
+            .unwrap();
+        let sent = sent.lock().unwrap();
+        let prefix = source_prefix(Some("a.txt"));
+        assert!(
+            sent[0]
+                .1
+                .ends_with(&format!("prefix={}", prefix.replace('#', "%23")))
+        );
+        assert_eq!(sent[1].1, "/vectors/delete");
+        assert_eq!(sent[1].2, json!({ "ids": ["old"], "namespace": "docs" }));
+        assert_eq!(sent[2].1, "/vectors/upsert");
+        let vector = &sent[2].2["vectors"][0];
+        assert!(vector["id"].as_str().unwrap().starts_with(&prefix));
+        assert_eq!(vector["metadata"]["source"], "a.txt");
+        assert_eq!(vector["metadata"]["lang"], "en");
+        let kept: Chunk =
+            serde_json::from_str(vector["metadata"]["chunk"].as_str().unwrap()).unwrap();
+        assert_eq!(kept.text, chunk.text);
+    }
+
+    #[tokio::test]
+    async fn searches_in_the_languages_asked_for() {
+        let chunk = json!({ "text": "one", "index": 4, "metadata": { "source": "a.txt" } });
+        let (host, sent) = serving(vec![(
+            200,
+            json!({ "matches": [
+                { "score": 0.75, "metadata": { "chunk": chunk.to_string() } },
+                { "score": 0.5, "metadata": { "source": "written elsewhere" } },
+            ] }),
+        )])
+        .await;
+        let found = store(&host)
+            .search(vec![1.0, 0.0], 2, Some(&["en".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(found, [(0.75, "a.txt#4".to_string(), chunk)]);
+        let request = &sent.lock().unwrap()[0].2;
+        assert_eq!(request["namespace"], "docs");
+        assert_eq!(
+            request["filter"],
+            json!({ "$or": [{ "lang": { "$in": ["en"] } }, { "lang": { "$exists": false } }] })
+        );
+    }
+}
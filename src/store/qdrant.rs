@@ -0,0 +1,327 @@
+//! Chunks kept in a Qdrant collection, through its REST API.
+
+use super::{chunk_name, point_id};
+use crate::progress;
+use crate::watch::EmbeddedChunk;
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use tracing::{debug, info};
+
+/// Where Qdrant serves its REST API, unless `--store-url` says otherwise.
+const QDRANT_URL: &str = "http://localhost:6333";
+
+/// Points written per request.
+const UPSERT_BATCH: usize = 128;
+
+pub struct Qdrant {
+    http: reqwest::Client,
+    /// The collection's URL.
+    url: String,
+    key: Option<String>,
+    embedding_model: String,
+}
+
+impl Qdrant {
+    /// `collection` on the server at `url`, with the key in
+    /// `QDRANT_API_KEY`, if set, created for `dimensions`-long vectors
+    /// compared by cosine similarity if it doesn't exist yet.
+    pub async fn open(
+        url: Option<&str>,
+        collection: &str,
+        embedding_model: &str,
+        dimensions: usize,
+    ) -> Result<Self> {
+        let url = url.unwrap_or(QDRANT_URL).trim_end_matches('/');
+        let store = Self {
+            http: reqwest::Client::new(),
+            url: format!("{}/collections/{}", url, collection),
+            key: std::env::var("QDRANT_API_KEY").ok(),
+            embedding_model: embedding_model.to_string(),
+        };
+        let response = store
+            .request(reqwest::Method::GET, "")
+            .send()
+            .await
+            .with_context(|| format!("Could not reach Qdrant at {}; is it running?", url))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            info!("Creating Qdrant collection {}", collection);
+            store
+                .call(
+                    reqwest::Method::PUT,
+                    "",
+                    json!({ "vectors": { "size": dimensions, "distance": "Cosine" } }),
+                )
+                .await
+                .with_context(|| format!("Failed to create Qdrant collection {}", collection))?;
+            return Ok(store);
+        }
+        let info = result(response)
+            .await
+            .with_context(|| format!("Failed to open Qdrant collection {}", collection))?;
+        let size = info
+            .pointer("/config/params/vectors/size")
+            .and_then(Value::as_u64)
+            .context("Qdrant collections with named vectors aren't supported")?;
+        if size as usize != dimensions {
+            bail!(
+                "Embedding dimension mismatch: Qdrant collection {} holds {}-dimensional vectors, but {} embeds into {}; pass another --store-collection",
+                collection,
+                size,
+                embedding_model,
+                dimensions
+            );
+        }
+        let points = store
+            .call(
+                reqwest::Method::POST,
+                "/points/scroll",
+                json!({ "limit": 1, "with_payload": ["embedding_model"], "with_vector": false }),
+            )
+            .await?;
+        if let Some(model) = points
+            .pointer("/points/0/payload/embedding_model")
+            .and_then(Value::as_str)
+            .filter(|&model| model != embedding_model)
+        {
+            bail!(
+                "Embedding model mismatch: Qdrant collection {} was embedded with {}, but questions are embedded with {}; pass --embedding-model {}, or another --store-collection",
+                collection,
+                model,
+                embedding_model,
+                model
+            );
+        }
+        Ok(store)
+    }
+
+    pub async fn replace(&self, chunks: &[EmbeddedChunk]) -> Result<()> {
+        let sources: BTreeSet<&str> = chunks.iter().filter_map(|(c, _)| c.source()).collect();
+        if !sources.is_empty() {
+            self.call(
+                reqwest::Method::POST,
+                "/points/delete?wait=true",
+                json!({
+                    "filter": { "must": [{ "key": "metadata.source", "match": { "any": sources } }] }
+                }),
+            )
+            .await
+            .context("Failed to remove old chunks from Qdrant")?;
+        }
+        let mut points = Vec::new();
+        for (chunk, embeddings) in chunks {
+            let chunk = serde_json::to_value(chunk)?;
+            let mut payload = chunk.clone();
+            payload["embedding_model"] = json!(self.embedding_model);
+            for (i, embedding) in embeddings.iter().enumerate() {
+                points.push(json!({
+                    "id": point_id(&chunk, i),
+                    "vector": embedding.vec,
+                    "payload": payload,
+                }));
+            }
+        }
+        let progress = progress::start("Writing to Qdrant", "points", points.len());
+        for batch in points.chunks(UPSERT_BATCH) {
+            self.call(
+                reqwest::Method::PUT,
+                "/points?wait=true",
+                json!({ "points": batch }),
+            )
+            .await
+            .context("Failed to write chunks to Qdrant")?;
+            progress.advance(batch.len());
+        }
+        debug!("Wrote {} points to Qdrant", points.len());
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        query: Vec<f64>,
+        wanted: usize,
+        languages: Option<&[String]>,
+    ) -> Result<Vec<(f64, String, Value)>> {
+        let mut request = json!({ "vector": query, "limit": wanted, "with_payload": true });
+        // Chunks in no known language are never passed over.
+        if let Some(languages) = languages {
+            request["filter"] = json!({
+                "should": [
+                    { "key": "metadata.lang", "match": { "any": languages } },
+                    { "is_empty": { "key": "metadata.lang" } },
+                ]
+            });
+        }
+        let found = self
+            .call(reqwest::Method::POST, "/points/search", request)
+            .await
+            .context("Failed to search Qdrant")?;
+        let found = found.as_array().cloned().unwrap_or_default();
+        Ok(found
+            .into_iter()
+            .filter_map(|mut point| {
+                let score = point.get("score")?.as_f64()?;
+                let mut chunk = point.get_mut("payload")?.take();
+                chunk.as_object_mut()?.remove("embedding_model");
+                Some((score, chunk_name(&chunk), chunk))
+            })
+            .collect())
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.url, path));
+        match &self.key {
+            Some(key) => request.header("api-key", key),
+            None => request,
+        }
+    }
+
+    /// Sends `body` to `path` under the collection and returns the `result`
+    /// of the reply.
+    async fn call(&self, method: reqwest::Method, path: &str, body: Value) -> Result<Value> {
+        let response = self.request(method, path).json(&body).send().await?;
+        result(response).await
+    }
+}
+
+async fn result(response: reqwest::Response) -> Result<Value> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        bail!("Qdrant returned {}: {}", status, body);
+    }
+    let mut reply: Value = serde_json::from_str(&body).context("Unexpected reply from Qdrant")?;
+    Ok(reply["result"].take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::Chunk;
+    use crate::store::fake::serving;
+    use rig::OneOrMany;
+    use rig::embeddings::Embedding;
+
+    fn store(url: &str) -> Qdrant {
+        Qdrant {
+            http: reqwest::Client::new(),
+            url: format!("{}/collections/test", url),
+            key: None,
+            embedding_model: "model".to_string(),
+        }
+    }
+
+    fn embedded(source: &str, text: &str) -> EmbeddedChunk {
+        let chunk = Chunk {
+            text: text.to_string(),
+            metadata: [("source".to_string(), source.to_string())].into(),
+            index: 0,
+            char_range: 0..0,
+            parent: None,
+            summary: None,
+            document: 0,
+            header: None,
+        };
+        let embedding = Embedding {
+            document: text.to_string(),
+            vec: vec![1.0, 0.0],
+        };
+        (chunk, OneOrMany::one(embedding))
+    }
+
+    #[tokio::test]
+    async fn creates_a_missing_collection() {
+        let (url, sent) = serving(vec![
+            (404, json!({ "status": { "error": "Not found" } })),
+            (200, json!({ "result": true })),
+        ])
+        .await;
+        Qdrant::open(Some(&url), "test", "model", 3).await.unwrap();
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent[1].0, "PUT");
+        assert_eq!(sent[1].1, "/collections/test");
+        assert_eq!(
+            sent[1].2,
+            json!({ "vectors": { "size": 3, "distance": "Cosine" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_collections_it_cant_search() {
+        let collection =
+            json!({ "result": { "config": { "params": { "vectors": { "size": 3 } } } } });
+        let (url, _) = serving(vec![(200, collection.clone())]).await;
+        let error = Qdrant::open(Some(&url), "test", "model", 4)
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("dimension mismatch"),
+            "{}",
+            error
+        );
+
+        let points =
+            json!({ "result": { "points": [{ "payload": { "embedding_model": "other" } }] } });
+        let (url, _) = serving(vec![(200, collection), (200, points)]).await;
+        let error = Qdrant::open(Some(&url), "test", "model", 3)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("model mismatch"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn deletes_old_chunks_then_writes_new_ones() {
+        let (url, sent) = serving(vec![
+            (200, json!({ "result": {} })),
+            (200, json!({ "result": {} })),
+        ])
+        .await;
+        store(&url)
+            .replace(&[embedded("a.txt", "one"), embedded("b.txt", "two")])
+            .await
+            .unwrap();
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent[0].1, "/collections/test/points/delete?wait=true");
+        assert_eq!(
+            sent[0].2.pointer("/filter/must/0/match/any"),
+            Some(&json!(["a.txt", "b.txt"]))
+        );
+        assert_eq!(sent[1].1, "/collections/test/points?wait=true");
+        let points = sent[1].2["points"].as_array().unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0]["payload"]["text"], "one");
+        assert_eq!(points[0]["payload"]["embedding_model"], "model");
+    }
+
+    #[tokio::test]
+    async fn searches_in_the_languages_asked_for() {
+        let (url, sent) = serving(vec![(
+            200,
+            json!({ "result": [{
+                "score": 0.5,
+                "payload": { "text": "one", "index": 2, "metadata": { "source": "a.txt" }, "embedding_model": "model" },
+            }] }),
+        )])
+        .await;
+        let found = store(&url)
+            .search(vec![1.0, 0.0], 3, Some(&["en".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(
+            found,
+            [(
+                0.5,
+                "a.txt#2".to_string(),
+                json!({ "text": "one", "index": 2, "metadata": { "source": "a.txt" } })
+            )]
+        );
+        let request = &sent.lock().unwrap()[0].2;
+        assert_eq!(request["limit"], 3);
+        assert_eq!(
+            request.pointer("/filter/should/0/match/any"),
+            Some(&json!(["en"]))
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Chunks kept in a SQLite database file, through the `sqlite3` command
+//! line tool, with their vectors as little-endian 32-bit float blobs. The
+//! search is a brute-force scan rather than a vector index: no extension
+//! is loaded, so every row is read in when the file is opened and each
+//! question is scored against all of them in memory.
+
+use super::{chunk_name, identifier, point_id, quote, run_script};
+use crate::quantize::{Query, Vector};
+use crate::watch::EmbeddedChunk;
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tokio::sync::RwLock;
+use tracing::debug;
+
+/// Database file used when `--store-url` isn't given.
+const SQLITE_PATH: &str = "rag-my-pdf.db";
+
+pub struct Sqlite {
+    path: PathBuf,
+    /// The table's name, quoted for SQL.
+    table: String,
+    embedding_model: String,
+    /// Every chunk in the table, with its vector.
+    rows: RwLock<Vec<(Value, Vector)>>,
+}
+
+#[derive(Deserialize)]
+struct Row {
+    chunk: String,
+    embedding_model: String,
+    vector: String,
+}
+
+impl Sqlite {
+    /// The table `collection` in the database at `path`, both created if
+    /// they don't exist, read in whole.
+    pub async fn open(
+        path: Option<&str>,
+        collection: &str,
+        embedding_model: &str,
+        dimensions: usize,
+    ) -> Result<Self> {
+        let path = PathBuf::from(path.unwrap_or(SQLITE_PATH));
+        let table = identifier(collection);
+        let index = identifier(&format!("{}_source", collection));
+        sqlite3(
+            &path,
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    id TEXT PRIMARY KEY,
+                    source TEXT,
+                    text TEXT NOT NULL,
+                    chunk TEXT NOT NULL,
+                    embedding_model TEXT NOT NULL,
+                    vector BLOB NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS {} ON {} (source);",
+                table, index, table
+            ),
+        )
+        .await
+        .with_context(|| format!("Failed to open {:?}", path))?;
+        let output = sqlite3(
+            &path,
+            &format!(
+                "SELECT chunk, embedding_model, hex(vector) AS vector FROM {};",
+                table
+            ),
+        )
+        .await
+        .with_context(|| format!("Failed to read {:?}", path))?;
+        // No rows print nothing rather than an empty array.
+        let found: Vec<Row> = match output.trim() {
+            "" => Vec::new(),
+            output => serde_json::from_str(output)
+                .with_context(|| format!("Unexpected rows in {:?}", path))?,
+        };
+        let mut rows = Vec::with_capacity(found.len());
+        for row in found {
+            if row.embedding_model != embedding_model {
+                bail!(
+                    "Embedding model mismatch: the chunks in {:?} were embedded with {}, but questions are embedded with {}; pass --embedding-model {}, or another --store-collection",
+                    path,
+                    row.embedding_model,
+                    embedding_model,
+                    row.embedding_model
+                );
+            }
+            let vec = from_hex(&row.vector)
+                .with_context(|| format!("A vector in {:?} isn't 32-bit floats", path))?;
+            if vec.len() != dimensions {
+                bail!(
+                    "Embedding dimension mismatch: {:?} holds {}-dimensional vectors, but {} embeds into {}; pass another --store-collection",
+                    path,
+                    vec.len(),
+                    embedding_model,
+                    dimensions
+                );
+            }
+            let chunk = serde_json::from_str(&row.chunk)
+                .with_context(|| format!("A chunk in {:?} isn't JSON", path))?;
+            rows.push((chunk, Vector::new(vec, None)));
+        }
+        debug!("Read {} vectors from {:?}", rows.len(), path);
+        Ok(Self {
+            path,
+            table,
+            embedding_model: embedding_model.to_string(),
+            rows: RwLock::new(rows),
+        })
+    }
+
+    pub async fn replace(&self, chunks: &[EmbeddedChunk]) -> Result<()> {
+        let sources: BTreeSet<&str> = chunks.iter().filter_map(|(c, _)| c.source()).collect();
+        let mut script = String::from("BEGIN;\n");
+        if !sources.is_empty() {
+            let sources: Vec<String> = sources.iter().map(|source| quote(source)).collect();
+            script.push_str(&format!(
+                "DELETE FROM {} WHERE source IN ({});\n",
+                self.table,
+                sources.join(", ")
+            ));
+        }
+        let mut added = Vec::new();
+        for (chunk, embeddings) in chunks {
+            let value = serde_json::to_value(chunk)?;
+            for (i, embedding) in embeddings.iter().enumerate() {
+                script.push_str(&format!(
+                    "INSERT OR REPLACE INTO {} VALUES ({}, {}, {}, {}, {}, X'{}');\n",
+                    self.table,
+                    quote(&point_id(&value, i)),
+                    chunk.source().map_or("NULL".to_string(), quote),
+                    quote(&chunk.text),
+                    quote(&value.to_string()),
+                    quote(&self.embedding_model),
+                    to_hex(&embedding.vec)
+                ));
+                added.push((value.clone(), Vector::new(embedding.vec.clone(), None)));
+            }
+        }
+        script.push_str("COMMIT;\n");
+        sqlite3(&self.path, &script)
+            .await
+            .with_context(|| format!("Failed to write chunks to {:?}", self.path))?;
+
+        let mut rows = self.rows.write().await;
+        rows.retain(|(chunk, _)| {
+            let source = chunk.pointer("/metadata/source").and_then(Value::as_str);
+            !source.is_some_and(|source| sources.contains(source))
+        });
+        // Chunks without a source are replaced by their copies.
+        let new: BTreeSet<String> = added.iter().map(|(chunk, _)| chunk.to_string()).collect();
+        rows.retain(|(chunk, _)| !new.contains(&chunk.to_string()));
+        rows.extend(added);
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        query: Vec<f64>,
+        wanted: usize,
+        languages: Option<&[String]>,
+    ) -> Result<Vec<(f64, String, Value)>> {
+        let query = Query::new(query);
+        let rows = self.rows.read().await;
+        let mut scored: Vec<(f64, &Value)> = rows
+            .iter()
+            .filter(|(chunk, _)| {
+                let lang = chunk.pointer("/metadata/lang").and_then(Value::as_str);
+                match (languages, lang) {
+                    (Some(languages), Some(lang)) => languages.iter().any(|code| code == lang),
+                    _ => true,
+                }
+            })
+            .map(|(chunk, vector)| (vector.score(&query), chunk))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored
+            .into_iter()
+            .take(wanted)
+            .map(|(score, chunk)| (score, chunk_name(chunk), chunk.clone()))
+            .collect())
+    }
+}
+
+/// Runs `script` against the database at `path`, returning what it
+/// printed, as JSON for queries.
+async fn sqlite3(path: &Path, script: &str) -> Result<String> {
+    let mut command = Command::new("sqlite3");
+    command.args(["-batch", "-bail", "-json"]).arg(path);
+    run_script(
+        command,
+        script.to_string(),
+        "SQLite's command line tool to use --store sqlite",
+    )
+    .await
+}
+
+fn to_hex(vector: &[f64]) -> String {
+    vector
+        .iter()
+        .flat_map(|&value| (value as f32).to_le_bytes())
+        .map(|byte| format!("{:02X}", byte))
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<f64>> {
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<_>>()?;
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(4)
+            .map(|b| f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::Chunk;
+    use rig::OneOrMany;
+    use rig::embeddings::Embedding;
+
+    fn embedded(source: &str, lang: &str, text: &str, vec: Vec<f64>) -> EmbeddedChunk {
+        let chunk = Chunk {
+            text: text.to_string(),
+            metadata: [
+                ("source".to_string(), source.to_string()),
+                ("lang".to_string(), lang.to_string()),
+            ]
+            .into(),
+            index: 0,
+            char_range: 0..0,
+            parent: None,
+            summary: None,
+            document: 0,
+            header: None,
+        };
+        let embedding = Embedding {
+            document: text.to_string(),
+            vec,
+        };
+        (chunk, OneOrMany::one(embedding))
+    }
+
+    fn texts(found: &[(f64, String, Value)]) -> Vec<&str> {
+        found
+            .iter()
+            .filter_map(|(_, _, chunk)| chunk["text"].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_vectors_as_32_bit_floats() {
+        assert_eq!(to_hex(&[1.0, -2.5]), "0000803F000020C0");
+        assert_eq!(from_hex("0000803F000020C0"), Some(vec![1.0, -2.5]));
+        assert_eq!(from_hex("0000803F00"), None);
+        assert_eq!(from_hex("zz00803F"), None);
+    }
+
+    /// Needs the `sqlite3` tool, and passes without it.
+    #[tokio::test]
+    async fn replaces_a_files_chunks_and_reads_them_back() {
+        if Command::new("sqlite3").arg("-version").output().is_err() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        let path = path.to_str().unwrap();
+        let store = Sqlite::open(Some(path), "docs", "model", 2).await.unwrap();
+        store
+            .replace(&[
+                embedded("a.txt", "en", "it's old", vec![1.0, 0.0]),
+                embedded("b.txt", "fr", "bonjour", vec![0.0, 1.0]),
+            ])
+            .await
+            .unwrap();
+        store
+            .replace(&[embedded("a.txt", "en", "new", vec![0.8, 0.6])])
+            .await
+            .unwrap();
+
+        // Another run reads back what this one wrote.
+        let store = Sqlite::open(Some(path), "docs", "model", 2).await.unwrap();
+        let found = store.search(vec![1.0, 0.0], 5, None).await.unwrap();
+        assert_eq!(texts(&found), ["new", "bonjour"]);
+        assert!((found[0].0 - 0.8).abs() < 1e-6);
+        let found = store
+            .search(vec![1.0, 0.0], 5, Some(&["fr".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(texts(&found), ["bonjour"]);
+
+        for (model, dimensions, expected) in [
+            ("other", 2, "model mismatch"),
+            ("model", 3, "dimension mismatch"),
+        ] {
+            let error = Sqlite::open(Some(path), "docs", model, dimensions)
+                .await
+                .err()
+                .unwrap();
+            assert!(error.to_string().contains(expected), "{}", error);
+        }
+    }
+}
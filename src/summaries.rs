@@ -0,0 +1,97 @@
+//! One-sentence chunk summaries for `--summaries`, embedded with or instead
+//! of the chunks' text so a long, wordy chunk is still found by what it is
+//! about.
+
+use crate::chat::ChatClient;
+use crate::chunking::Chunk;
+use crate::loaders;
+use crate::progress;
+use crate::rate_limit::estimate_tokens;
+use crate::retry::RetryPolicy;
+use anyhow::{Context, Result};
+use rig::Embed;
+use rig::client::CompletionClient;
+use rig::completion::Prompt;
+use rig::embeddings::{EmbedError, TextEmbedder};
+use std::fs;
+use tracing::{info, warn};
+
+const SUMMARY_PROMPT: &str = "Summarize the passage you are given in one sentence that says \
+what it is about, naming its subject rather than using pronouns. Answer with the sentence only.";
+
+/// What `--summaries` embeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SummaryMode {
+    /// The summary as well as the text, so a chunk matches either
+    Alongside,
+    /// Only the summary; the model still reads the full text
+    Instead,
+}
+
+/// A chunk embedded by its summary alone, or by its text when it has none.
+pub struct SummaryOnly(pub Chunk);
+
+impl Embed for SummaryOnly {
+    fn embed(&self, embedder: &mut TextEmbedder) -> Result<(), EmbedError> {
+        let chunk = &self.0;
+        embedder.embed(chunk.summary.clone().unwrap_or_else(|| chunk.text.clone()));
+        Ok(())
+    }
+}
+
+/// Has a chat model sum up every chunk in one sentence, kept on the chunk
+/// as its `summary`. Answers are cached by model and chunk text, so only
+/// new or changed chunks cost a call; a chunk that can't be summarized is
+/// left without one.
+pub async fn summarize_chunks(
+    client: &ChatClient,
+    model: &str,
+    retry: &RetryPolicy,
+    chunks: &mut [Chunk],
+) -> Result<()> {
+    if chunks.is_empty() {
+        return Ok(());
+    }
+    info!("Summarizing {} chunks with {}", chunks.len(), model);
+    let dir = loaders::cache_dir("summaries")?;
+    let agent = client.agent(model).preamble(SUMMARY_PROMPT).build();
+
+    let (mut summarized, mut cached) = (0, 0);
+    let progress = progress::start("Summarizing", "chunks", chunks.len());
+    for chunk in chunks.iter_mut() {
+        let path = dir.join(format!(
+            "{}.txt",
+            loaders::sha256_hex(format!("{}\n{}", model, chunk.text).as_bytes())
+        ));
+        if let Ok(summary) = fs::read_to_string(&path) {
+            chunk.summary = Some(summary);
+            summarized += 1;
+            cached += 1;
+            progress.advance(1);
+            continue;
+        }
+        let answer = retry
+            .run("Summarizing", estimate_tokens(&chunk.text), || {
+                agent.prompt(chunk.text.as_str()).into_future()
+            })
+            .await;
+        match answer {
+            Ok(answer) => {
+                let summary = answer.trim().replace('\n', " ");
+                fs::write(&path, &summary)
+                    .with_context(|| format!("Failed to write cached summary: {:?}", path))?;
+                chunk.summary = Some(summary);
+                summarized += 1;
+            }
+            Err(e) => warn!("Failed to summarize chunk {}: {}", chunk.index, e),
+        }
+        progress.advance(1);
+    }
+    info!(
+        "Summarized {} of {} chunks ({} cached)",
+        summarized,
+        chunks.len(),
+        cached
+    );
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use crate::loaders::{Document, DocumentAudio};
+use crate::retry::{HttpFailure, RetryPolicy};
+use anyhow::{Context, Result, bail};
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+const TRANSCRIPTIONS_URL: &str = "https://api.openai.com/v1/audio/transcriptions";
+
+/// Largest upload the transcription API accepts.
+const MAX_UPLOAD_BYTES: usize = 25 * 1024 * 1024;
+
+/// Consecutive transcript segments are grouped into documents spanning about
+/// this long, so each chunk covers a stretch of the recording.
+const WINDOW_SECONDS: f64 = 60.0;
+
+/// Video containers whose audio track is extracted before uploading.
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm", "avi"];
+
+/// Where recordings are transcribed.
+#[derive(Debug, Clone)]
+pub enum Transcriber {
+    /// The OpenAI transcription API, with the given model.
+    OpenAi { model: String },
+    /// A local whisper.cpp install, with the given ggml model file.
+    WhisperCpp { model: PathBuf },
+}
+
+/// A stretch of speech, with its offsets into the recording in seconds.
+#[derive(Debug, Deserialize)]
+struct Segment {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+/// Replaces every document carrying a recording with its transcript, split
+/// into documents of about a minute tagged with their start and end times.
+/// Recordings that fail to transcribe are dropped with a warning.
+pub async fn transcribe_audio(
+    transcriber: &Transcriber,
+    retry: &RetryPolicy,
+    documents: &mut Vec<Document>,
+) -> Result<()> {
+    let total = documents.iter().filter(|d| d.audio.is_some()).count();
+    if total == 0 {
+        return Ok(());
+    }
+    info!("Transcribing {} recordings", total);
+
+    let mut transcribed = Vec::with_capacity(documents.len());
+    for mut document in std::mem::take(documents) {
+        let Some(audio) = document.audio.take() else {
+            transcribed.push(document);
+            continue;
+        };
+        info!("Transcribing {}", audio.filename);
+        let segments = match transcriber {
+            Transcriber::OpenAi { model } => {
+                retry
+                    .run("Transcription", 0, || transcribe_openai(model, &audio))
+                    .await
+            }
+            Transcriber::WhisperCpp { model } => transcribe_whisper_cpp(model, &audio),
+        };
+        match segments {
+            Ok(segments) => {
+                debug!("{} has {} segments", audio.filename, segments.len());
+                transcribed.extend(windows(&segments).into_iter().map(|(start, end, text)| {
+                    let mut window = Document::new(text);
+                    window.metadata = document.metadata.clone();
+                    window
+                        .with_metadata("start", timestamp(start))
+                        .with_metadata("end", timestamp(end))
+                        .with_metadata("content", "transcript")
+                }));
+            }
+            Err(e) => warn!("Failed to transcribe {}: {:#}", audio.filename, e),
+        }
+    }
+    *documents = transcribed;
+    Ok(())
+}
+
+async fn transcribe_openai(model: &str, audio: &DocumentAudio) -> Result<Vec<Segment>> {
+    let (filename, data) = if VIDEO_EXTENSIONS.contains(&extension(&audio.filename).as_str())
+        || audio.data.len() > MAX_UPLOAD_BYTES
+    {
+        // Mono speech-quality MP3 keeps about 1.5 hours under the upload limit.
+        let mp3 =
+            with_work_dir(|work_dir| convert(audio, work_dir, "audio.mp3", &["-b:a", "32k"]))?;
+        (format!("{}.mp3", audio.filename), mp3)
+    } else {
+        (audio.filename.clone(), audio.data.clone())
+    };
+    if data.len() > MAX_UPLOAD_BYTES {
+        bail!("Recording is too long to upload for transcription");
+    }
+
+    let api_key = std::env::var("OPENAI_API_KEY").context("OPENAI_API_KEY is not set")?;
+    let form = Form::new()
+        .text("model", model.to_string())
+        .text("response_format", "verbose_json")
+        .text("timestamp_granularities[]", "segment")
+        .part("file", Part::bytes(data).file_name(filename));
+    let response = reqwest::Client::new()
+        .post(TRANSCRIPTIONS_URL)
+        .bearer_auth(api_key)
+        .multipart(form)
+        .send()
+        .await
+        .context("Failed to send transcription request")?;
+    if !response.status().is_success() {
+        let failure = HttpFailure::read(response).await;
+        return Err(anyhow::Error::new(failure).context("Transcription failed"));
+    }
+    let body = response.bytes().await?;
+
+    #[derive(Deserialize)]
+    struct Transcription {
+        segments: Vec<Segment>,
+    }
+    let transcription: Transcription =
+        serde_json::from_slice(&body).context("Failed to parse transcription response")?;
+    Ok(transcription.segments)
+}
+
+/// Runs `whisper-cli` on a 16 kHz WAV copy of the recording, made with
+/// `ffmpeg`.
+fn transcribe_whisper_cpp(model: &Path, audio: &DocumentAudio) -> Result<Vec<Segment>> {
+    with_work_dir(|work_dir| {
+        convert(audio, work_dir, "audio.wav", &[])?;
+        let status = Command::new("whisper-cli")
+            .arg("-m")
+            .arg(model)
+            .arg("-f")
+            .arg(work_dir.join("audio.wav"))
+            .args(["--output-json", "--no-prints", "--output-file"])
+            .arg(work_dir.join("transcript"))
+            .status()
+            .context("Failed to run `whisper-cli`; install whisper.cpp to transcribe locally")?;
+        if !status.success() {
+            bail!("`whisper-cli` failed");
+        }
+
+        #[derive(Deserialize)]
+        struct Output {
+            transcription: Vec<OutputSegment>,
+        }
+        #[derive(Deserialize)]
+        struct OutputSegment {
+            /// Start and end in milliseconds.
+            offsets: Offsets,
+            text: String,
+        }
+        #[derive(Deserialize)]
+        struct Offsets {
+            from: u64,
+            to: u64,
+        }
+        let path = work_dir.join("transcript.json");
+        let json = fs::read(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        let output: Output =
+            serde_json::from_slice(&json).context("Failed to parse whisper.cpp output")?;
+        Ok(output
+            .transcription
+            .into_iter()
+            .map(|segment| Segment {
+                start: segment.offsets.from as f64 / 1000.0,
+                end: segment.offsets.to as f64 / 1000.0,
+                text: segment.text,
+            })
+            .collect())
+    })
+}
+
+/// Converts a recording to mono 16 kHz audio with `ffmpeg`, dropping any
+/// video, and returns the converted file.
+fn convert(audio: &DocumentAudio, work_dir: &Path, output: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let input = work_dir.join(format!("input.{}", extension(&audio.filename)));
+    fs::write(&input, &audio.data).with_context(|| format!("Failed to write {:?}", input))?;
+    let output = work_dir.join(output);
+    let status = Command::new("ffmpeg")
+        .args(["-nostdin", "-loglevel", "error", "-y", "-i"])
+        .arg(&input)
+        .args(["-vn", "-ac", "1", "-ar", "16000"])
+        .args(args)
+        .arg(&output)
+        .status()
+        .context("Failed to run `ffmpeg`; install it to transcribe video, long recordings or with whisper.cpp")?;
+    if !status.success() {
+        bail!("`ffmpeg` failed to convert {}", audio.filename);
+    }
+    fs::read(&output).with_context(|| format!("Failed to read {:?}", output))
+}
+
+fn with_work_dir<T>(f: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+    let work_dir =
+        std::env::temp_dir().join(format!("rag-my-pdf-transcribe-{}", std::process::id()));
+    fs::create_dir_all(&work_dir).with_context(|| {
+        format!(
+            "Failed to create transcription work directory: {:?}",
+            work_dir
+        )
+    })?;
+    let result = f(&work_dir);
+    let _ = fs::remove_dir_all(&work_dir);
+    result
+}
+
+/// Groups segments into windows of about [`WINDOW_SECONDS`], returning each
+/// window's start, end and text.
+fn windows(segments: &[Segment]) -> Vec<(f64, f64, String)> {
+    let mut windows: Vec<(f64, f64, String)> = Vec::new();
+    for segment in segments {
+        let text = segment.text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        match windows.last_mut() {
+            Some((start, end, window)) if segment.start - *start < WINDOW_SECONDS => {
+                *end = segment.end;
+                window.push(' ');
+                window.push_str(text);
+            }
+            _ => windows.push((segment.start, segment.end, text.to_string())),
+        }
+    }
+    windows
+}
+
+/// Formats seconds as `HH:MM:SS`.
+fn timestamp(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn extension(filename: &str) -> String {
+    Path::new(filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
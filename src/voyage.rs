@@ -0,0 +1,178 @@
+//! Embeddings from Voyage AI's API, for `--embedding-provider voyage`.
+
+use crate::retry::{HttpFailure, request_error};
+use anyhow::Context;
+use rig::embeddings::{Embedding, EmbeddingError};
+use serde::Deserialize;
+use serde_json::json;
+
+/// Model used when `--embedding-model` isn't given.
+pub const VOYAGE_EMBEDDING_MODEL: &str = "voyage-3";
+
+const VOYAGE_URL: &str = "https://api.voyageai.com/v1";
+
+/// Most texts one request may hold.
+const MAX_TEXTS: usize = 1000;
+
+/// Tokens one request may hold by model, and for models not listed.
+const MAX_TOKENS: &[(&str, usize)] = &[
+    ("voyage-3-lite", 1_000_000),
+    ("voyage-3.5-lite", 1_000_000),
+    ("voyage-3", 320_000),
+    ("voyage-3.5", 320_000),
+    ("voyage-2", 320_000),
+];
+const DEFAULT_MAX_TOKENS: usize = 120_000;
+
+/// Tokens one text may hold by model, and for models not listed.
+const CONTEXT_LENGTHS: &[(&str, usize)] = &[
+    ("voyage-2", 4_000),
+    ("voyage-code-2", 16_000),
+    ("voyage-large-2", 16_000),
+];
+const DEFAULT_CONTEXT_LENGTH: usize = 32_000;
+
+/// Voyage counts tokens with its own tokenizer, so batches are filled to
+/// this share of the limit by OpenAI's count.
+const TOKEN_HEADROOM: f64 = 0.8;
+
+/// Sizes of the vectors Voyage's models embed into, and for models not
+/// listed.
+const DIMENSIONS: &[(&str, usize)] = &[
+    ("voyage-3-lite", 512),
+    ("voyage-code-2", 1536),
+    ("voyage-large-2", 1536),
+];
+const DEFAULT_DIMENSIONS: usize = 1024;
+
+/// A Voyage model, embedding either documents or queries: Voyage prompts
+/// the model differently for each.
+#[derive(Clone)]
+pub struct VoyageModel {
+    http: reqwest::Client,
+    url: String,
+    key: String,
+    model: String,
+    input_type: &'static str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f64>,
+    index: usize,
+}
+
+impl VoyageModel {
+    /// `model`, embedding documents, with the key in `VOYAGE_API_KEY` and
+    /// the API at `VOYAGE_BASE_URL`, if set.
+    pub fn new(model: &str) -> anyhow::Result<Self> {
+        let key = std::env::var("VOYAGE_API_KEY")
+            .context("--embedding-provider voyage needs VOYAGE_API_KEY to be set")?;
+        let url = std::env::var("VOYAGE_BASE_URL").unwrap_or_else(|_| VOYAGE_URL.to_string());
+        Ok(Self {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            key,
+            model: model.to_string(),
+            input_type: "document",
+        })
+    }
+
+    /// This model as it should embed questions.
+    pub fn for_queries(&self) -> Self {
+        Self {
+            input_type: "query",
+            ..self.clone()
+        }
+    }
+
+    pub fn cache_key(&self) -> String {
+        format!("voyage {} {}", self.model, self.input_type)
+    }
+
+    pub fn ndims(&self) -> usize {
+        DIMENSIONS
+            .iter()
+            .find(|(model, _)| *model == self.model)
+            .map_or(DEFAULT_DIMENSIONS, |&(_, ndims)| ndims)
+    }
+
+    /// The most tokens, by OpenAI's count, that one text should hold.
+    pub fn max_tokens(&self) -> usize {
+        let length = CONTEXT_LENGTHS
+            .iter()
+            .find(|(model, _)| *model == self.model)
+            .map_or(DEFAULT_CONTEXT_LENGTH, |&(_, length)| length);
+        (length as f64 * TOKEN_HEADROOM) as usize
+    }
+
+    /// Embeds `texts` in as few requests as Voyage's limits on texts and
+    /// tokens per request allow.
+    pub async fn embed_texts(&self, texts: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        let max_tokens = MAX_TOKENS
+            .iter()
+            .find(|(model, _)| *model == self.model)
+            .map_or(DEFAULT_MAX_TOKENS, |&(_, tokens)| tokens);
+        let max_tokens = (max_tokens as f64 * TOKEN_HEADROOM) as usize;
+        let bpe = tiktoken_rs::cl100k_base_singleton();
+
+        let mut embeddings = Vec::with_capacity(texts.len());
+        let mut batch = Vec::new();
+        let mut tokens = 0;
+        for text in texts {
+            let size = bpe.count_ordinary(&text);
+            if !batch.is_empty() && (batch.len() == MAX_TEXTS || tokens + size > max_tokens) {
+                embeddings.extend(self.embed_batch(std::mem::take(&mut batch)).await?);
+                tokens = 0;
+            }
+            batch.push(text);
+            tokens += size;
+        }
+        if !batch.is_empty() {
+            embeddings.extend(self.embed_batch(batch).await?);
+        }
+        Ok(embeddings)
+    }
+
+    async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        let request = json!({
+            "model": self.model,
+            "input": texts,
+            "input_type": self.input_type,
+        });
+        let response = self
+            .http
+            .post(format!("{}/embeddings", self.url))
+            .bearer_auth(&self.key)
+            .json(&request)
+            .send()
+            .await
+            .map_err(request_error)?;
+        if !response.status().is_success() {
+            return Err(HttpFailure::read(response).await.into());
+        }
+        let body = response.text().await.map_err(request_error)?;
+        let mut data = serde_json::from_str::<EmbeddingResponse>(&body)?.data;
+        if data.len() != texts.len() {
+            return Err(EmbeddingError::ResponseError(format!(
+                "Voyage returned {} embeddings for {} texts",
+                data.len(),
+                texts.len()
+            )));
+        }
+        data.sort_by_key(|data| data.index);
+        Ok(data
+            .into_iter()
+            .zip(texts)
+            .map(|(data, document)| Embedding {
+                document,
+                vec: data.embedding,
+            })
+            .collect())
+    }
+}
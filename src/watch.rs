@@ -0,0 +1,587 @@
+use crate::chunking::Chunk;
+use crate::hnsw::{Hnsw, HnswParams};
+use crate::language::LanguageRoute;
+use crate::loaders::{self, PathFilter};
+use crate::quantize::{Quantization, Query, RESCORE_FACTOR, Vector};
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+use rig::OneOrMany;
+use rig::embeddings::{Embedding, EmbeddingModel};
+use rig::vector_store::request::Filter;
+use rig::vector_store::{VectorSearchRequest, VectorStoreError, VectorStoreIndex};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, mpsc};
+use tracing::{debug, info, warn};
+use whatlang::Lang;
+
+/// Editors and copy tools touch a file several times while saving it;
+/// changes are handled once things have been quiet for this long.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// When retrieval is limited to some languages, this many times as many
+/// chunks are searched for as are wanted, since the rest are passed over.
+const LANGUAGE_OVERFETCH: usize = 5;
+
+/// With an ensemble, each model ranks this many times as many chunks as
+/// are wanted, so a chunk only one model ranks highly still makes the
+/// fused list.
+const ENSEMBLE_DEPTH: usize = 3;
+
+/// The constant of reciprocal rank fusion: a chunk scores 1 / (this + its
+/// rank) by each model, which keeps a single first place from outweighing
+/// agreement between the models.
+const RRF_K: f64 = 60.0;
+
+pub type EmbeddedChunk = (Chunk, OneOrMany<Embedding>);
+
+/// A vector index whose chunks can be replaced source by source while the
+/// agent is using it. With more than one model (`--ensemble-model`), each
+/// keeps its own vectors of the chunks, and their rankings are fused.
+#[derive(Clone)]
+pub struct LiveIndex<M: EmbeddingModel> {
+    models: Vec<M>,
+    route: LanguageRoute,
+    quantization: Option<Quantization>,
+    hnsw: Option<HnswParams>,
+    state: Arc<RwLock<State>>,
+}
+
+/// A chunk and its vectors, as the index keeps them. The vectors are
+/// shared with the `--hnsw` graph, if there is one.
+type StoredChunk = (Chunk, Vec<Arc<Vector>>);
+
+struct State {
+    /// Each model's chunks, by the `source` they came from.
+    spaces: Vec<BTreeMap<String, Vec<StoredChunk>>>,
+    /// With `--hnsw`, a graph over each model's vectors.
+    graphs: Vec<Option<Graph>>,
+    /// The languages the chunks are tagged with.
+    languages: Vec<Lang>,
+}
+
+impl<M: EmbeddingModel + Clone> LiveIndex<M> {
+    /// An index of the chunks each of `models` embedded, in the same
+    /// order, retrieving only those in the languages `route` picks for each
+    /// question, and keeping their vectors quantized with `quantization`.
+    /// With `hnsw`, questions are answered by walking a graph of the
+    /// vectors rather than scoring all of them.
+    pub fn new(
+        models: Vec<M>,
+        embedded: Vec<Vec<EmbeddedChunk>>,
+        route: LanguageRoute,
+        quantization: Option<Quantization>,
+        hnsw: Option<HnswParams>,
+    ) -> Self {
+        let spaces: Vec<BTreeMap<String, Vec<StoredChunk>>> = embedded
+            .into_iter()
+            .map(|chunks| {
+                let mut sources: BTreeMap<String, Vec<StoredChunk>> = BTreeMap::new();
+                for chunk in chunks {
+                    let source = chunk.0.source().unwrap_or_default().to_string();
+                    sources
+                        .entry(source)
+                        .or_default()
+                        .push(store(chunk, quantization));
+                }
+                sources
+            })
+            .collect();
+        let languages = languages(&spaces[0]);
+        let graphs = spaces
+            .iter()
+            .map(|sources| Some(Graph::build(sources, hnsw?)))
+            .collect();
+        Self {
+            models,
+            route,
+            quantization,
+            hnsw,
+            state: Arc::new(RwLock::new(State {
+                spaces,
+                graphs,
+                languages,
+            })),
+        }
+    }
+
+    /// Swaps the chunks of one source for a new set from each model; no
+    /// sets, or empty ones, remove the source. Returns whether the source was indexed
+    /// before.
+    pub async fn replace(&self, source: &str, embedded: Vec<Vec<EmbeddedChunk>>) -> bool {
+        let mut state = self.state.write().await;
+        let State { spaces, graphs, .. } = &mut *state;
+        let mut existed = false;
+        let mut embedded = embedded.into_iter();
+        for (sources, graph) in spaces.iter_mut().zip(graphs) {
+            let chunks = embedded.next().unwrap_or_default();
+            let chunks: Vec<StoredChunk> = chunks
+                .into_iter()
+                .map(|chunk| store(chunk, self.quantization))
+                .collect();
+            existed |= if chunks.is_empty() {
+                sources.remove(source).is_some()
+            } else {
+                sources.insert(source.to_string(), chunks).is_some()
+            };
+            if let (Some(graph), Some(params)) = (graph, self.hnsw) {
+                graph.replace(source, sources, params);
+            }
+        }
+        state.languages = languages(&state.spaces[0]);
+        existed
+    }
+}
+
+fn store((chunk, embeddings): EmbeddedChunk, quantization: Option<Quantization>) -> StoredChunk {
+    let vectors = embeddings
+        .into_iter()
+        .map(|embedding| Arc::new(Vector::new(embedding.vec, quantization)))
+        .collect();
+    (chunk, vectors)
+}
+
+/// With `--hnsw`, the graph over one model's vectors, which it knows by
+/// their place in `vectors`. Searching it asks for each candidate chunk's
+/// vectors up to `most`, so the graph finds all of them.
+struct Graph {
+    hnsw: Hnsw,
+    vectors: Vec<Arc<Vector>>,
+    /// The source and place of each vector's chunk.
+    chunks: Vec<(Arc<str>, usize)>,
+    /// Each source's vectors, by their places.
+    nodes: HashMap<Arc<str>, Vec<usize>>,
+    /// The most vectors one chunk has.
+    most: usize,
+}
+
+impl Graph {
+    fn build(sources: &BTreeMap<String, Vec<StoredChunk>>, params: HnswParams) -> Self {
+        let started = Instant::now();
+        let mut graph = Self {
+            hnsw: Hnsw::build::<Vector>(&[], params),
+            vectors: Vec::new(),
+            chunks: Vec::new(),
+            nodes: HashMap::new(),
+            most: 1,
+        };
+        for (source, chunks) in sources {
+            graph.push(source, chunks);
+        }
+        graph.hnsw = Hnsw::build(&graph.vectors, params);
+        debug!(
+            "Built an HNSW graph of {} vectors in {:.2?}",
+            graph.vectors.len(),
+            started.elapsed()
+        );
+        graph
+    }
+
+    /// Takes the vectors of `source`'s old chunks out of searches and links
+    /// in those of its chunks in `sources` now. Once most of the graph has
+    /// been taken out, it is built again without them.
+    fn replace(
+        &mut self,
+        source: &str,
+        sources: &BTreeMap<String, Vec<StoredChunk>>,
+        params: HnswParams,
+    ) {
+        for node in self.nodes.remove(source).unwrap_or_default() {
+            self.hnsw.remove(node);
+        }
+        if self.hnsw.removed() * 2 > self.hnsw.len() {
+            *self = Self::build(sources, params);
+            return;
+        }
+        if let Some(chunks) = sources.get(source) {
+            self.push(source, chunks);
+        }
+        self.hnsw.add(&self.vectors);
+    }
+
+    /// Adds `chunks`' vectors to those the graph knows, not yet linking
+    /// them in.
+    fn push(&mut self, source: &str, chunks: &[StoredChunk]) {
+        let source: Arc<str> = source.into();
+        let nodes = self.nodes.entry(source.clone()).or_default();
+        for (i, (_, vectors)) in chunks.iter().enumerate() {
+            self.most = self.most.max(vectors.len());
+            for vector in vectors {
+                nodes.push(self.vectors.len());
+                self.vectors.push(vector.clone());
+                self.chunks.push((source.clone(), i));
+            }
+        }
+    }
+}
+
+fn languages(sources: &BTreeMap<String, Vec<StoredChunk>>) -> Vec<Lang> {
+    let languages: BTreeSet<&str> = sources
+        .values()
+        .flatten()
+        .filter_map(|(chunk, _)| chunk.metadata.get("lang"))
+        .map(String::as_str)
+        .collect();
+    languages.into_iter().filter_map(Lang::from_code).collect()
+}
+
+/// The `wanted` chunks closest to `query`, best first, with their scores
+/// and ids. Quantized vectors pick [`RESCORE_FACTOR`] times as many
+/// candidates by their rough scores, which are then scored again against
+/// the question's full vector. With a `graph`, the candidates are those it
+/// leads to rather than every chunk.
+fn search<'a>(
+    sources: &'a BTreeMap<String, Vec<StoredChunk>>,
+    graph: Option<&Graph>,
+    query: &Query,
+    wanted: usize,
+    quantized: bool,
+) -> Vec<(f64, String, &'a Chunk)> {
+    // A chunk embedded more than once (with `--summaries`) scores as its
+    // best match.
+    let best = |vectors: &[Arc<Vector>], score: &dyn Fn(&Vector) -> f64| {
+        vectors
+            .iter()
+            .map(|vector| score(vector))
+            .fold(f64::NEG_INFINITY, f64::max)
+    };
+    let candidates = if quantized {
+        wanted * RESCORE_FACTOR
+    } else {
+        wanted
+    };
+    let mut scored: Vec<(f64, (&String, usize, &StoredChunk))> = match graph {
+        Some(graph) => {
+            let mut seen = HashSet::new();
+            graph
+                .hnsw
+                .search(&graph.vectors, query, candidates * graph.most)
+                .into_iter()
+                .filter(|&(_, node)| seen.insert(&graph.chunks[node]))
+                .filter_map(|(_, node)| {
+                    let (source, i) = &graph.chunks[node];
+                    let (source, chunks) = sources.get_key_value(&**source)?;
+                    let chunk = &chunks[*i];
+                    Some((
+                        best(&chunk.1, &|vector| vector.rough_score(query)),
+                        (source, *i, chunk),
+                    ))
+                })
+                .collect()
+        }
+        None => sources
+            .iter()
+            .flat_map(|(source, chunks)| {
+                chunks.iter().enumerate().map(move |(i, chunk)| {
+                    let score = best(&chunk.1, &|vector| vector.rough_score(query));
+                    (score, (source, i, chunk))
+                })
+            })
+            .collect(),
+    };
+    if quantized {
+        keep_best(&mut scored, candidates);
+        for (score, (_, _, chunk)) in &mut scored {
+            *score = best(&chunk.1, &|vector| vector.score(query));
+        }
+    }
+    keep_best(&mut scored, wanted);
+    scored
+        .into_iter()
+        .map(|(score, (source, i, (chunk, _)))| (score, format!("{}#{}", source, i), chunk))
+        .collect()
+}
+
+/// Leaves the `n` highest scored items, highest first.
+fn keep_best<T>(items: &mut Vec<(f64, T)>, n: usize) {
+    if items.len() > n {
+        items.select_nth_unstable_by(n, |a, b| b.0.total_cmp(&a.0));
+        items.truncate(n);
+    }
+    items.sort_by(|a, b| b.0.total_cmp(&a.0));
+}
+
+/// One model's `wanted` best chunks for the question it embedded into
+/// `query`, best first, as JSON: chunks in other than `languages` passed
+/// over, and chunks with a parent replaced by it, once.
+fn rank(
+    sources: &BTreeMap<String, Vec<StoredChunk>>,
+    graph: Option<&Graph>,
+    query: Vec<f64>,
+    ndims: usize,
+    languages: Option<&[String]>,
+    wanted: usize,
+    quantized: bool,
+) -> Result<Vec<(f64, String, Value)>, VectorStoreError> {
+    // Vectors of different sizes can't be compared, and would be compared
+    // over the shorter one's length without a word.
+    let indexed = sources
+        .values()
+        .flatten()
+        .filter_map(|(_, vectors)| vectors.first())
+        .map(|vector| vector.len())
+        .next();
+    if let Some(indexed) = indexed
+        && ndims != 0
+        && indexed != ndims
+    {
+        return Err(VectorStoreError::DatastoreError(
+            format!(
+                "The index holds {}-dimensional vectors, but questions are embedded into {}; index again with the same --embedding-dimensions",
+                indexed, ndims
+            )
+            .into(),
+        ));
+    }
+    // Enough are fetched for the wanted number to be left after the other
+    // languages are passed over.
+    let fetched = match languages {
+        Some(_) => wanted * LANGUAGE_OVERFETCH,
+        None => wanted,
+    };
+    let found = search(sources, graph, &Query::new(query), fetched, quantized);
+    debug!(
+        "Closest chunks: {}",
+        found
+            .iter()
+            .map(|(score, id, _)| format!("{} ({:.3})", id, score))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let found: Vec<_> = found
+        .into_iter()
+        .map(|(score, id, chunk)| Ok((score, id, serde_json::to_value(chunk)?)))
+        .collect::<Result<_, serde_json::Error>>()?;
+    Ok(pick(found, languages, wanted))
+}
+
+/// The first `wanted` of the chunks `found`, best first, as JSON: chunks
+/// in other than `languages` passed over, and chunks with a parent replaced
+/// by it, once. Indexes kept elsewhere (see [`crate::store`]) retrieve
+/// their chunks through this too.
+pub fn pick(
+    found: Vec<(f64, String, Value)>,
+    languages: Option<&[String]>,
+    wanted: usize,
+) -> Vec<(f64, String, Value)> {
+    let mut parents = HashSet::new();
+    let mut results = Vec::new();
+    for (score, id, chunk) in found {
+        let Value::Object(mut chunk) = chunk else {
+            continue;
+        };
+        let lang = chunk
+            .get("metadata")
+            .and_then(|metadata| metadata.get("lang"))
+            .and_then(Value::as_str);
+        if let (Some(languages), Some(lang)) = (languages, lang)
+            && !languages.iter().any(|code| code == lang)
+        {
+            continue;
+        }
+        if results.len() == wanted {
+            break;
+        }
+        let chunk = match chunk.remove("parent") {
+            Some(parent) if !parents.insert(parent.to_string()) => continue,
+            Some(parent) => parent,
+            None => Value::Object(chunk),
+        };
+        results.push((score, id, chunk));
+    }
+    results
+}
+
+/// Several models' rankings as one, by reciprocal rank fusion. The same
+/// chunk is known by its content, since each model's index numbers its
+/// chunks apart, and keeps the id the best ranking gave it.
+fn fuse(rankings: Vec<Vec<(f64, String, Value)>>) -> Vec<(f64, String, Value)> {
+    let mut fused: Vec<(f64, String, Value)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for ranking in rankings {
+        for (rank, (_, id, chunk)) in ranking.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f64 + 1.0);
+            let key = chunk.to_string();
+            match positions.get(&key) {
+                Some(&i) => fused[i].0 += score,
+                None => {
+                    positions.insert(key, fused.len());
+                    fused.push((score, id, chunk));
+                }
+            }
+        }
+    }
+    fused.sort_by(|a, b| b.0.total_cmp(&a.0));
+    debug!(
+        "Fused ranking: {}",
+        fused
+            .iter()
+            .map(|(score, id, _)| format!("{} ({:.4})", id, score))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    fused
+}
+
+impl<M: EmbeddingModel + Clone + Sync> VectorStoreIndex for LiveIndex<M> {
+    type Filter = Filter<Value>;
+
+    /// Chunks in the wrong language for the question are passed over (but
+    /// untagged ones never are). Chunks with a parent come back as the
+    /// parent, and only the best match of several with the same parent
+    /// comes back at all. With an ensemble, chunks are scored by reciprocal
+    /// rank fusion of the models' rankings.
+    async fn top_n<T: for<'a> Deserialize<'a> + Send>(
+        &self,
+        req: VectorSearchRequest<Self::Filter>,
+    ) -> Result<Vec<(f64, String, T)>, VectorStoreError> {
+        // The question is embedded before the index is locked, so a file
+        // being indexed meanwhile isn't held up by the embedding call.
+        let mut queries = Vec::with_capacity(self.models.len());
+        for model in &self.models {
+            queries.push(model.embed_text(req.query()).await?.vec);
+        }
+        let state = self.state.read().await;
+        let languages = self.route.languages(req.query(), &state.languages);
+        let samples = req.samples() as usize;
+        let depth = match self.models.len() {
+            1 => samples,
+            _ => samples * ENSEMBLE_DEPTH,
+        };
+        let mut rankings = Vec::new();
+        for (((model, query), sources), graph) in self
+            .models
+            .iter()
+            .zip(queries)
+            .zip(&state.spaces)
+            .zip(&state.graphs)
+        {
+            rankings.push(rank(
+                sources,
+                graph.as_ref(),
+                query,
+                model.ndims(),
+                languages.as_deref(),
+                depth,
+                self.quantization.is_some(),
+            )?);
+        }
+
+        let ranked = match <[_; 1]>::try_from(rankings) {
+            Ok([ranking]) => ranking,
+            Err(rankings) => fuse(rankings),
+        };
+        ranked
+            .into_iter()
+            .take(samples)
+            .map(|(score, id, chunk)| Ok((score, id, serde_json::from_value(chunk)?)))
+            .collect()
+    }
+
+    async fn top_n_ids(
+        &self,
+        req: VectorSearchRequest<Self::Filter>,
+    ) -> Result<Vec<(f64, String)>, VectorStoreError> {
+        let found = self.top_n::<Chunk>(req).await?;
+        Ok(found
+            .into_iter()
+            .map(|(score, id, _)| (score, id))
+            .collect())
+    }
+}
+
+/// Watches `root` for files being added, changed or deleted and keeps the
+/// index in step: changed files are run through `load` (which loads, chunks
+/// and embeds one file, with each of the index's models) and replace their
+/// old chunks, and deleted files are dropped. Runs until the watcher fails.
+pub async fn watch<M, F, Fut>(
+    root: PathBuf,
+    filter: PathFilter,
+    index: LiveIndex<M>,
+    load: F,
+) -> Result<()>
+where
+    M: EmbeddingModel + Clone,
+    F: Fn(PathBuf) -> Fut,
+    Fut: Future<Output = Result<Vec<Vec<EmbeddedChunk>>>>,
+{
+    // Events carry absolute paths; they are mapped back under `root` as
+    // given so sources match the ones loaded at startup.
+    let absolute = root
+        .canonicalize()
+        .with_context(|| format!("Failed to watch {:?}", root))?;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })
+    .context("Failed to start the file watcher")?;
+    watcher
+        .watch(&absolute, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {:?}", root))?;
+    info!("Watching {:?} for changes", root);
+
+    while let Some(event) = rx.recv().await {
+        let mut changed = BTreeSet::new();
+        let mut add = |event: notify::Result<notify::Event>| match event {
+            // Loading a file opens and reads it, which must not count as a
+            // change or every re-index would set off another.
+            Ok(event) if event.kind.is_access() => {}
+            Ok(event) => changed.extend(event.paths),
+            Err(e) => warn!("File watcher error: {}", e),
+        };
+        add(event);
+        while let Ok(Some(event)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+            add(event);
+        }
+
+        for path in changed {
+            let Ok(relative) = path.strip_prefix(&absolute) else {
+                continue;
+            };
+            let path = root.join(relative);
+            refresh(&path, relative, &filter, &index, &load).await;
+        }
+    }
+    Ok(())
+}
+
+async fn refresh<M, F, Fut>(
+    path: &Path,
+    relative: &Path,
+    filter: &PathFilter,
+    index: &LiveIndex<M>,
+    load: &F,
+) where
+    M: EmbeddingModel + Clone,
+    F: Fn(PathBuf) -> Fut,
+    Fut: Future<Output = Result<Vec<Vec<EmbeddedChunk>>>>,
+{
+    if !filter.matches(relative) || !loaders::is_supported(path) {
+        debug!("Ignoring change to {:?}", relative);
+        return;
+    }
+    let source = path.display().to_string();
+    if !path.is_file() {
+        if index.replace(&source, Vec::new()).await {
+            info!("Removed {} from the index", source);
+        }
+        return;
+    }
+
+    match load(path.to_path_buf()).await {
+        Ok(chunks) => {
+            let count = chunks.first().map_or(0, Vec::len);
+            if index.replace(&source, chunks).await {
+                info!("Re-indexed {} ({} chunks)", source, count);
+            } else {
+                info!("Indexed new file {} ({} chunks)", source, count);
+            }
+        }
+        Err(e) => warn!("Failed to index {}: {:#}", source, e),
+    }
+}